@@ -0,0 +1,9 @@
+-- Drop group sharing support
+DROP INDEX IF EXISTS idx_shares_group_id;
+DELETE FROM shares WHERE group_id IS NOT NULL;
+ALTER TABLE shares DROP CONSTRAINT IF EXISTS shares_recipient_check;
+ALTER TABLE shares ALTER COLUMN shared_with_user_id SET NOT NULL;
+ALTER TABLE shares DROP COLUMN IF EXISTS group_id;
+
+DROP TABLE IF EXISTS group_members;
+DROP TABLE IF EXISTS groups;
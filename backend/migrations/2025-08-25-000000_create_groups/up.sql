@@ -0,0 +1,28 @@
+-- Create groups and group membership tables for team sharing
+CREATE TABLE groups (
+    id UUID PRIMARY KEY,
+    owner_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE group_members (
+    group_id UUID NOT NULL REFERENCES groups(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    added_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (group_id, user_id)
+);
+
+CREATE INDEX idx_groups_owner_id ON groups(owner_id);
+CREATE INDEX idx_group_members_user_id ON group_members(user_id);
+
+-- Allow shares to target a group instead of a single user
+ALTER TABLE shares ADD COLUMN group_id UUID REFERENCES groups(id) ON DELETE CASCADE;
+ALTER TABLE shares ALTER COLUMN shared_with_user_id DROP NOT NULL;
+
+ALTER TABLE shares ADD CONSTRAINT shares_recipient_check CHECK (
+    (shared_with_user_id IS NOT NULL AND group_id IS NULL) OR
+    (shared_with_user_id IS NULL AND group_id IS NOT NULL)
+);
+
+CREATE INDEX idx_shares_group_id ON shares(group_id);
@@ -0,0 +1,2 @@
+-- Drop the admin role
+ALTER TABLE users DROP COLUMN IF EXISTS is_admin;
@@ -0,0 +1,2 @@
+-- Mark which users may call the administrative endpoints
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
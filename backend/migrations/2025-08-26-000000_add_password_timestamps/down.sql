@@ -0,0 +1,3 @@
+-- Remove password timestamps
+ALTER TABLE passwords DROP COLUMN IF EXISTS updated_at;
+ALTER TABLE passwords DROP COLUMN IF EXISTS created_at;
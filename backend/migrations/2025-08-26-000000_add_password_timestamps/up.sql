@@ -0,0 +1,3 @@
+-- Track when each password entry was created and last changed
+ALTER TABLE passwords ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT NOW();
+ALTER TABLE passwords ADD COLUMN updated_at TIMESTAMP NOT NULL DEFAULT NOW();
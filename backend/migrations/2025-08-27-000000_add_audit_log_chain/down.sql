@@ -0,0 +1,4 @@
+-- Remove audit log chaining
+DROP INDEX IF EXISTS idx_audit_logs_sequence;
+ALTER TABLE audit_logs DROP COLUMN IF EXISTS chain_hash;
+ALTER TABLE audit_logs DROP COLUMN IF EXISTS sequence;
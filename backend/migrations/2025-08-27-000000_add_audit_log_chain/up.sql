@@ -0,0 +1,6 @@
+-- Chain audit log entries so deleted or altered records are detectable
+-- Rows written before this migration keep a NULL chain_hash and sit before the chain
+ALTER TABLE audit_logs ADD COLUMN sequence BIGSERIAL NOT NULL;
+ALTER TABLE audit_logs ADD COLUMN chain_hash VARCHAR;
+
+CREATE UNIQUE INDEX idx_audit_logs_sequence ON audit_logs(sequence);
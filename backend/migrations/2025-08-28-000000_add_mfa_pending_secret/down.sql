@@ -0,0 +1,2 @@
+-- Remove pending MFA enrollment secrets
+ALTER TABLE users DROP COLUMN IF EXISTS mfa_pending_secret;
@@ -0,0 +1,2 @@
+-- Hold a new TOTP secret until the user confirms it with a valid code
+ALTER TABLE users ADD COLUMN mfa_pending_secret VARCHAR;
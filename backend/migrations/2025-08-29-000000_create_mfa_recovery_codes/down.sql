@@ -0,0 +1,3 @@
+-- Drop MFA recovery codes
+DROP INDEX IF EXISTS idx_mfa_recovery_codes_user_id;
+DROP TABLE IF EXISTS mfa_recovery_codes;
@@ -0,0 +1,3 @@
+-- Drop password change history
+DROP INDEX IF EXISTS idx_password_history_password_id;
+DROP TABLE IF EXISTS password_history;
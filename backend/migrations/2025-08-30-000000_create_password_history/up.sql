@@ -0,0 +1,9 @@
+-- Keep previous encrypted values so rotated passwords can be recovered
+CREATE TABLE password_history (
+    id UUID PRIMARY KEY,
+    password_id UUID NOT NULL REFERENCES passwords(id) ON DELETE CASCADE,
+    encrypted_password BYTEA NOT NULL,
+    changed_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_password_history_password_id ON password_history(password_id, changed_at DESC);
@@ -0,0 +1,3 @@
+-- Restore the audit log user reference; entries of deleted users are not revalidated
+ALTER TABLE audit_logs DROP CONSTRAINT IF EXISTS audit_logs_user_id_fkey;
+ALTER TABLE audit_logs ADD CONSTRAINT audit_logs_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) NOT VALID;
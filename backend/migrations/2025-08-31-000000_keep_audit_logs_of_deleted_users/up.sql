@@ -0,0 +1,2 @@
+-- Audit entries outlive the accounts they describe, and nulling user_id would break the hash chain
+ALTER TABLE audit_logs DROP CONSTRAINT IF EXISTS audit_logs_user_id_fkey;
@@ -0,0 +1,3 @@
+-- Drop failed login tracking
+DROP INDEX IF EXISTS idx_failed_login_attempts_username;
+DROP TABLE IF EXISTS failed_login_attempts;
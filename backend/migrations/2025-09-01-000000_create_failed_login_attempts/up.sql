@@ -0,0 +1,9 @@
+-- Recent failed logins per username, used to lock out online brute force
+CREATE TABLE failed_login_attempts (
+    id UUID PRIMARY KEY,
+    username VARCHAR NOT NULL,
+    ip_address VARCHAR,
+    attempted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_failed_login_attempts_username ON failed_login_attempts(username, attempted_at);
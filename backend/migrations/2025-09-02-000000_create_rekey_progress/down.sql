@@ -0,0 +1,2 @@
+-- Drop data-key rotation progress
+DROP TABLE IF EXISTS rekey_progress;
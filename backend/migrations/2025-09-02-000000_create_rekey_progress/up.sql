@@ -0,0 +1,8 @@
+-- Cursor of an in-progress data-key rotation per table, so an interrupted rekey can resume
+CREATE TABLE rekey_progress (
+    table_name VARCHAR PRIMARY KEY,
+    key_fingerprint VARCHAR NOT NULL,
+    last_id UUID,
+    rows_processed BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
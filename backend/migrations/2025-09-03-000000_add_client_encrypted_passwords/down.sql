@@ -0,0 +1,2 @@
+-- Drop the client-encrypted flag
+ALTER TABLE passwords DROP COLUMN IF EXISTS is_client_encrypted;
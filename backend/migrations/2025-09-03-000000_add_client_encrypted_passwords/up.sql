@@ -0,0 +1,2 @@
+-- Entries whose encrypted_password holds a client-encrypted blob the server cannot decrypt
+ALTER TABLE passwords ADD COLUMN is_client_encrypted BOOLEAN NOT NULL DEFAULT FALSE;
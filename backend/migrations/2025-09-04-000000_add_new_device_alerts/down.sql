@@ -0,0 +1,2 @@
+-- Drop the sign-in alert preference
+ALTER TABLE users DROP COLUMN IF EXISTS new_device_alerts;
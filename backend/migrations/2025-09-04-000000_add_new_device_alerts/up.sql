@@ -0,0 +1,2 @@
+-- Let users opt out of emails about sign-ins from new devices or locations
+ALTER TABLE users ADD COLUMN new_device_alerts BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,2 @@
+-- Drop the sharing email preference
+ALTER TABLE users DROP COLUMN IF EXISTS share_notifications;
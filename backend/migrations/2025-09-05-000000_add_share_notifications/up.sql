@@ -0,0 +1,2 @@
+-- Let users opt out of emails when something is shared with them
+ALTER TABLE users ADD COLUMN share_notifications BOOLEAN NOT NULL DEFAULT TRUE;
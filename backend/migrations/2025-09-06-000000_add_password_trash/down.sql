@@ -0,0 +1,3 @@
+-- Drop the password trash
+DROP INDEX IF EXISTS idx_passwords_deleted_at;
+ALTER TABLE passwords DROP COLUMN IF EXISTS deleted_at;
@@ -0,0 +1,3 @@
+-- Deleted passwords move to the trash until they are restored or purged
+ALTER TABLE passwords ADD COLUMN deleted_at TIMESTAMP;
+CREATE INDEX idx_passwords_deleted_at ON passwords(deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,2 @@
+-- Drop the favorite flag
+ALTER TABLE passwords DROP COLUMN IF EXISTS is_favorite;
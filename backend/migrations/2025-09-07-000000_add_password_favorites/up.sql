@@ -0,0 +1,2 @@
+-- Let users star the passwords they use most
+ALTER TABLE passwords ADD COLUMN is_favorite BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- Drop pending device trust confirmations
+ALTER TABLE trusted_devices DROP COLUMN IF EXISTS trust_token_expires_at;
+ALTER TABLE trusted_devices DROP COLUMN IF EXISTS trust_token;
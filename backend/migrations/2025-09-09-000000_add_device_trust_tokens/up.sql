@@ -0,0 +1,3 @@
+-- Pending email confirmation for marking a device trusted
+ALTER TABLE trusted_devices ADD COLUMN trust_token VARCHAR(255);
+ALTER TABLE trusted_devices ADD COLUMN trust_token_expires_at TIMESTAMPTZ;
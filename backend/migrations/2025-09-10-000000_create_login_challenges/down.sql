@@ -0,0 +1,2 @@
+-- Drop login challenges
+DROP TABLE IF EXISTS login_challenges;
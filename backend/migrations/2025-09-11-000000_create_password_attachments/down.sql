@@ -0,0 +1,2 @@
+-- Drop password attachments
+DROP TABLE IF EXISTS password_attachments;
@@ -0,0 +1,14 @@
+-- Files attached to passwords, stored encrypted with the data key
+CREATE TABLE password_attachments (
+    id UUID PRIMARY KEY,
+    password_id UUID NOT NULL REFERENCES passwords(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    filename VARCHAR NOT NULL,
+    content_type VARCHAR NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    encrypted_content BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_password_attachments_password_id ON password_attachments(password_id);
+CREATE INDEX idx_password_attachments_user_id ON password_attachments(user_id);
@@ -0,0 +1,2 @@
+-- Drop the password reset cooldown
+ALTER TABLE users DROP COLUMN IF EXISTS reset_requested_at;
@@ -0,0 +1,2 @@
+-- When a password reset was last requested, for the per-email cooldown
+ALTER TABLE users ADD COLUMN reset_requested_at TIMESTAMP;
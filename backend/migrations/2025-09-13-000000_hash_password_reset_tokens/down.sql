@@ -0,0 +1,2 @@
+-- Cleared reset tokens can't be restored; users request a new reset email
+SELECT 1;
@@ -0,0 +1,2 @@
+-- Reset tokens are now stored hashed; drop pending plaintext ones so none stay readable
+UPDATE users SET reset_token = NULL, reset_token_expires_at = NULL WHERE reset_token IS NOT NULL;
@@ -0,0 +1,2 @@
+-- Drop audit request ids
+ALTER TABLE audit_logs DROP COLUMN IF EXISTS request_id;
@@ -0,0 +1,2 @@
+-- Id of the request that caused each audit event, for matching entries to server logs
+ALTER TABLE audit_logs ADD COLUMN request_id VARCHAR(128);
@@ -0,0 +1,2 @@
+-- Drop the TOTP replay guard
+ALTER TABLE users DROP COLUMN IF EXISTS mfa_last_totp_step;
@@ -0,0 +1,2 @@
+-- Last TOTP time step accepted at login, so a code can only be used once
+ALTER TABLE users ADD COLUMN mfa_last_totp_step BIGINT;
@@ -0,0 +1,4 @@
+-- New email address waiting to be confirmed, with the hash of its confirmation token
+ALTER TABLE users ADD COLUMN pending_email VARCHAR;
+ALTER TABLE users ADD COLUMN email_change_token VARCHAR;
+ALTER TABLE users ADD COLUMN email_change_expires_at TIMESTAMP;
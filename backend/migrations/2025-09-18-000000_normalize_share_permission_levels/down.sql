@@ -0,0 +1,3 @@
+-- Allow free-form share permission levels again; normalized values are kept
+ALTER TABLE shares DROP CONSTRAINT IF EXISTS shares_permission_level_check;
+ALTER TABLE shares ALTER COLUMN permission_level SET DEFAULT 'view';
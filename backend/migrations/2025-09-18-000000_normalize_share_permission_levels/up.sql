@@ -0,0 +1,6 @@
+-- Store share permission levels as 'read' or 'write' only; unknown values lose write access
+UPDATE shares SET permission_level = 'write' WHERE permission_level IN ('write', 'edit');
+UPDATE shares SET permission_level = 'read' WHERE permission_level <> 'write';
+
+ALTER TABLE shares ALTER COLUMN permission_level SET DEFAULT 'read';
+ALTER TABLE shares ADD CONSTRAINT shares_permission_level_check CHECK (permission_level IN ('read', 'write'));
@@ -0,0 +1,2 @@
+-- Cleared trust tokens can't be restored; users request a new device trust email
+SELECT 1;
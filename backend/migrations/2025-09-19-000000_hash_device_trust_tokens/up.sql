@@ -0,0 +1,2 @@
+-- Device trust tokens are now stored hashed; drop pending plaintext ones so none stay readable
+UPDATE trusted_devices SET trust_token = NULL, trust_token_expires_at = NULL WHERE trust_token IS NOT NULL;
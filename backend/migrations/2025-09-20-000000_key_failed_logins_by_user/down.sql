@@ -0,0 +1,6 @@
+-- Restore the per-username key; recorded failures are dropped
+DELETE FROM failed_login_attempts;
+DROP INDEX IF EXISTS idx_failed_login_attempts_user_id;
+ALTER TABLE failed_login_attempts DROP COLUMN user_id;
+ALTER TABLE failed_login_attempts ADD COLUMN username VARCHAR NOT NULL;
+CREATE INDEX idx_failed_login_attempts_username ON failed_login_attempts(username, attempted_at);
@@ -0,0 +1,7 @@
+-- Failed logins were keyed by the identifier typed at login, so a username and its email counted
+-- separately; key them by user id instead. Existing rows can't be mapped reliably and are dropped.
+DELETE FROM failed_login_attempts;
+DROP INDEX IF EXISTS idx_failed_login_attempts_username;
+ALTER TABLE failed_login_attempts DROP COLUMN username;
+ALTER TABLE failed_login_attempts ADD COLUMN user_id UUID NOT NULL;
+CREATE INDEX idx_failed_login_attempts_user_id ON failed_login_attempts(user_id, attempted_at);
@@ -0,0 +1,138 @@
+//! Attachment module for files stored alongside passwords
+//!
+//! File contents are encrypted with the data key like the passwords they belong to. Each file
+//! is capped in size, and all files a user owns together are capped by a storage quota.
+
+use diesel::prelude::*;
+use diesel::PgConnection;
+use std::env;
+use uuid::Uuid;
+use crate::models::NewPasswordAttachment;
+
+/// Largest single file accepted when ATTACHMENT_MAX_BYTES isn't set (10 MiB)
+pub const DEFAULT_MAX_ATTACHMENT_BYTES: i64 = 10 * 1024 * 1024;
+/// Storage allowed per user when ATTACHMENT_QUOTA_BYTES isn't set (100 MiB)
+pub const DEFAULT_ATTACHMENT_QUOTA_BYTES: i64 = 100 * 1024 * 1024;
+
+const MAX_FILENAME_CHARS: usize = 255;
+
+/// Size limits for attachments
+#[derive(Debug, Clone, Copy)]
+pub struct AttachmentLimits {
+    pub max_file_bytes: i64,
+    pub quota_bytes: i64,
+}
+
+impl AttachmentLimits {
+    /// Loads the limits from ATTACHMENT_MAX_BYTES and ATTACHMENT_QUOTA_BYTES
+    pub fn from_env() -> Self {
+        Self {
+            max_file_bytes: parse_bytes("ATTACHMENT_MAX_BYTES", env::var("ATTACHMENT_MAX_BYTES").ok().as_deref(), DEFAULT_MAX_ATTACHMENT_BYTES),
+            quota_bytes: parse_bytes("ATTACHMENT_QUOTA_BYTES", env::var("ATTACHMENT_QUOTA_BYTES").ok().as_deref(), DEFAULT_ATTACHMENT_QUOTA_BYTES),
+        }
+    }
+}
+
+fn parse_bytes(name: &str, value: Option<&str>, default: i64) -> i64 {
+    match value.map(|v| v.trim().parse::<i64>()) {
+        Some(Ok(bytes)) if bytes > 0 => bytes,
+        Some(_) => {
+            log::warn!("Invalid {}, using {} bytes", name, default);
+            default
+        }
+        None => default,
+    }
+}
+
+/// Reduces an uploaded file name to its last path component without control characters
+pub fn sanitize_filename(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .take(MAX_FILENAME_CHARS)
+        .collect();
+    let cleaned = cleaned.trim();
+
+    if cleaned.is_empty() || cleaned == "." || cleaned == ".." {
+        "attachment".to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
+
+/// Whether a file of `size` bytes fits next to `used` bytes within the quota
+pub fn fits_quota(used: i64, size: i64, quota_bytes: i64) -> bool {
+    used.saturating_add(size) <= quota_bytes
+}
+
+/// Bytes taken up by the attachments a user owns, counted before encryption
+pub fn used_bytes(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<i64> {
+    use crate::schema::password_attachments;
+
+    let sizes = password_attachments::table
+        .filter(password_attachments::user_id.eq(user_id))
+        .select(password_attachments::size_bytes)
+        .load::<i64>(conn)?;
+
+    Ok(sizes.into_iter().sum())
+}
+
+/// Stores an attachment unless it would take its owner over the quota
+///
+/// The owner's row is locked while checking, so concurrent uploads can't both squeeze in.
+/// Returns whether the attachment was stored.
+pub fn insert_within_quota(conn: &mut PgConnection, attachment: &NewPasswordAttachment, quota_bytes: i64) -> QueryResult<bool> {
+    use crate::schema::{password_attachments, users};
+
+    conn.transaction(|conn| {
+        users::table
+            .find(attachment.user_id)
+            .select(users::id)
+            .for_update()
+            .first::<Uuid>(conn)?;
+
+        let used = used_bytes(conn, attachment.user_id)?;
+        if !fits_quota(used, attachment.size_bytes, quota_bytes) {
+            return Ok(false);
+        }
+
+        diesel::insert_into(password_attachments::table)
+            .values(attachment)
+            .execute(conn)?;
+        Ok(true)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bytes() {
+        assert_eq!(parse_bytes("TEST", None, 10), 10);
+        assert_eq!(parse_bytes("TEST", Some(" 2048 "), 10), 2048);
+        assert_eq!(parse_bytes("TEST", Some("0"), 10), 10);
+        assert_eq!(parse_bytes("TEST", Some("-5"), 10), 10);
+        assert_eq!(parse_bytes("TEST", Some("lots"), 10), 10);
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("recovery-codes.txt"), "recovery-codes.txt");
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("C:\\Users\\me\\key.pem"), "key.pem");
+        assert_eq!(sanitize_filename("evil\"\r\nname.pdf"), "evilname.pdf");
+        assert_eq!(sanitize_filename(""), "attachment");
+        assert_eq!(sanitize_filename("dir/.."), "attachment");
+        assert_eq!(sanitize_filename(&"a".repeat(300)).len(), MAX_FILENAME_CHARS);
+    }
+
+    #[test]
+    fn test_fits_quota() {
+        assert!(fits_quota(0, 100, 100));
+        assert!(fits_quota(60, 40, 100));
+        assert!(!fits_quota(60, 41, 100));
+        assert!(!fits_quota(i64::MAX, 1, 100));
+    }
+}
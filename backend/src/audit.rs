@@ -1,10 +1,14 @@
 //! Audit logging module with tamper protection
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, SubsecRound, Utc};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use diesel::prelude::*;
-use crate::db::DbPool;
+use crate::csv_export::{batched_csv_stream, csv_field};
+use crate::db::{self, DbPool};
+use actix_web::web::Bytes;
+use diesel::PgConnection;
+use futures_util::Stream;
 use crate::schema::audit_logs;
 use ring::hmac;
 use std::env;
@@ -30,6 +34,20 @@ pub enum AuditEventType {
     UnauthorizedAccess,
     DataExport,
     DataImport,
+    MfaEnabled,
+    MfaDisabled,
+    MfaRecoveryCodesRegenerated,
+    AccountDeleted,
+    EncryptionKeyRotated,
+    PasswordsBulkDeleted,
+    PasswordRestored,
+    TrashPurged,
+    SessionsRevoked,
+    AttachmentAdded,
+    AttachmentDeleted,
+    PasskeyRegistered,
+    PasskeyRemoved,
+    EmailChanged,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,6 +59,8 @@ pub struct AuditEvent {
     pub user_agent: Option<String>,
     pub details: Option<String>,
     pub timestamp: DateTime<Utc>,
+    /// Id of the request that caused the event, see `request_id`
+    pub request_id: Option<String>,
 }
 
 #[derive(Queryable, Insertable, Serialize, Deserialize, Debug)]
@@ -55,6 +75,9 @@ pub struct AuditLog {
     pub details: Option<String>,
     pub timestamp: chrono::NaiveDateTime,
     pub integrity_hash: String,
+    pub sequence: i64,
+    pub chain_hash: Option<String>,
+    pub request_id: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -69,14 +92,21 @@ pub struct NewAuditLog {
     pub details: Option<String>,
     pub timestamp: chrono::NaiveDateTime,
     pub integrity_hash: String,
+    pub chain_hash: Option<String>,
+    pub request_id: Option<String>,
 }
 
-/// Generate HMAC for audit log integrity
-fn generate_integrity_hash(log: &AuditEvent) -> Result<String, String> {
+/// Load the HMAC key used to sign audit logs
+fn audit_key() -> Result<hmac::Key, String> {
     let secret = env::var("AUDIT_SECRET")
         .map_err(|_| "AUDIT_SECRET environment variable not set".to_string())?;
     
-    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    Ok(hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()))
+}
+
+/// Generate HMAC for audit log integrity
+fn generate_integrity_hash(log: &AuditEvent) -> Result<String, String> {
+    let key = audit_key()?;
     
     // Create a deterministic string representation of the log
     let log_data = format!(
@@ -89,11 +119,84 @@ fn generate_integrity_hash(log: &AuditEvent) -> Result<String, String> {
         log.details.as_deref().unwrap_or_default(),
         log.timestamp.to_rfc3339()
     );
+    // Only appended when present, so entries from before request ids still verify
+    let log_data = match &log.request_id {
+        Some(request_id) => format!("{}|{}", log_data, request_id),
+        None => log_data,
+    };
     
     let signature = hmac::sign(&key, log_data.as_bytes());
     Ok(hex::encode(signature.as_ref()))
 }
 
+/// Record fields covered by the audit chain hash
+struct ChainRecord<'a> {
+    id: Uuid,
+    event_type: &'a str,
+    user_id: Option<Uuid>,
+    resource_id: Option<Uuid>,
+    ip_address: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    details: Option<&'a str>,
+    timestamp: NaiveDateTime,
+    request_id: Option<&'a str>,
+}
+
+impl<'a> From<&'a AuditLog> for ChainRecord<'a> {
+    fn from(log: &'a AuditLog) -> Self {
+        Self {
+            id: log.id,
+            event_type: &log.event_type,
+            user_id: log.user_id,
+            resource_id: log.resource_id,
+            ip_address: log.ip_address.as_deref(),
+            user_agent: log.user_agent.as_deref(),
+            details: log.details.as_deref(),
+            timestamp: log.timestamp,
+            request_id: log.request_id.as_deref(),
+        }
+    }
+}
+
+impl<'a> From<&'a NewAuditLog> for ChainRecord<'a> {
+    fn from(log: &'a NewAuditLog) -> Self {
+        Self {
+            id: log.id,
+            event_type: &log.event_type,
+            user_id: log.user_id,
+            resource_id: log.resource_id,
+            ip_address: log.ip_address.as_deref(),
+            user_agent: log.user_agent.as_deref(),
+            details: log.details.as_deref(),
+            timestamp: log.timestamp,
+            request_id: log.request_id.as_deref(),
+        }
+    }
+}
+
+/// Generate the HMAC linking a record to the previous entry in the chain
+fn compute_chain_hash(key: &hmac::Key, previous_hash: &str, record: ChainRecord) -> String {
+    let chain_data = format!(
+        "{}|{}|{}|{}|{}|{}|{}|{}|{}",
+        previous_hash,
+        record.id,
+        record.event_type,
+        record.user_id.map(|id| id.to_string()).unwrap_or_default(),
+        record.resource_id.map(|id| id.to_string()).unwrap_or_default(),
+        record.ip_address.unwrap_or_default(),
+        record.user_agent.unwrap_or_default(),
+        record.details.unwrap_or_default(),
+        record.timestamp.format("%Y-%m-%dT%H:%M:%S%.6f")
+    );
+    let chain_data = match record.request_id {
+        Some(request_id) => format!("{}|{}", chain_data, request_id),
+        None => chain_data,
+    };
+    
+    let signature = hmac::sign(key, chain_data.as_bytes());
+    hex::encode(signature.as_ref())
+}
+
 /// Verify audit log integrity
 #[allow(dead_code)]
 pub fn verify_log_integrity(log: &AuditLog) -> Result<bool, String> {
@@ -105,6 +208,7 @@ pub fn verify_log_integrity(log: &AuditLog) -> Result<bool, String> {
         user_agent: log.user_agent.clone(),
         details: log.details.clone(),
         timestamp: DateTime::from_naive_utc_and_offset(log.timestamp, Utc),
+        request_id: log.request_id.clone(),
     };
     
     let expected_hash = generate_integrity_hash(&event)?;
@@ -133,6 +237,20 @@ fn parse_event_type(event_type: &str) -> Result<AuditEventType, String> {
         "UnauthorizedAccess" => Ok(AuditEventType::UnauthorizedAccess),
         "DataExport" => Ok(AuditEventType::DataExport),
         "DataImport" => Ok(AuditEventType::DataImport),
+        "MfaEnabled" => Ok(AuditEventType::MfaEnabled),
+        "MfaDisabled" => Ok(AuditEventType::MfaDisabled),
+        "MfaRecoveryCodesRegenerated" => Ok(AuditEventType::MfaRecoveryCodesRegenerated),
+        "AccountDeleted" => Ok(AuditEventType::AccountDeleted),
+        "EncryptionKeyRotated" => Ok(AuditEventType::EncryptionKeyRotated),
+        "PasswordsBulkDeleted" => Ok(AuditEventType::PasswordsBulkDeleted),
+        "PasswordRestored" => Ok(AuditEventType::PasswordRestored),
+        "TrashPurged" => Ok(AuditEventType::TrashPurged),
+        "SessionsRevoked" => Ok(AuditEventType::SessionsRevoked),
+        "AttachmentAdded" => Ok(AuditEventType::AttachmentAdded),
+        "AttachmentDeleted" => Ok(AuditEventType::AttachmentDeleted),
+        "PasskeyRegistered" => Ok(AuditEventType::PasskeyRegistered),
+        "PasskeyRemoved" => Ok(AuditEventType::PasskeyRemoved),
+        "EmailChanged" => Ok(AuditEventType::EmailChanged),
         _ => Err(format!("Unknown event type: {}", event_type)),
     }
 }
@@ -145,9 +263,14 @@ pub async fn log_event(
     let mut conn = db_pool.get()
         .map_err(|e| format!("Failed to get database connection: {}", e))?;
     
+    // Postgres stores microseconds, so truncate to keep hashes reproducible from stored rows
+    let mut event = event;
+    event.timestamp = event.timestamp.trunc_subsecs(6);
+    
+    let key = audit_key()?;
     let integrity_hash = generate_integrity_hash(&event)?;
     
-    let new_log = NewAuditLog {
+    let mut new_log = NewAuditLog {
         id: Uuid::new_v4(),
         event_type: format!("{:?}", event.event_type),
         user_id: event.user_id,
@@ -157,41 +280,47 @@ pub async fn log_event(
         details: event.details,
         timestamp: event.timestamp.naive_utc(),
         integrity_hash,
+        chain_hash: None,
+        request_id: event.request_id,
     };
     
-    diesel::insert_into(audit_logs::table)
-        .values(&new_log)
-        .execute(&mut conn)
+    // Hold the chain lock while reading the head so concurrent writes can't fork the chain
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        db::lock_audit_chain(conn)?;
+        
+        let previous_hash = audit_logs::table
+            .filter(audit_logs::chain_hash.is_not_null())
+            .order(audit_logs::sequence.desc())
+            .select(audit_logs::chain_hash)
+            .first::<Option<String>>(conn)
+            .optional()?
+            .flatten()
+            .unwrap_or_default();
+        
+        new_log.chain_hash = Some(compute_chain_hash(&key, &previous_hash, ChainRecord::from(&new_log)));
+        
+        diesel::insert_into(audit_logs::table)
+            .values(&new_log)
+            .execute(conn)
+    })
         .map_err(|e| {
             log::error!("Failed to insert audit log: {}", e);
             format!("Failed to insert audit log: {}", e)
         })?;
     
-    log::info!("Audit event logged: {:?} for user {:?}", event.event_type, event.user_id);
+    let user_id = new_log.user_id.map(|id| id.to_string());
+    log::info!(
+        user_id = user_id.as_deref(),
+        client_ip = new_log.ip_address.as_deref(),
+        request_id = new_log.request_id.as_deref();
+        "Audit event logged: {:?} for user {:?}", event.event_type, new_log.user_id
+    );
     Ok(())
 }
 
 /// Extract IP address from request
 pub fn extract_ip_address(req: &actix_web::HttpRequest) -> Option<String> {
-    // Check X-Forwarded-For header first (for proxies)
-    if let Some(forwarded) = req.headers().get("X-Forwarded-For") {
-        if let Ok(forwarded_str) = forwarded.to_str() {
-            // Take the first IP in the chain
-            if let Some(first_ip) = forwarded_str.split(',').next() {
-                return Some(first_ip.trim().to_string());
-            }
-        }
-    }
-    
-    // Check X-Real-IP header
-    if let Some(real_ip) = req.headers().get("X-Real-IP") {
-        if let Ok(real_ip_str) = real_ip.to_str() {
-            return Some(real_ip_str.to_string());
-        }
-    }
-    
-    // Fall back to connection info
-    req.connection_info().peer_addr().map(|addr| addr.to_string())
+    crate::ip_controls::extract_client_ip(req).map(|ip| ip.to_string())
 }
 
 /// Extract User-Agent from request
@@ -224,6 +353,176 @@ pub async fn get_user_audit_logs(
         .map_err(|e| format!("Failed to load audit logs: {}", e))
 }
 
+const DEFAULT_EVENTS_PER_PAGE: i64 = 50;
+const MAX_EVENTS_PER_PAGE: i64 = 200;
+
+/// Filters and pagination for reading back audit events
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditEventQuery {
+    pub event_type: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+/// An audit event as returned to the user it belongs to
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = audit_logs)]
+pub struct AuditEventEntry {
+    pub id: Uuid,
+    pub timestamp: NaiveDateTime,
+    pub event_type: String,
+    pub ip_address: Option<String>,
+    pub resource_id: Option<Uuid>,
+    pub request_id: Option<String>,
+}
+
+/// One page of audit events, newest first
+#[derive(Serialize, Debug)]
+pub struct AuditEventPage {
+    pub events: Vec<AuditEventEntry>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+}
+
+/// Date range for exporting a user's audit events
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditExportRequest {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// An audit event row in a CSV export
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = audit_logs)]
+pub struct AuditExportEntry {
+    pub timestamp: NaiveDateTime,
+    pub sequence: i64,
+    pub event_type: String,
+    pub ip_address: Option<String>,
+    pub resource_id: Option<Uuid>,
+    pub details: Option<String>,
+}
+
+/// Header row of the audit log export
+pub const AUDIT_CSV_HEADER: &str = "timestamp,event_type,ip_address,resource_id,detail\n";
+
+/// Audit events loaded and written per step of the export stream
+const EXPORT_BATCH_SIZE: i64 = 1000;
+
+/// Returns whether an event type name is one the audit log records
+pub fn is_known_event_type(event_type: &str) -> bool {
+    parse_event_type(event_type).is_ok()
+}
+
+/// Clamps requested pagination to a 1-based page and a bounded page size
+fn page_bounds(page: Option<i64>, per_page: Option<i64>) -> (i64, i64) {
+    let page = page.unwrap_or(1).max(1);
+    let per_page = per_page.unwrap_or(DEFAULT_EVENTS_PER_PAGE).clamp(1, MAX_EVENTS_PER_PAGE);
+    (page, per_page)
+}
+
+/// Selects a user's audit events, optionally narrowed by type and inclusive time range
+fn user_events_filter(
+    user_id: Uuid,
+    event_type: Option<&str>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> audit_logs::BoxedQuery<'static, diesel::pg::Pg> {
+    let mut filtered = audit_logs::table
+        .filter(audit_logs::user_id.eq(user_id))
+        .into_boxed();
+    if let Some(event_type) = event_type {
+        filtered = filtered.filter(audit_logs::event_type.eq(event_type.to_string()));
+    }
+    if let Some(from) = from {
+        filtered = filtered.filter(audit_logs::timestamp.ge(from.naive_utc()));
+    }
+    if let Some(to) = to {
+        filtered = filtered.filter(audit_logs::timestamp.le(to.naive_utc()));
+    }
+    filtered
+}
+
+/// Get a page of a user's audit events matching the query filters
+pub async fn query_user_events(
+    db_pool: &DbPool,
+    user_id: Uuid,
+    query: &AuditEventQuery,
+) -> Result<AuditEventPage, String> {
+    let mut conn = db_pool.get()
+        .map_err(|e| format!("Failed to get database connection: {}", e))?;
+    
+    let filtered = || user_events_filter(user_id, query.event_type.as_deref(), query.from, query.to);
+    
+    let (page, per_page) = page_bounds(query.page, query.per_page);
+    let total = filtered()
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(|e| format!("Failed to count audit logs: {}", e))?;
+    let events = filtered()
+        .order((audit_logs::timestamp.desc(), audit_logs::sequence.desc()))
+        .limit(per_page)
+        .offset((page - 1).saturating_mul(per_page))
+        .select(AuditEventEntry::as_select())
+        .load(&mut conn)
+        .map_err(|e| format!("Failed to load audit logs: {}", e))?;
+    
+    Ok(AuditEventPage { events, page, per_page, total })
+}
+
+/// Renders one audit event as a CSV row
+fn audit_csv_row(event: &AuditExportEntry) -> String {
+    format!(
+        "{},{},{},{},{}\n",
+        csv_field(&event.timestamp.and_utc().to_rfc3339()),
+        csv_field(&event.event_type),
+        csv_field(event.ip_address.as_deref().unwrap_or_default()),
+        csv_field(&event.resource_id.map(|id| id.to_string()).unwrap_or_default()),
+        csv_field(event.details.as_deref().unwrap_or_default())
+    )
+}
+
+/// Loads a user's next batch of events in the range, oldest first, after the (timestamp, sequence) cursor
+fn export_batch(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    range: &AuditExportRequest,
+    after: Option<(NaiveDateTime, i64)>,
+    limit: i64,
+) -> QueryResult<Vec<AuditExportEntry>> {
+    let mut query = user_events_filter(user_id, None, range.from, range.to);
+    if let Some((timestamp, sequence)) = after {
+        query = query.filter(
+            audit_logs::timestamp.gt(timestamp)
+                .or(audit_logs::timestamp.eq(timestamp).and(audit_logs::sequence.gt(sequence)))
+        );
+    }
+    query
+        .order((audit_logs::timestamp.asc(), audit_logs::sequence.asc()))
+        .limit(limit)
+        .select(AuditExportEntry::as_select())
+        .load(conn)
+}
+
+/// Streams a user's audit events in a time range as CSV, oldest first
+pub fn user_events_csv_stream(
+    db_pool: DbPool,
+    user_id: Uuid,
+    range: AuditExportRequest,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    batched_csv_stream(db_pool, AUDIT_CSV_HEADER, format!("audit events of user {}", user_id), move |conn, after| {
+        let batch = export_batch(conn, user_id, &range, after, EXPORT_BATCH_SIZE)?;
+        let next = match batch.last() {
+            Some(last) if batch.len() as i64 == EXPORT_BATCH_SIZE => Some((last.timestamp, last.sequence)),
+            _ => None,
+        };
+        Ok((batch.iter().map(audit_csv_row).collect(), next))
+    })
+}
+
 /// Verify integrity of all audit logs (admin function)
 #[allow(dead_code)]
 pub async fn verify_all_logs_integrity(
@@ -256,6 +555,115 @@ pub async fn verify_all_logs_integrity(
     Ok((valid_count, invalid_count))
 }
 
+/// First audit entry at which the hash chain fails to verify
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ChainBreak {
+    pub log_id: Uuid,
+    pub sequence: i64,
+    pub timestamp: NaiveDateTime,
+    pub reason: String,
+}
+
+/// Result of walking the audit hash chain
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainVerification {
+    pub valid: bool,
+    pub verified: usize,
+    pub unchained: usize, // Entries written before chaining was enabled
+    pub head_hash: Option<String>,
+    pub first_break: Option<ChainBreak>,
+}
+
+/// Walks audit entries in sequence order, stopping at the first break
+struct ChainVerifier {
+    key: hmac::Key,
+    previous_hash: Option<String>,
+    verified: usize,
+    unchained: usize,
+    first_break: Option<ChainBreak>,
+}
+
+impl ChainVerifier {
+    fn new(key: hmac::Key) -> Self {
+        Self { key, previous_hash: None, verified: 0, unchained: 0, first_break: None }
+    }
+
+    /// Checks the next entry, returning false once the chain is broken
+    fn check(&mut self, log: &AuditLog) -> bool {
+        if self.first_break.is_some() {
+            return false;
+        }
+        
+        let reason = match &log.chain_hash {
+            None if self.previous_hash.is_none() => {
+                self.unchained += 1;
+                return true;
+            }
+            None => "Entry is missing its chain hash",
+            Some(stored) => {
+                let previous = self.previous_hash.as_deref().unwrap_or_default();
+                if compute_chain_hash(&self.key, previous, ChainRecord::from(log)) == *stored {
+                    self.verified += 1;
+                    self.previous_hash = Some(stored.clone());
+                    return true;
+                }
+                "Chain hash mismatch: this entry or the one before it was altered or deleted"
+            }
+        };
+        
+        self.first_break = Some(ChainBreak {
+            log_id: log.id,
+            sequence: log.sequence,
+            timestamp: log.timestamp,
+            reason: reason.to_string(),
+        });
+        false
+    }
+
+    fn finish(self) -> ChainVerification {
+        ChainVerification {
+            valid: self.first_break.is_none(),
+            verified: self.verified,
+            unchained: self.unchained,
+            head_hash: self.previous_hash,
+            first_break: self.first_break,
+        }
+    }
+}
+
+/// Verify the audit hash chain from the first entry to the head (admin function)
+pub async fn verify_audit_chain(db_pool: &DbPool) -> Result<ChainVerification, String> {
+    const BATCH_SIZE: i64 = 1000;
+    
+    let mut conn = db_pool.get()
+        .map_err(|e| format!("Failed to get database connection: {}", e))?;
+    
+    let mut verifier = ChainVerifier::new(audit_key()?);
+    let mut last_sequence = i64::MIN;
+    
+    loop {
+        let batch: Vec<AuditLog> = audit_logs::table
+            .filter(audit_logs::sequence.gt(last_sequence))
+            .order(audit_logs::sequence.asc())
+            .limit(BATCH_SIZE)
+            .load(&mut conn)
+            .map_err(|e| format!("Failed to load audit logs: {}", e))?;
+        
+        let Some(last) = batch.last() else { break };
+        last_sequence = last.sequence;
+        
+        if !batch.iter().all(|log| verifier.check(log)) {
+            break;
+        }
+    }
+    
+    let verification = verifier.finish();
+    if let Some(chain_break) = &verification.first_break {
+        log::warn!("Audit chain broken at entry {} (sequence {}): {}", chain_break.log_id, chain_break.sequence, chain_break.reason);
+    }
+    Ok(verification)
+}
+
 /// Helper macro for logging audit events
 #[macro_export]
 macro_rules! audit_log {
@@ -269,9 +677,11 @@ macro_rules! audit_log {
                 user_agent: $crate::audit::extract_user_agent($req),
                 details: None,
                 timestamp: chrono::Utc::now(),
+                request_id: $crate::request_id::of($req),
             };
+            let request_id = event.request_id.clone();
             if let Err(e) = $crate::audit::log_event($db_pool, event).await {
-                log::error!("Failed to log audit event: {}", e);
+                log::error!(request_id = request_id.as_deref(); "Failed to log audit event: {}", e);
             }
         }
     };
@@ -285,9 +695,11 @@ macro_rules! audit_log {
                 user_agent: $crate::audit::extract_user_agent($req),
                 details: None,
                 timestamp: chrono::Utc::now(),
+                request_id: $crate::request_id::of($req),
             };
+            let request_id = event.request_id.clone();
             if let Err(e) = $crate::audit::log_event($db_pool, event).await {
-                log::error!("Failed to log audit event: {}", e);
+                log::error!(request_id = request_id.as_deref(); "Failed to log audit event: {}", e);
             }
         }
     };
@@ -301,10 +713,151 @@ macro_rules! audit_log {
                 user_agent: $crate::audit::extract_user_agent($req),
                 details: Some($details),
                 timestamp: chrono::Utc::now(),
+                request_id: $crate::request_id::of($req),
             };
+            let request_id = event.request_id.clone();
             if let Err(e) = $crate::audit::log_event($db_pool, event).await {
-                log::error!("Failed to log audit event: {}", e);
+                log::error!(request_id = request_id.as_deref(); "Failed to log audit event: {}", e);
             }
         }
     };
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key() -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA256, b"test-audit-secret")
+    }
+
+    fn chained_logs(key: &hmac::Key, count: usize) -> Vec<AuditLog> {
+        let mut previous_hash = String::new();
+        (0..count)
+            .map(|i| {
+                let mut log = AuditLog {
+                    id: Uuid::new_v4(),
+                    event_type: "PasswordCreated".to_string(),
+                    user_id: Some(Uuid::new_v4()),
+                    resource_id: None,
+                    ip_address: Some("10.0.0.1".to_string()),
+                    user_agent: None,
+                    details: Some(format!("entry {}", i)),
+                    timestamp: Utc::now().naive_utc().trunc_subsecs(6),
+                    integrity_hash: String::new(),
+                    sequence: i as i64 + 1,
+                    chain_hash: None,
+                    request_id: (i % 2 == 0).then(|| format!("request-{}", i)),
+                };
+                let chain_hash = compute_chain_hash(key, &previous_hash, ChainRecord::from(&log));
+                previous_hash = chain_hash.clone();
+                log.chain_hash = Some(chain_hash);
+                log
+            })
+            .collect()
+    }
+
+    fn verify(logs: &[AuditLog]) -> ChainVerification {
+        let mut verifier = ChainVerifier::new(test_key());
+        for log in logs {
+            if !verifier.check(log) {
+                break;
+            }
+        }
+        verifier.finish()
+    }
+
+    #[test]
+    fn test_intact_chain_verifies() {
+        let logs = chained_logs(&test_key(), 5);
+        let result = verify(&logs);
+        assert!(result.valid);
+        assert_eq!(result.verified, 5);
+        assert_eq!(result.head_hash, logs[4].chain_hash);
+    }
+
+    #[test]
+    fn test_modified_entry_breaks_chain() {
+        let mut logs = chained_logs(&test_key(), 5);
+        logs[2].details = Some("tampered".to_string());
+        let result = verify(&logs);
+        assert!(!result.valid);
+        assert_eq!(result.verified, 2);
+        assert_eq!(result.first_break.unwrap().log_id, logs[2].id);
+    }
+
+    #[test]
+    fn test_deleted_entry_breaks_chain() {
+        let mut logs = chained_logs(&test_key(), 5);
+        logs.remove(1);
+        let result = verify(&logs);
+        assert!(!result.valid);
+        assert_eq!(result.first_break.unwrap().log_id, logs[1].id);
+    }
+
+    #[test]
+    fn test_page_bounds() {
+        assert_eq!(page_bounds(None, None), (1, DEFAULT_EVENTS_PER_PAGE));
+        assert_eq!(page_bounds(Some(0), Some(0)), (1, 1));
+        assert_eq!(page_bounds(Some(3), Some(10_000)), (3, MAX_EVENTS_PER_PAGE));
+        assert!(is_known_event_type("UserLogin"));
+        assert!(!is_known_event_type("userlogin"));
+    }
+
+    #[test]
+    fn test_audit_csv_row() {
+        let event = AuditExportEntry {
+            timestamp: chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap().naive_utc(),
+            sequence: 7,
+            event_type: "LoginFailed".to_string(),
+            ip_address: None,
+            resource_id: None,
+            details: Some("=HYPERLINK(\"x\")".to_string()),
+        };
+        assert_eq!(
+            audit_csv_row(&event),
+            "\"2023-11-14T22:13:20+00:00\",\"LoginFailed\",\"\",\"\",\"'=HYPERLINK(\"\"x\"\")\"\n"
+        );
+    }
+
+    #[test]
+    fn test_export_batches_page_through_equal_timestamps() {
+        let Some(mut conn) = db::test_connection(
+            "CREATE TEMPORARY TABLE audit_logs (id UUID PRIMARY KEY, event_type VARCHAR NOT NULL, \
+             user_id UUID, resource_id UUID, ip_address VARCHAR, details TEXT, \
+             timestamp TIMESTAMP NOT NULL, sequence BIGINT NOT NULL)",
+        ) else {
+            return;
+        };
+        let user_id = Uuid::new_v4();
+        let at = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap().naive_utc();
+        let later = |seconds| at + chrono::Duration::seconds(seconds);
+        // Five events of the user, three sharing a timestamp, and one of another user
+        let events = [(1, at, user_id), (2, at, user_id), (3, at, user_id), (4, at, Uuid::new_v4()), (5, later(1), user_id), (6, later(2), user_id)];
+        for (sequence, timestamp, owner) in events {
+            diesel::insert_into(audit_logs::table)
+                .values((
+                    audit_logs::id.eq(Uuid::new_v4()),
+                    audit_logs::event_type.eq("UserLogin"),
+                    audit_logs::user_id.eq(owner),
+                    audit_logs::timestamp.eq(timestamp),
+                    audit_logs::sequence.eq(sequence as i64),
+                ))
+                .execute(&mut conn)
+                .unwrap();
+        }
+
+        let range = AuditExportRequest::default();
+        let mut after = None;
+        let mut sequences = Vec::new();
+        loop {
+            let batch = export_batch(&mut conn, user_id, &range, after, 2).unwrap();
+            sequences.extend(batch.iter().map(|event| event.sequence));
+            match batch.last() {
+                Some(last) if batch.len() == 2 => after = Some((last.timestamp, last.sequence)),
+                _ => break,
+            }
+        }
+        assert_eq!(sequences, vec![1, 2, 3, 5, 6]);
+    }
+}
@@ -1,89 +1,131 @@
 //! Authentication module
 
-use bcrypt::{hash, verify, DEFAULT_COST};
-use jsonwebtoken::{encode, decode, Header, Algorithm, Validation, EncodingKey, DecodingKey};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use jsonwebtoken::{decode, Algorithm, Validation, DecodingKey};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use std::env;
-use chrono::{Utc, Duration};
-use regex::Regex;
+use chrono::{DateTime, Utc, Duration};
+use std::sync::OnceLock;
+use unicode_normalization::UnicodeNormalization;
+use unicode_properties::{GeneralCategory, GeneralCategoryGroup, UnicodeGeneralCategory};
 use log;
 use ring::rand::{SystemRandom, SecureRandom};
 
-/// Validates and sanitizes a username
-pub fn sanitize_username(username: &str) -> Result<String, String> {
-    if username.is_empty() {
-        log::warn!("Empty username provided");
-        return Err("Username cannot be empty".to_string());
-    }
+/// Prefix of the PHC strings produced by `hash_password`
+const ARGON2ID_PREFIX: &str = "$argon2id$";
 
-    if username.len() > 50 {
-        log::warn!("Username too long: {} characters", username.len());
-        return Err("Username too long".to_string());
-    }
+/// Argon2id cost parameters for new password hashes, fixed at startup
+static ARGON2_PARAMS: OnceLock<argon2::Params> = OnceLock::new();
 
-    // Remove any potentially dangerous characters
-    let sanitized: String = username
-        .chars()
-        .filter(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
-        .collect();
+/// Parses one Argon2 cost setting, falling back to the crate default when unset
+fn parse_argon2_cost(name: &str, value: Option<&str>, default: u32) -> Result<u32, String> {
+    let Some(value) = value else { return Ok(default) };
+    value.trim().parse().map_err(|_| format!("{} must be a number, got '{}'", name, value))
+}
 
-    if sanitized.is_empty() {
-        log::warn!("Username contains only invalid characters: {}", username);
-        return Err("Username contains only invalid characters".to_string());
-    }
+/// Builds the Argon2id parameters from `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM` values
+fn parse_argon2_params(memory_kib: Option<&str>, iterations: Option<&str>, parallelism: Option<&str>) -> Result<argon2::Params, String> {
+    let memory_kib = parse_argon2_cost("ARGON2_MEMORY_KIB", memory_kib, argon2::Params::DEFAULT_M_COST)?;
+    let iterations = parse_argon2_cost("ARGON2_ITERATIONS", iterations, argon2::Params::DEFAULT_T_COST)?;
+    let parallelism = parse_argon2_cost("ARGON2_PARALLELISM", parallelism, argon2::Params::DEFAULT_P_COST)?;
+    argon2::Params::new(memory_kib, iterations, parallelism, None).map_err(|e| {
+        format!(
+            "Invalid Argon2 parameters (memory {} KiB, {} iterations, parallelism {}): {}",
+            memory_kib, iterations, parallelism, e
+        )
+    })
+}
 
-    // Check for patterns that might indicate injection attempts
-    let regex = Regex::new(r"^[\w\-]+$").unwrap();
-    if !regex.is_match(&sanitized) {
-        log::warn!("Username contains invalid characters: {}", username);
-        return Err("Username contains invalid characters".to_string());
-    }
+/// Reads the Argon2 settings and fixes the parameters used for new hashes; call once at startup
+pub fn init_argon2_params() -> Result<argon2::Params, String> {
+    let params = parse_argon2_params(
+        env::var("ARGON2_MEMORY_KIB").ok().as_deref(),
+        env::var("ARGON2_ITERATIONS").ok().as_deref(),
+        env::var("ARGON2_PARALLELISM").ok().as_deref(),
+    )?;
+    Ok(ARGON2_PARAMS.get_or_init(|| params).clone())
+}
 
-    log::info!("Username successfully sanitized: {}", username);
-    Ok(sanitized)
+/// The hasher for new passwords, with the crate defaults if startup didn't configure it
+fn argon2_hasher() -> Argon2<'static> {
+    let params = ARGON2_PARAMS.get_or_init(argon2::Params::default).clone();
+    Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
 }
 
-/// Validates a password strength
-pub fn validate_password(password: &str) -> Result<(), String> {
-    if password.is_empty() {
-        log::warn!("Empty password provided");
-        return Err("Password cannot be empty".to_string());
-    }
+/// Most characters a username may have
+const MAX_USERNAME_CHARS: usize = 50;
 
-    if password.len() < 8 {
-        log::warn!("Password too short: {} characters", password.len());
-        return Err("Password must be at least 8 characters long".to_string());
+/// Whether a character may appear in a username: letters, marks and digits of any script, `_` and `-`
+fn is_username_char(c: char) -> bool {
+    match c.general_category_group() {
+        GeneralCategoryGroup::Letter | GeneralCategoryGroup::Mark => true,
+        _ => c.general_category() == GeneralCategory::DecimalNumber || c == '_' || c == '-',
     }
+}
+
+/// Whether a character is invisible or reorders text, so a name containing it could pass for another
+fn is_hidden_char(c: char) -> bool {
+    matches!(c.general_category(), GeneralCategory::Control | GeneralCategory::Format)
+        || matches!(c, '\u{115F}' | '\u{1160}' | '\u{3164}' | '\u{FFA0}')
+}
 
-    if password.len() > 128 {
-        log::warn!("Password too long: {} characters", password.len());
-        return Err("Password too long".to_string());
+/// Validates a username, returning it NFC-normalized
+///
+/// Names are NFC-normalized so precomposed and combining spellings match. Invisible and
+/// formatting characters are rejected, as are punctuation and symbols; the error names the
+/// characters that aren't allowed instead of silently dropping them.
+pub fn sanitize_username(username: &str) -> Result<String, String> {
+    if username.is_empty() {
+        log::warn!("Empty username provided");
+        return Err("Username cannot be empty".to_string());
     }
 
-    // Check for at least one uppercase letter
-    if !password.chars().any(|c| c.is_uppercase()) {
-        log::warn!("Password missing uppercase letter");
-        return Err("Password must contain at least one uppercase letter".to_string());
+    let normalized: String = username.nfc().collect();
+
+    if normalized.chars().count() > MAX_USERNAME_CHARS {
+        log::warn!("Username too long: {} characters", normalized.chars().count());
+        return Err("Username too long".to_string());
     }
 
-    // Check for at least one lowercase letter
-    if !password.chars().any(|c| c.is_lowercase()) {
-        log::warn!("Password missing lowercase letter");
-        return Err("Password must contain at least one lowercase letter".to_string());
+    if normalized.chars().any(is_hidden_char) {
+        log::warn!("Username contains invisible or formatting characters");
+        return Err("Username contains invisible or formatting characters".to_string());
     }
 
-    // Check for at least one digit
-    if !password.chars().any(|c| c.is_ascii_digit()) {
-        log::warn!("Password missing digit");
-        return Err("Password must contain at least one digit".to_string());
+    let mut disallowed: Vec<char> = Vec::new();
+    for c in normalized.chars().filter(|c| !is_username_char(*c)) {
+        if !disallowed.contains(&c) {
+            disallowed.push(c);
+        }
+    }
+    if !disallowed.is_empty() {
+        let listed: Vec<String> = disallowed.iter().map(|c| format!("'{}'", c)).collect();
+        log::warn!("Username contains disallowed characters: {}", username);
+        return Err(format!(
+            "Username may only contain letters, digits, '_' and '-', not {}",
+            listed.join(", ")
+        ));
     }
 
-    log::info!("Password validation successful");
-    Ok(())
+    log::info!("Username successfully validated: {}", normalized);
+    Ok(normalized)
 }
 
-/// Sanitizes and validates a website URL
+/// Validates a password against the configured password policy
+pub fn validate_password(password: &str) -> Result<(), String> {
+    crate::password_policy::current().validate(password).map_err(|e| {
+        log::warn!("Password rejected by policy: {}", e);
+        e
+    })
+}
+
+/// Sanitizes, validates and normalizes a website URL
+///
+/// The URL must be http(s) with a host. The scheme and host are lowercased, default ports
+/// and dot segments are dropped, and a bare root path is left off, so the same site is always
+/// stored the same way.
 pub fn sanitize_website_url(url: &str) -> Result<String, String> {
     if url.is_empty() {
         return Ok(String::new());
@@ -94,21 +136,51 @@ pub fn sanitize_website_url(url: &str) -> Result<String, String> {
         return Err("Website URL too long".to_string());
     }
 
-    // Basic URL validation - must start with http:// or https://
-    let trimmed = url.trim();
-    if !trimmed.starts_with("http://") && !trimmed.starts_with("https://") {
-        log::warn!("Invalid URL format: {}", url);
+    let parsed = match url::Url::parse(url.trim()) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            log::warn!("Invalid URL format: {}", e);
+            return Err("Invalid website URL".to_string());
+        }
+    };
+
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        log::warn!("Invalid URL scheme: {}", parsed.scheme());
         return Err("URL must start with http:// or https://".to_string());
     }
 
-    // Remove any potentially dangerous characters
-    let sanitized: String = trimmed
-        .chars()
-        .filter(|c| c.is_ascii() && !c.is_control())
-        .collect();
+    if parsed.host_str().is_none_or(|host| host.is_empty()) {
+        log::warn!("Website URL has no host");
+        return Err("Website URL must include a host".to_string());
+    }
+
+    // Serialization percent-encodes anything outside ASCII, and the host is punycode
+    let mut normalized = parsed.to_string();
+    if parsed.path() == "/" && parsed.query().is_none() && parsed.fragment().is_none() {
+        normalized.pop();
+    }
 
     log::info!("Website URL successfully sanitized");
-    Ok(sanitized)
+    Ok(normalized)
+}
+
+/// Validates a folder name, trimming surrounding whitespace
+pub fn sanitize_folder_name(name: &str) -> Result<String, String> {
+    let trimmed = name.trim();
+    if trimmed.is_empty() {
+        return Err("Folder name cannot be empty".to_string());
+    }
+
+    if trimmed.chars().count() > 255 {
+        log::warn!("Folder name too long: {} characters", trimmed.chars().count());
+        return Err("Folder name too long (max 255 characters)".to_string());
+    }
+
+    if trimmed.chars().any(|c| c.is_control()) {
+        return Err("Folder name cannot contain control characters".to_string());
+    }
+
+    Ok(trimmed.to_string())
 }
 
 /// Sanitizes notes field
@@ -143,6 +215,13 @@ pub fn sanitize_otp_secret(secret: &str) -> Result<String, String> {
         return Err("OTP secret too long".to_string());
     }
 
+    // otpauth URIs carry digits, period and algorithm, so they're kept whole once they parse
+    if secret.trim().starts_with("otpauth://") {
+        let uri = secret.trim();
+        crate::mfa::totp_for_secret(uri)?;
+        return Ok(uri.to_string());
+    }
+
     // OTP secrets should only contain base32 characters
     let sanitized: String = secret
         .chars()
@@ -166,12 +245,30 @@ pub struct User {
     pub password_hash: String,
 }
 
-/// Claims structure for JWT tokens
+/// Claims structure for base JWT tokens
+///
+/// Logins issue `token_management::EnhancedClaims`; base tokens are only still read from refresh
+/// tokens issued before that, and on logout.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Claims {
     pub sub: Uuid,
     pub exp: usize,
     pub token_type: String, // "access" or "refresh"
+    /// Unique token id, the key of the revocation list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// When the token was issued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+    /// Token family; every token issued for one login, across rotations, shares it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<Uuid>,
+    /// Device id a refresh token was issued to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Coarse network a refresh token was issued to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -181,13 +278,25 @@ pub struct TokenPair {
     pub expires_in: i64, // seconds until access token expires
 }
 
-/// Hashes a password with bcrypt
+/// Hashes a password with Argon2id
 pub fn hash_password(password: &str) -> String {
     log::debug!("Hashing password");
-    match hash(password, DEFAULT_COST) {
+    let mut salt_bytes = [0u8; 16];
+    if let Err(e) = SystemRandom::new().fill(&mut salt_bytes) {
+        log::error!("Failed to generate password salt: {:?}", e);
+        panic!("Failed to generate password salt");
+    }
+    let salt = match SaltString::encode_b64(&salt_bytes) {
+        Ok(salt) => salt,
+        Err(e) => {
+            log::error!("Failed to encode password salt: {}", e);
+            panic!("Failed to encode password salt: {}", e)
+        }
+    };
+    match argon2_hasher().hash_password(password.as_bytes(), &salt) {
         Ok(hash) => {
             log::debug!("Password hashed successfully");
-            hash
+            hash.to_string()
         }
         Err(e) => {
             log::error!("Failed to hash password: {}", e);
@@ -196,10 +305,25 @@ pub fn hash_password(password: &str) -> String {
     }
 }
 
-/// Verifies a password against its hash
+/// Verifies a password against an Argon2id or legacy bcrypt hash
 pub fn verify_password(password: &str, hash: &str) -> bool {
     log::debug!("Verifying password against hash");
-    match verify(password, hash) {
+    if hash.starts_with(ARGON2ID_PREFIX) {
+        // The parameters stored in the hash apply, so hashes made before a change keep verifying
+        return match PasswordHash::new(hash) {
+            Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+            Err(e) => {
+                log::error!("Failed to parse Argon2 hash: {}", e);
+                false
+            }
+        };
+    }
+
+    if !hash.starts_with("$2") {
+        log::error!("Unrecognized password hash format");
+        return false;
+    }
+    match bcrypt::verify(password, hash) {
         Ok(result) => {
             log::debug!("Password verification successful");
             result
@@ -210,41 +334,78 @@ pub fn verify_password(password: &str, hash: &str) -> bool {
         }
     }
 }
-/// Generates a JWT token for a user
-#[allow(dead_code)]
-pub fn generate_token(user_id: Uuid) -> Result<String, jsonwebtoken::errors::Error> {
-    log::info!("Generating JWT token for user: {}", user_id);
-    
-    let expiration = Utc::now() + Duration::days(7);
-    let claims = Claims {
-        sub: user_id,
-        exp: expiration.timestamp() as usize,
-        token_type: "access".to_string(),
-    };
 
-    let secret = match env::var("JWT_SECRET") {
-        Ok(secret) => {
-            log::debug!("JWT secret loaded successfully");
-            secret
-        }
-        Err(_) => {
-            log::error!("Environment variable JWT_SECRET is not set");
-            return Err(jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken));
-        }
-    };
+/// Returns an Argon2id replacement for a legacy hash the password was just verified against
+///
+/// Login handlers store the result so bcrypt hashes are upgraded transparently.
+pub fn upgraded_password_hash(password: &str, stored_hash: &str) -> Option<String> {
+    if stored_hash.starts_with(ARGON2ID_PREFIX) {
+        return None;
+    }
+    Some(hash_password(password))
+}
 
-    match encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_ref())) {
-        Ok(token) => {
-            log::info!("JWT token generated successfully for user: {}", user_id);
-            Ok(token)
-        }
+/// Replaces a legacy bcrypt hash with Argon2id after a successful login
+///
+/// Failures are only logged; the old hash keeps working and the upgrade is retried next login.
+pub fn upgrade_legacy_password_hash(conn: &mut diesel::PgConnection, user_id: Uuid, password: &str, stored_hash: &str) {
+    use crate::schema::users;
+    use diesel::prelude::*;
+
+    let Some(new_hash) = upgraded_password_hash(password, stored_hash) else { return };
+    match diesel::update(users::table.filter(users::id.eq(user_id)))
+        .set(users::password_hash.eq(new_hash))
+        .execute(conn)
+    {
+        Ok(_) => log::info!("Upgraded password hash to Argon2id for user: {}", user_id),
+        Err(e) => log::error!("Failed to upgrade password hash for user {}: {}", user_id, e),
+    }
+}
+
+/// Shortest JWT_SECRET accepted at startup
+pub const MIN_JWT_SECRET_BYTES: usize = 32;
+
+/// Checks that the JWT signing secret is set and long enough to resist guessing
+pub fn check_jwt_secret(secret: Option<&str>) -> Result<(), String> {
+    match secret {
+        None | Some("") => Err("JWT_SECRET is not set".to_string()),
+        Some(secret) if secret.len() < MIN_JWT_SECRET_BYTES => Err(format!(
+            "JWT_SECRET is only {} bytes long; at least {} are required",
+            secret.len(),
+            MIN_JWT_SECRET_BYTES
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+/// How far in the future a token's issue time may lie, allowing for clocks of other instances
+const MAX_CLOCK_SKEW_SECS: i64 = 60;
+
+/// End of the migration window for tokens issued before they carried a jti
+///
+/// Read once from ACCEPT_TOKENS_WITHOUT_JTI_UNTIL (RFC 3339). Unset means they are still accepted.
+fn legacy_token_cutoff() -> Option<DateTime<Utc>> {
+    static CUTOFF: OnceLock<Option<DateTime<Utc>>> = OnceLock::new();
+    *CUTOFF.get_or_init(|| parse_legacy_token_cutoff(env::var("ACCEPT_TOKENS_WITHOUT_JTI_UNTIL").ok().as_deref()))
+}
+
+fn parse_legacy_token_cutoff(value: Option<&str>) -> Option<DateTime<Utc>> {
+    let value = value?.trim();
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(cutoff) => Some(cutoff.with_timezone(&Utc)),
         Err(e) => {
-            log::error!("Failed to encode JWT token: {}", e);
-            Err(e)
+            // Closing the window early only means a few extra sign-ins
+            log::warn!("Invalid ACCEPT_TOKENS_WITHOUT_JTI_UNTIL {:?} ({}); rejecting tokens without jti", value, e);
+            Some(DateTime::<Utc>::MIN_UTC)
         }
     }
 }
 
+/// Whether a token without a jti, which can't be revoked, is still accepted at `now`
+fn accepts_token_without_jti(cutoff: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    cutoff.is_none_or(|cutoff| now < cutoff)
+}
+
 /// Decodes a JWT token and validates it
 pub fn validate_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
     log::debug!("Validating JWT token");
@@ -266,8 +427,18 @@ pub fn validate_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error
         &Validation::new(Algorithm::HS256),
     ) {
         Ok(token_data) => {
-            log::info!("JWT token validation successful for user: {:?}", token_data.claims.sub);
-            Ok(token_data.claims)
+            let claims = token_data.claims;
+            let now = Utc::now();
+            if claims.jti.is_none() && !accepts_token_without_jti(legacy_token_cutoff(), now) {
+                log::warn!("Rejected token without jti for user: {:?}", claims.sub);
+                return Err(jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken));
+            }
+            if claims.iat.is_some_and(|iat| iat as i64 > now.timestamp() + MAX_CLOCK_SKEW_SECS) {
+                log::warn!("Rejected token issued in the future for user: {:?}", claims.sub);
+                return Err(jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::ImmatureSignature));
+            }
+            log::info!("JWT token validation successful for user: {:?}", claims.sub);
+            Ok(claims)
         }
         Err(e) => {
             log::error!("JWT token validation failed: {}", e);
@@ -277,7 +448,39 @@ pub fn validate_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error
 }
 
 /// Extracts user ID from HTTP request cookies or Authorization header
+///
+/// Only enhanced access tokens are accepted. The registered token manager checks that neither the
+/// token nor its session was revoked and that the session hasn't gone idle.
 pub fn extract_user_id_from_request(req: &actix_web::HttpRequest) -> Result<Uuid, String> {
+    use crate::token_management::{decode_enhanced_token, TokenManager};
+
+    let token = token_from_request(req).ok_or_else(|| {
+        log::warn!("Missing authentication token in both cookie and Authorization header");
+        "Missing authentication token".to_string()
+    })?;
+    
+    let claims = decode_enhanced_token(&token).map_err(|e| {
+        log::error!("JWT validation failed: {}", e);
+        "Invalid token".to_string()
+    })?;
+    if claims.token_type != "access" {
+        log::error!("Invalid token type for access: {}", claims.token_type);
+        return Err("Invalid token".to_string());
+    }
+    
+    // Requests without a registered token manager, e.g. in unit tests, skip the session checks
+    if let Some(token_manager) = req.app_data::<actix_web::web::Data<std::sync::Arc<TokenManager>>>() {
+        token_manager.check_enhanced_claims(&claims).map_err(|e| {
+            log::warn!("Rejected access token of user {}: {}", claims.sub, e);
+            "Invalid token".to_string()
+        })?;
+    }
+    
+    Ok(claims.sub)
+}
+
+/// The raw token from the auth_token cookie or the Authorization header
+pub fn token_from_request(req: &actix_web::HttpRequest) -> Option<String> {
     // First try to get token from cookie
     let token = if let Some(cookie_header) = req.headers().get("Cookie") {
         if let Ok(cookie_str) = cookie_header.to_str() {
@@ -299,24 +502,72 @@ pub fn extract_user_id_from_request(req: &actix_web::HttpRequest) -> Result<Uuid
     };
     
     // Fallback to Authorization header for backward compatibility
-    let token = token.or_else(|| {
+    token.or_else(|| {
         req.headers().get("Authorization")
             .and_then(|h| h.to_str().ok())
             .and_then(|h| h.strip_prefix("Bearer "))
             .map(|t| t.to_string())
-    });
-    
-    let token = token.ok_or_else(|| {
-        log::warn!("Missing authentication token in both cookie and Authorization header");
-        "Missing authentication token".to_string()
-    })?;
-    
-    let claims = validate_access_token(&token).map_err(|e| {
-        log::error!("JWT validation failed: {}", e);
-        "Invalid token".to_string()
-    })?;
-    
-    Ok(claims.sub)
+    })
+}
+
+/// The 401 answered to every request without a valid access token
+///
+/// The reason a token was rejected is only logged, never sent to the client.
+pub fn unauthorized_response() -> actix_web::HttpResponse {
+    use crate::models::ApiResponse;
+
+    actix_web::HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or missing token".to_string()))
+}
+
+/// Authenticates a request, failing with the uniform 401 when it has no valid access token
+pub fn authenticate(req: &actix_web::HttpRequest) -> Result<Uuid, actix_web::Error> {
+    extract_user_id_from_request(req).map_err(|_| {
+        actix_web::error::InternalError::from_response("Invalid or missing token", unauthorized_response()).into()
+    })
+}
+
+/// Authenticates the request and checks that the caller is an administrator
+///
+/// The flag is read from the database on every call, so revoking it takes effect immediately.
+/// Returns the admin's id, or the 401/403/500 response to send back.
+pub fn require_admin(req: &actix_web::HttpRequest) -> Result<Uuid, actix_web::HttpResponse> {
+    check_admin(req, |user_id| {
+        use crate::schema::users;
+        use diesel::prelude::*;
+
+        let pool = req
+            .app_data::<actix_web::web::Data<crate::db::DbPool>>()
+            .ok_or_else(|| "Database pool is not configured".to_string())?;
+        let mut conn = pool.get().map_err(|e| format!("Failed to get database connection: {}", e))?;
+        users::table
+            .filter(users::id.eq(user_id))
+            .select(users::is_admin)
+            .first::<bool>(&mut conn)
+            .optional()
+            .map_err(|e| format!("Database error: {}", e))
+    })
+}
+
+fn check_admin(
+    req: &actix_web::HttpRequest,
+    is_admin: impl FnOnce(Uuid) -> Result<Option<bool>, String>,
+) -> Result<Uuid, actix_web::HttpResponse> {
+    use crate::models::ApiResponse;
+    use actix_web::HttpResponse;
+
+    let user_id = extract_user_id_from_request(req).map_err(|_| unauthorized_response())?;
+
+    match is_admin(user_id) {
+        Ok(Some(true)) => Ok(user_id),
+        Ok(_) => {
+            log::warn!("User {} was refused access to an admin endpoint", user_id);
+            Err(HttpResponse::Forbidden().json(ApiResponse::<()>::error("Administrator access required".to_string())))
+        }
+        Err(e) => {
+            log::error!("Failed to check admin role for user {}: {}", user_id, e);
+            Err(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error".to_string())))
+        }
+    }
 }
 
 /// Generate a CSRF token
@@ -338,7 +589,6 @@ pub fn generate_csrf_token() -> Result<String, String> {
 }
 
 /// Validate a CSRF token
-#[allow(dead_code)]
 pub fn validate_csrf_token(token: &str, expected_token: &str) -> bool {
     if token.is_empty() || expected_token.is_empty() {
         log::warn!("Empty CSRF token provided");
@@ -397,126 +647,283 @@ pub fn generate_password_reset_token() -> Result<String, String> {
     }
 }
 
-/// Validates if a password reset token is still valid (not expired)
-pub fn is_reset_token_valid(expires_at: Option<chrono::NaiveDateTime>) -> bool {
-    match expires_at {
-        Some(expiry) => {
-            let now = Utc::now().naive_utc();
-            let is_valid = expiry > now;
-            
-            if is_valid {
-                log::debug!("Password reset token is still valid");
-            } else {
-                log::warn!("Password reset token has expired");
-            }
-            
-            is_valid
-        }
-        None => {
-            log::warn!("No expiration time set for password reset token");
-            false
-        }
-    }
-}
+/// How long a password reset token stays valid
+pub const RESET_TOKEN_EXPIRY_MINUTES: i64 = 15;
 
-/// Generates expiration time for password reset token (15 minutes from now)
+/// Generates expiration time for password reset token (RESET_TOKEN_EXPIRY_MINUTES from now)
 pub fn generate_reset_token_expiry() -> chrono::NaiveDateTime {
-    let expiry = Utc::now() + Duration::minutes(15);
+    let expiry = Utc::now() + Duration::minutes(RESET_TOKEN_EXPIRY_MINUTES);
     expiry.naive_utc()
 }
 
-/// Generates a token pair with short-lived access token and long-lived refresh token
-pub fn generate_token_pair(user_id: Uuid) -> Result<TokenPair, jsonwebtoken::errors::Error> {
-    log::info!("Generating token pair for user: {}", user_id);
-    
-    let secret = match env::var("JWT_SECRET") {
-        Ok(secret) => secret,
-        Err(_) => {
-            log::error!("Environment variable JWT_SECRET is not set");
-            return Err(jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken));
-        }
-    };
-
-    // Generate short-lived access token (15 minutes)
-    let access_expiration = Utc::now() + Duration::minutes(15);
-    let access_claims = Claims {
-        sub: user_id,
-        exp: access_expiration.timestamp() as usize,
-        token_type: "access".to_string(),
-    };
-
-    let access_token = encode(&Header::default(), &access_claims, &EncodingKey::from_secret(secret.as_ref()))?;
-
-    // Generate long-lived refresh token (7 days)
-    let refresh_expiration = Utc::now() + Duration::days(7);
-    let refresh_claims = Claims {
-        sub: user_id,
-        exp: refresh_expiration.timestamp() as usize,
-        token_type: "refresh".to_string(),
-    };
-
-    let refresh_token = encode(&Header::default(), &refresh_claims, &EncodingKey::from_secret(secret.as_ref()))?;
-
-    log::info!("Token pair generated successfully for user: {}", user_id);
-    Ok(TokenPair {
-        access_token,
-        refresh_token,
-        expires_in: 900, // 15 minutes in seconds
-    })
-}
-
-/// Refreshes an access token using a valid refresh token
-pub fn refresh_access_token(refresh_token: &str) -> Result<TokenPair, jsonwebtoken::errors::Error> {
-    log::debug!("Refreshing access token");
-    
-    // Validate the refresh token
+/// Validates token and ensures it's a refresh token
+///
+/// Only base refresh tokens issued before logins moved to enhanced tokens are still presented;
+/// `TokenManager::refresh_token_pair` exchanges them once for a new session.
+pub fn validate_refresh_token(refresh_token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
     let claims = validate_token(refresh_token)?;
     
-    // Ensure it's actually a refresh token
     if claims.token_type != "refresh" {
         log::error!("Invalid token type for refresh: {}", claims.token_type);
         return Err(jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken));
     }
     
-    // Generate new token pair
-    generate_token_pair(claims.sub)
+    Ok(claims)
 }
 
-/// Validates token and ensures it's an access token
-pub fn validate_access_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let claims = validate_token(token)?;
-    
-    if claims.token_type != "access" {
-        log::error!("Invalid token type for access: {}", claims.token_type);
-        return Err(jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken));
-    }
-    
-    Ok(claims)
+/// Builds a token with an `alg: none` header, as an attacker would, for rejection tests
+#[cfg(test)]
+pub(crate) fn unsigned_token(claims: &serde_json::Value) -> String {
+    use base64::Engine;
+
+    let encode_part = |part: &serde_json::Value| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(part.to_string());
+    format!("{}.{}.", encode_part(&serde_json::json!({"alg": "none", "typ": "JWT"})), encode_part(claims))
 }
 
-/// Validate password strength
-pub fn validate_password_strength(password: &str) -> Result<(), String> {
-    if password.len() < 8 {
-        return Err("Password must be at least 8 characters long".to_string());
-    }
-    
-    let has_uppercase = password.chars().any(|c| c.is_uppercase());
-    let has_lowercase = password.chars().any(|c| c.is_lowercase());
-    let has_digit = password.chars().any(|c| c.is_digit(10));
-    let has_special = password.chars().any(|c| !c.is_alphanumeric());
-    
-    if !has_uppercase {
-        return Err("Password must contain at least one uppercase letter".to_string());
-    }
-    if !has_lowercase {
-        return Err("Password must contain at least one lowercase letter".to_string());
-    }
-    if !has_digit {
-        return Err("Password must contain at least one digit".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    #[test]
+    fn test_parse_argon2_params() {
+        let defaults = parse_argon2_params(None, None, None).unwrap();
+        assert_eq!((defaults.m_cost(), defaults.t_cost(), defaults.p_cost()), (19456, 2, 1));
+
+        let tuned = parse_argon2_params(Some(" 65536 "), Some("3"), Some("4")).unwrap();
+        assert_eq!((tuned.m_cost(), tuned.t_cost(), tuned.p_cost()), (65536, 3, 4));
+
+        assert!(parse_argon2_params(Some("lots"), None, None).is_err());
+        assert!(parse_argon2_params(None, Some("0"), None).is_err());
+        assert!(parse_argon2_params(None, None, Some("0")).is_err());
+        // Memory must be at least 8 KiB per lane
+        assert!(parse_argon2_params(Some("16"), None, Some("4")).is_err());
+    }
+
+    #[test]
+    fn test_sanitize_username_ascii() {
+        assert_eq!(sanitize_username("alice").unwrap(), "alice");
+        assert_eq!(sanitize_username("bob_smith-2").unwrap(), "bob_smith-2");
+        assert!(sanitize_username("").is_err());
+        assert!(sanitize_username(&"a".repeat(MAX_USERNAME_CHARS)).is_ok());
+        assert!(sanitize_username(&"a".repeat(MAX_USERNAME_CHARS + 1)).is_err());
+    }
+
+    #[test]
+    fn test_sanitize_username_unicode() {
+        assert_eq!(sanitize_username("José").unwrap(), "José");
+        // A combining accent is composed, so both spellings name the same user
+        assert_eq!(sanitize_username("Jose\u{0301}").unwrap(), "José");
+        assert_eq!(sanitize_username("Ærøskøbing").unwrap(), "Ærøskøbing");
+        assert_eq!(sanitize_username("дмитрий").unwrap(), "дмитрий");
+        assert_eq!(sanitize_username("山田太郎").unwrap(), "山田太郎");
+        assert_eq!(sanitize_username("अनुराग").unwrap(), "अनुराग");
+        assert_eq!(sanitize_username("محمد_٣").unwrap(), "محمد_٣");
+        // The cap counts characters, not bytes
+        assert!(sanitize_username(&"é".repeat(MAX_USERNAME_CHARS)).is_ok());
+    }
+
+    #[test]
+    fn test_sanitize_username_names_disallowed_characters() {
+        assert_eq!(
+            sanitize_username("john.doe").unwrap_err(),
+            "Username may only contain letters, digits, '_' and '-', not '.'"
+        );
+        assert_eq!(
+            sanitize_username("anna😀").unwrap_err(),
+            "Username may only contain letters, digits, '_' and '-', not '😀'"
+        );
+        // Each character is named once, in the order it first appears
+        assert_eq!(
+            sanitize_username("<script>x</script>").unwrap_err(),
+            "Username may only contain letters, digits, '_' and '-', not '<', '>', '/'"
+        );
+        assert!(sanitize_username("!!!").is_err());
+        assert!(sanitize_username("alice@example.com").is_err());
+    }
+
+    #[test]
+    fn test_sanitize_username_rejects_hidden_characters() {
+        assert!(sanitize_username("ad\u{200B}min").is_err());
+        assert!(sanitize_username("ad\u{200D}min").is_err());
+        assert!(sanitize_username("admin\u{202E}").is_err());
+        assert!(sanitize_username("\u{FEFF}admin").is_err());
+        assert!(sanitize_username("ad\u{3164}min").is_err());
+        assert!(sanitize_username("ad\u{0007}min").is_err());
+    }
+
+    #[test]
+    fn test_sanitize_folder_name() {
+        assert_eq!(sanitize_folder_name("  Work ").unwrap(), "Work");
+        assert_eq!(sanitize_folder_name("Bank & Finance").unwrap(), "Bank & Finance");
+        assert!(sanitize_folder_name("   ").is_err());
+        assert!(sanitize_folder_name("a\nb").is_err());
+        assert!(sanitize_folder_name(&"x".repeat(256)).is_err());
+    }
+
+    #[test]
+    fn test_sanitize_website_url_normalizes() {
+        assert_eq!(sanitize_website_url("").unwrap(), "");
+        assert_eq!(sanitize_website_url("https://example.com").unwrap(), "https://example.com");
+        assert_eq!(sanitize_website_url("  HTTPS://Example.COM/  ").unwrap(), "https://example.com");
+        assert_eq!(sanitize_website_url("https://example.com:443/login").unwrap(), "https://example.com/login");
+        assert_eq!(sanitize_website_url("http://example.com:80").unwrap(), "http://example.com");
+        assert_eq!(sanitize_website_url("http://example.com:8080").unwrap(), "http://example.com:8080");
+        assert_eq!(sanitize_website_url("https://example.com/a/../b/./c").unwrap(), "https://example.com/b/c");
+        assert_eq!(sanitize_website_url("https://example.com/../").unwrap(), "https://example.com");
+        assert_eq!(sanitize_website_url("https://example.com/?next=/home").unwrap(), "https://example.com/?next=/home");
+        assert_eq!(sanitize_website_url("https://bücher.de/straße").unwrap(), "https://xn--bcher-kva.de/stra%C3%9Fe");
+    }
+
+    #[test]
+    fn test_sanitize_website_url_rejects_malformed() {
+        assert!(sanitize_website_url("https://").is_err());
+        assert!(sanitize_website_url("https://?q=1").is_err());
+        assert!(sanitize_website_url("example.com").is_err());
+        assert!(sanitize_website_url("ftp://example.com").is_err());
+        assert!(sanitize_website_url("javascript:alert(1)").is_err());
+        assert!(sanitize_website_url("https://exa mple.com").is_err());
+        assert!(sanitize_website_url("https://example.com:99999").is_err());
+        assert!(sanitize_website_url(&format!("https://example.com/{}", "a".repeat(2048))).is_err());
+    }
+
+    #[test]
+    fn test_verify_stored_bcrypt_hash() {
+        let stored = bcrypt::hash("correct horse", 4).unwrap();
+        assert!(verify_password("correct horse", &stored));
+        assert!(!verify_password("wrong horse", &stored));
+    }
+
+    #[test]
+    fn test_verify_stored_argon2_hash() {
+        let stored = hash_password("correct horse");
+        assert!(stored.starts_with(ARGON2ID_PREFIX));
+        assert!(verify_password("correct horse", &stored));
+        assert!(!verify_password("wrong horse", &stored));
+        assert!(!verify_password("correct horse", "plaintext"));
+    }
+
+    #[test]
+    fn test_upgrade_on_login() {
+        let legacy = bcrypt::hash("correct horse", 4).unwrap();
+        assert!(verify_password("correct horse", &legacy));
+
+        let upgraded = upgraded_password_hash("correct horse", &legacy).unwrap();
+        assert!(upgraded.starts_with(ARGON2ID_PREFIX));
+        assert!(verify_password("correct horse", &upgraded));
+        assert_eq!(upgraded_password_hash("correct horse", &upgraded), None);
+    }
+
+    #[test]
+    fn test_require_admin_rejects_normal_users() {
+        std::env::set_var("JWT_SECRET", "test-jwt-secret");
+        let user_id = Uuid::new_v4();
+        let token = crate::token_management::encode_enhanced_token_pair(user_id, "session", None, None).unwrap().tokens.access_token;
+        let req = actix_web::test::TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_http_request();
+
+        assert_eq!(check_admin(&req, |_| Ok(Some(true))).unwrap(), user_id);
+        let status = |result: Result<Uuid, actix_web::HttpResponse>| result.unwrap_err().status();
+        assert_eq!(status(check_admin(&req, |_| Ok(Some(false)))), actix_web::http::StatusCode::FORBIDDEN);
+        // A deleted user holding a valid token is no admin either
+        assert_eq!(status(check_admin(&req, |_| Ok(None))), actix_web::http::StatusCode::FORBIDDEN);
+        assert_eq!(status(check_admin(&req, |_| Err("down".to_string()))), actix_web::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+        let anonymous = actix_web::test::TestRequest::default().to_http_request();
+        assert_eq!(status(check_admin(&anonymous, |_| Ok(Some(true)))), actix_web::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_authenticate_failures_are_uniform() {
+        std::env::set_var("JWT_SECRET", "test-jwt-secret");
+        let user_id = Uuid::new_v4();
+        let token = crate::token_management::encode_enhanced_token_pair(user_id, "session", None, None).unwrap().tokens.access_token;
+        let req = actix_web::test::TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_http_request();
+        assert_eq!(authenticate(&req).unwrap(), user_id);
+
+        let anonymous = actix_web::test::TestRequest::default().to_http_request();
+        let forged = actix_web::test::TestRequest::default()
+            .insert_header(("Authorization", "Bearer not-a-jwt"))
+            .to_http_request();
+        for req in [anonymous, forged] {
+            let response = authenticate(&req).unwrap_err().error_response();
+            assert_eq!(response.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+            let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                serde_json::json!({ "success": false, "message": "Invalid or missing token", "data": null })
+            );
+        }
     }
-    if !has_special {
-        return Err("Password must contain at least one special character".to_string());
+
+    #[test]
+    fn test_unsigned_tokens_are_rejected() {
+        std::env::set_var("JWT_SECRET", "test-jwt-secret");
+        let user_id = Uuid::new_v4();
+        let exp = (Utc::now() + Duration::hours(1)).timestamp();
+
+        let signed = encode(
+            &Header::default(),
+            &serde_json::json!({"sub": user_id, "exp": exp, "token_type": "access", "jti": "id"}),
+            &EncodingKey::from_secret(b"test-jwt-secret"),
+        ).unwrap();
+        assert_eq!(validate_token(&signed).unwrap().sub, user_id);
+
+        let token = unsigned_token(&serde_json::json!({"sub": user_id, "exp": exp, "token_type": "access"}));
+        assert!(validate_token(&token).is_err());
+        // Appending the original signature must not help either
+        let forged = format!("{}{}", token, signed.rsplit('.').next().unwrap());
+        assert!(validate_token(&forged).is_err());
+
+        let pool = diesel::r2d2::Pool::builder()
+            .build_unchecked(diesel::r2d2::ConnectionManager::new("postgres://localhost/unused"));
+        let token_manager = crate::token_management::TokenManager::new(pool);
+        let token = unsigned_token(&serde_json::json!({
+            "sub": user_id, "exp": exp, "iat": exp - 900, "jti": Uuid::new_v4().to_string(),
+            "token_type": "access", "session_id": "session", "aud": "passq-api", "iss": "passq-auth",
+            "device_id": null, "scope": ["read", "write"],
+        }));
+        assert!(token_manager.validate_enhanced_token(&token).is_err());
+    }
+
+    #[test]
+    fn test_tokens_without_jti_migration_window() {
+        let now = Utc::now();
+        assert!(accepts_token_without_jti(None, now));
+        assert!(accepts_token_without_jti(Some(now + Duration::days(1)), now));
+        assert!(!accepts_token_without_jti(Some(now), now));
+
+        let cutoff = parse_legacy_token_cutoff(Some("2026-11-01T00:00:00Z")).unwrap();
+        assert_eq!(cutoff.to_rfc3339(), "2026-11-01T00:00:00+00:00");
+        assert_eq!(parse_legacy_token_cutoff(None), None);
+        // A typo closes the window rather than leaving it open
+        assert!(!accepts_token_without_jti(parse_legacy_token_cutoff(Some("next week")), now));
+    }
+
+    #[test]
+    fn test_tokens_issued_in_the_future_are_rejected() {
+        std::env::set_var("JWT_SECRET", "test-jwt-secret");
+        let now = Utc::now().timestamp();
+        let token = |iat: i64| encode(
+            &Header::default(),
+            &serde_json::json!({"sub": Uuid::new_v4(), "exp": now + 900, "iat": iat, "jti": "id", "token_type": "access"}),
+            &EncodingKey::from_secret(b"test-jwt-secret"),
+        ).unwrap();
+
+        assert!(validate_token(&token(now)).is_ok());
+        assert!(validate_token(&token(now + 30)).is_ok());
+        assert!(validate_token(&token(now + 3600)).is_err());
+    }
+
+    #[test]
+    fn test_check_jwt_secret() {
+        assert!(check_jwt_secret(None).is_err());
+        assert!(check_jwt_secret(Some("")).is_err());
+        assert!(check_jwt_secret(Some("your-secret-key")).is_err());
+        assert!(check_jwt_secret(Some(&"x".repeat(MIN_JWT_SECRET_BYTES - 1))).is_err());
+        assert!(check_jwt_secret(Some(&"x".repeat(MIN_JWT_SECRET_BYTES))).is_ok());
     }
-    
-    Ok(())
 }
@@ -0,0 +1,79 @@
+//! Breach check module using the Have I Been Pwned range API
+//!
+//! Only the first five hex characters of the password's SHA-1 hash are sent
+//! (k-anonymity), so neither the password nor its full hash leaves the server.
+
+use ring::digest;
+use std::time::Duration;
+
+const RANGE_API_URL: &str = "https://api.pwnedpasswords.com/range";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Returns the uppercase hex SHA-1 of a password split into the 5 character prefix and suffix
+fn hash_prefix_and_suffix(password: &str) -> (String, String) {
+    let hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes());
+    let hex = hex::encode_upper(hash.as_ref());
+    let (prefix, suffix) = hex.split_at(5);
+    (prefix.to_string(), suffix.to_string())
+}
+
+/// Finds the breach count for a hash suffix in a range API response body
+fn count_in_range(body: &str, suffix: &str) -> u64 {
+    body.lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .find(|(candidate, _)| candidate.eq_ignore_ascii_case(suffix))
+        .and_then(|(_, count)| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Returns how many times a password appears in known breaches, or None if the lookup failed
+pub async fn check_password_breach(password: &str) -> Option<u64> {
+    let (prefix, suffix) = hash_prefix_and_suffix(password);
+
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent("PassQ")
+        .build()
+        .map_err(|e| log::warn!("Failed to build breach check client: {}", e))
+        .ok()?;
+
+    // Padding hides the real number of matches in the response size
+    let response = client
+        .get(format!("{}/{}", RANGE_API_URL, prefix))
+        .header("Add-Padding", "true")
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| log::warn!("Breach check request failed: {}", e))
+        .ok()?;
+
+    let body = response
+        .text()
+        .await
+        .map_err(|e| log::warn!("Failed to read breach check response: {}", e))
+        .ok()?;
+
+    Some(count_in_range(&body, &suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_prefix_and_suffix() {
+        // SHA-1("password") = 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        let (prefix, suffix) = hash_prefix_and_suffix("password");
+        assert_eq!(prefix, "5BAA6");
+        assert_eq!(suffix, "1E4C9B93F3F0682250B6CF8331B7EE68FD8");
+    }
+
+    #[test]
+    fn test_count_in_range() {
+        let body = "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9659365\r\n01330C689E5D64F660D6947A93AD634EF8F:0\r\n";
+        assert_eq!(count_in_range(body, "1E4C9B93F3F0682250B6CF8331B7EE68FD8"), 9659365);
+        // Padding entries report a count of zero
+        assert_eq!(count_in_range(body, "01330C689E5D64F660D6947A93AD634EF8F"), 0);
+        assert_eq!(count_in_range(body, "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"), 0);
+    }
+}
@@ -0,0 +1,101 @@
+//! Cleanup module running the periodic maintenance jobs in the background
+//!
+//! Expired tokens, enterprise session data, shares and passkey ceremonies are removed every
+//! CLEANUP_INTERVAL_MINUTES, so the tables don't depend on the cleanup endpoints being called.
+
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::db::DbPool;
+use crate::enterprise_session_manager::EnterpriseSessionManager;
+use crate::token_management::TokenManager;
+
+/// Minutes between cleanup runs when CLEANUP_INTERVAL_MINUTES isn't set
+pub const DEFAULT_CLEANUP_INTERVAL_MINUTES: u64 = 60;
+
+/// Minutes between cleanup runs; 0 disables the background task
+pub fn interval_minutes() -> u64 {
+    parse_interval_minutes(env::var("CLEANUP_INTERVAL_MINUTES").ok().as_deref())
+}
+
+fn parse_interval_minutes(value: Option<&str>) -> u64 {
+    match value.map(|v| v.trim().parse::<u64>()) {
+        Some(Ok(minutes)) => minutes,
+        Some(Err(_)) => {
+            log::warn!("Invalid CLEANUP_INTERVAL_MINUTES, using {} minutes", DEFAULT_CLEANUP_INTERVAL_MINUTES);
+            DEFAULT_CLEANUP_INTERVAL_MINUTES
+        }
+        None => DEFAULT_CLEANUP_INTERVAL_MINUTES,
+    }
+}
+
+/// Starts the periodic cleanup on the current runtime, running once right away
+pub fn spawn(db_pool: DbPool, token_manager: Arc<TokenManager>, session_manager: Arc<EnterpriseSessionManager>) {
+    let minutes = interval_minutes();
+    if minutes == 0 {
+        log::info!("Background cleanup disabled");
+        return;
+    }
+
+    log::info!("Background cleanup runs every {} minutes", minutes);
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(minutes * 60));
+        loop {
+            interval.tick().await;
+            run_once(&db_pool, &token_manager, &session_manager).await;
+        }
+    });
+}
+
+/// Runs every cleanup job once; a failing job is logged and doesn't stop the others
+async fn run_once(db_pool: &DbPool, token_manager: &Arc<TokenManager>, session_manager: &EnterpriseSessionManager) {
+    let tokens = token_manager.clone();
+    match actix_web::rt::task::spawn_blocking(move || tokens.cleanup_expired_tokens()).await {
+        Ok(Ok(count)) => log::info!("Background cleanup removed {} expired token revocations", count),
+        Ok(Err(e)) => log::error!("Background token cleanup failed: {}", e),
+        Err(e) => log::error!("Background token cleanup task failed: {}", e),
+    }
+
+    // Counts are logged by the session manager itself
+    if let Err(e) = session_manager.cleanup_expired_data().await {
+        log::error!("Background enterprise data cleanup failed: {}", e);
+    }
+
+    let pool = db_pool.clone();
+    let purged = actix_web::rt::task::spawn_blocking(move || -> Result<usize, String> {
+        let mut conn = pool.get().map_err(|e| e.to_string())?;
+        crate::sharing::purge_expired_shares(&mut conn).map_err(|e| e.to_string())
+    })
+    .await;
+    match purged {
+        Ok(Ok(count)) => log::info!("Background cleanup removed {} expired shares", count),
+        Ok(Err(e)) => log::error!("Background share cleanup failed: {}", e),
+        Err(e) => log::error!("Background share cleanup task failed: {}", e),
+    }
+
+    let pool = db_pool.clone();
+    let purged = actix_web::rt::task::spawn_blocking(move || -> Result<usize, String> {
+        let mut conn = pool.get().map_err(|e| e.to_string())?;
+        crate::webauthn::purge_expired_ceremonies(&mut conn, chrono::Utc::now().naive_utc()).map_err(|e| e.to_string())
+    })
+    .await;
+    match purged {
+        Ok(Ok(count)) => log::info!("Background cleanup removed {} expired passkey ceremonies", count),
+        Ok(Err(e)) => log::error!("Background passkey ceremony cleanup failed: {}", e),
+        Err(e) => log::error!("Background passkey ceremony cleanup task failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval_minutes() {
+        assert_eq!(parse_interval_minutes(None), DEFAULT_CLEANUP_INTERVAL_MINUTES);
+        assert_eq!(parse_interval_minutes(Some(" 15 ")), 15);
+        assert_eq!(parse_interval_minutes(Some("0")), 0);
+        assert_eq!(parse_interval_minutes(Some("-5")), DEFAULT_CLEANUP_INTERVAL_MINUTES);
+        assert_eq!(parse_interval_minutes(Some("hourly")), DEFAULT_CLEANUP_INTERVAL_MINUTES);
+    }
+}
@@ -0,0 +1,127 @@
+//! CORS module for building the cross-origin policy from the environment
+//!
+//! Allowed origins come from CORS_ALLOWED_ORIGINS. Debug builds fall back to the local
+//! development servers; release builds allow no cross-origin requests until it is set.
+
+use actix_cors::Cors;
+use std::env;
+
+/// Origins of the local development servers, allowed in debug builds when nothing is configured
+const DEV_ORIGINS: [&str; 3] = ["http://localhost:3000", "http://127.0.0.1:3000", "http://localhost:8080"];
+
+/// Cross-origin policy settings
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allow_extensions: bool,
+}
+
+impl CorsConfig {
+    /// Loads CORS_ALLOWED_ORIGINS and CORS_ALLOW_EXTENSIONS
+    pub fn from_env() -> Self {
+        let allowed_origins = match env::var("CORS_ALLOWED_ORIGINS") {
+            Ok(value) => parse_origins(&value),
+            Err(_) if cfg!(debug_assertions) => DEV_ORIGINS.iter().map(|o| o.to_string()).collect(),
+            Err(_) => {
+                log::warn!("CORS_ALLOWED_ORIGINS is not set; cross-origin requests will be rejected");
+                Vec::new()
+            }
+        };
+        let allow_extensions = env::var("CORS_ALLOW_EXTENSIONS").map(|v| v == "true").unwrap_or(false);
+
+        log::info!("CORS allows {} origins (browser extensions: {})", allowed_origins.len(), allow_extensions);
+        Self { allowed_origins, allow_extensions }
+    }
+
+    /// Builds the middleware for one worker
+    pub fn build(&self) -> Cors {
+        let mut cors = Cors::default();
+        for origin in &self.allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
+        if self.allow_extensions {
+            cors = cors.allowed_origin_fn(|origin, _req_head| {
+                // Allow Chrome and Firefox extension origins
+                origin.as_bytes().starts_with(b"chrome-extension://") || origin.as_bytes().starts_with(b"moz-extension://")
+            });
+        }
+
+        cors.allowed_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
+            .allowed_headers(vec![
+                "Content-Type",
+                "Authorization",
+                "Accept",
+                "X-Request-Id",
+                crate::csrf::CSRF_HEADER,
+                crate::token_management::DEVICE_ID_HEADER,
+            ])
+            .expose_headers(vec!["X-Request-Id"])
+            .supports_credentials()
+    }
+}
+
+/// Splits a comma-separated origin list, dropping blanks, trailing slashes and wildcards
+fn parse_origins(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/'))
+        .filter(|origin| !origin.is_empty())
+        .filter(|origin| {
+            // Credentialed requests can't use a wildcard, and allowing any origin defeats the point
+            let valid = !origin.contains('*') && url::Url::parse(origin).is_ok_and(|url| url.has_host());
+            if !valid {
+                log::warn!("Ignoring invalid CORS origin: {}", origin);
+            }
+            valid
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_origins() {
+        assert_eq!(
+            parse_origins(" https://vault.example.com/ ,http://localhost:3000,,"),
+            vec!["https://vault.example.com", "http://localhost:3000"]
+        );
+        assert!(parse_origins("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_origins_rejects_wildcards_and_garbage() {
+        assert!(parse_origins("*").is_empty());
+        assert!(parse_origins("https://*.example.com").is_empty());
+        assert!(parse_origins("vault.example.com").is_empty());
+        assert_eq!(parse_origins("*,https://vault.example.com"), vec!["https://vault.example.com"]);
+    }
+
+    #[actix_web::test]
+    async fn test_preflight_allows_client_headers() {
+        use actix_web::{http::StatusCode, test as actix_test, web, App, HttpResponse};
+
+        let config = CorsConfig { allowed_origins: vec!["https://vault.example.com".to_string()], allow_extensions: false };
+        let app = actix_test::init_service(
+            App::new().wrap(config.build()).route("/", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let preflight = |headers: &'static str| {
+            actix_test::TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri("/")
+                .insert_header(("Origin", "https://vault.example.com"))
+                .insert_header(("Access-Control-Request-Method", "POST"))
+                .insert_header(("Access-Control-Request-Headers", headers))
+                .to_request()
+        };
+        let res = actix_test::call_service(&app, preflight("content-type, x-csrf-token, x-device-id")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = actix_test::call_service(&app, preflight("x-unknown")).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
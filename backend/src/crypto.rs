@@ -46,22 +46,32 @@ pub fn generate_key() -> Result<aead::LessSafeKey, String> {
     }
 }
 
+/// Prefix of versioned ciphertext; untagged legacy values start directly with their nonce
+const ENVELOPE_MAGIC: &[u8; 2] = b"PQ";
+/// AES-256-GCM with the envelope header authenticated as associated data
+const ENVELOPE_V1: u8 = 1;
+const NONCE_LEN: usize = 12;
+
 /// Encrypts data using AES-256-GCM
-/// Returns nonce + encrypted_data + tag
+/// Returns envelope header + nonce + encrypted_data + tag
 pub fn encrypt(mut data: Vec<u8>, key: &aead::LessSafeKey) -> Result<Vec<u8>, String> {
     log::debug!("Encrypting {} bytes of data", data.len());
     
-    let mut nonce_bytes = [0u8; 12];
+    let mut nonce_bytes = [0u8; NONCE_LEN];
     let system_random = SystemRandom::new();
     system_random.fill(&mut nonce_bytes)
         .map_err(|e| format!("Failed to generate random nonce: {}", e))?;
     let nonce = aead::Nonce::assume_unique_for_key(nonce_bytes);
     
-    match key.seal_in_place_append_tag(nonce, aead::Aad::empty(), &mut data) {
+    let mut header = ENVELOPE_MAGIC.to_vec();
+    header.push(ENVELOPE_V1);
+    
+    match key.seal_in_place_append_tag(nonce, aead::Aad::from(&header), &mut data) {
         Ok(_) => {
             log::debug!("Encryption successful");
-            // Prepend nonce to encrypted data for storage
-            let mut result = nonce_bytes.to_vec();
+            // Prepend header and nonce to encrypted data for storage
+            let mut result = header;
+            result.extend_from_slice(&nonce_bytes);
             result.extend_from_slice(&data);
             Ok(result)
         }
@@ -73,26 +83,27 @@ pub fn encrypt(mut data: Vec<u8>, key: &aead::LessSafeKey) -> Result<Vec<u8>, St
 }
 
 /// Decrypts data using AES-256-GCM
-/// Expects nonce + encrypted_data + tag format
+/// Expects a v1 envelope, or the legacy (v0) nonce + encrypted_data + tag format
 pub fn decrypt(encrypted: Vec<u8>, key: &aead::LessSafeKey) -> Result<Vec<u8>, String> {
     log::debug!("Decrypting {} bytes of data", encrypted.len());
     
-    if encrypted.len() < 12 {
-        return Err("Encrypted data too short to contain nonce".to_string());
-    }
+    let header_len = ENVELOPE_MAGIC.len() + 1;
+    let tagged_v1 = encrypted.len() >= header_len
+        && encrypted[..ENVELOPE_MAGIC.len()] == ENVELOPE_MAGIC[..]
+        && encrypted[ENVELOPE_MAGIC.len()] == ENVELOPE_V1;
     
-    // Extract nonce from the beginning of encrypted data
-    let nonce_bytes: [u8; 12] = encrypted[0..12].try_into()
-        .map_err(|_| "Failed to extract nonce from encrypted data".to_string())?;
-    let nonce = aead::Nonce::assume_unique_for_key(nonce_bytes);
-    
-    // Remove nonce from encrypted data
-    let mut ciphertext = encrypted[12..].to_vec();
+    // A legacy nonce can start with the envelope header by chance, so fall back to v0 if v1 fails
+    let result = if tagged_v1 {
+        open(&encrypted[..header_len], &encrypted[header_len..], key)
+            .or_else(|_| open(&[], &encrypted, key))
+    } else {
+        open(&[], &encrypted, key)
+    };
     
-    match key.open_in_place(nonce, aead::Aad::empty(), &mut ciphertext) {
+    match result {
         Ok(decrypted_data) => {
             log::debug!("Decryption successful");
-            Ok(decrypted_data.to_vec())
+            Ok(decrypted_data)
         }
         Err(e) => {
             log::error!("Decryption failed: {}", e);
@@ -101,6 +112,22 @@ pub fn decrypt(encrypted: Vec<u8>, key: &aead::LessSafeKey) -> Result<Vec<u8>, S
     }
 }
 
+/// Opens nonce + encrypted_data + tag, authenticating `header` as associated data
+fn open(header: &[u8], sealed: &[u8], key: &aead::LessSafeKey) -> Result<Vec<u8>, String> {
+    if sealed.len() < NONCE_LEN {
+        return Err("Encrypted data too short to contain nonce".to_string());
+    }
+    
+    let nonce_bytes: [u8; NONCE_LEN] = sealed[..NONCE_LEN].try_into()
+        .map_err(|_| "Failed to extract nonce from encrypted data".to_string())?;
+    let nonce = aead::Nonce::assume_unique_for_key(nonce_bytes);
+    
+    let mut ciphertext = sealed[NONCE_LEN..].to_vec();
+    key.open_in_place(nonce, aead::Aad::from(header), &mut ciphertext)
+        .map(|decrypted_data| decrypted_data.to_vec())
+        .map_err(|e| e.to_string())
+}
+
 /// Encrypts a password string
 pub fn encrypt_password(password: &str) -> Result<Vec<u8>, String> {
     let key = generate_key()?;
@@ -160,3 +187,83 @@ pub async fn decrypt_metadata_async(encrypted_metadata: &[u8]) -> Result<String,
     String::from_utf8(decrypted_data)
         .map_err(|e| format!("Failed to convert decrypted metadata to string: {}", e))
 }
+
+/// Encrypts the contents of an attached file
+pub fn encrypt_attachment(content: Vec<u8>) -> Result<Vec<u8>, String> {
+    let key = generate_key()?;
+    encrypt(content, &key)
+}
+
+/// Decrypts the contents of an attached file
+pub fn decrypt_attachment(encrypted_content: &[u8]) -> Result<Vec<u8>, String> {
+    let key = generate_key()?;
+    decrypt(encrypted_content.to_vec(), &key)
+}
+
+/// Whether freshly encrypted values are decrypted and checked before being stored
+///
+/// Controlled by VERIFY_ENCRYPTION_ON_WRITE; defaults to on in debug builds and off in release.
+pub fn verify_on_write_enabled() -> bool {
+    match env::var("VERIFY_ENCRYPTION_ON_WRITE") {
+        Ok(value) => value == "true",
+        Err(_) => cfg!(debug_assertions),
+    }
+}
+
+/// Verifies that ciphertext decrypts back to the expected plaintext
+pub fn verify_round_trip(ciphertext: &[u8], expected: &str) -> Result<(), String> {
+    let key = generate_key()?;
+    let decrypted_data = decrypt(ciphertext.to_vec(), &key)
+        .map_err(|e| format!("Freshly encrypted value failed to decrypt: {}", e))?;
+
+    if decrypted_data != expected.as_bytes() {
+        return Err("Freshly encrypted value does not decrypt to the original plaintext".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key() -> aead::LessSafeKey {
+        aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_256_GCM, &[7u8; 32]).unwrap())
+    }
+
+    /// Encrypts the way values were stored before the envelope existed
+    fn encrypt_legacy(mut data: Vec<u8>, key: &aead::LessSafeKey, nonce_bytes: [u8; NONCE_LEN]) -> Vec<u8> {
+        let nonce = aead::Nonce::assume_unique_for_key(nonce_bytes);
+        key.seal_in_place_append_tag(nonce, aead::Aad::empty(), &mut data).unwrap();
+        let mut result = nonce_bytes.to_vec();
+        result.extend_from_slice(&data);
+        result
+    }
+
+    #[test]
+    fn test_legacy_v0_blob_decrypts() {
+        let key = test_key();
+        let legacy = encrypt_legacy(b"hunter2".to_vec(), &key, [3u8; NONCE_LEN]);
+        assert_eq!(decrypt(legacy, &key).unwrap(), b"hunter2");
+
+        // A legacy nonce that happens to look like a v1 header is still read as v0
+        let mut nonce = [9u8; NONCE_LEN];
+        nonce[..3].copy_from_slice(b"PQ\x01");
+        let lookalike = encrypt_legacy(b"hunter2".to_vec(), &key, nonce);
+        assert_eq!(decrypt(lookalike, &key).unwrap(), b"hunter2");
+    }
+
+    #[test]
+    fn test_v1_blob_round_trips() {
+        let key = test_key();
+        let encrypted = encrypt(b"hunter2".to_vec(), &key).unwrap();
+        assert_eq!(&encrypted[..3], b"PQ\x01");
+        assert_eq!(decrypt(encrypted.clone(), &key).unwrap(), b"hunter2");
+
+        // The header is authenticated, so stripping or changing it breaks decryption
+        assert!(decrypt(encrypted[3..].to_vec(), &key).is_err());
+        let mut downgraded = encrypted;
+        downgraded[2] = 2;
+        assert!(decrypt(downgraded, &key).is_err());
+    }
+}
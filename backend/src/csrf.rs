@@ -0,0 +1,156 @@
+//! CSRF module enforcing the double-submit pattern for cookie-authenticated writes
+//!
+//! `GET /auth/csrf-token` returns a token and stores the same value in the `csrf_token` cookie.
+//! A state-changing request that authenticates with the `auth_token` cookie must echo it in the
+//! `X-CSRF-Token` header; another site can make the browser send the cookies but can't read them.
+//! Requests carrying a bearer token are exempt, since browsers never attach one on their own.
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{http::Method, Error, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use crate::auth;
+use crate::models::ApiResponse;
+
+/// Cookie holding the token handed out by `GET /auth/csrf-token`
+pub const CSRF_COOKIE: &str = "csrf_token";
+/// Header the client echoes the token in
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Seconds the CSRF cookie lives; clients fetch a fresh token after a 403
+const CSRF_COOKIE_MAX_AGE_SECS: u64 = 60 * 60;
+
+/// Set-Cookie value storing a freshly issued token
+pub fn csrf_cookie(token: &str) -> String {
+    format!("{}={}; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age={}", CSRF_COOKIE, token, CSRF_COOKIE_MAX_AGE_SECS)
+}
+
+/// Whether the request may proceed without further CSRF checks, or carries a matching token
+fn csrf_satisfied(req: &ServiceRequest) -> bool {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return true;
+    }
+
+    let bearer = req.headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.starts_with("Bearer "));
+    if bearer || req.cookie("auth_token").is_none() {
+        return true;
+    }
+
+    let header_token = req.headers().get(CSRF_HEADER).and_then(|h| h.to_str().ok());
+    match (header_token, req.cookie(CSRF_COOKIE)) {
+        (Some(header_token), Some(cookie)) => auth::validate_csrf_token(header_token, cookie.value()),
+        _ => false,
+    }
+}
+
+pub struct CsrfMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CsrfMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct CsrfMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        if !csrf_satisfied(&req) {
+            log::warn!("Rejected {} {} with a missing or mismatched CSRF token", req.method(), req.path());
+            let response = HttpResponse::Forbidden().json(ApiResponse::<()>::error("Invalid or missing CSRF token".to_string()));
+            return Box::pin(async move { Ok(req.into_response(response).map_into_right_body()) });
+        }
+
+        Box::pin(async move {
+            let res = service.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::cookie::Cookie;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+
+    const TOKEN: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    async fn status(req: test::TestRequest) -> StatusCode {
+        let app = test::init_service(
+            App::new()
+                .wrap(CsrfMiddleware)
+                .route("/passwords", web::get().to(HttpResponse::Ok))
+                .route("/passwords", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+        test::call_service(&app, req.to_request()).await.status()
+    }
+
+    fn cookie_post() -> test::TestRequest {
+        test::TestRequest::post().uri("/passwords").cookie(Cookie::new("auth_token", "jwt"))
+    }
+
+    #[actix_web::test]
+    async fn test_missing_token_is_rejected() {
+        assert_eq!(status(cookie_post()).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(cookie_post().cookie(Cookie::new(CSRF_COOKIE, TOKEN))).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(cookie_post().insert_header((CSRF_HEADER, TOKEN))).await, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn test_mismatched_token_is_rejected() {
+        let other = "f".repeat(64);
+        let req = cookie_post().cookie(Cookie::new(CSRF_COOKIE, TOKEN)).insert_header((CSRF_HEADER, other));
+        assert_eq!(status(req).await, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn test_matching_token_is_accepted() {
+        let req = cookie_post().cookie(Cookie::new(CSRF_COOKIE, TOKEN)).insert_header((CSRF_HEADER, TOKEN));
+        assert_eq!(status(req).await, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_exempt_requests() {
+        // Safe methods, bearer clients and requests without the auth cookie aren't checked
+        let get = test::TestRequest::get().uri("/passwords").cookie(Cookie::new("auth_token", "jwt"));
+        assert_eq!(status(get).await, StatusCode::OK);
+        assert_eq!(status(cookie_post().insert_header(("Authorization", "Bearer jwt"))).await, StatusCode::OK);
+        assert_eq!(status(test::TestRequest::post().uri("/passwords")).await, StatusCode::OK);
+    }
+}
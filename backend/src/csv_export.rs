@@ -0,0 +1,227 @@
+//! CSV export module for writing cells that spreadsheets open safely
+//!
+//! The password export is streamed in batches, so neither memory nor a pooled connection is
+//! held for the size of the whole vault.
+
+use actix_web::web::Bytes;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use futures_util::{future, stream, Stream, StreamExt};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::crypto;
+use crate::db::DbPool;
+use crate::models::Password;
+
+/// Header row of the password export
+pub const PASSWORD_CSV_HEADER: &str = "name,url,username,password,notes,folder\n";
+
+/// Passwords loaded, decrypted and written per step of the export stream
+const EXPORT_BATCH_SIZE: i64 = 500;
+
+/// Characters that make spreadsheet applications read a cell as a formula
+const FORMULA_TRIGGERS: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// Whether a value gets a leading `'` on export
+///
+/// Values whose leading `'`s are followed by a trigger get one more, so `strip_formula_guard`
+/// can always tell the added quote from ones that belong to the value.
+fn needs_formula_guard(value: &str) -> bool {
+    value.trim_start_matches('\'').starts_with(FORMULA_TRIGGERS)
+}
+
+/// Quotes a CSV field, doubling any embedded quotes
+///
+/// Values starting with a formula trigger get a leading `'` so Excel and friends show them
+/// as text instead of evaluating them (OWASP CSV injection guidance).
+pub fn csv_field(value: &str) -> String {
+    let neutralized = if needs_formula_guard(value) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    format!("\"{}\"", neutralized.replace('"', "\"\""))
+}
+
+/// Undoes the `'` that `csv_field` added, so a PassQ export imports back unchanged
+pub fn strip_formula_guard(value: &str) -> &str {
+    match value.strip_prefix('\'') {
+        Some(guarded) if needs_formula_guard(guarded) => guarded,
+        _ => value,
+    }
+}
+
+/// Renders one password as a CSV row
+pub fn password_row(name: &str, url: &str, username: &str, password: &str, notes: &str, folder: &str) -> String {
+    format!(
+        "{},{},{},{},{},{}\n",
+        csv_field(name),
+        csv_field(url),
+        csv_field(username),
+        csv_field(password),
+        csv_field(notes),
+        csv_field(folder)
+    )
+}
+
+/// Renders the user's next batch of exportable passwords after `after`, ordered by id
+///
+/// Returns the rows, the id to continue from and how many entries were read.
+fn password_batch(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    after: Option<Uuid>,
+    folder_names: &HashMap<Uuid, String>,
+) -> QueryResult<(String, Option<Uuid>, usize)> {
+    use crate::schema::passwords;
+
+    let mut query = passwords::table
+        .filter(passwords::user_id.eq(user_id))
+        .filter(passwords::deleted_at.is_null())
+        // Only the client holding the key can export client-encrypted entries
+        .filter(passwords::is_client_encrypted.eq(false))
+        .order(passwords::id.asc())
+        .limit(EXPORT_BATCH_SIZE)
+        .into_boxed();
+    if let Some(after) = after {
+        query = query.filter(passwords::id.gt(after));
+    }
+    let batch = query.load::<Password>(conn)?;
+
+    let mut rows = String::new();
+    for password in &batch {
+        let decrypted_password = match crypto::decrypt_password(&password.encrypted_password) {
+            Ok(pwd) => pwd,
+            Err(e) => {
+                log::error!("Failed to decrypt password {}: {}", password.id, e);
+                continue; // Skip this entry if decryption fails
+            }
+        };
+
+        let folder_name = password.folder_id
+            .and_then(|id| folder_names.get(&id))
+            .map(String::as_str)
+            .unwrap_or("No Folder");
+
+        rows.push_str(&password_row(
+            &password.website,
+            &password.website,
+            &password.username,
+            &decrypted_password,
+            password.notes.as_deref().unwrap_or_default(),
+            folder_name,
+        ));
+    }
+
+    Ok((rows, batch.last().map(|p| p.id), batch.len()))
+}
+
+/// Streams a CSV file, header first, from batches rendered by `load_batch`
+///
+/// `load_batch` gets the cursor to continue from, None for the first batch, and returns the
+/// rendered rows with the next cursor, None after the last batch. Each batch borrows a pooled
+/// connection only while it loads. A database error after the header went out ends the stream
+/// with an error, which aborts the download.
+pub fn batched_csv_stream<C, F>(
+    db_pool: DbPool,
+    header: &'static str,
+    label: String,
+    load_batch: F,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>>
+where
+    C: Send + 'static,
+    F: Fn(&mut PgConnection, Option<C>) -> QueryResult<(String, Option<C>)> + Send + Sync + 'static,
+{
+    let load_batch = Arc::new(load_batch);
+    let header = stream::once(future::ready(Ok(Bytes::from_static(header.as_bytes()))));
+
+    // The state is the cursor to continue from, or None once the last batch was sent
+    let rows = stream::unfold(Some(None), move |cursor: Option<Option<C>>| {
+        let db_pool = db_pool.clone();
+        let load_batch = load_batch.clone();
+        let label = label.clone();
+        async move {
+            let after = cursor?;
+            let batch = actix_web::rt::task::spawn_blocking(move || -> Result<_, String> {
+                let mut conn = db_pool.get().map_err(|e| e.to_string())?;
+                load_batch(&mut conn, after).map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|result| result);
+
+            match batch {
+                Ok((rows, None)) if rows.is_empty() => None,
+                Ok((rows, next)) => Some((Ok(Bytes::from(rows)), next.map(Some))),
+                Err(e) => {
+                    log::error!("CSV export of {} failed: {}", label, e);
+                    Some((Err(actix_web::error::ErrorInternalServerError("Export failed")), None))
+                }
+            }
+        }
+    });
+
+    header.chain(rows)
+}
+
+/// Streams a user's passwords as CSV, header first
+pub fn password_csv_stream(
+    db_pool: DbPool,
+    user_id: Uuid,
+    folder_names: HashMap<Uuid, String>,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    batched_csv_stream(db_pool, PASSWORD_CSV_HEADER, format!("passwords of user {}", user_id), move |conn, after| {
+        let (rows, last_id, count) = password_batch(conn, user_id, after, &folder_names)?;
+        let next = if (count as i64) < EXPORT_BATCH_SIZE { None } else { last_id };
+        Ok((rows, next))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_fields_are_quoted() {
+        assert_eq!(csv_field("example.com"), "\"example.com\"");
+        assert_eq!(csv_field(""), "\"\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_formulas_are_neutralized() {
+        assert_eq!(csv_field("=cmd|' /C calc'!A0"), "\"'=cmd|' /C calc'!A0\"");
+        assert_eq!(csv_field("=cmd|calc"), "\"'=cmd|calc\"");
+        assert_eq!(csv_field("+1+1"), "\"'+1+1\"");
+        assert_eq!(csv_field("-2+3"), "\"'-2+3\"");
+        assert_eq!(csv_field("@SUM(A1)"), "\"'@SUM(A1)\"");
+        assert_eq!(csv_field("\t=1"), "\"'\t=1\"");
+        assert_eq!(csv_field("\r=1"), "\"'\r=1\"");
+    }
+
+    #[test]
+    fn test_password_row_neutralizes_notes() {
+        assert_eq!(
+            password_row("Bank", "https://bank.example", "alice", "pw", "=cmd|calc", "No Folder"),
+            "\"Bank\",\"https://bank.example\",\"alice\",\"pw\",\"'=cmd|calc\",\"No Folder\"\n"
+        );
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let values = ["=1+1", "+49 170 1234567", "-secret-", "@home", "'=quoted", "''=twice", "'plain", "'", "p@ss", ""];
+        for value in values {
+            let row = password_row("Name", "https://example.com", "alice", value, value, "No Folder");
+            let fields = crate::csv_import::parse_csv_line(row.trim_end());
+            assert_eq!(strip_formula_guard(&fields[3]), value);
+            assert_eq!(strip_formula_guard(&fields[4]), value);
+        }
+    }
+
+    #[test]
+    fn test_triggers_only_matter_at_the_start() {
+        assert_eq!(csv_field("a=b"), "\"a=b\"");
+        assert_eq!(csv_field("user@example.com"), "\"user@example.com\"");
+    }
+}
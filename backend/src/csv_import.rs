@@ -0,0 +1,245 @@
+//! CSV import module for recognizing password manager exports
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CsvFormat {
+    PassQ,      // name,url,username,password,notes,folder
+    Bitwarden,  // folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp
+    LastPass,   // url,username,password,extra,name,grouping,fav (or with totp)
+    OnePassword, // Title,Website,Username,Password,One-time password,Favorite status,Archived status,Tags,Notes
+    Chrome,     // name,url,username,password
+    ChromeWithNote, // name,url,username,password,note
+    Firefox,    // url,username,password,httpRealm,formActionOrigin,guid,timeCreated,timeLastUsed,timePasswordChanged
+    Dashlane,   // Username,Password,Website,Title,Note
+    KeePass,    // Account,Login Name,Password,Web Site,Comments
+    Kaspersky,  // Name,Website,Login,Password,Comment
+    Enpass,     // Title,Username,Password,URL,Notes,...
+}
+
+/// Picks the exporter a CSV file came from by its header row
+pub fn detect_csv_format(header_line: &str) -> CsvFormat {
+    let headers = parse_csv_line(header_line);
+    let headers_lower: Vec<String> = headers.iter().map(|h| h.to_lowercase()).collect();
+    
+    // Check for Bitwarden format
+    if headers_lower.contains(&"folder".to_string()) && 
+       headers_lower.contains(&"favorite".to_string()) && 
+       headers_lower.contains(&"login_uri".to_string()) && 
+       headers_lower.contains(&"login_username".to_string()) && 
+       headers_lower.contains(&"login_password".to_string()) {
+        return CsvFormat::Bitwarden;
+    }
+    
+    // Check for LastPass format
+    if headers_lower.contains(&"url".to_string()) && 
+       headers_lower.contains(&"username".to_string()) && 
+       headers_lower.contains(&"password".to_string()) && 
+       headers_lower.contains(&"grouping".to_string()) && 
+       headers_lower.contains(&"extra".to_string()) {
+        return CsvFormat::LastPass;
+    }
+    
+    // Check for 1Password format
+    if headers_lower.contains(&"title".to_string()) && 
+       headers_lower.contains(&"website".to_string()) && 
+       headers_lower.contains(&"username".to_string()) && 
+       headers_lower.contains(&"password".to_string()) && 
+       headers_lower.contains(&"one-time password".to_string()) {
+        return CsvFormat::OnePassword;
+    }
+    
+    // Check for Firefox format
+    if headers_lower.contains(&"url".to_string()) && 
+       headers_lower.contains(&"username".to_string()) && 
+       headers_lower.contains(&"password".to_string()) && 
+       headers_lower.contains(&"httprealm".to_string()) && 
+       headers_lower.contains(&"formactionorigin".to_string()) {
+        return CsvFormat::Firefox;
+    }
+    
+    // Check for KeePass format
+    if headers_lower.contains(&"account".to_string()) && 
+       headers_lower.contains(&"login name".to_string()) && 
+       headers_lower.contains(&"password".to_string()) && 
+       headers_lower.contains(&"web site".to_string()) {
+        return CsvFormat::KeePass;
+    }
+    
+    // Check for Dashlane format
+    if headers_lower.contains(&"username".to_string()) && 
+       headers_lower.contains(&"password".to_string()) && 
+       headers_lower.contains(&"website".to_string()) && 
+       headers_lower.contains(&"title".to_string()) && 
+       headers_lower.contains(&"note".to_string()) {
+        return CsvFormat::Dashlane;
+    }
+    
+    // Check for Enpass format
+    if headers_lower.contains(&"title".to_string()) && 
+       headers_lower.contains(&"username".to_string()) && 
+       headers_lower.contains(&"password".to_string()) && 
+       headers_lower.contains(&"url".to_string()) && 
+       headers_lower.contains(&"notes".to_string()) {
+        return CsvFormat::Enpass;
+    }
+    
+    // Check for Kaspersky format
+    if headers_lower.contains(&"name".to_string()) && 
+       headers_lower.contains(&"website".to_string()) && 
+       headers_lower.contains(&"login".to_string()) && 
+       headers_lower.contains(&"password".to_string()) && 
+       headers_lower.contains(&"comment".to_string()) {
+        return CsvFormat::Kaspersky;
+    }
+    
+    // Check for Chrome format (simple: name,url,username,password)
+    if headers_lower.len() == 4 && 
+       headers_lower.contains(&"name".to_string()) && 
+       headers_lower.contains(&"url".to_string()) && 
+       headers_lower.contains(&"username".to_string()) && 
+       headers_lower.contains(&"password".to_string()) {
+        return CsvFormat::Chrome;
+    }
+    
+    // Check for the newer Chrome format that adds a note column
+    if headers_lower.len() == 5 && 
+       headers_lower.contains(&"name".to_string()) && 
+       headers_lower.contains(&"url".to_string()) && 
+       headers_lower.contains(&"username".to_string()) && 
+       headers_lower.contains(&"password".to_string()) && 
+       headers_lower.contains(&"note".to_string()) {
+        return CsvFormat::ChromeWithNote;
+    }
+    
+    // Default to PassQ format
+    CsvFormat::PassQ
+}
+
+/// Splits CSV data into records paired with the line number each one starts on
+///
+/// Quoted fields may span several lines, so physical lines are joined until their quotes
+/// balance. Line endings may be LF or CRLF, a leading UTF-8 BOM is dropped and blank records
+/// are skipped.
+pub fn split_csv_records(data: &str) -> Vec<(usize, String)> {
+    let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+    let mut records = Vec::new();
+    let mut current = String::new();
+    let mut start_line = 1;
+    let mut in_quotes = false;
+
+    for (index, line) in data.split('\n').enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if in_quotes {
+            current.push('\n');
+        } else {
+            start_line = index + 1;
+        }
+        current.push_str(line);
+
+        // Doubled quotes flip the state twice, so only real field boundaries count
+        if line.matches('"').count() % 2 == 1 {
+            in_quotes = !in_quotes;
+        }
+
+        if !in_quotes {
+            if !current.trim().is_empty() {
+                records.push((start_line, std::mem::take(&mut current)));
+            } else {
+                current.clear();
+            }
+        }
+    }
+
+    // An unterminated quote swallows the rest of the file; keep it so the row is reported
+    if !current.trim().is_empty() {
+        records.push((start_line, current));
+    }
+
+    records
+}
+
+/// Splits one CSV line into trimmed fields, honouring quotes and doubled quotes
+pub fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut current_field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => {
+                if in_quotes && chars.peek() == Some(&'"') {
+                    // Escaped quote
+                    current_field.push('"');
+                    chars.next(); // Skip the second quote
+                } else {
+                    in_quotes = !in_quotes;
+                }
+            }
+            ',' if !in_quotes => {
+                fields.push(current_field.trim().to_string());
+                current_field.clear();
+            }
+            _ => {
+                current_field.push(ch);
+            }
+        }
+    }
+    
+    fields.push(current_field.trim().to_string());
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_enpass() {
+        assert_eq!(detect_csv_format("Title,Username,Password,URL,Notes"), CsvFormat::Enpass);
+        assert_eq!(detect_csv_format("\"Title\",\"Username\",\"Password\",\"URL\",\"Notes\",\"TOTP\",\"Category\""), CsvFormat::Enpass);
+    }
+
+    #[test]
+    fn test_detects_chrome_variants() {
+        assert_eq!(detect_csv_format("name,url,username,password"), CsvFormat::Chrome);
+        assert_eq!(detect_csv_format("name,url,username,password,note"), CsvFormat::ChromeWithNote);
+    }
+
+    #[test]
+    fn test_similar_headers_keep_their_format() {
+        assert_eq!(detect_csv_format("name,url,username,password,notes,folder"), CsvFormat::PassQ);
+        assert_eq!(detect_csv_format("Username,Password,Website,Title,Note"), CsvFormat::Dashlane);
+        assert_eq!(
+            detect_csv_format("Title,Website,Username,Password,One-time password,Favorite status,Archived status,Tags,Notes"),
+            CsvFormat::OnePassword
+        );
+    }
+
+    #[test]
+    fn test_split_csv_records_keeps_multi_line_notes() {
+        let data = "name,url,username,password,notes\r\nBank,https://bank.example,alice,pw,\"PIN: 1234\r\nSecurity answer: \"\"Rex\"\"\"\r\n\r\nMail,https://mail.example,alice,pw2,\r\n";
+        let records = split_csv_records(data);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].0, 2);
+        assert_eq!(records[2].0, 5);
+
+        let fields = parse_csv_line(&records[1].1);
+        assert_eq!(fields.len(), 5);
+        assert_eq!(fields[4], "PIN: 1234\nSecurity answer: \"Rex\"");
+        assert_eq!(parse_csv_line(&records[2].1)[0], "Mail");
+    }
+
+    #[test]
+    fn test_split_csv_records_strips_bom() {
+        let records = split_csv_records("\u{feff}Title,Username,Password,URL,Notes\nSite,bob,pw,https://site.example,");
+        assert_eq!(detect_csv_format(&records[0].1), CsvFormat::Enpass);
+        assert_eq!(records[1], (2, "Site,bob,pw,https://site.example,".to_string()));
+        assert!(split_csv_records("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_csv_line() {
+        assert_eq!(parse_csv_line("a, b ,\"c,d\",\"say \"\"hi\"\"\""), vec!["a", "b", "c,d", "say \"hi\""]);
+        assert_eq!(parse_csv_line(""), vec![""]);
+    }
+}
@@ -3,15 +3,92 @@
 use diesel::prelude::*;
 use diesel::r2d2::{self, ConnectionManager};
 use std::env;
+use std::ops::Deref;
 use log;
 
 pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
+/// Advisory lock key shared by account deletion and orphaned-record cleanup
+pub const ACCOUNT_MAINTENANCE_LOCK: i64 = 0x5041_5353_5100_0001;
+
+/// Advisory lock key serializing audit log writes so the hash chain stays linear
+pub const AUDIT_CHAIN_LOCK: i64 = 0x5041_5353_5100_0002;
+
+/// Advisory lock key held for the whole of a data-key rotation
+pub const REKEY_LOCK: i64 = 0x5041_5353_5100_0003;
+
+define_sql_function! {
+    /// Takes a transaction-scoped exclusive advisory lock if it is free
+    fn pg_try_advisory_xact_lock(key: diesel::sql_types::BigInt) -> diesel::sql_types::Bool;
+}
+
+define_sql_function! {
+    /// Takes a session-scoped exclusive advisory lock if it is free
+    fn pg_try_advisory_lock(key: diesel::sql_types::BigInt) -> diesel::sql_types::Bool;
+}
+
+define_sql_function! {
+    /// Releases a session-scoped exclusive advisory lock
+    fn pg_advisory_unlock(key: diesel::sql_types::BigInt) -> diesel::sql_types::Bool;
+}
+
+/// Tries to take the account maintenance lock exclusively for the current transaction
+///
+/// Returns false while any account deletion (holding the lock in shared mode) is running.
+pub fn try_lock_account_maintenance(conn: &mut PgConnection) -> QueryResult<bool> {
+    diesel::select(pg_try_advisory_xact_lock(ACCOUNT_MAINTENANCE_LOCK)).get_result(conn)
+}
+
+/// Waits for the account maintenance lock in shared mode, held until the current transaction ends
+///
+/// Account deletions share the lock with each other but exclude orphaned-record cleanup.
+pub fn lock_account_maintenance_shared(conn: &mut PgConnection) -> QueryResult<()> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock_shared($1)")
+        .bind::<diesel::sql_types::BigInt, _>(ACCOUNT_MAINTENANCE_LOCK)
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Waits for the audit chain lock, held until the current transaction ends
+pub fn lock_audit_chain(conn: &mut PgConnection) -> QueryResult<()> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+        .bind::<diesel::sql_types::BigInt, _>(AUDIT_CHAIN_LOCK)
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Tries to take the rekey lock for the lifetime of the connection's session
+///
+/// Rekeying commits in batches, so the lock can't be transaction-scoped. Release it with `unlock_rekey`.
+pub fn try_lock_rekey(conn: &mut PgConnection) -> QueryResult<bool> {
+    diesel::select(pg_try_advisory_lock(REKEY_LOCK)).get_result(conn)
+}
+
+/// Releases the session-scoped rekey lock
+pub fn unlock_rekey(conn: &mut PgConnection) -> QueryResult<bool> {
+    diesel::select(pg_advisory_unlock(REKEY_LOCK)).get_result(conn)
+}
+
+/// Connection pool for read-only queries, backed by a replica when one is configured
+///
+/// Replicas may lag behind the primary, so a read issued right after a write can
+/// return stale data. Handlers that must observe their own writes use the primary pool.
+#[derive(Clone)]
+pub struct ReadPool(DbPool);
+
+impl Deref for ReadPool {
+    type Target = DbPool;
+
+    fn deref(&self) -> &DbPool {
+        &self.0
+    }
+}
+
 /// Establishes a connection pool to the database with TLS enforcement
 pub fn establish_connection() -> DbPool {
     log::info!("Establishing database connection pool with TLS enforcement");
     
-    let mut database_url = match env::var("DATABASE_URL") {
+    let database_url = match env::var("DATABASE_URL") {
         Ok(url) => {
             log::debug!("Database URL loaded successfully");
             url
@@ -22,6 +99,29 @@ pub fn establish_connection() -> DbPool {
         }
     };
 
+    let pool = build_pool(database_url);
+    log::info!("Database connection pool established successfully with TLS");
+    pool
+}
+
+/// Establishes the read pool from DATABASE_READ_URL, falling back to the primary pool
+pub fn establish_read_connection(primary: &DbPool) -> ReadPool {
+    match env::var("DATABASE_READ_URL") {
+        Ok(url) if !url.trim().is_empty() => {
+            log::info!("Establishing read replica connection pool with TLS enforcement");
+            let pool = build_pool(url);
+            log::info!("Read replica connection pool established successfully with TLS");
+            ReadPool(pool)
+        }
+        _ => {
+            log::info!("DATABASE_READ_URL not set, read-only queries will use the primary pool");
+            ReadPool(primary.clone())
+        }
+    }
+}
+
+/// Builds a connection pool for the given URL, enforcing TLS
+fn build_pool(mut database_url: String) -> DbPool {
     // Enforce TLS for database connections
     if !database_url.contains("sslmode=") {
         if database_url.contains("?") {
@@ -37,13 +137,23 @@ pub fn establish_connection() -> DbPool {
     let manager = ConnectionManager::<PgConnection>::new(database_url);
     
     match r2d2::Pool::builder().build(manager) {
-        Ok(pool) => {
-            log::info!("Database connection pool established successfully with TLS");
-            pool
-        }
+        Ok(pool) => pool,
         Err(e) => {
             log::error!("Failed to create database connection pool: {}", e);
             panic!("Database configuration error");
         }
     }
-}
\ No newline at end of file
+}
+
+/// Connection to TEST_DATABASE_URL inside a rolled back transaction, or None when it isn't set
+///
+/// `schema` creates temporary tables that shadow the real ones, so the database needs no
+/// migrations and nothing a test writes outlives it.
+#[cfg(test)]
+pub fn test_connection(schema: &str) -> Option<PgConnection> {
+    let url = env::var("TEST_DATABASE_URL").ok()?;
+    let mut conn = PgConnection::establish(&url).expect("TEST_DATABASE_URL is not reachable");
+    conn.begin_test_transaction().unwrap();
+    diesel::sql_query(schema).execute(&mut conn).unwrap();
+    Some(conn)
+}
@@ -1,16 +1,184 @@
-//! Email service module for sending password reset emails
+//! Email service module for sending templated emails such as password resets
+//!
+//! Templates live in `templates/email` as an HTML and a plain-text file each and are embedded
+//! at compile time. Placeholders are written `{{name}}`; values are HTML-escaped in the HTML part.
+//! The HTML part holds only the content and footer of the email; `layout.html` supplies the page
+//! around it, so the header and styles are shared by every template.
 
-use lettre::message::header::ContentType;
+use lettre::message::MultiPart;
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{Message, SmtpTransport, Transport};
+use chrono::{DateTime, Utc};
 use std::env;
 use log;
 
+use crate::auth::RESET_TOKEN_EXPIRY_MINUTES;
+
+const DEFAULT_FRONTEND_BASE_URL: &str = "http://localhost";
+
+/// Page the HTML part of a template is placed in, with `{{title}}` and `{{content}}` slots
+const LAYOUT_HTML: &str = include_str!("../templates/email/layout.html");
+
+/// Emails that can be sent through `EmailService::send_email`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmailTemplate {
+    PasswordReset,
+    NewDeviceLogin,
+    ShareNotification,
+    DeviceTrust,
+    LoginChallenge,
+    EmailChange,
+}
+
+impl EmailTemplate {
+    /// Returns the HTML and plain-text sources of the template
+    fn sources(self) -> (&'static str, &'static str) {
+        match self {
+            EmailTemplate::PasswordReset => (
+                include_str!("../templates/email/password_reset.html"),
+                include_str!("../templates/email/password_reset.txt"),
+            ),
+            EmailTemplate::NewDeviceLogin => (
+                include_str!("../templates/email/new_device_login.html"),
+                include_str!("../templates/email/new_device_login.txt"),
+            ),
+            EmailTemplate::ShareNotification => (
+                include_str!("../templates/email/share_notification.html"),
+                include_str!("../templates/email/share_notification.txt"),
+            ),
+            EmailTemplate::DeviceTrust => (
+                include_str!("../templates/email/device_trust.html"),
+                include_str!("../templates/email/device_trust.txt"),
+            ),
+            EmailTemplate::LoginChallenge => (
+                include_str!("../templates/email/login_challenge.html"),
+                include_str!("../templates/email/login_challenge.txt"),
+            ),
+            EmailTemplate::EmailChange => (
+                include_str!("../templates/email/email_change.html"),
+                include_str!("../templates/email/email_change.txt"),
+            ),
+        }
+    }
+
+    /// Title of the layout page the template is rendered into
+    fn page_title(self) -> &'static str {
+        match self {
+            EmailTemplate::PasswordReset => "Password Reset",
+            EmailTemplate::NewDeviceLogin => "New Sign-In",
+            EmailTemplate::ShareNotification => "Shared With You",
+            EmailTemplate::DeviceTrust => "Trust Device",
+            EmailTemplate::LoginChallenge => "Sign-In Code",
+            EmailTemplate::EmailChange => "Confirm Email",
+        }
+    }
+
+    /// Renders both parts of the template with the given variables
+    pub fn render(self, vars: &[(&str, &str)]) -> Result<(String, String), String> {
+        let (html, text) = self.sources();
+        let content = render_template(html, vars, true)?;
+        // The content is already escaped and isn't scanned for placeholders again
+        let html = render_template(LAYOUT_HTML, &[("title", &html_escape(self.page_title())), ("content", &content)], false)?;
+        Ok((html, render_template(text, vars, false)?))
+    }
+}
+
+/// Replaces every `{{name}}` in the template; a placeholder without a value is an error
+fn render_template(template: &str, vars: &[(&str, &str)], escape_html: bool) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| "Unterminated placeholder in email template".to_string())?;
+        let name = &after[..end];
+        let value = vars.iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| format!("No value for email template variable '{}'", name))?;
+
+        if escape_html {
+            rendered.push_str(&html_escape(value));
+        } else {
+            rendered.push_str(value);
+        }
+        rest = &after[end + 2..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+fn html_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Details of a sign-in the user is warned about
+#[derive(Debug, Clone)]
+pub struct NewDeviceAlert {
+    /// Completes "signed in to from ...", e.g. "a new device"
+    pub reason: String,
+    pub ip_address: Option<String>,
+    pub location: Option<String>,
+    pub user_agent: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+/// How the SMTP connection is secured, from SMTP_ENCRYPTION
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SmtpEncryption {
+    /// Plain-text connection; only meant for a local MailHog
+    None,
+    /// Plain connection upgraded with STARTTLS (usually port 587)
+    StartTls,
+    /// TLS from the first byte (usually port 465)
+    Tls,
+}
+
+impl SmtpEncryption {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(SmtpEncryption::None),
+            "starttls" => Ok(SmtpEncryption::StartTls),
+            "tls" => Ok(SmtpEncryption::Tls),
+            other => Err(format!("Invalid SMTP_ENCRYPTION '{}', expected none, starttls or tls", other)),
+        }
+    }
+}
+
+/// Builds the link a user follows to reset their password
+fn reset_url(frontend_base_url: &str, reset_token: &str) -> String {
+    format!("{}/reset-password?token={}", frontend_base_url.trim_end_matches('/'), reset_token)
+}
+
+/// Builds the link a user follows to confirm trusting a device
+fn device_trust_url(frontend_base_url: &str, trust_token: &str) -> String {
+    format!("{}/trust-device?token={}", frontend_base_url.trim_end_matches('/'), trust_token)
+}
+
+/// Builds the link a user follows to confirm a new email address
+fn email_change_url(frontend_base_url: &str, token: &str) -> String {
+    format!("{}/confirm-email?token={}", frontend_base_url.trim_end_matches('/'), token)
+}
+
 /// Email service for sending password reset emails
 pub struct EmailService {
     smtp_transport: SmtpTransport,
     from_email: String,
     from_name: String,
+    frontend_base_url: String,
 }
 
 impl EmailService {
@@ -26,10 +194,25 @@ impl EmailService {
         let smtp_password = env::var("SMTP_PASSWORD").unwrap_or_default();
         let from_email = env::var("SMTP_FROM_EMAIL").map_err(|_| "SMTP_FROM_EMAIL not set".to_string())?;
         let from_name = env::var("SMTP_FROM_NAME").unwrap_or_else(|_| "PassQ".to_string());
+        let frontend_base_url = env::var("FRONTEND_BASE_URL")
+            .unwrap_or_else(|_| DEFAULT_FRONTEND_BASE_URL.to_string());
+
+        let encryption = match env::var("SMTP_ENCRYPTION") {
+            Ok(value) => SmtpEncryption::parse(&value)?,
+            Err(_) => SmtpEncryption::StartTls,
+        };
 
-        // Build SMTP transport - use unencrypted connection for MailHog
-        let mut transport_builder = SmtpTransport::builder_dangerous(&smtp_host)
-            .port(smtp_port);
+        let mut transport_builder = match encryption {
+            SmtpEncryption::None => {
+                log::warn!("SMTP_ENCRYPTION is none; mail is sent unencrypted, which is only suitable for a local MailHog");
+                SmtpTransport::builder_dangerous(&smtp_host)
+            }
+            SmtpEncryption::StartTls => SmtpTransport::starttls_relay(&smtp_host)
+                .map_err(|e| format!("Failed to configure STARTTLS for {}: {}", smtp_host, e))?,
+            SmtpEncryption::Tls => SmtpTransport::relay(&smtp_host)
+                .map_err(|e| format!("Failed to configure TLS for {}: {}", smtp_host, e))?,
+        }
+        .port(smtp_port);
 
         // Add authentication if credentials are provided
         if !smtp_username.is_empty() && !smtp_password.is_empty() {
@@ -39,255 +222,193 @@ impl EmailService {
 
         let smtp_transport = transport_builder.build();
 
-        log::info!("Email service initialized with SMTP host: {}:{}", smtp_host, smtp_port);
+        log::info!("Email service initialized with SMTP host: {}:{} ({:?})", smtp_host, smtp_port, encryption);
 
         Ok(EmailService {
             smtp_transport,
             from_email,
             from_name,
+            frontend_base_url,
         })
     }
 
-    /// Sends a password reset email
-    pub async fn send_password_reset_email(
+    /// Renders a template and sends it as a multipart HTML and plain-text email
+    pub async fn send_email(
         &self,
         to_email: &str,
-        username: &str,
-        reset_token: &str,
+        subject: &str,
+        template: EmailTemplate,
+        vars: &[(&str, &str)],
     ) -> Result<(), String> {
-        let reset_url = format!("http://localhost/reset-password?token={}", reset_token);
-        
-        let html_body = format!(
-            r#"
-            <!DOCTYPE html>
-            <html>
-            <head>
-                <meta charset="utf-8">
-                <meta name="viewport" content="width=device-width, initial-scale=1.0">
-                <title>Password Reset - PassQ</title>
-                <style>
-                    * {{ margin: 0; padding: 0; box-sizing: border-box; }}
-                    body {{ 
-                        font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, 'Helvetica Neue', Arial, sans-serif;
-                        line-height: 1.6; 
-                        color: #1f2937;
-                        background-color: #f3f4f6;
-                        padding: 20px;
-                    }}
-                    .email-container {{ 
-                        max-width: 600px; 
-                        margin: 0 auto; 
-                        background-color: #ffffff;
-                        border-radius: 16px;
-                        overflow: hidden;
-                        box-shadow: 0 10px 25px rgba(0, 0, 0, 0.1);
-                        border: 3px solid #000000;
-                    }}
-                    .header {{ 
-                        background: #000000;
-                        color: white; 
-                        padding: 40px 30px;
-                        text-align: center;
-                        position: relative;
-                    }}
-                    .header::after {{
-                        content: '';
-                        position: absolute;
-                        bottom: -3px;
-                        left: 0;
-                        right: 0;
-                        height: 3px;
-                        background-color: #000000;
-                    }}
-                    .header h1 {{ 
-                        font-size: 32px;
-                        font-weight: 900;
-                        letter-spacing: 2px;
-                        text-transform: uppercase;
-                        margin: 0;
-                        text-shadow: 2px 2px 4px rgba(0, 0, 0, 0.3);
-                    }}
-                    .header .subtitle {{
-                        font-size: 14px;
-                        opacity: 0.9;
-                        margin-top: 8px;
-                        font-weight: 600;
-                        letter-spacing: 1px;
-                        text-transform: uppercase;
-                    }}
-                    .content {{ 
-                        padding: 40px 30px;
-                        background-color: #ffffff;
-                    }}
-                    .content h2 {{
-                        font-size: 24px;
-                        font-weight: 800;
-                        color: #1f2937;
-                        margin-bottom: 20px;
-                        text-transform: uppercase;
-                        letter-spacing: 1px;
-                    }}
-                    .content p {{
-                        margin-bottom: 16px;
-                        color: #4b5563;
-                        font-size: 16px;
-                        line-height: 1.7;
-                    }}
-                    .greeting {{
-                        font-size: 18px;
-                        font-weight: 700;
-                        color: #1f2937;
-                        margin-bottom: 24px;
-                    }}
-                    .button-container {{
-                        text-align: center;
-                        margin: 32px 0;
-                    }}
-                    .button {{ 
-                        display: inline-block;
-                        padding: 16px 32px;
-                        background: #8B0000;
-                        color: white;
-                        text-decoration: none;
-                        border-radius: 12px;
-                        font-weight: 800;
-                        font-size: 16px;
-                        text-transform: uppercase;
-                        letter-spacing: 1px;
-                        border: 3px solid #000000;
-                        box-shadow: 4px 4px 0px #000000;
-                        transition: all 0.2s ease;
-                    }}
-                    .button:hover {{
-                        transform: translate(-2px, -2px);
-                        box-shadow: 6px 6px 0px #000000;
-                    }}
-                    .link-section {{
-                        background-color: #f9fafb;
-                        border: 2px solid #e5e7eb;
-                        border-radius: 8px;
-                        padding: 20px;
-                        margin: 24px 0;
-                        text-align: center;
-                    }}
-                    .link-section p {{
-                        margin-bottom: 12px;
-                        font-size: 14px;
-                        color: #6b7280;
-                    }}
-                    .reset-link {{
-                        word-break: break-all;
-                        color: #1f2937;
-                        text-decoration: underline;
-                        font-weight: 600;
-                    }}
-                    .warning {{
-                        background-color: #fef3c7;
-                        border: 2px solid #f59e0b;
-                        border-radius: 8px;
-                        padding: 16px;
-                        margin: 24px 0;
-                        text-align: center;
-                    }}
-                    .warning p {{
-                        color: #92400e;
-                        font-weight: 700;
-                        margin: 0;
-                        font-size: 14px;
-                        text-transform: uppercase;
-                        letter-spacing: 0.5px;
-                    }}
-                    .footer {{ 
-                        background-color: #f9fafb;
-                        padding: 30px;
-                        text-align: center;
-                        border-top: 3px solid #000000;
-                    }}
-                    .footer p {{
-                        color: #6b7280;
-                        font-size: 12px;
-                        margin: 0;
-                        font-weight: 500;
-                        text-transform: uppercase;
-                        letter-spacing: 0.5px;
-                    }}
-                    @media (max-width: 600px) {{
-                        body {{ padding: 10px; }}
-                        .email-container {{ border-radius: 12px; }}
-                        .header {{ padding: 30px 20px; }}
-                        .header h1 {{ font-size: 24px; }}
-                        .content {{ padding: 30px 20px; }}
-                        .button {{ padding: 14px 24px; font-size: 14px; }}
-                    }}
-                </style>
-            </head>
-            <body>
-                <div class="email-container">
-                    <div class="header">
-                        <h1>PassQ</h1>
-                        <div class="subtitle">Password Manager</div>
-                    </div>
-                    <div class="content">
-                        <h2>🔐 Password Reset Request</h2>
-                        <p class="greeting">Hello {},</p>
-                        <p>We received a request to reset your password for your PassQ account. If you didn't make this request, you can safely ignore this email.</p>
-                        <p>To reset your password, click the button below:</p>
-                        
-                        <div class="button-container">
-                            <a href="{}" class="button">Reset Password</a>
-                        </div>
-                        
-                        <div class="link-section">
-                            <p>Or copy and paste this link into your browser:</p>
-                            <a href="{}" class="reset-link">{}</a>
-                        </div>
-                        
-                        <div class="warning">
-                            <p>⚠️ This link will expire in 15 minutes for security reasons</p>
-                        </div>
-                    </div>
-                    <div class="footer">
-                        <p>This email was sent by PassQ Password Manager.<br>If you didn't request this email, please ignore it.</p>
-                    </div>
-                </div>
-            </body>
-            </html>
-            "#,
-            username, reset_url, reset_url, reset_url
-        );
-
-        let _text_body = format!(
-            "Hello {},\n\n\
-            We received a request to reset your password for your PassQ account.\n\n\
-            To reset your password, visit this link:\n\
-            {}\n\n\
-            This link will expire in 15 minutes for security reasons.\n\n\
-            If you didn't request this password reset, you can safely ignore this email.\n\n\
-            Best regards,\n\
-            The PassQ Team",
-            username, reset_url
-        );
+        let (html_body, text_body) = template.render(vars)?;
 
         let email = Message::builder()
             .from(format!("{} <{}>", self.from_name, self.from_email).parse().map_err(|e| format!("Invalid from address: {}", e))?)
             .to(to_email.parse().map_err(|e| format!("Invalid to address: {}", e))?)
-            .subject("Password Reset - PassQ")
-            .header(ContentType::TEXT_HTML)
-            .body(html_body)
+            .subject(subject)
+            .multipart(MultiPart::alternative_plain_html(text_body, html_body))
             .map_err(|e| format!("Failed to build email: {}", e))?;
 
         match self.smtp_transport.send(&email) {
             Ok(_) => {
-                log::info!("Password reset email sent successfully to: {}", to_email);
+                log::info!("{:?} email sent successfully to: {}", template, to_email);
                 Ok(())
             }
             Err(e) => {
-                log::error!("Failed to send password reset email to {}: {}", to_email, e);
+                log::error!("Failed to send {:?} email to {}: {}", template, to_email, e);
                 Err(format!("Failed to send email: {}", e))
             }
         }
     }
 
+    /// Sends a password reset email
+    pub async fn send_password_reset_email(
+        &self,
+        to_email: &str,
+        username: &str,
+        reset_token: &str,
+    ) -> Result<(), String> {
+        let reset_url = reset_url(&self.frontend_base_url, reset_token);
+        let expiry_minutes = RESET_TOKEN_EXPIRY_MINUTES.to_string();
+
+        self.send_email(
+            to_email,
+            "Password Reset - PassQ",
+            EmailTemplate::PasswordReset,
+            &[
+                ("username", username),
+                ("reset_url", &reset_url),
+                ("expiry_minutes", &expiry_minutes),
+            ],
+        )
+        .await
+    }
+
+    /// Warns a user about a sign-in from a device or location we haven't seen before
+    pub async fn send_new_device_alert(
+        &self,
+        to_email: &str,
+        username: &str,
+        alert: &NewDeviceAlert,
+    ) -> Result<(), String> {
+        let time = alert.time.format("%Y-%m-%d %H:%M:%S UTC").to_string();
+        let unknown = "Unknown";
+
+        self.send_email(
+            to_email,
+            "New sign-in to your account - PassQ",
+            EmailTemplate::NewDeviceLogin,
+            &[
+                ("username", username),
+                ("reason", &alert.reason),
+                ("time", &time),
+                ("ip_address", alert.ip_address.as_deref().unwrap_or(unknown)),
+                ("location", alert.location.as_deref().unwrap_or(unknown)),
+                ("user_agent", alert.user_agent.as_deref().unwrap_or(unknown)),
+            ],
+        )
+        .await
+    }
+
+    /// Tells a user that someone shared a password or folder with them
+    pub async fn send_share_notification(
+        &self,
+        to_email: &str,
+        username: &str,
+        sharer_username: &str,
+        item_kind: &str,
+    ) -> Result<(), String> {
+        let app_url = self.frontend_base_url.trim_end_matches('/').to_string();
+
+        self.send_email(
+            to_email,
+            &format!("{} shared a {} with you - PassQ", sharer_username, item_kind),
+            EmailTemplate::ShareNotification,
+            &[
+                ("username", username),
+                ("sharer_username", sharer_username),
+                ("item_kind", item_kind),
+                ("app_url", &app_url),
+            ],
+        )
+        .await
+    }
+
+    /// Asks a user to confirm that one of their devices should be trusted
+    pub async fn send_device_trust_email(
+        &self,
+        to_email: &str,
+        username: &str,
+        device_name: &str,
+        trust_token: &str,
+        expiry_minutes: i64,
+    ) -> Result<(), String> {
+        let confirm_url = device_trust_url(&self.frontend_base_url, trust_token);
+        let expiry_minutes = expiry_minutes.to_string();
+
+        self.send_email(
+            to_email,
+            "Confirm trusted device - PassQ",
+            EmailTemplate::DeviceTrust,
+            &[
+                ("username", username),
+                ("device_name", device_name),
+                ("confirm_url", &confirm_url),
+                ("expiry_minutes", &expiry_minutes),
+            ],
+        )
+        .await
+    }
+
+    /// Sends the code that completes a sign-in flagged as unusual
+    pub async fn send_login_challenge_email(
+        &self,
+        to_email: &str,
+        username: &str,
+        code: &str,
+        ip_address: Option<&str>,
+        expiry_minutes: i64,
+    ) -> Result<(), String> {
+        let expiry_minutes = expiry_minutes.to_string();
+
+        self.send_email(
+            to_email,
+            "Your sign-in code - PassQ",
+            EmailTemplate::LoginChallenge,
+            &[
+                ("username", username),
+                ("code", code),
+                ("ip_address", ip_address.unwrap_or("an unknown address")),
+                ("expiry_minutes", &expiry_minutes),
+            ],
+        )
+        .await
+    }
+
+    /// Asks a user to confirm the new address they want their account to use
+    pub async fn send_email_change_email(
+        &self,
+        to_email: &str,
+        username: &str,
+        token: &str,
+        expiry_minutes: i64,
+    ) -> Result<(), String> {
+        let confirm_url = email_change_url(&self.frontend_base_url, token);
+        let expiry_minutes = expiry_minutes.to_string();
+
+        self.send_email(
+            to_email,
+            "Confirm your new email - PassQ",
+            EmailTemplate::EmailChange,
+            &[
+                ("username", username),
+                ("confirm_url", &confirm_url),
+                ("expiry_minutes", &expiry_minutes),
+            ],
+        )
+        .await
+    }
+
     /// Test email connectivity
     #[allow(dead_code)]
     pub async fn test_connection(&self) -> Result<(), String> {
@@ -306,4 +427,141 @@ impl EmailService {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reset_url_uses_configured_base() {
+        let token = "0123456789abcdef";
+        assert_eq!(
+            reset_url("https://vault.example.com", token),
+            "https://vault.example.com/reset-password?token=0123456789abcdef"
+        );
+        assert_eq!(
+            reset_url("https://vault.example.com/passq/", token),
+            "https://vault.example.com/passq/reset-password?token=0123456789abcdef"
+        );
+    }
+
+    #[test]
+    fn test_smtp_encryption_parse() {
+        assert_eq!(SmtpEncryption::parse("none"), Ok(SmtpEncryption::None));
+        assert_eq!(SmtpEncryption::parse("STARTTLS"), Ok(SmtpEncryption::StartTls));
+        assert_eq!(SmtpEncryption::parse(" tls "), Ok(SmtpEncryption::Tls));
+        assert!(SmtpEncryption::parse("ssl").is_err());
+    }
+
+    #[test]
+    fn test_password_reset_template_matches_fixture() {
+        let reset_url = reset_url("https://vault.example.com", "0123456789abcdef");
+        let (html, text) = EmailTemplate::PasswordReset
+            .render(&[("username", "alice"), ("reset_url", &reset_url), ("expiry_minutes", "15")])
+            .unwrap();
+
+        assert_eq!(html, include_str!("../tests/fixtures/password_reset.html"));
+        assert_eq!(text, include_str!("../tests/fixtures/password_reset.txt"));
+    }
+
+    #[test]
+    fn test_render_template() {
+        let vars = [("name", "<b>Tom & Jerry</b>")];
+        assert_eq!(render_template("Hi {{name}}!", &vars, false).unwrap(), "Hi <b>Tom & Jerry</b>!");
+        assert_eq!(
+            render_template("Hi {{name}}!", &vars, true).unwrap(),
+            "Hi &lt;b&gt;Tom &amp; Jerry&lt;/b&gt;!"
+        );
+        assert!(render_template("Hi {{missing}}", &vars, false).is_err());
+        assert!(render_template("Hi {{name", &vars, false).is_err());
+    }
+
+    #[test]
+    fn test_new_device_template_escapes_user_agent() {
+        let (html, text) = EmailTemplate::NewDeviceLogin
+            .render(&[
+                ("username", "alice"),
+                ("reason", "a new device"),
+                ("time", "2025-09-04 12:00:00 UTC"),
+                ("ip_address", "203.0.113.7"),
+                ("location", "Berlin, Germany"),
+                ("user_agent", "<script>alert(1)</script>"),
+            ])
+            .unwrap();
+
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!html.contains("<script>"));
+        assert!(text.contains("Device: <script>alert(1)</script>"));
+        assert!(text.contains("signed in to from a new device"));
+    }
+
+    #[test]
+    fn test_share_notification_template() {
+        let (html, text) = EmailTemplate::ShareNotification
+            .render(&[
+                ("username", "bob"),
+                ("sharer_username", "alice"),
+                ("item_kind", "folder"),
+                ("app_url", "https://vault.example.com"),
+            ])
+            .unwrap();
+
+        assert!(html.contains("<strong>alice</strong> shared a folder with you"));
+        assert!(html.contains("href=\"https://vault.example.com\""));
+        assert!(text.starts_with("Hello bob,"));
+    }
+
+    #[test]
+    fn test_device_trust_template() {
+        let confirm_url = device_trust_url("https://vault.example.com/", "abc123");
+        assert_eq!(confirm_url, "https://vault.example.com/trust-device?token=abc123");
+
+        let (html, text) = EmailTemplate::DeviceTrust
+            .render(&[
+                ("username", "bob"),
+                ("device_name", "<Firefox on Windows>"),
+                ("confirm_url", &confirm_url),
+                ("expiry_minutes", "60"),
+            ])
+            .unwrap();
+
+        assert!(html.contains("<strong>&lt;Firefox on Windows&gt;</strong>"));
+        assert!(html.contains("href=\"https://vault.example.com/trust-device?token=abc123\""));
+        assert!(text.contains("expire in 60 minutes"));
+    }
+
+    #[test]
+    fn test_email_change_template() {
+        let confirm_url = email_change_url("https://vault.example.com/", "abc123");
+        assert_eq!(confirm_url, "https://vault.example.com/confirm-email?token=abc123");
+
+        let (html, text) = EmailTemplate::EmailChange
+            .render(&[("username", "<bob>"), ("confirm_url", &confirm_url), ("expiry_minutes", "60")])
+            .unwrap();
+
+        assert!(html.contains("<strong>&lt;bob&gt;</strong>"));
+        assert!(html.contains("href=\"https://vault.example.com/confirm-email?token=abc123\""));
+        assert!(text.contains("expire in 60 minutes"));
+    }
+
+    #[test]
+    fn test_templates_share_the_layout() {
+        let (html, _) = EmailTemplate::ShareNotification
+            .render(&[
+                ("username", "{{title}}"),
+                ("sharer_username", "alice"),
+                ("item_kind", "password"),
+                ("app_url", "https://vault.example.com"),
+            ])
+            .unwrap();
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>Shared With You - PassQ</title>"));
+        assert!(html.contains("<div class=\"subtitle\">Password Manager</div>"));
+        assert!(html.contains("<h2>🤝 Shared With You</h2>"));
+        assert!(html.trim_end().ends_with("</html>"));
+        // Values are inserted once; placeholder syntax in them isn't expanded by the layout
+        assert!(html.contains("Hello {{title}},"));
+    }
+}
@@ -0,0 +1,121 @@
+//! Email change module for moving an account to a new address
+//!
+//! The new address is kept as pending until the link emailed to it is followed, so an account
+//! only ever uses an address its owner has shown they can read. As with password resets, only a
+//! hash of the confirmation token is stored.
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::PgConnection;
+use uuid::Uuid;
+
+use crate::models::User;
+use crate::password_reset::hash_token;
+
+/// How long the confirmation link stays valid
+pub const EMAIL_CHANGE_EXPIRY_MINUTES: i64 = 60;
+
+/// Whether the account's email is owned by its SSO provider
+///
+/// Accounts created through SSO are found by the provider's address on every login, so changing
+/// it here would desync them. Password accounts that later linked SSO ("hybrid") keep the
+/// provider's address on the linked account and may change their own.
+pub fn managed_by_sso(user: &User) -> bool {
+    sso_owns_email(user.auth_method.as_deref(), user.is_sso_user)
+}
+
+fn sso_owns_email(auth_method: Option<&str>, is_sso_user: Option<bool>) -> bool {
+    is_sso_user == Some(true) && !matches!(auth_method, Some("password") | Some("hybrid"))
+}
+
+/// Whether another account already uses the address
+pub fn email_in_use(conn: &mut PgConnection, email: &str, user_id: Uuid) -> QueryResult<bool> {
+    use crate::schema::users;
+
+    diesel::select(diesel::dsl::exists(
+        users::table.filter(users::email.eq(email)).filter(users::id.ne(user_id)),
+    ))
+    .get_result(conn)
+}
+
+/// Stores the address waiting for confirmation, replacing any earlier pending change
+pub fn store_pending(conn: &mut PgConnection, user_id: Uuid, new_email: &str, token: &str, now: NaiveDateTime) -> QueryResult<()> {
+    use crate::schema::users;
+
+    diesel::update(users::table.find(user_id))
+        .set((
+            users::pending_email.eq(Some(new_email)),
+            users::email_change_token.eq(Some(hash_token(token))),
+            users::email_change_expires_at.eq(Some(now + Duration::minutes(EMAIL_CHANGE_EXPIRY_MINUTES))),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// What following a confirmation link did
+#[derive(Debug, PartialEq)]
+pub enum ConfirmOutcome {
+    Changed { user_id: Uuid, old_email: String, new_email: String },
+    Expired,
+    Invalid,
+    /// Another account took the address while the change was pending
+    EmailTaken,
+}
+
+/// Switches the account to its pending address if the token matches and hasn't expired
+pub fn confirm(conn: &mut PgConnection, token: &str, now: NaiveDateTime) -> QueryResult<ConfirmOutcome> {
+    use crate::schema::users;
+
+    let outcome = conn.transaction(|conn| {
+        let pending = users::table
+            .filter(users::email_change_token.eq(hash_token(token)))
+            .select((users::id, users::email, users::pending_email, users::email_change_expires_at))
+            .for_update()
+            .first::<(Uuid, String, Option<String>, Option<NaiveDateTime>)>(conn)
+            .optional()?;
+
+        let (user_id, old_email, new_email) = match pending {
+            Some((user_id, old_email, Some(new_email), Some(expires_at))) => {
+                if now >= expires_at {
+                    return Ok(ConfirmOutcome::Expired);
+                }
+                (user_id, old_email, new_email)
+            }
+            _ => return Ok(ConfirmOutcome::Invalid),
+        };
+        if email_in_use(conn, &new_email, user_id)? {
+            return Ok(ConfirmOutcome::EmailTaken);
+        }
+
+        diesel::update(users::table.find(user_id))
+            .set((
+                users::email.eq(&new_email),
+                users::pending_email.eq(None::<String>),
+                users::email_change_token.eq(None::<String>),
+                users::email_change_expires_at.eq(None::<NaiveDateTime>),
+            ))
+            .execute(conn)?;
+        Ok(ConfirmOutcome::Changed { user_id, old_email, new_email })
+    });
+
+    // Another account can still take the address between the check and the update
+    match outcome {
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(ConfirmOutcome::EmailTaken),
+        outcome => outcome,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sso_owns_email() {
+        assert!(!sso_owns_email(Some("password"), Some(false)));
+        assert!(!sso_owns_email(None, None));
+        assert!(!sso_owns_email(Some("hybrid"), Some(true)));
+        assert!(sso_owns_email(Some("google"), Some(true)));
+        assert!(sso_owns_email(Some("oidc"), Some(true)));
+    }
+}
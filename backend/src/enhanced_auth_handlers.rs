@@ -10,7 +10,7 @@ use crate::{
     auth::{self},
     db::DbPool,
     models::{RefreshTokenRequest, User},
-    token_management::{TokenManager, EnhancedClaims},
+    token_management::{ActiveSession, TokenManager, EnhancedClaims},
     ip_controls,
     mfa,
     schema::users,
@@ -55,6 +55,45 @@ pub struct EnhancedLogoutRequest {
     pub reason: Option<String>,
 }
 
+/// A session as shown on the "manage devices" screen
+#[derive(Serialize)]
+pub struct SessionSummary {
+    pub session_id: String,
+    /// The custom device name if one was set, otherwise a description of the user agent
+    pub label: String,
+    pub device_name: Option<String>,
+    pub device_type: Option<String>,
+    pub browser: Option<&'static str>,
+    pub os: Option<&'static str>,
+    pub ip_address: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_activity: chrono::DateTime<chrono::Utc>,
+    pub is_current: bool,
+}
+
+impl SessionSummary {
+    fn new(session: ActiveSession, current_session_id: Option<&str>) -> Self {
+        let device = session.user_agent.as_deref().map(crate::user_agent::parse);
+        let label = match (&session.device_name, &device) {
+            (Some(name), _) => name.clone(),
+            (None, Some(device)) => device.label(),
+            (None, None) => "Unknown device".to_string(),
+        };
+        Self {
+            is_current: current_session_id == Some(session.session_id.as_str()),
+            label,
+            device_type: session.device_type.or_else(|| device.as_ref().map(|d| d.device_type.to_string())),
+            browser: device.as_ref().and_then(|d| d.browser),
+            os: device.as_ref().and_then(|d| d.os),
+            session_id: session.session_id,
+            device_name: session.device_name,
+            ip_address: session.ip_address,
+            created_at: session.created_at,
+            last_activity: session.last_activity,
+        }
+    }
+}
+
 /// Enhanced login handler with advanced token management
 pub async fn enhanced_login(
     _req: HttpRequest,
@@ -134,7 +173,13 @@ pub async fn enhanced_login(
             // Check if MFA is required (check if user has mfa_secret)
             if let Some(ref mfa_secret) = user.mfa_secret {
                 if let Some(ref mfa_code) = user_data.mfa_code {
-                    if !mfa::verify_totp_code(mfa_secret, mfa_code) {
+                    // Fall back to single-use recovery codes when the TOTP code doesn't match
+                    let valid = mfa::verify_login_code(&mut conn, user.id, mfa_secret, mfa_code)
+                        .map_err(|e| {
+                            log::error!("Database error: {}", e);
+                            actix_web::error::ErrorInternalServerError("Database error")
+                        })?;
+                    if !valid {
                         return Ok(HttpResponse::Unauthorized().json(EnhancedLoginResponse {
                             success: false,
                             message: "Invalid MFA code".to_string(),
@@ -158,6 +203,8 @@ pub async fn enhanced_login(
                 }
             }
             
+            auth::upgrade_legacy_password_hash(&mut conn, user.id, &user_data.password, &user.password_hash);
+            
             // Generate session ID
             let session_id = Uuid::new_v4().to_string();
             
@@ -165,7 +212,7 @@ pub async fn enhanced_login(
             match token_manager.generate_enhanced_token_pair(
                 user.id,
                 session_id.clone(),
-                None, // device_id - could be extracted from headers
+                crate::token_management::device_id_from_request(&_req),
                 ip_address.clone(),
                 user_agent.clone(),
             ) {
@@ -224,7 +271,7 @@ pub async fn enhanced_refresh_token(
     
     match token_manager.refresh_token_pair(
         &refresh_data.refresh_token,
-        None, // device_id
+        crate::token_management::device_id_from_request(&_req),
         ip_address,
         user_agent,
     ) {
@@ -261,11 +308,17 @@ pub async fn enhanced_logout(
     
     if logout_data.revoke_all_sessions.unwrap_or(false) {
         // Revoke all user sessions
-        token_manager.revoke_all_user_tokens(_claims.sub, reason);
+        if let Err(e) = token_manager.revoke_all_user_tokens(_claims.sub, reason) {
+            log::error!("Failed to revoke sessions for user {}: {}", _claims.sub, e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "success": false,
+                "message": "Failed to revoke sessions"
+            })));
+        }
         log::info!("All sessions revoked for user: {}", _claims.sub);
     } else {
         // Revoke current session only
-        let _ = token_manager.revoke_session(&_claims.session_id, reason);
+        let _ = token_manager.revoke_session(_claims.sub, &_claims.session_id, reason);
         log::info!("Session {} revoked for user: {}", _claims.session_id, _claims.sub);
     }
     
@@ -298,9 +351,14 @@ pub async fn enhanced_verify_auth(
                     }
                     Err(e) => {
                         log::warn!("Token validation failed: {:?}", e);
+                        // Also returned when the session timed out for inactivity
+                        let error = match e.kind() {
+                            jsonwebtoken::errors::ErrorKind::ExpiredSignature => "Session expired",
+                            _ => "Invalid or expired token",
+                        };
                         Ok(HttpResponse::Unauthorized().json(serde_json::json!({
                             "valid": false,
-                            "error": "Invalid or expired token"
+                            "error": error
                         })))
                     }
                 }
@@ -324,12 +382,21 @@ pub async fn enhanced_verify_auth(
     }
 }
 
-/// Get user sessions handler
+/// Get user sessions handler, most recently used first
 pub async fn get_user_sessions(
+    req: HttpRequest,
     token_manager: web::Data<Arc<TokenManager>>,
-    claims: web::ReqData<EnhancedClaims>,
 ) -> ActixResult<HttpResponse> {
-    let sessions = token_manager.get_user_sessions(claims.sub);
+    let user_id = auth::authenticate(&req)?;
+    // The token was just accepted, so it decodes and names the caller's session
+    let current_session_id = auth::token_from_request(&req)
+        .and_then(|token| crate::token_management::decode_enhanced_token(&token).ok())
+        .map(|claims| claims.session_id);
+    
+    let sessions: Vec<SessionSummary> = token_manager.get_user_sessions(user_id)
+        .into_iter()
+        .map(|session| SessionSummary::new(session, current_session_id.as_deref()))
+        .collect();
     
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "success": true,
@@ -339,14 +406,16 @@ pub async fn get_user_sessions(
 
 /// Token statistics handler (admin only)
 pub async fn get_token_statistics(
+    req: HttpRequest,
     token_manager: web::Data<Arc<TokenManager>>,
-    _claims: web::ReqData<EnhancedClaims>,
 ) -> ActixResult<HttpResponse> {
-    // TODO: Add admin role check
+    if let Err(response) = crate::auth::require_admin(&req) {
+        return Ok(response);
+    }
     let stats = token_manager.get_token_statistics();
     
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "success": true,
         "statistics": stats
     })))
-}
\ No newline at end of file
+}
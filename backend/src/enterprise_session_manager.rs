@@ -8,7 +8,7 @@
 //! - Real-time security event detection
 //! - Automated threat response
 
-use actix_web::{web, HttpResponse, Result as ActixResult};
+use actix_web::{web, HttpRequest, HttpResponse, Result as ActixResult};
 use chrono::{Duration, Utc};
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
@@ -19,12 +19,28 @@ use std::env;
 use std::sync::Arc;
 use uuid::Uuid;
 use crate::auth::TokenPair;
+use crate::email::{EmailService, NewDeviceAlert};
 use crate::schema::*;
 use log::{info, error};
 
 /// Database connection pool type
 type DbPool = Pool<ConnectionManager<PgConnection>>;
 
+/// Formats the session's approximate location as "city, region, country"
+fn session_location(session: &EnterpriseSession) -> Option<String> {
+    let parts: Vec<&str> = [&session.location_city, &session.location_region, &session.location_country]
+        .into_iter()
+        .filter_map(|part| part.as_deref())
+        .filter(|part| !part.is_empty())
+        .collect();
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(", "))
+    }
+}
+
 /// Enhanced session information with enterprise features
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable, Selectable)]
 #[diesel(table_name = active_sessions)]
@@ -128,6 +144,76 @@ pub struct SessionLimits {
     pub updated_at: chrono::DateTime<Utc>,
 }
 
+impl SessionLimits {
+    /// Limits applied to users without a stored policy
+    pub fn default_for(user_id: Uuid) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            user_id,
+            max_concurrent_sessions: 5,
+            max_sessions_per_device: 3,
+            session_timeout_minutes: 15,
+            refresh_timeout_days: 7,
+            enforce_single_session: Some(false),
+            allow_concurrent_mobile: Some(true),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+/// Select the sessions a policy would terminate, oldest first, with the limit each violates
+///
+/// With `incoming_device` set, a slot is reserved for a new session on that device (as when
+/// enforcing limits at login) and only that device's sessions are checked against the
+/// per-device limit. With `None`, the policy is applied to the sessions as they stand.
+pub fn sessions_exceeding_limits<'a>(
+    max_concurrent_sessions: i32,
+    max_sessions_per_device: i32,
+    sessions: &'a [EnterpriseSession],
+    incoming_device: Option<Option<&str>>,
+) -> Vec<(&'a EnterpriseSession, &'static str)> {
+    let reserved = if incoming_device.is_some() { 1 } else { 0 };
+    
+    let mut by_activity: Vec<&EnterpriseSession> = sessions.iter().collect();
+    by_activity.sort_by_key(|session| session.last_activity);
+    
+    let mut selected: Vec<(&EnterpriseSession, &'static str)> = Vec::new();
+    
+    // Enforce concurrent session limit
+    let excess = sessions.len() as i64 + reserved - max_concurrent_sessions as i64;
+    if excess > 0 {
+        selected.extend(by_activity.iter().take(excess as usize).map(|session| (*session, "concurrent_session_limit")));
+    }
+    
+    // Check device-specific limits against the sessions that remain
+    let mut remaining_by_device: HashMap<&str, Vec<&EnterpriseSession>> = HashMap::new();
+    for session in &by_activity {
+        if selected.iter().any(|(s, _)| s.session_id == session.session_id) {
+            continue;
+        }
+        if let Some(ref fingerprint) = session.device_fingerprint {
+            remaining_by_device.entry(fingerprint.as_str()).or_default().push(session);
+        }
+    }
+    
+    let devices: Vec<&str> = match incoming_device {
+        Some(Some(fingerprint)) => vec![fingerprint],
+        Some(None) => vec![],
+        None => remaining_by_device.keys().copied().collect(),
+    };
+    
+    for device in devices {
+        let device_sessions = remaining_by_device.get(device).cloned().unwrap_or_default();
+        let excess = device_sessions.len() as i64 + reserved - max_sessions_per_device as i64;
+        if excess > 0 {
+            selected.extend(device_sessions.into_iter().take(excess as usize).map(|session| (session, "device_session_limit")));
+        }
+    }
+    
+    selected
+}
+
 /// Trusted device information
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
 #[diesel(table_name = trusted_devices)]
@@ -149,6 +235,57 @@ pub struct TrustedDevice {
     pub notes: Option<String>,
     pub created_at: chrono::DateTime<Utc>,
     pub updated_at: chrono::DateTime<Utc>,
+    #[serde(skip)]
+    pub trust_token: Option<String>,
+    #[serde(skip)]
+    pub trust_token_expires_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// How long the link in a device trust email stays valid
+pub const DEVICE_TRUST_TOKEN_EXPIRY_MINUTES: i64 = 60;
+
+/// Trust score given to a device the user confirmed
+const TRUSTED_DEVICE_SCORE: i32 = 100;
+
+/// Outcome of asking to trust one of the user's devices
+#[derive(Debug)]
+pub enum DeviceTrustRequest {
+    NotFound,
+    AlreadyTrusted,
+    /// The confirmation token to email to the user
+    Pending {
+        token: String,
+        device_name: String,
+        email: String,
+        username: String,
+    },
+}
+
+/// Device trust confirmation request
+#[derive(Debug, Deserialize)]
+pub struct ConfirmDeviceTrustRequest {
+    pub token: String,
+}
+
+/// Generates the token a user confirms a trusted device with
+fn generate_trust_token() -> Result<String, String> {
+    use ring::rand::{SecureRandom, SystemRandom};
+
+    let mut token_bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut token_bytes)
+        .map_err(|_| "Failed to generate secure token".to_string())?;
+    Ok(hex::encode(token_bytes))
+}
+
+/// How much the device's trust level adds to (or takes off) a session's risk score
+fn device_trust_risk(trust_level: &str) -> i32 {
+    match trust_level {
+        "trusted" => -20,
+        "suspicious" => 25,
+        "blocked" => 100,
+        _ => 0,
+    }
 }
 
 /// Session monitoring rule
@@ -200,6 +337,94 @@ pub struct CreateSessionRequest {
     pub location: Option<serde_json::Value>,
 }
 
+/// Session rename request
+#[derive(Debug, Deserialize)]
+pub struct RenameSessionRequest {
+    pub name: String,
+}
+
+/// Upper bounds accepted when a user configures their session limits
+const MAX_CONCURRENT_SESSIONS_CAP: i32 = 100;
+const SESSION_TIMEOUT_MINUTES_CAP: i32 = 7 * 24 * 60;
+const REFRESH_TIMEOUT_DAYS_CAP: i32 = 90;
+
+/// Session limits to store for a user
+#[derive(Debug, Deserialize)]
+pub struct SessionLimitsUpdate {
+    pub max_concurrent_sessions: i32,
+    pub max_sessions_per_device: i32,
+    pub session_timeout_minutes: i32,
+    pub refresh_timeout_days: i32,
+    #[serde(default)]
+    pub enforce_single_session: bool,
+    #[serde(default = "default_allow_concurrent_mobile")]
+    pub allow_concurrent_mobile: bool,
+}
+
+fn default_allow_concurrent_mobile() -> bool {
+    true
+}
+
+impl SessionLimitsUpdate {
+    /// Check every limit is positive and below its cap
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_CONCURRENT_SESSIONS_CAP).contains(&self.max_concurrent_sessions) {
+            return Err(format!("max_concurrent_sessions must be between 1 and {}", MAX_CONCURRENT_SESSIONS_CAP));
+        }
+        if !(1..=self.max_concurrent_sessions).contains(&self.max_sessions_per_device) {
+            return Err("max_sessions_per_device must be between 1 and max_concurrent_sessions".to_string());
+        }
+        if !(1..=SESSION_TIMEOUT_MINUTES_CAP).contains(&self.session_timeout_minutes) {
+            return Err(format!("session_timeout_minutes must be between 1 and {}", SESSION_TIMEOUT_MINUTES_CAP));
+        }
+        if !(1..=REFRESH_TIMEOUT_DAYS_CAP).contains(&self.refresh_timeout_days) {
+            return Err(format!("refresh_timeout_days must be between 1 and {}", REFRESH_TIMEOUT_DAYS_CAP));
+        }
+        Ok(())
+    }
+}
+
+/// Query naming the user whose session limits an admin is managing
+#[derive(Debug, Deserialize)]
+pub struct SessionLimitsQuery {
+    pub user_id: Option<Uuid>,
+}
+
+/// Proposed session limit policy to evaluate without applying it
+#[derive(Debug, Deserialize)]
+pub struct SessionLimitPolicy {
+    pub max_concurrent_sessions: i32,
+    pub max_sessions_per_device: i32,
+    pub user_id: Option<Uuid>, // Restrict the simulation to one user
+}
+
+/// Session that a simulated policy would terminate
+#[derive(Debug, Serialize)]
+pub struct SimulatedTermination {
+    pub session_id: String,
+    pub device_name: Option<String>,
+    pub device_fingerprint: Option<String>,
+    pub last_activity: chrono::DateTime<Utc>,
+    pub reason: String,
+}
+
+/// Per-user result of a session limit simulation
+#[derive(Debug, Serialize)]
+pub struct UserSessionLimitImpact {
+    pub user_id: Uuid,
+    pub active_sessions: usize,
+    pub sessions_to_terminate: Vec<SimulatedTermination>,
+}
+
+/// Result of a session limit simulation
+#[derive(Debug, Serialize)]
+pub struct SessionLimitSimulation {
+    pub users_evaluated: usize,
+    pub users_affected: usize,
+    pub sessions_to_terminate: usize,
+    pub users: Vec<UserSessionLimitImpact>,
+}
+
 /// Session validation response
 #[derive(Debug, Serialize)]
 pub struct SessionValidationResponse {
@@ -211,9 +436,80 @@ pub struct SessionValidationResponse {
     pub actions_required: Vec<String>,
 }
 
+/// What a password login looked like, as far as its risk is concerned
+#[derive(Debug, Clone, Default)]
+pub struct LoginRiskSignals {
+    /// Trust level of the device named by the client, `None` when no device id was sent
+    pub device_trust_level: Option<String>,
+    /// Whether the user has signed in from this IP address before
+    pub known_ip: bool,
+    /// Failed logins for the account within the lockout window
+    pub recent_failures: usize,
+    /// Severities of the user's unresolved security events from the last week
+    pub open_event_severities: Vec<String>,
+}
+
+/// Risk of a password login and what should happen before it completes
+#[derive(Debug, Clone, Serialize)]
+pub struct LoginRiskAssessment {
+    pub risk_score: i32,
+    pub trust_level: String,
+    pub actions_required: Vec<String>,
+}
+
+impl LoginRiskAssessment {
+    /// Whether the login needs a second factor before tokens are issued
+    pub fn requires_mfa(&self) -> bool {
+        self.actions_required.iter().any(|action| action == "require_mfa")
+    }
+}
+
+/// Risk score of a login from its signals, between 0 and 100
+fn login_risk_score(signals: &LoginRiskSignals) -> i32 {
+    let mut risk_score = match signals.device_trust_level.as_deref() {
+        None => 20,           // No device id
+        Some("unknown") => 15, // Device never seen
+        Some(trust_level) => device_trust_risk(trust_level),
+    };
+    
+    if !signals.known_ip {
+        risk_score += 25;
+    }
+    
+    if signals.recent_failures >= 3 {
+        risk_score += 25;
+    } else if signals.recent_failures > 0 {
+        risk_score += 10;
+    }
+    
+    for severity in &signals.open_event_severities {
+        risk_score += match severity.as_str() {
+            "critical" => 40,
+            "high" => 25,
+            _ => 0,
+        };
+    }
+    
+    risk_score.clamp(0, 100)
+}
+
+/// Actions a risk score calls for
+fn risk_actions(risk_score: i32) -> Vec<String> {
+    if risk_score > 80 {
+        vec!["require_mfa".to_string(), "notify_user".to_string()]
+    } else if risk_score > 60 {
+        vec!["require_mfa".to_string()]
+    } else if risk_score > 40 {
+        vec!["monitor_closely".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
 /// Enterprise Session Manager
 pub struct EnterpriseSessionManager {
     db_pool: DbPool,
+    read_pool: DbPool,
     jwt_secret: String,
     issuer: String,
     audience: String,
@@ -221,16 +517,17 @@ pub struct EnterpriseSessionManager {
 
 impl EnterpriseSessionManager {
     /// Create a new enterprise session manager
-    #[allow(dead_code)]
     pub fn new(db_pool: DbPool) -> Self {
+        // Checked at startup, so a missing secret here is a programming error
         let jwt_secret = env::var("JWT_SECRET")
-            .unwrap_or_else(|_| "your-secret-key".to_string());
+            .expect("JWT_SECRET must be set");
         let issuer = env::var("JWT_ISSUER")
             .unwrap_or_else(|_| "passq-api".to_string());
         let audience = env::var("JWT_AUDIENCE")
             .unwrap_or_else(|_| "passq-client".to_string());
 
         Self {
+            read_pool: db_pool.clone(),
             db_pool,
             jwt_secret,
             issuer,
@@ -238,6 +535,12 @@ impl EnterpriseSessionManager {
         }
     }
 
+    /// Use a separate (possibly replica-backed) pool for read-only analytics queries
+    pub fn with_read_pool(mut self, read_pool: &crate::db::ReadPool) -> Self {
+        self.read_pool = DbPool::clone(read_pool);
+        self
+    }
+
     /// Create a new enterprise session with comprehensive tracking
     pub async fn create_session(
         &self,
@@ -252,7 +555,7 @@ impl EnterpriseSessionManager {
         self.enforce_session_limits(&mut conn, request.user_id, &request.device_fingerprint).await?;
         
         // Update device trust
-        self.update_device_trust(&mut conn, &request).await?;
+        let is_new_device = self.update_device_trust(&mut conn, &request).await?;
         
         // Generate token JTIs
         let access_jti = Uuid::new_v4().to_string();
@@ -330,7 +633,7 @@ impl EnterpriseSessionManager {
         }).await?;
         
         // Check for security events
-        self.check_security_events(&mut conn, &inserted_session, &request).await?;
+        self.check_security_events(&mut conn, &inserted_session, &request, is_new_device).await?;
         
         info!("Created enterprise session {} for user {}", session_id, request.user_id);
         
@@ -379,12 +682,12 @@ impl EnterpriseSessionManager {
                 // Get recent security events
                 let security_events = self.get_session_security_events(&mut conn, &claims.session_id).await?;
                 
-                // Calculate risk score
-                let risk_score = self.calculate_risk_score(&session, &security_events).await;
-                
                 // Get trust level
                 let trust_level = self.get_device_trust_level(&mut conn, &session.user_id, &session.device_fingerprint).await?;
                 
+                // Calculate risk score
+                let risk_score = self.calculate_risk_score(&session, &security_events, &trust_level).await;
+                
                 // Determine required actions
                 let actions_required = self.determine_required_actions(&session, &security_events, risk_score).await;
                 
@@ -459,13 +762,348 @@ impl EnterpriseSessionManager {
         Ok(())
     }
     
+    /// Revoke every active session of a user, returning how many were terminated
+    pub async fn revoke_user_sessions(
+        &self,
+        user_id: Uuid,
+        reason: &str,
+        revoked_by: Option<Uuid>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let session_ids = {
+            let mut conn = self.db_pool.get()?;
+            active_sessions::table
+                .filter(active_sessions::user_id.eq(user_id))
+                .filter(active_sessions::is_active.eq(true))
+                .select(active_sessions::session_id)
+                .load::<String>(&mut conn)?
+        };
+        
+        for session_id in &session_ids {
+            self.revoke_session(session_id, reason, revoked_by).await?;
+        }
+        
+        Ok(session_ids.len())
+    }
+    
+    /// List a user's active sessions, most recently used first
+    pub async fn list_user_sessions(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<EnterpriseSession>, Box<dyn std::error::Error>> {
+        let mut conn = self.read_pool.get()?;
+        
+        let sessions = active_sessions::table
+            .filter(active_sessions::user_id.eq(user_id))
+            .filter(active_sessions::is_active.eq(true))
+            .filter(active_sessions::expires_at.gt(Utc::now()))
+            .order(active_sessions::last_activity.desc())
+            .load::<EnterpriseSession>(&mut conn)?;
+        
+        Ok(sessions)
+    }
+    
+    /// Set the user-facing name of one of the user's sessions, returning false if not found
+    pub async fn rename_session(
+        &self,
+        user_id: Uuid,
+        session_id: &str,
+        name: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut conn = self.db_pool.get()?;
+        
+        let updated = diesel::update(active_sessions::table)
+            .filter(active_sessions::session_id.eq(session_id))
+            .filter(active_sessions::user_id.eq(user_id))
+            .filter(active_sessions::is_active.eq(true))
+            .set(active_sessions::device_name.eq(name))
+            .execute(&mut conn)?;
+        
+        if updated > 0 {
+            info!("Renamed session {} for user {}", session_id, user_id);
+        }
+        
+        Ok(updated > 0)
+    }
+    
+    /// The user's stored session limits, or the defaults if they have none
+    pub async fn get_session_limits(&self, user_id: Uuid) -> Result<SessionLimits, Box<dyn std::error::Error>> {
+        let mut conn = self.read_pool.get()?;
+        
+        let limits: Option<SessionLimits> = session_limits::table
+            .filter(session_limits::user_id.eq(user_id))
+            .first(&mut conn)
+            .optional()?;
+        
+        Ok(limits.unwrap_or_else(|| SessionLimits::default_for(user_id)))
+    }
+    
+    /// Create or replace the user's session limits
+    pub async fn set_session_limits(
+        &self,
+        user_id: Uuid,
+        update: &SessionLimitsUpdate,
+    ) -> Result<SessionLimits, Box<dyn std::error::Error>> {
+        let mut conn = self.db_pool.get()?;
+        let now = Utc::now();
+        
+        let limits = SessionLimits {
+            id: Uuid::new_v4(),
+            user_id,
+            max_concurrent_sessions: update.max_concurrent_sessions,
+            max_sessions_per_device: update.max_sessions_per_device,
+            session_timeout_minutes: update.session_timeout_minutes,
+            refresh_timeout_days: update.refresh_timeout_days,
+            enforce_single_session: Some(update.enforce_single_session),
+            allow_concurrent_mobile: Some(update.allow_concurrent_mobile),
+            created_at: now,
+            updated_at: now,
+        };
+        
+        let stored = diesel::insert_into(session_limits::table)
+            .values(&limits)
+            .on_conflict(session_limits::user_id)
+            .do_update()
+            .set((
+                session_limits::max_concurrent_sessions.eq(limits.max_concurrent_sessions),
+                session_limits::max_sessions_per_device.eq(limits.max_sessions_per_device),
+                session_limits::session_timeout_minutes.eq(limits.session_timeout_minutes),
+                session_limits::refresh_timeout_days.eq(limits.refresh_timeout_days),
+                session_limits::enforce_single_session.eq(limits.enforce_single_session),
+                session_limits::allow_concurrent_mobile.eq(limits.allow_concurrent_mobile),
+                session_limits::updated_at.eq(now),
+            ))
+            .get_result(&mut conn)?;
+        
+        info!("Updated session limits for user {}", user_id);
+        Ok(stored)
+    }
+    
+    /// Assess the risk of a password login that has just passed its password check
+    pub async fn assess_login(
+        &self,
+        user_id: Uuid,
+        device_fingerprint: Option<&str>,
+        ip_address: Option<&str>,
+        recent_failures: usize,
+    ) -> Result<LoginRiskAssessment, Box<dyn std::error::Error>> {
+        let mut conn = self.read_pool.get()?;
+        
+        let device_fingerprint = device_fingerprint.map(|fp| fp.to_string());
+        let device_trust_level = match device_fingerprint {
+            Some(_) => Some(self.get_device_trust_level(&mut conn, &user_id, &device_fingerprint).await?),
+            None => None,
+        };
+        
+        // Earlier logins are in the audit log; enterprise sessions record their own IPs
+        let known_ip = match ip_address {
+            Some(ip) => {
+                diesel::select(diesel::dsl::exists(
+                    audit_logs::table
+                        .filter(audit_logs::user_id.eq(user_id))
+                        .filter(audit_logs::event_type.eq("UserLogin"))
+                        .filter(audit_logs::ip_address.eq(ip))
+                )).get_result::<bool>(&mut conn)?
+                    || diesel::select(diesel::dsl::exists(
+                        active_sessions::table
+                            .filter(active_sessions::user_id.eq(user_id))
+                            .filter(active_sessions::ip_address.eq(ip))
+                    )).get_result::<bool>(&mut conn)?
+            }
+            None => false,
+        };
+        
+        let open_event_severities = session_security_events::table
+            .filter(session_security_events::user_id.eq(user_id))
+            .filter(session_security_events::resolved.is_distinct_from(true))
+            .filter(session_security_events::timestamp.gt(Utc::now() - Duration::days(7)))
+            .select(session_security_events::severity)
+            .load::<String>(&mut conn)?;
+        
+        let signals = LoginRiskSignals {
+            device_trust_level,
+            known_ip,
+            recent_failures,
+            open_event_severities,
+        };
+        let risk_score = login_risk_score(&signals);
+        
+        Ok(LoginRiskAssessment {
+            risk_score,
+            trust_level: signals.device_trust_level.unwrap_or_else(|| "unknown".to_string()),
+            actions_required: risk_actions(risk_score),
+        })
+    }
+    
+    /// The devices the user has signed in from, most recently seen first
+    pub async fn list_devices(&self, user_id: Uuid) -> Result<Vec<TrustedDevice>, Box<dyn std::error::Error>> {
+        let mut conn = self.read_pool.get()?;
+        
+        let devices = trusted_devices::table
+            .filter(trusted_devices::user_id.eq(user_id))
+            .order(trusted_devices::last_seen.desc())
+            .load(&mut conn)?;
+        
+        Ok(devices)
+    }
+    
+    /// Start trusting one of the user's devices, storing a token they confirm by email
+    pub async fn request_device_trust(
+        &self,
+        user_id: Uuid,
+        device_fingerprint: &str,
+    ) -> Result<DeviceTrustRequest, Box<dyn std::error::Error>> {
+        let mut conn = self.db_pool.get()?;
+        
+        let device: Option<TrustedDevice> = trusted_devices::table
+            .filter(trusted_devices::user_id.eq(user_id))
+            .filter(trusted_devices::device_fingerprint.eq(device_fingerprint))
+            .first(&mut conn)
+            .optional()?;
+        
+        let device = match device {
+            Some(device) if device.trust_level == "trusted" => return Ok(DeviceTrustRequest::AlreadyTrusted),
+            Some(device) => device,
+            None => return Ok(DeviceTrustRequest::NotFound),
+        };
+        
+        let (email, username) = users::table
+            .find(user_id)
+            .select((users::email, users::username))
+            .first::<(String, String)>(&mut conn)?;
+        
+        // A new request replaces any earlier link; like reset tokens, only a hash is stored
+        let token = generate_trust_token()?;
+        diesel::update(trusted_devices::table.find(device.id))
+            .set((
+                trusted_devices::trust_token.eq(Some(crate::password_reset::hash_token(&token))),
+                trusted_devices::trust_token_expires_at.eq(Some(Utc::now() + Duration::minutes(DEVICE_TRUST_TOKEN_EXPIRY_MINUTES))),
+            ))
+            .execute(&mut conn)?;
+        
+        Ok(DeviceTrustRequest::Pending {
+            token,
+            device_name: device.device_name.unwrap_or(device.device_fingerprint),
+            email,
+            username,
+        })
+    }
+    
+    /// Mark the device a trust token was issued for as trusted, if the token is still valid
+    pub async fn confirm_device_trust(
+        &self,
+        user_id: Uuid,
+        token: &str,
+    ) -> Result<Option<TrustedDevice>, Box<dyn std::error::Error>> {
+        let mut conn = self.db_pool.get()?;
+        
+        let device = diesel::update(trusted_devices::table)
+            .filter(trusted_devices::user_id.eq(user_id))
+            .filter(trusted_devices::trust_token.eq(crate::password_reset::hash_token(token)))
+            .filter(trusted_devices::trust_token_expires_at.gt(Utc::now()))
+            .set((
+                trusted_devices::trust_level.eq("trusted"),
+                trusted_devices::trust_score.eq(Some(TRUSTED_DEVICE_SCORE)),
+                trusted_devices::trust_token.eq(None::<String>),
+                trusted_devices::trust_token_expires_at.eq(None::<chrono::DateTime<Utc>>),
+            ))
+            .get_result::<TrustedDevice>(&mut conn)
+            .optional()?;
+        
+        if let Some(ref device) = device {
+            info!("Device {} of user {} is now trusted", device.device_fingerprint, user_id);
+        }
+        
+        Ok(device)
+    }
+    
+    /// Forget one of the user's devices, returning false if they had no such device
+    pub async fn forget_device(
+        &self,
+        user_id: Uuid,
+        device_fingerprint: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut conn = self.db_pool.get()?;
+        
+        let deleted = diesel::delete(
+            trusted_devices::table
+                .filter(trusted_devices::user_id.eq(user_id))
+                .filter(trusted_devices::device_fingerprint.eq(device_fingerprint))
+        )
+            .execute(&mut conn)?;
+        
+        Ok(deleted > 0)
+    }
+    
+    /// Report which sessions a proposed policy would terminate, without revoking anything
+    pub async fn simulate_session_limits(
+        &self,
+        policy: &SessionLimitPolicy,
+    ) -> Result<SessionLimitSimulation, Box<dyn std::error::Error>> {
+        let mut conn = self.read_pool.get()?;
+        
+        let mut query = active_sessions::table
+            .filter(active_sessions::is_active.eq(true))
+            .filter(active_sessions::expires_at.gt(Utc::now()))
+            .into_boxed();
+        
+        if let Some(uid) = policy.user_id {
+            query = query.filter(active_sessions::user_id.eq(uid));
+        }
+        
+        let sessions: Vec<EnterpriseSession> = query.load(&mut conn)?;
+        
+        let mut sessions_by_user: HashMap<Uuid, Vec<EnterpriseSession>> = HashMap::new();
+        for session in sessions {
+            sessions_by_user.entry(session.user_id).or_default().push(session);
+        }
+        
+        let users_evaluated = sessions_by_user.len();
+        let mut users = Vec::new();
+        for (user_id, user_sessions) in &sessions_by_user {
+            let excess = sessions_exceeding_limits(
+                policy.max_concurrent_sessions,
+                policy.max_sessions_per_device,
+                user_sessions,
+                None,
+            );
+            
+            if excess.is_empty() {
+                continue;
+            }
+            
+            users.push(UserSessionLimitImpact {
+                user_id: *user_id,
+                active_sessions: user_sessions.len(),
+                sessions_to_terminate: excess
+                    .into_iter()
+                    .map(|(session, reason)| SimulatedTermination {
+                        session_id: session.session_id.clone(),
+                        device_name: session.device_name.clone(),
+                        device_fingerprint: session.device_fingerprint.clone(),
+                        last_activity: session.last_activity,
+                        reason: reason.to_string(),
+                    })
+                    .collect(),
+            });
+        }
+        
+        users.sort_by_key(|u| std::cmp::Reverse(u.sessions_to_terminate.len()));
+        
+        Ok(SessionLimitSimulation {
+            users_evaluated,
+            users_affected: users.len(),
+            sessions_to_terminate: users.iter().map(|u| u.sessions_to_terminate.len()).sum(),
+            users,
+        })
+    }
+    
     /// Get comprehensive session analytics
     pub async fn get_session_analytics(
         &self,
         user_id: Option<Uuid>,
         days: i64,
     ) -> Result<HashMap<String, serde_json::Value>, Box<dyn std::error::Error>> {
-        let mut conn = self.db_pool.get()?;
+        let mut conn = self.read_pool.get()?;
         let since = Utc::now() - Duration::days(days);
         
         let mut query = token_analytics::table
@@ -547,16 +1185,80 @@ impl EnterpriseSessionManager {
                 .filter(session_security_events::timestamp.lt(now - Duration::days(180)))
         ).execute(&mut conn)?;
         
+        // Purge trashed passwords past their retention period
+        let purged_trash = crate::trash::purge_expired(
+            &mut conn,
+            None,
+            crate::trash::purge_cutoff(now.naive_utc(), crate::trash::retention_days()),
+        )?;
+        
         let mut result = HashMap::new();
         result.insert("expired_sessions".to_string(), expired_sessions as u64);
         result.insert("expired_tokens".to_string(), expired_tokens as u64);
         result.insert("old_analytics".to_string(), old_analytics as u64);
         result.insert("old_events".to_string(), old_events as u64);
+        result.insert("purged_trash".to_string(), purged_trash as u64);
         
-        info!("Cleanup completed: {} sessions, {} tokens, {} analytics, {} events", 
-              expired_sessions, expired_tokens, old_analytics, old_events);
+        info!("Cleanup completed: {} sessions, {} tokens, {} analytics, {} events, {} trashed passwords", 
+              expired_sessions, expired_tokens, old_analytics, old_events, purged_trash);
         
-        Ok(result)
+        if Self::orphan_cleanup_enabled() {
+            result.extend(self.cleanup_orphaned_records(&mut conn)?);
+        }
+        
+        Ok(result)
+    }
+    
+    /// Whether orphaned-record cleanup runs as part of cleanup (CLEANUP_ORPHANED_RECORDS, default on)
+    fn orphan_cleanup_enabled() -> bool {
+        env::var("CLEANUP_ORPHANED_RECORDS").map(|v| v != "false").unwrap_or(true)
+    }
+    
+    /// Remove oauth accounts, sessions and trusted devices whose user no longer exists
+    ///
+    /// Skipped while an account deletion holds the maintenance lock, so it never races
+    /// the deletion transaction.
+    pub fn cleanup_orphaned_records(
+        &self,
+        conn: &mut PgConnection,
+    ) -> Result<HashMap<String, u64>, Box<dyn std::error::Error>> {
+        use diesel::dsl::{exists, not};
+        
+        let counts = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            if !crate::db::try_lock_account_maintenance(conn)? {
+                return Ok(None);
+            }
+            
+            let oauth = diesel::delete(oauth_accounts::table.filter(not(exists(
+                users::table.filter(users::id.eq(oauth_accounts::user_id))
+            )))).execute(conn)?;
+            
+            let sessions = diesel::delete(active_sessions::table.filter(not(exists(
+                users::table.filter(users::id.eq(active_sessions::user_id))
+            )))).execute(conn)?;
+            
+            let devices = diesel::delete(trusted_devices::table.filter(not(exists(
+                users::table.filter(users::id.eq(trusted_devices::user_id))
+            )))).execute(conn)?;
+            
+            Ok(Some((oauth, sessions, devices)))
+        })?;
+        
+        let mut result = HashMap::new();
+        match counts {
+            Some((oauth, sessions, devices)) => {
+                result.insert("orphaned_oauth_accounts".to_string(), oauth as u64);
+                result.insert("orphaned_sessions".to_string(), sessions as u64);
+                result.insert("orphaned_trusted_devices".to_string(), devices as u64);
+                info!("Orphan cleanup completed: {} oauth accounts, {} sessions, {} trusted devices",
+                      oauth, sessions, devices);
+            }
+            None => {
+                info!("Orphan cleanup skipped: account deletion in progress");
+            }
+        }
+        
+        Ok(result)
     }
     
     // Private helper methods
@@ -573,78 +1275,36 @@ impl EnterpriseSessionManager {
             .first(conn)
             .optional()?;
         
-        let limits = limits.unwrap_or_else(|| SessionLimits {
-            id: Uuid::new_v4(),
-            user_id,
-            max_concurrent_sessions: 5,
-            max_sessions_per_device: 3,
-            session_timeout_minutes: 15,
-            refresh_timeout_days: 7,
-            enforce_single_session: Some(false),
-            allow_concurrent_mobile: Some(true),
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
-        });
+        let limits = limits.unwrap_or_else(|| SessionLimits::default_for(user_id));
         
-        // Count current active sessions
-        let current_sessions = active_sessions::table
+        // Load current active sessions
+        let current_sessions: Vec<EnterpriseSession> = active_sessions::table
             .filter(active_sessions::user_id.eq(user_id))
             .filter(active_sessions::is_active.eq(true))
             .filter(active_sessions::expires_at.gt(Utc::now()))
-            .count()
-            .get_result::<i64>(conn)? as i32;
+            .load(conn)?;
         
-        // Enforce concurrent session limit
-        if current_sessions >= limits.max_concurrent_sessions {
-            // Terminate oldest sessions
-            let oldest_sessions: Vec<String> = active_sessions::table
-                .filter(active_sessions::user_id.eq(user_id))
-                .filter(active_sessions::is_active.eq(true))
-                .order(active_sessions::last_activity.asc())
-                .limit((current_sessions - limits.max_concurrent_sessions + 1) as i64)
-                .select(active_sessions::session_id)
-                .load(conn)?;
-            
-            for session_id in oldest_sessions {
-                self.revoke_session(&session_id, "concurrent_session_limit", None).await?;
-            }
-        }
+        // Terminate the oldest sessions so the new one fits within the limits
+        let excess = sessions_exceeding_limits(
+            limits.max_concurrent_sessions,
+            limits.max_sessions_per_device,
+            &current_sessions,
+            Some(device_fingerprint.as_deref()),
+        );
         
-        // Check device-specific limits
-        if let Some(ref device_fp) = device_fingerprint {
-            let device_sessions = active_sessions::table
-                .filter(active_sessions::user_id.eq(user_id))
-                .filter(active_sessions::device_fingerprint.eq(device_fp))
-                .filter(active_sessions::is_active.eq(true))
-                .filter(active_sessions::expires_at.gt(Utc::now()))
-                .count()
-                .get_result::<i64>(conn)? as i32;
-            
-            if device_sessions >= limits.max_sessions_per_device {
-                // Terminate oldest device sessions
-                let oldest_device_sessions: Vec<String> = active_sessions::table
-                    .filter(active_sessions::user_id.eq(user_id))
-                    .filter(active_sessions::device_fingerprint.eq(device_fp))
-                    .filter(active_sessions::is_active.eq(true))
-                    .order(active_sessions::last_activity.asc())
-                    .limit((device_sessions - limits.max_sessions_per_device + 1) as i64)
-                    .select(active_sessions::session_id)
-                    .load(conn)?;
-                
-                for session_id in oldest_device_sessions {
-                    self.revoke_session(&session_id, "device_session_limit", None).await?;
-                }
-            }
+        for (session, reason) in excess {
+            self.revoke_session(&session.session_id, reason, None).await?;
         }
         
         Ok(())
     }
     
+    /// Records the device the session comes from, returning true if it hadn't been seen before
     async fn update_device_trust(
         &self,
         conn: &mut PgConnection,
         request: &CreateSessionRequest,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<bool, Box<dyn std::error::Error>> {
         if let Some(ref device_fp) = request.device_fingerprint {
             let now = Utc::now();
             
@@ -714,16 +1374,20 @@ impl EnterpriseSessionManager {
                         notes: None,
                         created_at: now,
                         updated_at: now,
+                        trust_token: None,
+                        trust_token_expires_at: None,
                     };
                     
                     diesel::insert_into(trusted_devices::table)
                         .values(&new_device)
                         .execute(conn)?;
+
+                    return Ok(true);
                 }
             }
         }
         
-        Ok(())
+        Ok(false)
     }
     
     fn generate_token_pair(
@@ -839,7 +1503,10 @@ impl EnterpriseSessionManager {
         conn: &mut PgConnection,
         session: &EnterpriseSession,
         request: &CreateSessionRequest,
+        is_new_device: bool,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut alert_reasons = Vec::new();
+
         // Check for concurrent logins from different locations
         if let (Some(ref current_ip), Some(ref country)) = (&request.ip_address, &session.location_country) {
             let recent_sessions = active_sessions::table
@@ -870,18 +1537,13 @@ impl EnterpriseSessionManager {
                         "recent_sessions": recent_sessions
                     })),
                 }).await?;
+                alert_reasons.push("an unusual location");
             }
         }
         
-        // Check for new device
+        // Check for new device; update_device_trust has already registered it by now
         if let Some(ref device_fp) = request.device_fingerprint {
-            let device_exists = trusted_devices::table
-                .filter(trusted_devices::user_id.eq(session.user_id))
-                .filter(trusted_devices::device_fingerprint.eq(device_fp))
-                .count()
-                .get_result::<i64>(conn)?;
-            
-            if device_exists == 0 {
+            if is_new_device {
                 self.record_security_event(conn, SessionSecurityEvent {
                     id: Uuid::new_v4(),
                     session_id: session.session_id.clone(),
@@ -901,11 +1563,58 @@ impl EnterpriseSessionManager {
                         "device_type": request.device_type
                     })),
                 }).await?;
+                alert_reasons.insert(0, "a new device");
             }
         }
+
+        if !alert_reasons.is_empty() {
+            self.send_security_alert(conn, session, request, &alert_reasons);
+        }
         
         Ok(())
     }
+
+    /// Emails the user about a flagged sign-in unless they opted out; failures are only logged
+    fn send_security_alert(
+        &self,
+        conn: &mut PgConnection,
+        session: &EnterpriseSession,
+        request: &CreateSessionRequest,
+        reasons: &[&str],
+    ) {
+        let recipient = users::table
+            .find(session.user_id)
+            .select((users::email, users::username, users::new_device_alerts))
+            .first::<(String, String, bool)>(conn);
+
+        let (email, username) = match recipient {
+            Ok((email, username, true)) => (email, username),
+            Ok(_) => return,
+            Err(e) => {
+                error!("Failed to load user {} for sign-in alert: {}", session.user_id, e);
+                return;
+            }
+        };
+
+        let alert = NewDeviceAlert {
+            reason: reasons.join(" and "),
+            ip_address: request.ip_address.clone(),
+            location: session_location(session),
+            user_agent: request.user_agent.clone(),
+            time: session.created_at,
+        };
+
+        // Sent in the background so a slow or unreachable SMTP server never holds up the login
+        actix_web::rt::spawn(async move {
+            let result = match EmailService::new() {
+                Ok(email_service) => email_service.send_new_device_alert(&email, &username, &alert).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!("Failed to send sign-in alert to {}: {}", email, e);
+            }
+        });
+    }
     
     async fn get_session_security_events(
         &self,
@@ -925,6 +1634,7 @@ impl EnterpriseSessionManager {
         &self,
         session: &EnterpriseSession,
         events: &[SessionSecurityEvent],
+        trust_level: &str,
     ) -> i32 {
         let mut risk_score = 0;
         
@@ -933,6 +1643,8 @@ impl EnterpriseSessionManager {
             risk_score += 20; // Unknown device
         }
         
+        risk_score += device_trust_risk(trust_level);
+        
         if session.location_country.is_none() {
             risk_score += 10; // Unknown location
         }
@@ -960,8 +1672,8 @@ impl EnterpriseSessionManager {
             risk_score += 15; // Inactive session
         }
         
-        // Cap at 100
-        std::cmp::min(risk_score, 100)
+        // Keep within 0-100
+        risk_score.clamp(0, 100)
     }
     
     async fn get_device_trust_level(
@@ -989,16 +1701,7 @@ impl EnterpriseSessionManager {
         events: &[SessionSecurityEvent],
         risk_score: i32,
     ) -> Vec<String> {
-        let mut actions = Vec::new();
-        
-        if risk_score > 80 {
-            actions.push("require_mfa".to_string());
-            actions.push("notify_user".to_string());
-        } else if risk_score > 60 {
-            actions.push("require_mfa".to_string());
-        } else if risk_score > 40 {
-            actions.push("monitor_closely".to_string());
-        }
+        let mut actions = risk_actions(risk_score);
         
         // Check for critical events
         for event in events {
@@ -1074,9 +1777,15 @@ impl EnterpriseSessionManager {
 
 /// Create a new enterprise session
 pub async fn create_enterprise_session(
+    http_req: HttpRequest,
     session_manager: web::Data<Arc<EnterpriseSessionManager>>,
     req: web::Json<CreateSessionRequest>,
 ) -> ActixResult<HttpResponse> {
+    // Only admins may open a session for someone else
+    if let Err(response) = own_or_admin_target(&http_req, Some(req.user_id)) {
+        return Ok(response);
+    }
+    
     match session_manager.create_session(req.into_inner()).await {
         Ok((session, tokens)) => {
             Ok(HttpResponse::Ok().json(serde_json::json!({
@@ -1118,13 +1827,316 @@ pub async fn validate_enterprise_session(
     }
 }
 
+/// List the authenticated user's sessions
+pub async fn list_enterprise_sessions(
+    http_req: HttpRequest,
+    session_manager: web::Data<Arc<EnterpriseSessionManager>>,
+) -> ActixResult<HttpResponse> {
+    let user_id = crate::auth::authenticate(&http_req)?;
+    
+    match session_manager.list_user_sessions(user_id).await {
+        Ok(sessions) => {
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "sessions": sessions,
+                "status": "success"
+            })))
+        }
+        Err(e) => {
+            error!("Failed to list enterprise sessions: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to list sessions",
+                "status": "error"
+            })))
+        }
+    }
+}
+
+/// Assign a friendly name to one of the authenticated user's sessions
+pub async fn rename_session(
+    http_req: HttpRequest,
+    session_manager: web::Data<Arc<EnterpriseSessionManager>>,
+    path: web::Path<String>,
+    req: web::Json<RenameSessionRequest>,
+) -> ActixResult<HttpResponse> {
+    let user_id = crate::auth::authenticate(&http_req)?;
+    
+    let name: String = req.name.trim().chars().filter(|c| !c.is_control()).collect();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Session name must be between 1 and 100 characters",
+            "status": "error"
+        })));
+    }
+    
+    let session_id = path.into_inner();
+    match session_manager.rename_session(user_id, &session_id, &name).await {
+        Ok(true) => {
+            // A custom name replaces the label derived from the user agent
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "session_id": session_id,
+                "device_name": name,
+                "label": name,
+                "status": "success"
+            })))
+        }
+        Ok(false) => {
+            Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Session not found",
+                "status": "error"
+            })))
+        }
+        Err(e) => {
+            error!("Failed to rename session: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to rename session",
+                "status": "error"
+            })))
+        }
+    }
+}
+
+/// List the caller's known devices
+pub async fn list_devices(
+    http_req: HttpRequest,
+    session_manager: web::Data<Arc<EnterpriseSessionManager>>,
+) -> ActixResult<HttpResponse> {
+    let user_id = crate::auth::authenticate(&http_req)?;
+    
+    match session_manager.list_devices(user_id).await {
+        Ok(devices) => {
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "devices": devices,
+                "status": "success"
+            })))
+        }
+        Err(e) => {
+            error!("Failed to list devices: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to list devices",
+                "status": "error"
+            })))
+        }
+    }
+}
+
+/// Ask to trust one of the caller's devices; the change takes effect once confirmed by email
+pub async fn trust_device(
+    http_req: HttpRequest,
+    session_manager: web::Data<Arc<EnterpriseSessionManager>>,
+    path: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    let user_id = crate::auth::authenticate(&http_req)?;
+    
+    // Without email there is no way to confirm, so don't hand out a token
+    let email_service = match EmailService::new() {
+        Ok(email_service) => email_service,
+        Err(e) => {
+            error!("Cannot send device trust confirmation: {}", e);
+            return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                "error": "Email delivery is not configured",
+                "status": "error"
+            })));
+        }
+    };
+    
+    let fingerprint = path.into_inner();
+    let (token, device_name, email, username) = match session_manager.request_device_trust(user_id, &fingerprint).await {
+        Ok(DeviceTrustRequest::Pending { token, device_name, email, username }) => (token, device_name, email, username),
+        Ok(DeviceTrustRequest::AlreadyTrusted) => {
+            return Ok(HttpResponse::Ok().json(serde_json::json!({
+                "trust_level": "trusted",
+                "status": "success"
+            })));
+        }
+        Ok(DeviceTrustRequest::NotFound) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Device not found",
+                "status": "error"
+            })));
+        }
+        Err(e) => {
+            error!("Failed to request device trust: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to request device trust",
+                "status": "error"
+            })));
+        }
+    };
+    
+    if let Err(e) = email_service
+        .send_device_trust_email(&email, &username, &device_name, &token, DEVICE_TRUST_TOKEN_EXPIRY_MINUTES)
+        .await
+    {
+        error!("Failed to send device trust confirmation to {}: {}", email, e);
+        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to send confirmation email",
+            "status": "error"
+        })));
+    }
+    
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "message": "Check your email to confirm this device",
+        "status": "pending"
+    })))
+}
+
+/// Confirm a device trust request with the token from the email
+pub async fn confirm_device_trust(
+    http_req: HttpRequest,
+    session_manager: web::Data<Arc<EnterpriseSessionManager>>,
+    req: web::Json<ConfirmDeviceTrustRequest>,
+) -> ActixResult<HttpResponse> {
+    let user_id = crate::auth::authenticate(&http_req)?;
+    
+    match session_manager.confirm_device_trust(user_id, req.token.trim()).await {
+        Ok(Some(device)) => {
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "device": device,
+                "status": "success"
+            })))
+        }
+        Ok(None) => {
+            Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid or expired confirmation token",
+                "status": "error"
+            })))
+        }
+        Err(e) => {
+            error!("Failed to confirm device trust: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to confirm device trust",
+                "status": "error"
+            })))
+        }
+    }
+}
+
+/// Forget one of the caller's devices
+pub async fn forget_device(
+    http_req: HttpRequest,
+    session_manager: web::Data<Arc<EnterpriseSessionManager>>,
+    path: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    let user_id = crate::auth::authenticate(&http_req)?;
+    
+    match session_manager.forget_device(user_id, &path.into_inner()).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => {
+            Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Device not found",
+                "status": "error"
+            })))
+        }
+        Err(e) => {
+            error!("Failed to forget device: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to forget device",
+                "status": "error"
+            })))
+        }
+    }
+}
+
+/// Resolve which user a request acts on: the caller, or any requested user for admins
+fn own_or_admin_target(http_req: &HttpRequest, requested: Option<Uuid>) -> Result<Uuid, HttpResponse> {
+    let caller = crate::auth::extract_user_id_from_request(http_req).map_err(|_| crate::auth::unauthorized_response())?;
+    
+    match requested {
+        Some(user_id) if user_id != caller => crate::auth::require_admin(http_req).map(|_| user_id),
+        _ => Ok(caller),
+    }
+}
+
+/// Resolve whose session limits a request manages: the caller's own, or any user's for admins
+fn session_limits_target(http_req: &HttpRequest, query: &SessionLimitsQuery) -> Result<Uuid, HttpResponse> {
+    own_or_admin_target(http_req, query.user_id)
+}
+
+/// Get the session limits of the caller, or of `?user_id=` for admins
+pub async fn get_session_limits(
+    http_req: HttpRequest,
+    session_manager: web::Data<Arc<EnterpriseSessionManager>>,
+    query: web::Query<SessionLimitsQuery>,
+) -> ActixResult<HttpResponse> {
+    let user_id = match session_limits_target(&http_req, &query) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(response),
+    };
+    
+    match session_manager.get_session_limits(user_id).await {
+        Ok(limits) => {
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "session_limits": limits,
+                "status": "success"
+            })))
+        }
+        Err(e) => {
+            error!("Failed to load session limits: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to load session limits",
+                "status": "error"
+            })))
+        }
+    }
+}
+
+/// Set the session limits of the caller, or of `?user_id=` for admins
+pub async fn update_session_limits(
+    http_req: HttpRequest,
+    session_manager: web::Data<Arc<EnterpriseSessionManager>>,
+    token_manager: web::Data<Arc<crate::token_management::TokenManager>>,
+    query: web::Query<SessionLimitsQuery>,
+    req: web::Json<SessionLimitsUpdate>,
+) -> ActixResult<HttpResponse> {
+    let user_id = match session_limits_target(&http_req, &query) {
+        Ok(user_id) => user_id,
+        Err(response) => return Ok(response),
+    };
+    
+    if let Err(message) = req.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": message,
+            "status": "error"
+        })));
+    }
+    
+    match session_manager.set_session_limits(user_id, &req).await {
+        Ok(limits) => {
+            token_manager.invalidate_idle_timeout(user_id);
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "session_limits": limits,
+                "status": "success"
+            })))
+        }
+        Err(e) => {
+            error!("Failed to update session limits: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to update session limits",
+                "status": "error"
+            })))
+        }
+    }
+}
+
 /// Get enterprise session analytics
 pub async fn get_enterprise_analytics(
+    http_req: HttpRequest,
     session_manager: web::Data<Arc<EnterpriseSessionManager>>,
     query: web::Query<HashMap<String, String>>,
 ) -> ActixResult<HttpResponse> {
-    let user_id = query.get("user_id")
-        .and_then(|id| Uuid::parse_str(id).ok());
+    // Users see their own analytics; another user's or everyone's (`?all=true`) is for admins
+    let user_id = if query.get("all").map(String::as_str) == Some("true") {
+        if let Err(response) = crate::auth::require_admin(&http_req) {
+            return Ok(response);
+        }
+        None
+    } else {
+        let requested = query.get("user_id").and_then(|id| Uuid::parse_str(id).ok());
+        match own_or_admin_target(&http_req, requested) {
+            Ok(user_id) => Some(user_id),
+            Err(response) => return Ok(response),
+        }
+    };
     let days = query.get("days")
         .and_then(|d| d.parse::<i64>().ok())
         .unwrap_or(30);
@@ -1143,10 +2155,49 @@ pub async fn get_enterprise_analytics(
     }
 }
 
-/// Cleanup expired enterprise data
+/// Simulate a proposed session limit policy (admin only)
+pub async fn simulate_session_limits(
+    http_req: HttpRequest,
+    session_manager: web::Data<Arc<EnterpriseSessionManager>>,
+    req: web::Json<SessionLimitPolicy>,
+) -> ActixResult<HttpResponse> {
+    if let Err(response) = crate::auth::require_admin(&http_req) {
+        return Ok(response);
+    }
+    
+    if req.max_concurrent_sessions < 1 || req.max_sessions_per_device < 1 {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Session limits must be at least 1",
+            "status": "error"
+        })));
+    }
+    
+    match session_manager.simulate_session_limits(&req).await {
+        Ok(simulation) => {
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "simulation": simulation,
+                "status": "success"
+            })))
+        }
+        Err(e) => {
+            error!("Failed to simulate session limits: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to simulate session limits",
+                "status": "error"
+            })))
+        }
+    }
+}
+
+/// Cleanup expired enterprise data (admin only)
 pub async fn cleanup_enterprise_data(
+    http_req: HttpRequest,
     session_manager: web::Data<Arc<EnterpriseSessionManager>>,
 ) -> ActixResult<HttpResponse> {
+    if let Err(response) = crate::auth::require_admin(&http_req) {
+        return Ok(response);
+    }
+    
     match session_manager.cleanup_expired_data().await {
         Ok(stats) => {
             Ok(HttpResponse::Ok().json(serde_json::json!({
@@ -1174,4 +2225,179 @@ pub fn configure_enterprise_session_routes(cfg: &mut web::ServiceConfig) {
             .route("/analytics", web::get().to(get_enterprise_analytics))
             .route("/cleanup", web::post().to(cleanup_enterprise_data))
     );
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(id: &str, device: Option<&str>, minutes_ago: i64) -> EnterpriseSession {
+        let now = Utc::now();
+        EnterpriseSession {
+            id: Uuid::new_v4(),
+            session_id: id.to_string(),
+            user_id: Uuid::nil(),
+            access_token_jti: String::new(),
+            refresh_token_jti: String::new(),
+            created_at: now,
+            last_activity: now - Duration::minutes(minutes_ago),
+            expires_at: now + Duration::hours(1),
+            ip_address: None,
+            user_agent: None,
+            device_fingerprint: device.map(|d| d.to_string()),
+            device_name: None,
+            device_type: None,
+            location_country: None,
+            location_region: None,
+            location_city: None,
+            is_active: true,
+            created_by_ip: None,
+            last_seen_ip: None,
+            session_flags: None,
+        }
+    }
+
+    fn ids(selected: &[(&EnterpriseSession, &'static str)]) -> Vec<String> {
+        selected.iter().map(|(s, _)| s.session_id.clone()).collect()
+    }
+
+    #[test]
+    fn test_simulation_selects_oldest_over_concurrent_limit() {
+        let sessions = vec![
+            session("newest", Some("laptop"), 1),
+            session("oldest", Some("phone"), 30),
+            session("middle", Some("tablet"), 10),
+        ];
+
+        let selected = sessions_exceeding_limits(2, 3, &sessions, None);
+        assert_eq!(ids(&selected), vec!["oldest"]);
+        assert_eq!(selected[0].1, "concurrent_session_limit");
+
+        assert!(sessions_exceeding_limits(3, 3, &sessions, None).is_empty());
+    }
+
+    #[test]
+    fn test_simulation_applies_device_limit_per_device() {
+        let sessions = vec![
+            session("a1", Some("laptop"), 5),
+            session("a2", Some("laptop"), 20),
+            session("a3", Some("laptop"), 40),
+            session("b1", Some("phone"), 50),
+        ];
+
+        let selected = sessions_exceeding_limits(10, 2, &sessions, None);
+        assert_eq!(ids(&selected), vec!["a3"]);
+        assert_eq!(selected[0].1, "device_session_limit");
+    }
+
+    #[test]
+    fn test_enforcement_reserves_slot_for_incoming_session() {
+        let sessions = vec![
+            session("a1", Some("laptop"), 5),
+            session("a2", Some("laptop"), 20),
+            session("b1", Some("phone"), 50),
+        ];
+
+        // A new laptop session needs one concurrent slot and one laptop slot
+        let selected = sessions_exceeding_limits(3, 2, &sessions, Some(Some("laptop")));
+        assert_eq!(ids(&selected), vec!["b1", "a2"]);
+
+        // Sessions on other devices are not checked against the device limit
+        let selected = sessions_exceeding_limits(10, 1, &sessions, Some(Some("tablet")));
+        assert!(selected.is_empty());
+    }
+
+    #[test]
+    fn test_unsigned_tokens_are_rejected() {
+        let pool = diesel::r2d2::Pool::builder()
+            .build_unchecked(diesel::r2d2::ConnectionManager::new("postgres://localhost/unused"));
+        let manager = EnterpriseSessionManager {
+            read_pool: pool.clone(),
+            db_pool: pool,
+            jwt_secret: "test-jwt-secret".to_string(),
+            issuer: "passq-api".to_string(),
+            audience: "passq-client".to_string(),
+        };
+
+        let now = Utc::now();
+        let claims = serde_json::json!({
+            "sub": Uuid::new_v4(), "exp": (now + Duration::hours(1)).timestamp(), "iat": now.timestamp(),
+            "jti": Uuid::new_v4().to_string(), "session_id": "session", "token_type": "access",
+            "aud": "passq-client", "iss": "passq-api", "scope": ["read"],
+        });
+
+        let signed = encode(&Header::default(), &claims, &EncodingKey::from_secret(b"test-jwt-secret")).unwrap();
+        assert!(manager.validate_jwt_token(&signed).is_ok());
+        assert!(manager.validate_jwt_token(&crate::auth::unsigned_token(&claims)).is_err());
+    }
+
+    #[test]
+    fn test_session_limits_update_validation() {
+        let update = |concurrent, per_device, timeout, refresh| SessionLimitsUpdate {
+            max_concurrent_sessions: concurrent,
+            max_sessions_per_device: per_device,
+            session_timeout_minutes: timeout,
+            refresh_timeout_days: refresh,
+            enforce_single_session: false,
+            allow_concurrent_mobile: true,
+        };
+
+        assert!(update(5, 3, 15, 7).validate().is_ok());
+        assert!(update(MAX_CONCURRENT_SESSIONS_CAP, 1, SESSION_TIMEOUT_MINUTES_CAP, REFRESH_TIMEOUT_DAYS_CAP).validate().is_ok());
+
+        assert!(update(0, 1, 15, 7).validate().is_err());
+        assert!(update(MAX_CONCURRENT_SESSIONS_CAP + 1, 1, 15, 7).validate().is_err());
+        assert!(update(2, 3, 15, 7).validate().is_err());
+        assert!(update(5, 0, 15, 7).validate().is_err());
+        assert!(update(5, 3, 0, 7).validate().is_err());
+        assert!(update(5, 3, -15, 7).validate().is_err());
+        assert!(update(5, 3, 15, REFRESH_TIMEOUT_DAYS_CAP + 1).validate().is_err());
+    }
+
+    #[test]
+    fn test_device_trust_risk() {
+        assert!(device_trust_risk("trusted") < 0);
+        assert_eq!(device_trust_risk("untrusted"), 0);
+        assert_eq!(device_trust_risk("unknown"), 0);
+        assert!(device_trust_risk("suspicious") > 0);
+        assert_eq!(device_trust_risk("blocked"), 100);
+
+        let token = generate_trust_token().unwrap();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_trust_token().unwrap());
+    }
+
+    #[test]
+    fn test_login_risk_score() {
+        let usual = LoginRiskSignals {
+            device_trust_level: Some("trusted".to_string()),
+            known_ip: true,
+            ..Default::default()
+        };
+        assert_eq!(login_risk_score(&usual), 0);
+        assert!(risk_actions(login_risk_score(&usual)).is_empty());
+
+        // A browser without a device id signing in from a new IP is watched, not challenged
+        let new_ip = LoginRiskSignals::default();
+        assert_eq!(risk_actions(login_risk_score(&new_ip)), vec!["monitor_closely".to_string()]);
+
+        // ... unless it follows a run of failed logins
+        let after_failures = LoginRiskSignals { recent_failures: 3, ..Default::default() };
+        assert_eq!(risk_actions(login_risk_score(&after_failures)), vec!["require_mfa".to_string()]);
+
+        let blocked = LoginRiskSignals {
+            device_trust_level: Some("blocked".to_string()),
+            known_ip: true,
+            ..Default::default()
+        };
+        assert_eq!(login_risk_score(&blocked), 100);
+        assert_eq!(risk_actions(100), vec!["require_mfa".to_string(), "notify_user".to_string()]);
+
+        let open_events = LoginRiskSignals {
+            device_trust_level: Some("untrusted".to_string()),
+            known_ip: false,
+            open_event_severities: vec!["critical".to_string(), "low".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(login_risk_score(&open_events), 65);
+    }
+}
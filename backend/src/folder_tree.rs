@@ -0,0 +1,144 @@
+//! Folder tree module for validating and walking a user's folder hierarchy
+
+use diesel::prelude::*;
+use diesel::PgConnection;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Each of a user's folders mapped to its parent
+pub type ParentMap = HashMap<Uuid, Option<Uuid>>;
+
+/// Loads the parent links of all folders owned by the user
+pub fn load_parent_map(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<ParentMap> {
+    use crate::schema::folders;
+
+    let links = folders::table
+        .filter(folders::user_id.eq(user_id))
+        .select((folders::id, folders::parent_folder_id))
+        .load::<(Uuid, Option<Uuid>)>(conn)?;
+
+    Ok(links.into_iter().collect())
+}
+
+/// Checks that moving `folder_id` under `new_parent_id` keeps the tree acyclic
+pub fn validate_reparent(folder_id: Uuid, new_parent_id: Uuid, parents: &ParentMap) -> Result<(), String> {
+    if folder_id == new_parent_id {
+        return Err("A folder cannot be its own parent".to_string());
+    }
+    if !parents.contains_key(&new_parent_id) {
+        return Err("Parent folder not found".to_string());
+    }
+
+    // Walk up from the new parent; reaching the moved folder means it would become its own ancestor
+    let mut visited = HashSet::new();
+    let mut ancestor = Some(new_parent_id);
+    while let Some(id) = ancestor {
+        if id == folder_id {
+            return Err("Cannot move a folder into one of its own subfolders".to_string());
+        }
+        if !visited.insert(id) {
+            // An existing cycle that doesn't involve this folder; stop rather than loop forever
+            break;
+        }
+        ancestor = parents.get(&id).copied().flatten();
+    }
+
+    Ok(())
+}
+
+/// Returns the folder followed by its parent, grandparent and so on up to the root
+pub fn ancestor_ids(folder_id: Uuid, parents: &ParentMap) -> Vec<Uuid> {
+    let mut ancestors = Vec::new();
+    let mut visited = HashSet::new();
+    let mut ancestor = Some(folder_id);
+    while let Some(id) = ancestor {
+        if !visited.insert(id) {
+            break;
+        }
+        ancestors.push(id);
+        ancestor = parents.get(&id).copied().flatten();
+    }
+    ancestors
+}
+
+/// Returns the folder and all of its descendants, parents before children
+pub fn subtree_ids(root_id: Uuid, parents: &ParentMap) -> Vec<Uuid> {
+    let mut children: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for (id, parent_id) in parents {
+        if let Some(parent_id) = parent_id {
+            children.entry(*parent_id).or_default().push(*id);
+        }
+    }
+
+    let mut subtree = vec![root_id];
+    let mut visited: HashSet<Uuid> = subtree.iter().copied().collect();
+    let mut next = 0;
+    while next < subtree.len() {
+        let id = subtree[next];
+        next += 1;
+        for child_id in children.get(&id).into_iter().flatten() {
+            if visited.insert(*child_id) {
+                subtree.push(*child_id);
+            }
+        }
+    }
+
+    subtree
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_reparent_three_level_tree() {
+        // root -> child -> grandchild
+        let (root, child, grandchild, other) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let parents: ParentMap = [
+            (root, None),
+            (child, Some(root)),
+            (grandchild, Some(child)),
+            (other, None),
+        ]
+        .into_iter()
+        .collect();
+
+        assert!(validate_reparent(root, grandchild, &parents).is_err());
+        assert!(validate_reparent(root, child, &parents).is_err());
+        assert!(validate_reparent(child, child, &parents).is_err());
+        assert!(validate_reparent(child, Uuid::new_v4(), &parents).is_err());
+
+        assert!(validate_reparent(grandchild, root, &parents).is_ok());
+        assert!(validate_reparent(root, other, &parents).is_ok());
+    }
+
+    #[test]
+    fn test_subtree_ids() {
+        let (root, child, grandchild, sibling) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let parents: ParentMap = [
+            (root, None),
+            (child, Some(root)),
+            (grandchild, Some(child)),
+            (sibling, None),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(subtree_ids(root, &parents), vec![root, child, grandchild]);
+        assert_eq!(subtree_ids(grandchild, &parents), vec![grandchild]);
+    }
+
+    #[test]
+    fn test_ancestor_ids() {
+        let (root, child, grandchild) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let parents: ParentMap = [(root, None), (child, Some(root)), (grandchild, Some(child))].into_iter().collect();
+
+        assert_eq!(ancestor_ids(grandchild, &parents), vec![grandchild, child, root]);
+        assert_eq!(ancestor_ids(root, &parents), vec![root]);
+
+        // A corrupted tree with a cycle still terminates
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let cyclic: ParentMap = [(a, Some(b)), (b, Some(a))].into_iter().collect();
+        assert_eq!(ancestor_ids(a, &cyclic), vec![a, b]);
+    }
+}
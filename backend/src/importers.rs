@@ -0,0 +1,802 @@
+//! Structured importers for 1Password (.1pux), Bitwarden JSON and PassQ JSON exports
+//!
+//! Parsers map the exported items to `ImportedEntry` values; saving them is left
+//! to the import handlers so all import paths share the same persistence logic.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use uuid::Uuid;
+use crate::models::JsonExportFolder;
+
+/// An entry parsed from an external export, ready to be saved
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportedEntry {
+    pub name: String,
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    pub notes: String,
+    pub otp_secret: Option<String>,
+    /// Folder path from the top-level folder down, empty for no folder
+    pub folder_path: Vec<String>,
+    pub custom_fields: Vec<(String, String)>,
+}
+
+impl ImportedEntry {
+    /// Notes with custom fields appended, since entries have no dedicated field storage
+    pub fn notes_with_custom_fields(&self) -> String {
+        if self.custom_fields.is_empty() {
+            return self.notes.clone();
+        }
+
+        let fields = self.custom_fields
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if self.notes.is_empty() {
+            format!("Custom fields:\n{}", fields)
+        } else {
+            format!("{}\n\nCustom fields:\n{}", self.notes, fields)
+        }
+    }
+}
+
+/// Result of parsing one exported item
+#[derive(Debug)]
+pub enum ParsedItem {
+    Entry(ImportedEntry),
+    Skipped { name: String, reason: String },
+}
+
+/// Outcome of importing a single item
+#[derive(Debug, Serialize)]
+pub struct ImportItemResult {
+    pub index: usize,
+    pub name: String,
+    pub status: String, // "imported", "skipped" or "failed"
+    pub password_id: Option<Uuid>,
+    pub error: Option<String>,
+}
+
+/// Summary returned by the structured import endpoints
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub folders_created: usize,
+    pub items: Vec<ImportItemResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub folder_errors: Vec<String>,
+}
+
+impl ImportSummary {
+    pub fn imported(&mut self, index: usize, name: String, password_id: Uuid) {
+        self.imported += 1;
+        self.items.push(ImportItemResult { index, name, status: "imported".to_string(), password_id: Some(password_id), error: None });
+    }
+
+    pub fn skipped(&mut self, index: usize, name: String, reason: String) {
+        self.skipped += 1;
+        self.items.push(ImportItemResult { index, name, status: "skipped".to_string(), password_id: None, error: Some(reason) });
+    }
+
+    pub fn failed(&mut self, index: usize, name: String, error: String) {
+        self.failed += 1;
+        self.items.push(ImportItemResult { index, name, status: "failed".to_string(), password_id: None, error: Some(error) });
+    }
+}
+
+/// Identity of an entry when imports skip existing ones: website and username, trimmed and lowercased
+pub fn entry_key(website: &str, username: &str) -> (String, String) {
+    (website.trim().to_lowercase(), username.trim().to_lowercase())
+}
+
+/// Extracts the secret from an `otpauth://` URI, or returns the value as-is
+///
+/// URIs that set `digits`, `period` or `algorithm` are kept whole so those settings survive.
+pub fn extract_totp_secret(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    if value.starts_with("otpauth://") {
+        let parsed = url::Url::parse(value).ok()?;
+        if parsed.query_pairs().any(|(key, _)| matches!(key.as_ref(), "digits" | "period" | "algorithm")) {
+            return Some(value.to_string());
+        }
+        return parsed
+            .query_pairs()
+            .find(|(key, _)| key == "secret")
+            .map(|(_, secret)| secret.into_owned());
+    }
+
+    Some(value.to_string())
+}
+
+/// Splits a nested folder name such as `Work/Servers` into its path components
+fn split_folder_path(name: &str) -> Vec<String> {
+    name.split('/')
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_string())
+        .collect()
+}
+
+fn str_field<'a>(value: &'a Value, key: &str) -> &'a str {
+    value.get(key).and_then(|v| v.as_str()).unwrap_or("")
+}
+
+// 1Password .1pux
+
+/// Largest `export.data` read from a .1pux archive once decompressed
+///
+/// A small archive can inflate to gigabytes, so reading stops past this size.
+pub const MAX_EXPORT_DATA_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Reads `export.data` from a .1pux archive and parses its items
+pub fn parse_1pux(archive: &[u8]) -> Result<Vec<ParsedItem>, String> {
+    let export_data = read_1pux_export_data(archive, MAX_EXPORT_DATA_BYTES)?;
+    parse_1pux_export_data(&export_data)
+}
+
+fn read_1pux_export_data(archive: &[u8], limit: u64) -> Result<String, String> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(archive))
+        .map_err(|e| format!("Invalid .1pux archive: {}", e))?;
+
+    let mut export_data = String::new();
+    zip.by_name("export.data")
+        .map_err(|_| "Invalid .1pux archive: export.data not found".to_string())?
+        .take(limit + 1)
+        .read_to_string(&mut export_data)
+        .map_err(|e| format!("Failed to read export.data: {}", e))?;
+    if export_data.len() as u64 > limit {
+        return Err(format!("Invalid .1pux archive: export.data is larger than {} MB", limit / (1024 * 1024)));
+    }
+
+    Ok(export_data)
+}
+
+/// Parses the JSON `export.data` document from a .1pux archive
+pub fn parse_1pux_export_data(export_data: &str) -> Result<Vec<ParsedItem>, String> {
+    let document: Value = serde_json::from_str(export_data)
+        .map_err(|e| format!("Invalid 1Password export data: {}", e))?;
+
+    let accounts = document.get("accounts")
+        .and_then(|a| a.as_array())
+        .ok_or_else(|| "Invalid 1Password export data: missing accounts".to_string())?;
+
+    let mut items = Vec::new();
+    for account in accounts {
+        let vaults = account.get("vaults").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+        for vault in vaults {
+            let vault_name = vault.get("attrs").map(|attrs| str_field(attrs, "name")).unwrap_or("").to_string();
+            let vault_items = vault.get("items").and_then(|i| i.as_array()).cloned().unwrap_or_default();
+            for item in vault_items {
+                items.push(parse_1pux_item(&item, &vault_name));
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+// 1Password category UUIDs for logins and standalone passwords
+const ONEPUX_CATEGORY_LOGIN: &str = "001";
+const ONEPUX_CATEGORY_PASSWORD: &str = "005";
+
+fn parse_1pux_item(item: &Value, vault_name: &str) -> ParsedItem {
+    let overview = item.get("overview").cloned().unwrap_or(Value::Null);
+    let details = item.get("details").cloned().unwrap_or(Value::Null);
+    let name = str_field(&overview, "title").to_string();
+
+    if str_field(item, "state") == "archived" {
+        return ParsedItem::Skipped { name, reason: "Archived item".to_string() };
+    }
+
+    let category = str_field(item, "categoryUuid");
+    if category != ONEPUX_CATEGORY_LOGIN && category != ONEPUX_CATEGORY_PASSWORD {
+        return ParsedItem::Skipped { name, reason: format!("Unsupported item category {}", category) };
+    }
+
+    let mut entry = ImportedEntry {
+        name,
+        url: str_field(&overview, "url").to_string(),
+        notes: str_field(&details, "notesPlain").to_string(),
+        password: str_field(&details, "password").to_string(),
+        ..Default::default()
+    };
+
+    if entry.url.is_empty() {
+        if let Some(url) = overview.get("urls").and_then(|u| u.as_array()).and_then(|u| u.first()) {
+            entry.url = str_field(url, "url").to_string();
+        }
+    }
+
+    // Login fields carry the username and password designations
+    for field in details.get("loginFields").and_then(|f| f.as_array()).cloned().unwrap_or_default() {
+        match str_field(&field, "designation") {
+            "username" => entry.username = str_field(&field, "value").to_string(),
+            "password" => entry.password = str_field(&field, "value").to_string(),
+            _ => {}
+        }
+    }
+
+    // Section fields hold TOTP secrets and custom fields
+    for section in details.get("sections").and_then(|s| s.as_array()).cloned().unwrap_or_default() {
+        for field in section.get("fields").and_then(|f| f.as_array()).cloned().unwrap_or_default() {
+            let title = str_field(&field, "title").to_string();
+            let value = match field.get("value").and_then(|v| v.as_object()) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            if let Some(totp) = value.get("totp").and_then(|t| t.as_str()) {
+                if entry.otp_secret.is_none() {
+                    entry.otp_secret = extract_totp_secret(totp);
+                }
+                continue;
+            }
+
+            // Every field value is a single-key object such as {"string": "..."}
+            let text = value.values().find_map(|v| match v {
+                Value::String(s) => Some(s.clone()),
+                Value::Number(n) => Some(n.to_string()),
+                Value::Bool(b) => Some(b.to_string()),
+                _ => None,
+            });
+            if let Some(text) = text {
+                if !text.is_empty() {
+                    entry.custom_fields.push((if title.is_empty() { "Field".to_string() } else { title }, text));
+                }
+            }
+        }
+    }
+
+    // Vault name becomes the top-level folder, nested under it the first tag path
+    if !vault_name.is_empty() {
+        entry.folder_path.push(vault_name.to_string());
+    }
+    if let Some(tag) = overview.get("tags").and_then(|t| t.as_array()).and_then(|t| t.first()).and_then(|t| t.as_str()) {
+        entry.folder_path.extend(split_folder_path(tag));
+    }
+
+    ParsedItem::Entry(entry)
+}
+
+// Bitwarden JSON
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitwardenExport {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    password_protected: bool,
+    // Key derivation and ciphertext of a password-protected export
+    salt: Option<String>,
+    kdf_type: Option<u32>,
+    kdf_iterations: Option<u32>,
+    kdf_memory: Option<u32>,
+    kdf_parallelism: Option<u32>,
+    #[serde(rename = "encKeyValidation_DO_NOT_EDIT")]
+    enc_key_validation: Option<String>,
+    data: Option<String>,
+    #[serde(default)]
+    folders: Vec<BitwardenFolder>,
+    #[serde(default)]
+    collections: Vec<BitwardenFolder>,
+    #[serde(default)]
+    items: Vec<BitwardenItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitwardenFolder {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitwardenItem {
+    #[serde(rename = "type")]
+    item_type: i32,
+    #[serde(default)]
+    name: String,
+    notes: Option<String>,
+    folder_id: Option<String>,
+    #[serde(default)]
+    collection_ids: Option<Vec<String>>,
+    #[serde(default)]
+    fields: Option<Vec<BitwardenField>>,
+    login: Option<BitwardenLogin>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitwardenField {
+    name: Option<String>,
+    value: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitwardenLogin {
+    uris: Option<Vec<BitwardenUri>>,
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitwardenUri {
+    uri: Option<String>,
+}
+
+// Bitwarden item type for logins
+const BITWARDEN_TYPE_LOGIN: i32 = 1;
+
+// Bitwarden KDF types
+const BITWARDEN_KDF_PBKDF2: u32 = 0;
+const BITWARDEN_KDF_ARGON2ID: u32 = 1;
+
+/// Parses a Bitwarden JSON export (personal or organization)
+///
+/// Password-protected exports are decrypted with `password`. Account-restricted exports are
+/// encrypted with the Bitwarden account key, which never leaves Bitwarden, so they are rejected.
+pub fn parse_bitwarden_json(data: &str, password: Option<&str>) -> Result<Vec<ParsedItem>, String> {
+    let mut export: BitwardenExport = serde_json::from_str(data)
+        .map_err(|e| format!("Invalid Bitwarden JSON export: {}", e))?;
+
+    if export.encrypted {
+        if !export.password_protected {
+            return Err("Account-restricted Bitwarden exports can't be decrypted outside Bitwarden; export with a password or unencrypted".to_string());
+        }
+        let password = password
+            .filter(|p| !p.is_empty())
+            .ok_or_else(|| "This Bitwarden export is password protected; provide its password".to_string())?;
+        let decrypted = decrypt_bitwarden_export(&export, password)?;
+        export = serde_json::from_slice(&decrypted)
+            .map_err(|e| format!("Invalid Bitwarden JSON export: {}", e))?;
+        if export.encrypted {
+            return Err("Invalid Bitwarden JSON export: decrypted data is still encrypted".to_string());
+        }
+    }
+
+    let folder_names: HashMap<String, String> = export.folders
+        .into_iter()
+        .chain(export.collections)
+        .map(|f| (f.id, f.name))
+        .collect();
+
+    let items = export.items
+        .into_iter()
+        .map(|item| {
+            if item.item_type != BITWARDEN_TYPE_LOGIN {
+                return ParsedItem::Skipped { name: item.name, reason: format!("Unsupported item type {}", item.item_type) };
+            }
+
+            let login = item.login.unwrap_or(BitwardenLogin { uris: None, username: None, password: None, totp: None });
+
+            // Personal folders take precedence over organization collections
+            let folder_name = item.folder_id
+                .as_ref()
+                .or_else(|| item.collection_ids.as_ref().and_then(|ids| ids.first()))
+                .and_then(|id| folder_names.get(id));
+
+            ParsedItem::Entry(ImportedEntry {
+                name: item.name,
+                url: login.uris
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find_map(|u| u.uri)
+                    .unwrap_or_default(),
+                username: login.username.unwrap_or_default(),
+                password: login.password.unwrap_or_default(),
+                notes: item.notes.unwrap_or_default(),
+                otp_secret: login.totp.as_deref().and_then(extract_totp_secret),
+                folder_path: folder_name.map(|name| split_folder_path(name)).unwrap_or_default(),
+                custom_fields: item.fields
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|f| Some((f.name.unwrap_or_else(|| "Field".to_string()), f.value?)))
+                    .collect(),
+            })
+        })
+        .collect();
+
+    Ok(items)
+}
+
+/// Decrypts the `data` of a password-protected Bitwarden export
+///
+/// The export password is stretched with the export's KDF into an AES-256-CBC key and an
+/// HMAC-SHA256 key; a wrong password fails the MAC of `encKeyValidation_DO_NOT_EDIT`.
+fn decrypt_bitwarden_export(export: &BitwardenExport, password: &str) -> Result<Vec<u8>, String> {
+    let invalid = |field: &str| format!("Invalid Bitwarden JSON export: missing or invalid {}", field);
+    let salt = export.salt.as_deref().ok_or_else(|| invalid("salt"))?;
+    let iterations = export.kdf_iterations.ok_or_else(|| invalid("kdfIterations"))?;
+
+    let mut master_key = [0u8; 32];
+    match export.kdf_type.ok_or_else(|| invalid("kdfType"))? {
+        BITWARDEN_KDF_PBKDF2 => {
+            // Bounds of Bitwarden's own KDF settings; they also cap the work an upload can cause
+            if !(5_000..=2_000_000).contains(&iterations) {
+                return Err(invalid("kdfIterations"));
+            }
+            ring::pbkdf2::derive(
+                ring::pbkdf2::PBKDF2_HMAC_SHA256,
+                std::num::NonZeroU32::new(iterations).ok_or_else(|| invalid("kdfIterations"))?,
+                salt.as_bytes(),
+                password.as_bytes(),
+                &mut master_key,
+            );
+        }
+        BITWARDEN_KDF_ARGON2ID => {
+            let memory_mib = export.kdf_memory.ok_or_else(|| invalid("kdfMemory"))?;
+            let parallelism = export.kdf_parallelism.ok_or_else(|| invalid("kdfParallelism"))?;
+            if !(2..=10).contains(&iterations) || !(15..=1024).contains(&memory_mib) || !(1..=16).contains(&parallelism) {
+                return Err(invalid("Argon2 parameters"));
+            }
+            let params = argon2::Params::new(memory_mib * 1024, iterations, parallelism, Some(32))
+                .map_err(|_| invalid("Argon2 parameters"))?;
+            // Bitwarden hashes the salt for Argon2id
+            let salt_hash = ring::digest::digest(&ring::digest::SHA256, salt.as_bytes());
+            argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                .hash_password_into(password.as_bytes(), salt_hash.as_ref(), &mut master_key)
+                .map_err(|e| format!("Failed to derive the Bitwarden export key: {}", e))?;
+        }
+        other => return Err(format!("Unsupported Bitwarden KDF type {}", other)),
+    }
+
+    let keys = BitwardenKeys::stretch(&master_key);
+    let validation = export.enc_key_validation.as_deref().ok_or_else(|| invalid("encKeyValidation_DO_NOT_EDIT"))?;
+    keys.decrypt(validation).map_err(|_| "Wrong password for the Bitwarden export".to_string())?;
+    keys.decrypt(export.data.as_deref().ok_or_else(|| invalid("data"))?)
+}
+
+/// Encryption and MAC keys of a Bitwarden symmetric key
+struct BitwardenKeys {
+    enc: [u8; 32],
+    mac: [u8; 32],
+}
+
+/// Output length for HKDF-Expand
+struct HkdfLen(usize);
+
+impl ring::hkdf::KeyType for HkdfLen {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+impl BitwardenKeys {
+    /// Expands a derived key into separate encryption and MAC keys with HKDF-Expand
+    fn stretch(key: &[u8; 32]) -> Self {
+        let prk = ring::hkdf::Prk::new_less_safe(ring::hkdf::HKDF_SHA256, key);
+        let expand = |info: &[u8]| {
+            let mut out = [0u8; 32];
+            prk.expand(&[info], HkdfLen(out.len()))
+                .and_then(|okm| okm.fill(&mut out))
+                .expect("32 bytes is a valid HKDF-SHA256 output length");
+            out
+        };
+        Self { enc: expand(b"enc"), mac: expand(b"mac") }
+    }
+
+    /// Decrypts a type 2 (AES-256-CBC with HMAC-SHA256) encrypted string `2.iv|data|mac`
+    fn decrypt(&self, enc_string: &str) -> Result<Vec<u8>, String> {
+        use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+        use base64::Engine;
+
+        let invalid = || "Invalid Bitwarden encrypted string".to_string();
+        let parts = enc_string.strip_prefix("2.").ok_or_else(|| "Unsupported Bitwarden encryption type".to_string())?;
+        let decoded = parts
+            .split('|')
+            .map(|part| base64::engine::general_purpose::STANDARD.decode(part).map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let [iv, data, mac] = decoded.as_slice() else {
+            return Err(invalid());
+        };
+        if iv.len() != 16 {
+            return Err(invalid());
+        }
+
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, &self.mac);
+        ring::hmac::verify(&key, &[iv.as_slice(), data.as_slice()].concat(), mac)
+            .map_err(|_| "Bitwarden encrypted string failed its integrity check".to_string())?;
+
+        cbc::Decryptor::<aes::Aes256>::new(&self.enc.into(), iv.as_slice().into())
+            .decrypt_padded_vec_mut::<Pkcs7>(data)
+            .map_err(|_| invalid())
+    }
+}
+
+/// Returns the (folder, parent) links of a PassQ JSON export that are safe to restore
+///
+/// Links to folders missing from the export, or that would close a cycle, are dropped
+/// and reported so those folders are imported at the top level instead.
+pub fn resolve_folder_parents(folders: &[JsonExportFolder]) -> (Vec<(Uuid, Uuid)>, Vec<String>) {
+    let exported: HashMap<Uuid, &str> = folders.iter().map(|f| (f.id, f.name.as_str())).collect();
+    let mut parents: HashMap<Uuid, Uuid> = HashMap::new();
+    let mut links = Vec::new();
+    let mut errors = Vec::new();
+
+    for folder in folders {
+        let Some(parent_id) = folder.parent_folder_id else { continue };
+
+        if !exported.contains_key(&parent_id) {
+            errors.push(format!("Folder '{}': parent folder not in export, imported at top level", folder.name));
+            continue;
+        }
+
+        // Walk up from the parent; reaching this folder means the link closes a cycle
+        let mut ancestor = Some(parent_id);
+        let mut creates_cycle = false;
+        while let Some(id) = ancestor {
+            if id == folder.id {
+                creates_cycle = true;
+                break;
+            }
+            ancestor = parents.get(&id).copied();
+        }
+        if creates_cycle {
+            errors.push(format!("Folder '{}': parent link would create a cycle, imported at top level", folder.name));
+            continue;
+        }
+
+        parents.insert(folder.id, parent_id);
+        links.push((folder.id, parent_id));
+    }
+
+    (links, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_key_ignores_case_and_padding() {
+        assert_eq!(entry_key(" https://Example.com ", "Alice@Example.com"), entry_key("https://example.com", "alice@example.com"));
+        assert_ne!(entry_key("https://example.com", "alice"), entry_key("https://example.com", "bob"));
+    }
+
+    #[test]
+    fn test_extract_totp_secret() {
+        assert_eq!(
+            extract_totp_secret("otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&issuer=Example"),
+            Some("JBSWY3DPEHPK3PXP".to_string())
+        );
+        assert_eq!(extract_totp_secret("JBSWY3DPEHPK3PXP"), Some("JBSWY3DPEHPK3PXP".to_string()));
+        let eight_digits = "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&digits=8";
+        assert_eq!(extract_totp_secret(eight_digits), Some(eight_digits.to_string()));
+        assert_eq!(extract_totp_secret("  "), None);
+    }
+
+    #[test]
+    fn test_parse_bitwarden_json() {
+        let data = r#"{
+            "encrypted": false,
+            "folders": [{"id": "f1", "name": "Work/Servers"}],
+            "items": [
+                {
+                    "type": 1, "name": "Router", "notes": "rack 2", "folderId": "f1",
+                    "fields": [{"name": "PIN", "value": "1234", "type": 1}],
+                    "login": {
+                        "uris": [{"match": null, "uri": "https://router.local"}],
+                        "username": "admin", "password": "hunter2",
+                        "totp": "otpauth://totp/router?secret=JBSWY3DPEHPK3PXP"
+                    }
+                },
+                {"type": 2, "name": "Secure note", "notes": "text"}
+            ]
+        }"#;
+
+        let items = parse_bitwarden_json(data, None).unwrap();
+        assert_eq!(items.len(), 2);
+
+        match &items[0] {
+            ParsedItem::Entry(entry) => {
+                assert_eq!(entry.url, "https://router.local");
+                assert_eq!(entry.username, "admin");
+                assert_eq!(entry.password, "hunter2");
+                assert_eq!(entry.otp_secret.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+                assert_eq!(entry.folder_path, vec!["Work", "Servers"]);
+                assert_eq!(entry.notes_with_custom_fields(), "rack 2\n\nCustom fields:\nPIN: 1234");
+            }
+            other => panic!("Expected entry, got {:?}", other),
+        }
+        assert!(matches!(items[1], ParsedItem::Skipped { .. }));
+    }
+
+    #[test]
+    fn test_parse_bitwarden_json_rejects_account_restricted() {
+        assert!(parse_bitwarden_json(r#"{"encrypted": true, "items": []}"#, Some("secret")).is_err());
+    }
+
+    // Encrypts like Bitwarden's type 2 encrypted strings so exports can be built in tests
+    fn encrypt_bitwarden(keys: &BitwardenKeys, plaintext: &[u8]) -> String {
+        use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+        use base64::Engine;
+
+        let iv = [9u8; 16];
+        let data = cbc::Encryptor::<aes::Aes256>::new(&keys.enc.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(plaintext);
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, &keys.mac);
+        let mac = ring::hmac::sign(&key, &[iv.as_slice(), data.as_slice()].concat());
+        let b64 = |bytes: &[u8]| base64::engine::general_purpose::STANDARD.encode(bytes);
+        format!("2.{}|{}|{}", b64(&iv), b64(&data), b64(mac.as_ref()))
+    }
+
+    fn password_protected_export(kdf: serde_json::Value, master_key: [u8; 32]) -> String {
+        let keys = BitwardenKeys::stretch(&master_key);
+        let inner = r#"{"encrypted": false, "folders": [{"id": "f1", "name": "Work"}],
+            "items": [{"type": 1, "name": "Mail", "folderId": "f1",
+                       "login": {"username": "alice", "password": "hunter2", "uris": [{"uri": "https://mail.example.com"}]}}]}"#;
+        let mut export = serde_json::json!({
+            "encrypted": true,
+            "passwordProtected": true,
+            "salt": "c2FsdHNhbHRzYWx0c2FsdA==",
+            "encKeyValidation_DO_NOT_EDIT": encrypt_bitwarden(&keys, b"0f1e2d3c-4b5a-6978-8796-a5b4c3d2e1f0"),
+            "data": encrypt_bitwarden(&keys, inner.as_bytes()),
+        });
+        export.as_object_mut().unwrap().extend(kdf.as_object().unwrap().clone());
+        export.to_string()
+    }
+
+    fn assert_decrypted_items(items: Vec<ParsedItem>) {
+        match &items[..] {
+            [ParsedItem::Entry(entry)] => {
+                assert_eq!(entry.name, "Mail");
+                assert_eq!(entry.url, "https://mail.example.com");
+                assert_eq!(entry.password, "hunter2");
+                assert_eq!(entry.folder_path, vec!["Work"]);
+            }
+            other => panic!("Expected one entry, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_password_protected_bitwarden_json_pbkdf2() {
+        let mut master_key = [0u8; 32];
+        ring::pbkdf2::derive(
+            ring::pbkdf2::PBKDF2_HMAC_SHA256,
+            std::num::NonZeroU32::new(5_000).unwrap(),
+            b"c2FsdHNhbHRzYWx0c2FsdA==",
+            b"export password",
+            &mut master_key,
+        );
+        let data = password_protected_export(serde_json::json!({"kdfType": 0, "kdfIterations": 5_000}), master_key);
+
+        assert_decrypted_items(parse_bitwarden_json(&data, Some("export password")).unwrap());
+        assert_eq!(parse_bitwarden_json(&data, Some("wrong password")).unwrap_err(), "Wrong password for the Bitwarden export");
+        assert!(parse_bitwarden_json(&data, None).unwrap_err().contains("provide its password"));
+
+        // A KDF cost outside Bitwarden's own bounds is refused before any work is done
+        let costly = data.replace("\"kdfIterations\":5000", "\"kdfIterations\":100000000");
+        assert!(parse_bitwarden_json(&costly, Some("export password")).unwrap_err().contains("kdfIterations"));
+    }
+
+    #[test]
+    fn test_parse_password_protected_bitwarden_json_argon2id() {
+        let mut master_key = [0u8; 32];
+        let salt_hash = ring::digest::digest(&ring::digest::SHA256, b"c2FsdHNhbHRzYWx0c2FsdA==");
+        argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, argon2::Params::new(15 * 1024, 2, 1, Some(32)).unwrap())
+            .hash_password_into(b"export password", salt_hash.as_ref(), &mut master_key)
+            .unwrap();
+        let kdf = serde_json::json!({"kdfType": 1, "kdfIterations": 2, "kdfMemory": 15, "kdfParallelism": 1});
+        let data = password_protected_export(kdf, master_key);
+
+        assert_decrypted_items(parse_bitwarden_json(&data, Some("export password")).unwrap());
+    }
+
+    #[test]
+    fn test_bitwarden_keys_reject_tampered_data() {
+        let keys = BitwardenKeys::stretch(&[1u8; 32]);
+        let enc_string = encrypt_bitwarden(&keys, b"secret");
+        assert_eq!(keys.decrypt(&enc_string).unwrap(), b"secret");
+
+        let (head, mac) = enc_string.rsplit_once('|').unwrap();
+        let mut mac = mac.to_string();
+        mac.replace_range(0..1, if mac.starts_with('A') { "B" } else { "A" });
+        assert!(keys.decrypt(&format!("{}|{}", head, mac)).is_err());
+        assert!(keys.decrypt("0.abc|def").is_err());
+    }
+
+    #[test]
+    fn test_parse_1pux_export_data() {
+        let data = r#"{
+            "accounts": [{
+                "attrs": {"name": "Alice"},
+                "vaults": [{
+                    "attrs": {"uuid": "v1", "name": "Personal"},
+                    "items": [{
+                        "uuid": "i1", "state": "active", "categoryUuid": "001",
+                        "overview": {"title": "Mail", "url": "https://mail.example.com", "tags": ["Email/Work"]},
+                        "details": {
+                            "loginFields": [
+                                {"value": "alice", "designation": "username"},
+                                {"value": "s3cret", "designation": "password"}
+                            ],
+                            "notesPlain": "main account",
+                            "sections": [{
+                                "title": "",
+                                "fields": [
+                                    {"title": "one-time password", "value": {"totp": "otpauth://totp/mail?secret=JBSWY3DPEHPK3PXP"}},
+                                    {"title": "Recovery email", "value": {"email": "alice@backup.example.com"}}
+                                ]
+                            }]
+                        }
+                    }, {
+                        "uuid": "i2", "state": "active", "categoryUuid": "002",
+                        "overview": {"title": "Visa"}, "details": {}
+                    }]
+                }]
+            }]
+        }"#;
+
+        let items = parse_1pux_export_data(data).unwrap();
+        assert_eq!(items.len(), 2);
+
+        match &items[0] {
+            ParsedItem::Entry(entry) => {
+                assert_eq!(entry.name, "Mail");
+                assert_eq!(entry.username, "alice");
+                assert_eq!(entry.password, "s3cret");
+                assert_eq!(entry.otp_secret.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+                assert_eq!(entry.folder_path, vec!["Personal", "Email", "Work"]);
+                assert_eq!(entry.custom_fields, vec![("Recovery email".to_string(), "alice@backup.example.com".to_string())]);
+            }
+            other => panic!("Expected entry, got {:?}", other),
+        }
+        assert!(matches!(items[1], ParsedItem::Skipped { .. }));
+    }
+
+    fn zip_archive(name: &str, content: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        writer.start_file(name, options).unwrap();
+        writer.write_all(content).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_1pux_export_data_size_limit() {
+        // Highly compressible, like a zip bomb
+        let export_data = format!("{{\"accounts\": [], \"padding\": \"{}\"}}", " ".repeat(4096));
+        let archive = zip_archive("export.data", export_data.as_bytes());
+        assert!(archive.len() < 1024);
+
+        assert_eq!(read_1pux_export_data(&archive, export_data.len() as u64).unwrap(), export_data);
+        assert!(read_1pux_export_data(&archive, export_data.len() as u64 - 1).unwrap_err().contains("larger than"));
+        assert!(read_1pux_export_data(&zip_archive("other.json", b"{}"), 1024).unwrap_err().contains("not found"));
+    }
+
+    #[test]
+    fn test_resolve_folder_parents() {
+        let folder = |name: &str, id: Uuid, parent_folder_id: Option<Uuid>| JsonExportFolder {
+            id,
+            name: name.to_string(),
+            parent_folder_id,
+        };
+        let ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        let folders = vec![
+            folder("Work", ids[0], None),
+            folder("Projects", ids[1], Some(ids[0])),
+            folder("Orphan", ids[2], Some(Uuid::new_v4())),
+            folder("A", ids[3], Some(ids[4])),
+            folder("B", ids[4], Some(ids[3])),
+        ];
+
+        let (links, errors) = resolve_folder_parents(&folders);
+        assert_eq!(links, vec![(ids[1], ids[0]), (ids[3], ids[4])]);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("Orphan"));
+        assert!(errors[1].contains("'B'") && errors[1].contains("cycle"));
+    }
+}
@@ -1,8 +1,9 @@
-//! IP-based controls module for geolocation alerts and IP whitelisting
+//! IP-based controls module for geolocation alerts, IP whitelisting and blocklisting
 
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
 use std::collections::HashSet;
+use std::sync::OnceLock;
 use ipnetwork::IpNetwork;
 use log;
 
@@ -73,29 +74,81 @@ impl IpWhitelist {
         
         // Load from environment variable if set
         if let Ok(whitelist_str) = std::env::var("IP_WHITELIST") {
-            for entry in whitelist_str.split(',') {
-                let entry = entry.trim();
-                if entry.is_empty() {
-                    continue;
-                }
-
-                // Try to parse as IP address first
-                if let Ok(ip) = entry.parse::<IpAddr>() {
-                    whitelist.add_ip(ip);
-                    log::info!("Added IP to whitelist: {}", ip);
-                } else if let Ok(network) = entry.parse::<IpNetwork>() {
-                    whitelist.add_network(network);
-                    log::info!("Added network to whitelist: {}", network);
-                } else {
-                    log::warn!("Invalid IP or network in whitelist: {}", entry);
-                }
-            }
+            let (ips, networks) = parse_ip_list(&whitelist_str, "whitelist");
+            ips.into_iter().for_each(|ip| whitelist.add_ip(ip));
+            networks.into_iter().for_each(|network| whitelist.add_network(network));
         }
 
         whitelist
     }
 }
 
+/// IPs and networks refused outright, checked before the whitelist
+#[derive(Debug, Clone)]
+pub struct IpBlocklist {
+    blocked_ips: HashSet<IpAddr>,
+    blocked_networks: Vec<IpNetwork>,
+}
+
+impl IpBlocklist {
+    pub fn new() -> Self {
+        Self {
+            blocked_ips: HashSet::new(),
+            blocked_networks: Vec::new(),
+        }
+    }
+
+    pub fn add_ip(&mut self, ip: IpAddr) {
+        self.blocked_ips.insert(ip);
+    }
+
+    pub fn add_network(&mut self, network: IpNetwork) {
+        self.blocked_networks.push(network);
+    }
+
+    pub fn is_blocked(&self, ip: &IpAddr) -> bool {
+        self.blocked_ips.contains(ip) || self.blocked_networks.iter().any(|network| network.contains(*ip))
+    }
+
+    pub fn from_env() -> Self {
+        let mut blocklist = Self::new();
+
+        if let Ok(blocklist_str) = std::env::var("IP_BLOCKLIST") {
+            let (ips, networks) = parse_ip_list(&blocklist_str, "blocklist");
+            ips.into_iter().for_each(|ip| blocklist.add_ip(ip));
+            networks.into_iter().for_each(|network| blocklist.add_network(network));
+        }
+
+        blocklist
+    }
+}
+
+/// Parses a comma-separated list of IPs and CIDR networks, skipping invalid entries
+fn parse_ip_list(list: &str, list_name: &str) -> (HashSet<IpAddr>, Vec<IpNetwork>) {
+    let mut ips = HashSet::new();
+    let mut networks = Vec::new();
+
+    for entry in list.split(',') {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+
+        // Try to parse as IP address first
+        if let Ok(ip) = entry.parse::<IpAddr>() {
+            ips.insert(ip);
+            log::info!("Added IP to {}: {}", list_name, ip);
+        } else if let Ok(network) = entry.parse::<IpNetwork>() {
+            networks.push(network);
+            log::info!("Added network to {}: {}", list_name, network);
+        } else {
+            log::warn!("Invalid IP or network in {}: {}", list_name, entry);
+        }
+    }
+
+    (ips, networks)
+}
+
 /// Get geolocation information for an IP address using ip-api.com
 #[allow(dead_code)]
 pub async fn get_geolocation(ip: &IpAddr) -> Result<GeolocationInfo, String> {
@@ -159,35 +212,87 @@ fn is_private_ip(ip: &IpAddr) -> bool {
     }
 }
 
-/// Extract client IP from request headers
-pub fn extract_client_ip(req: &actix_web::HttpRequest) -> Option<IpAddr> {
-    // Check X-Forwarded-For header first (for reverse proxies)
-    if let Some(forwarded_for) = req.headers().get("X-Forwarded-For") {
-        if let Ok(forwarded_str) = forwarded_for.to_str() {
-            // Take the first IP in the chain
-            if let Some(first_ip) = forwarded_str.split(',').next() {
-                if let Ok(ip) = first_ip.trim().parse::<IpAddr>() {
-                    return Some(ip);
-                }
-            }
+/// Reverse proxies whose X-Forwarded-For header is trusted
+#[derive(Debug, Clone)]
+pub struct TrustedProxies {
+    proxy_ips: HashSet<IpAddr>,
+    proxy_networks: Vec<IpNetwork>,
+}
+
+impl TrustedProxies {
+    pub fn new() -> Self {
+        Self {
+            proxy_ips: HashSet::new(),
+            proxy_networks: Vec::new(),
         }
     }
 
-    // Check X-Real-IP header
-    if let Some(real_ip) = req.headers().get("X-Real-IP") {
-        if let Ok(ip_str) = real_ip.to_str() {
-            if let Ok(ip) = ip_str.parse::<IpAddr>() {
-                return Some(ip);
-            }
+    pub fn add_ip(&mut self, ip: IpAddr) {
+        self.proxy_ips.insert(ip);
+    }
+
+    pub fn add_network(&mut self, network: IpNetwork) {
+        self.proxy_networks.push(network);
+    }
+
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.proxy_ips.contains(ip) || self.proxy_networks.iter().any(|network| network.contains(*ip))
+    }
+
+    pub fn from_env() -> Self {
+        let mut proxies = Self::new();
+
+        if let Ok(proxies_str) = std::env::var("TRUSTED_PROXIES") {
+            let (ips, networks) = parse_ip_list(&proxies_str, "trusted proxies");
+            ips.into_iter().for_each(|ip| proxies.add_ip(ip));
+            networks.into_iter().for_each(|network| proxies.add_network(network));
         }
+
+        proxies
+    }
+}
+
+/// Trusted proxies read once from the environment
+fn trusted_proxies() -> &'static TrustedProxies {
+    static TRUSTED_PROXIES: OnceLock<TrustedProxies> = OnceLock::new();
+    TRUSTED_PROXIES.get_or_init(TrustedProxies::from_env)
+}
+
+/// Resolves the client IP from the socket peer and its X-Forwarded-For header
+///
+/// The header is only honoured when the peer is a trusted proxy. Entries are walked from the
+/// right, skipping further trusted proxies, and the first untrusted entry is the client. A
+/// malformed entry makes the whole header untrustworthy, so the peer is used instead.
+pub fn resolve_client_ip(peer: IpAddr, forwarded_for: Option<&str>, proxies: &TrustedProxies) -> IpAddr {
+    if !proxies.is_trusted(&peer) {
+        return peer;
     }
+    let Some(forwarded_for) = forwarded_for else {
+        return peer;
+    };
 
-    // Fall back to connection info
-    if let Some(peer_addr) = req.peer_addr() {
-        return Some(peer_addr.ip());
+    let mut client = peer;
+    for entry in forwarded_for.rsplit(',') {
+        match entry.trim().parse::<IpAddr>() {
+            Ok(ip) if proxies.is_trusted(&ip) => client = ip,
+            Ok(ip) => return ip,
+            Err(_) => return peer,
+        }
     }
 
-    None
+    // Every hop was a trusted proxy; the leftmost one is the closest we have to the client
+    client
+}
+
+/// Extract client IP from the connection, honouring X-Forwarded-For only from trusted proxies
+pub fn extract_client_ip(req: &actix_web::HttpRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let forwarded_for = req
+        .headers()
+        .get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok());
+
+    Some(resolve_client_ip(peer, forwarded_for, trusted_proxies()))
 }
 
 /// Check if login is from a suspicious location
@@ -236,6 +341,51 @@ mod tests {
         assert!(!whitelist.is_allowed(&ip_outside_network));
     }
 
+    #[test]
+    fn test_ip_blocklist() {
+        let mut blocklist = IpBlocklist::new();
+        let ip = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+        assert!(!blocklist.is_blocked(&ip));
+
+        blocklist.add_ip(ip);
+        blocklist.add_network("198.51.100.0/24".parse::<IpNetwork>().unwrap());
+        assert!(blocklist.is_blocked(&ip));
+        assert!(blocklist.is_blocked(&IpAddr::V4(Ipv4Addr::new(198, 51, 100, 42))));
+        assert!(!blocklist.is_blocked(&IpAddr::V4(Ipv4Addr::new(198, 51, 101, 1))));
+    }
+
+    #[test]
+    fn test_parse_ip_list() {
+        let (ips, networks) = parse_ip_list("203.0.113.7, 10.0.0.0/8,,not-an-ip, ::1", "blocklist");
+        assert_eq!(ips.len(), 2);
+        assert_eq!(networks.len(), 1);
+    }
+
+    #[test]
+    fn test_resolve_client_ip_through_trusted_proxies() {
+        let mut proxies = TrustedProxies::new();
+        proxies.add_network("10.0.0.0/8".parse::<IpNetwork>().unwrap());
+        let proxy = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let client = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+
+        // The rightmost untrusted entry wins, so a client-supplied leftmost entry is ignored
+        assert_eq!(resolve_client_ip(proxy, Some("1.2.3.4, 203.0.113.7, 10.0.0.5"), &proxies), client);
+        assert_eq!(resolve_client_ip(proxy, Some("203.0.113.7"), &proxies), client);
+        assert_eq!(resolve_client_ip(proxy, Some("10.0.0.9, 10.0.0.5"), &proxies), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9)));
+
+        // Absent or malformed headers fall back to the socket address
+        assert_eq!(resolve_client_ip(proxy, None, &proxies), proxy);
+        assert_eq!(resolve_client_ip(proxy, Some("203.0.113.7, garbage"), &proxies), proxy);
+        assert_eq!(resolve_client_ip(proxy, Some(""), &proxies), proxy);
+    }
+
+    #[test]
+    fn test_resolve_client_ip_ignores_untrusted_peer() {
+        let proxies = TrustedProxies::new();
+        let peer = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1));
+        assert_eq!(resolve_client_ip(peer, Some("127.0.0.1"), &proxies), peer);
+    }
+
     #[test]
     fn test_private_ip_detection() {
         assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))));
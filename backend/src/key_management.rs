@@ -115,14 +115,7 @@ impl KeyManager {
         let key_material = env::var("ENCRYPTION_KEY")
             .map_err(|_| "ENCRYPTION_KEY environment variable must be set".to_string())?;
 
-        let key_bytes = key_material.as_bytes();
-        if key_bytes.len() != 32 {
-            return Err("ENCRYPTION_KEY must be exactly 32 bytes for AES-256-GCM".to_string());
-        }
-
-        aead::UnboundKey::new(&aead::AES_256_GCM, key_bytes)
-            .map(aead::LessSafeKey::new)
-            .map_err(|e| format!("Failed to create encryption key: {}", e))
+        key_from_material("ENCRYPTION_KEY", key_material.as_bytes())
     }
 
     #[allow(dead_code)]
@@ -221,6 +214,25 @@ impl KeyManager {
     }
 }
 
+/// Loads an AES-256-GCM key from the named environment variable
+pub fn key_from_env_var(var_name: &str) -> Result<aead::LessSafeKey, String> {
+    let key_material = env::var(var_name)
+        .map_err(|_| format!("{} environment variable must be set", var_name))?;
+
+    key_from_material(var_name, key_material.as_bytes())
+}
+
+/// Builds an AES-256-GCM key, naming its source in errors
+fn key_from_material(source: &str, key_bytes: &[u8]) -> Result<aead::LessSafeKey, String> {
+    if key_bytes.len() != 32 {
+        return Err(format!("{} must be exactly 32 bytes for AES-256-GCM", source));
+    }
+
+    aead::UnboundKey::new(&aead::AES_256_GCM, key_bytes)
+        .map(aead::LessSafeKey::new)
+        .map_err(|e| format!("Failed to create encryption key: {}", e))
+}
+
 // Singleton instance for global access
 use std::sync::OnceLock;
 #[allow(dead_code)]
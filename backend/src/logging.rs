@@ -0,0 +1,154 @@
+//! Logging module for the process-wide logger
+//!
+//! Logs are human-readable text by default. With LOG_FORMAT=json every record is written as one
+//! JSON object per line with `timestamp`, `level`, `target` and `message`, for log aggregators.
+//! Request-scoped fields are attached as key-values and become fields of their own:
+//! `log::info!(client_ip = ip.as_str(), user_id:% = id; "Password changed")`.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::kv::{self, VisitSource, VisitValue};
+use log::Record;
+use serde_json::{Map, Number, Value};
+use std::env;
+use std::io::Write;
+
+/// How log records are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Installs the logger, honouring RUST_LOG for levels and LOG_FORMAT for the output format
+pub fn init() {
+    let format = parse_format(env::var("LOG_FORMAT").ok().as_deref());
+
+    let mut builder = env_logger::Builder::from_default_env();
+    if format == Ok(LogFormat::Json) {
+        builder.format(|buf, record| writeln!(buf, "{}", json_line(record, Utc::now())));
+    }
+    builder.init();
+
+    if let Err(value) = format {
+        log::warn!("Unknown LOG_FORMAT {:?}, using text", value);
+    }
+}
+
+fn parse_format(value: Option<&str>) -> Result<LogFormat, String> {
+    match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+        None | Some("") | Some("text") => Ok(LogFormat::Text),
+        Some("json") => Ok(LogFormat::Json),
+        Some(other) => Err(other.to_string()),
+    }
+}
+
+/// Renders a record as a single JSON line
+fn json_line(record: &Record, timestamp: DateTime<Utc>) -> String {
+    let mut fields = Map::new();
+    fields.insert("timestamp".to_string(), Value::String(timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)));
+    fields.insert("level".to_string(), Value::String(record.level().to_string()));
+    fields.insert("target".to_string(), Value::String(record.target().to_string()));
+    fields.insert("message".to_string(), Value::String(record.args().to_string()));
+
+    // Key-values can't replace the fields above
+    let _ = record.key_values().visit(&mut FieldCollector(&mut fields));
+
+    Value::Object(fields).to_string()
+}
+
+struct FieldCollector<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for FieldCollector<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let mut json = JsonValue(Value::Null);
+        value.visit(&mut json)?;
+        self.0.entry(key.as_str().to_string()).or_insert(json.0);
+        Ok(())
+    }
+}
+
+/// Keeps numbers, booleans and missing values typed instead of turning everything into text
+struct JsonValue(Value);
+
+impl<'v> VisitValue<'v> for JsonValue {
+    fn visit_any(&mut self, value: kv::Value) -> Result<(), kv::Error> {
+        self.0 = Value::String(value.to_string());
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        self.0 = Value::Null;
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+        self.0 = Value::from(value);
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+        self.0 = Value::from(value);
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+        self.0 = Number::from_f64(value).map(Value::Number).unwrap_or_else(|| Value::String(value.to_string()));
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+        self.0 = Value::Bool(value);
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), kv::Error> {
+        self.0 = Value::String(value.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(parse_format(None), Ok(LogFormat::Text));
+        assert_eq!(parse_format(Some("text")), Ok(LogFormat::Text));
+        assert_eq!(parse_format(Some(" JSON ")), Ok(LogFormat::Json));
+        assert_eq!(parse_format(Some("logfmt")), Err("logfmt".to_string()));
+    }
+
+    #[test]
+    fn test_json_line() {
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 123_000_000).unwrap();
+        let user_id: Option<&str> = None;
+        let key_values: [(&str, kv::Value); 5] = [
+            ("client_ip", kv::Value::from("203.0.113.7")),
+            ("user_id", kv::ToValue::to_value(&user_id)),
+            ("status", kv::Value::from(401u16)),
+            ("mfa", kv::Value::from(true)),
+            ("level", kv::Value::from("spoofed")),
+        ];
+        let line = json_line(
+            &Record::builder()
+                .args(format_args!("Login \"failed\""))
+                .level(log::Level::Warn)
+                .target("backend::handlers")
+                .key_values(&key_values)
+                .build(),
+            timestamp,
+        );
+
+        assert!(!line.contains('\n'));
+        let parsed: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed["timestamp"], "2023-11-14T22:13:20.123Z");
+        assert_eq!(parsed["level"], "WARN");
+        assert_eq!(parsed["target"], "backend::handlers");
+        assert_eq!(parsed["message"], "Login \"failed\"");
+        assert_eq!(parsed["client_ip"], "203.0.113.7");
+        assert_eq!(parsed["user_id"], Value::Null);
+        assert_eq!(parsed["status"], 401);
+        assert_eq!(parsed["mfa"], true);
+    }
+}
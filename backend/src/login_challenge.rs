@@ -0,0 +1,159 @@
+//! Login challenge module for stepping up high-risk password logins
+//!
+//! When the risk assessment of a login asks for MFA and the account has no TOTP, a six-digit code
+//! is emailed to the user and the login only completes once the code is posted back. Codes are
+//! stored hashed, expire after a few minutes and allow a handful of attempts.
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use uuid::Uuid;
+
+/// How long an emailed login code stays valid
+pub const CHALLENGE_EXPIRY_MINUTES: i64 = 10;
+
+/// Wrong codes accepted before a challenge is thrown away
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+/// Result of answering a login challenge
+#[derive(Debug, PartialEq)]
+pub enum ChallengeOutcome {
+    /// The code matched; the login of this user can complete
+    Verified(Uuid),
+    /// The code was wrong but the challenge can be retried
+    Invalid,
+    /// No such challenge, or it expired or ran out of attempts
+    Expired,
+}
+
+/// Generates a uniformly random six-digit code
+pub fn generate_code() -> Result<String, String> {
+    let rng = SystemRandom::new();
+    // Largest multiple of a million that fits, so the modulo doesn't favour low codes
+    let limit = u32::MAX - u32::MAX % 1_000_000;
+    loop {
+        let mut bytes = [0u8; 4];
+        rng.fill(&mut bytes)
+            .map_err(|_| "Failed to generate login code".to_string())?;
+        let value = u32::from_le_bytes(bytes);
+        if value < limit {
+            return Ok(format!("{:06}", value % 1_000_000));
+        }
+    }
+}
+
+/// Hash of a code, salted with its challenge id
+fn hash_code(challenge_id: Uuid, code: &str) -> String {
+    let mut input = challenge_id.as_bytes().to_vec();
+    input.extend_from_slice(code.trim().as_bytes());
+    hex::encode(digest(&SHA256, &input))
+}
+
+/// Whether a challenge may still be answered
+fn is_usable(attempts: i32, expires_at: NaiveDateTime, now: NaiveDateTime) -> bool {
+    attempts < MAX_CHALLENGE_ATTEMPTS && expires_at > now
+}
+
+/// Stores a challenge for the code, replacing any the user had open
+pub fn create(conn: &mut PgConnection, user_id: Uuid, code: &str, now: NaiveDateTime) -> QueryResult<Uuid> {
+    use crate::schema::login_challenges;
+
+    let challenge_id = Uuid::new_v4();
+    conn.transaction(|conn| {
+        diesel::delete(login_challenges::table.filter(login_challenges::user_id.eq(user_id)))
+            .execute(conn)?;
+
+        diesel::insert_into(login_challenges::table)
+            .values((
+                login_challenges::id.eq(challenge_id),
+                login_challenges::user_id.eq(user_id),
+                login_challenges::code_hash.eq(hash_code(challenge_id, code)),
+                login_challenges::expires_at.eq(now + Duration::minutes(CHALLENGE_EXPIRY_MINUTES)),
+                login_challenges::created_at.eq(now),
+            ))
+            .execute(conn)
+    })?;
+
+    Ok(challenge_id)
+}
+
+/// The user a challenge was issued to, if it exists
+pub fn challenge_user(conn: &mut PgConnection, challenge_id: Uuid) -> QueryResult<Option<Uuid>> {
+    use crate::schema::login_challenges;
+
+    login_challenges::table
+        .find(challenge_id)
+        .select(login_challenges::user_id)
+        .first(conn)
+        .optional()
+}
+
+/// Checks a code against a challenge, consuming the challenge once it is answered correctly
+pub fn verify(conn: &mut PgConnection, challenge_id: Uuid, code: &str, now: NaiveDateTime) -> QueryResult<ChallengeOutcome> {
+    use crate::schema::login_challenges;
+
+    conn.transaction(|conn| {
+        let challenge = login_challenges::table
+            .find(challenge_id)
+            .select((login_challenges::user_id, login_challenges::code_hash, login_challenges::attempts, login_challenges::expires_at))
+            .for_update()
+            .first::<(Uuid, String, i32, NaiveDateTime)>(conn)
+            .optional()?;
+
+        let (user_id, code_hash, attempts, expires_at) = match challenge {
+            Some(challenge) => challenge,
+            None => return Ok(ChallengeOutcome::Expired),
+        };
+
+        if !is_usable(attempts, expires_at, now) {
+            diesel::delete(login_challenges::table.find(challenge_id)).execute(conn)?;
+            return Ok(ChallengeOutcome::Expired);
+        }
+
+        if hash_code(challenge_id, code) == code_hash {
+            diesel::delete(login_challenges::table.find(challenge_id)).execute(conn)?;
+            return Ok(ChallengeOutcome::Verified(user_id));
+        }
+
+        diesel::update(login_challenges::table.find(challenge_id))
+            .set(login_challenges::attempts.eq(attempts + 1))
+            .execute(conn)?;
+        Ok(ChallengeOutcome::Invalid)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_generate_code() {
+        for _ in 0..20 {
+            let code = generate_code().unwrap();
+            assert_eq!(code.len(), 6);
+            assert!(code.chars().all(|c| c.is_ascii_digit()));
+        }
+    }
+
+    #[test]
+    fn test_hash_code_is_salted_with_challenge() {
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        assert_eq!(hash_code(first, "123456"), hash_code(first, " 123456 "));
+        assert_ne!(hash_code(first, "123456"), hash_code(second, "123456"));
+        assert_ne!(hash_code(first, "123456"), hash_code(first, "123457"));
+    }
+
+    #[test]
+    fn test_challenge_usable() {
+        let now = Utc::now().naive_utc();
+        let expires_at = now + Duration::minutes(CHALLENGE_EXPIRY_MINUTES);
+        assert!(is_usable(0, expires_at, now));
+        assert!(is_usable(MAX_CHALLENGE_ATTEMPTS - 1, expires_at, now));
+        assert!(!is_usable(MAX_CHALLENGE_ATTEMPTS, expires_at, now));
+        assert!(!is_usable(0, now, now));
+    }
+}
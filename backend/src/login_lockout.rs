@@ -0,0 +1,188 @@
+//! Login lockout module for per-account brute force protection
+//!
+//! Failed logins are recorded per user id, so the username and the email of an account share one
+//! count. Once an account collects the threshold number of failures within the window, further
+//! logins are refused until the oldest counted failures fall out of the window. Failures for
+//! identifiers that match no account are all recorded under UNKNOWN_USER.
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use std::env;
+use uuid::Uuid;
+
+const DEFAULT_MAX_ATTEMPTS: usize = 5;
+const DEFAULT_WINDOW_MINUTES: i64 = 15;
+
+/// Key recording failed logins for identifiers that don't match any account
+pub const UNKNOWN_USER: Uuid = Uuid::nil();
+
+/// How many failures within how long lock an account
+#[derive(Debug, Clone, Copy)]
+pub struct LockoutPolicy {
+    pub max_attempts: usize,
+    pub window: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            window: Duration::minutes(DEFAULT_WINDOW_MINUTES),
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// Reads LOGIN_LOCKOUT_THRESHOLD and LOGIN_LOCKOUT_WINDOW_MINUTES; a threshold of 0 disables lockout
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let max_attempts = env::var("LOGIN_LOCKOUT_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(defaults.max_attempts);
+        let window = env::var("LOGIN_LOCKOUT_WINDOW_MINUTES")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|minutes| *minutes > 0)
+            .map(Duration::minutes)
+            .unwrap_or(defaults.window);
+
+        Self { max_attempts, window }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_attempts > 0
+    }
+
+    /// Returns how long the account stays locked given its failures in ascending order
+    pub fn remaining_lockout(&self, failures: &[NaiveDateTime], now: NaiveDateTime) -> Option<Duration> {
+        if !self.enabled() {
+            return None;
+        }
+
+        let window_start = now - self.window;
+        let recent: Vec<&NaiveDateTime> = failures.iter().filter(|at| **at > window_start).collect();
+        if recent.len() < self.max_attempts {
+            return None;
+        }
+
+        // Unlocks once enough failures age out that fewer than the threshold remain
+        let unlock_at = *recent[recent.len() - self.max_attempts] + self.window;
+        Some(unlock_at - now).filter(|remaining| *remaining > Duration::zero())
+    }
+}
+
+/// Returns the remaining lockout for an account, if it is currently locked
+pub fn check(conn: &mut PgConnection, policy: &LockoutPolicy, user_id: Uuid, now: NaiveDateTime) -> QueryResult<Option<Duration>> {
+    use crate::schema::failed_login_attempts;
+
+    if !policy.enabled() {
+        return Ok(None);
+    }
+
+    let failures = failed_login_attempts::table
+        .filter(failed_login_attempts::user_id.eq(user_id))
+        .filter(failed_login_attempts::attempted_at.gt(now - policy.window))
+        .order(failed_login_attempts::attempted_at.asc())
+        .select(failed_login_attempts::attempted_at)
+        .load::<NaiveDateTime>(conn)?;
+
+    Ok(policy.remaining_lockout(&failures, now))
+}
+
+/// Number of failed logins for an account that still count towards a lockout
+pub fn recent_failures(conn: &mut PgConnection, policy: &LockoutPolicy, user_id: Uuid, now: NaiveDateTime) -> QueryResult<usize> {
+    use crate::schema::failed_login_attempts;
+
+    if !policy.enabled() {
+        return Ok(0);
+    }
+
+    let count = failed_login_attempts::table
+        .filter(failed_login_attempts::user_id.eq(user_id))
+        .filter(failed_login_attempts::attempted_at.gt(now - policy.window))
+        .count()
+        .get_result::<i64>(conn)?;
+
+    Ok(count as usize)
+}
+
+/// Records a failed login and drops failures that no longer count towards a lockout
+pub fn record_failure(
+    conn: &mut PgConnection,
+    policy: &LockoutPolicy,
+    user_id: Uuid,
+    ip_address: Option<String>,
+    now: NaiveDateTime,
+) -> QueryResult<()> {
+    use crate::schema::failed_login_attempts;
+
+    if !policy.enabled() {
+        return Ok(());
+    }
+
+    diesel::delete(
+        failed_login_attempts::table
+            .filter(failed_login_attempts::user_id.eq(user_id))
+            .filter(failed_login_attempts::attempted_at.le(now - policy.window)),
+    )
+    .execute(conn)?;
+
+    diesel::insert_into(failed_login_attempts::table)
+        .values((
+            failed_login_attempts::id.eq(Uuid::new_v4()),
+            failed_login_attempts::user_id.eq(user_id),
+            failed_login_attempts::ip_address.eq(ip_address),
+            failed_login_attempts::attempted_at.eq(now),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Clears the failure count after a successful login
+pub fn reset(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<usize> {
+    use crate::schema::failed_login_attempts;
+
+    diesel::delete(failed_login_attempts::table.filter(failed_login_attempts::user_id.eq(user_id)))
+        .execute(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn policy() -> LockoutPolicy {
+        LockoutPolicy { max_attempts: 3, window: Duration::minutes(10) }
+    }
+
+    #[test]
+    fn test_repeated_failures_cross_threshold() {
+        let start = Utc::now().naive_utc();
+        let mut failures = Vec::new();
+
+        for attempt in 0..3 {
+            let now = start + Duration::minutes(attempt);
+            assert_eq!(policy().remaining_lockout(&failures, now), None);
+            failures.push(now);
+        }
+
+        // Third failure at start+2m; the first one ages out at start+10m
+        let now = start + Duration::minutes(2);
+        assert_eq!(policy().remaining_lockout(&failures, now), Some(Duration::minutes(8)));
+        assert_eq!(policy().remaining_lockout(&failures, start + Duration::minutes(10)), None);
+    }
+
+    #[test]
+    fn test_old_failures_and_disabled_policy() {
+        let now = Utc::now().naive_utc();
+        let old = vec![now - Duration::minutes(30), now - Duration::minutes(20), now - Duration::minutes(11)];
+        assert_eq!(policy().remaining_lockout(&old, now), None);
+
+        let recent = vec![now - Duration::minutes(3), now - Duration::minutes(2), now - Duration::minutes(1)];
+        let disabled = LockoutPolicy { max_attempts: 0, ..policy() };
+        assert_eq!(disabled.remaining_lockout(&recent, now), None);
+    }
+}
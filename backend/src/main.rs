@@ -3,23 +3,48 @@
 #[macro_use]
 mod audit;
 mod auth;
+mod attachments;
+mod breach_check;
+mod cleanup;
+mod cors;
 mod crypto;
+mod csrf;
+mod csv_export;
+mod csv_import;
 mod db;
 mod email;
+mod email_change;
 mod enhanced_auth_handlers;
 mod enterprise_session_manager;
+mod folder_tree;
+mod importers;
 mod ip_controls;
 mod key_management;
+mod login_challenge;
+mod login_lockout;
+mod logging;
 mod mfa;
 mod models;
 mod oauth;
+mod password_generator;
+mod password_health;
+mod password_policy;
+mod password_reset;
+mod quotas;
+mod rekey;
+mod request_id;
 mod schema;
+mod security_headers;
+mod sharing;
 mod sso_auth;
+mod token_binding;
 mod token_management;
+mod trash;
+mod user_agent;
+mod webauthn;
 mod zero_knowledge;
 
 use actix_web::{web, App, HttpServer, middleware::Logger, http::header, dev::{ServiceRequest, ServiceResponse}, Error, Result};
-use actix_cors::Cors;
 use actix_governor::{Governor, GovernorConfigBuilder};
 use dotenv::dotenv;
 use std::env;
@@ -30,7 +55,15 @@ use std::future::{ready, Ready};
 use std::rc::Rc;
 
 // CSP Middleware
-pub struct CspMiddleware;
+pub struct CspMiddleware {
+    headers: security_headers::SecurityHeaders,
+}
+
+impl CspMiddleware {
+    pub fn new(headers: security_headers::SecurityHeaders) -> Self {
+        Self { headers }
+    }
+}
 
 impl<S, B> Transform<S, ServiceRequest> for CspMiddleware
 where
@@ -47,12 +80,14 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(CspMiddlewareService {
             service: Rc::new(service),
+            headers: self.headers.clone(),
         }))
     }
 }
 
 pub struct CspMiddlewareService<S> {
     service: Rc<S>,
+    headers: security_headers::SecurityHeaders,
 }
 
 impl<S, B> Service<ServiceRequest> for CspMiddlewareService<S>
@@ -69,16 +104,17 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let service = self.service.clone();
+        let headers = self.headers.clone();
+        // Honours X-Forwarded-Proto, so HSTS also works behind a TLS-terminating proxy
+        let https = req.connection_info().scheme() == "https";
         
         Box::pin(async move {
             let mut res = service.call(req).await?;
             
             // Add CSP headers
-            let csp_policy = "default-src 'self'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline'; img-src 'self' data: https:; font-src 'self'; connect-src 'self'; frame-ancestors 'none'; base-uri 'self'; form-action 'self'";
-            
             res.headers_mut().insert(
                 header::HeaderName::from_static("content-security-policy"),
-                header::HeaderValue::from_static(csp_policy),
+                headers.content_security_policy,
             );
             
             // Add additional security headers
@@ -97,6 +133,16 @@ where
                 header::HeaderValue::from_static("strict-origin-when-cross-origin"),
             );
             
+            res.headers_mut().insert(
+                header::HeaderName::from_static("permissions-policy"),
+                header::HeaderValue::from_static(security_headers::PERMISSIONS_POLICY),
+            );
+            
+            // Browsers ignore HSTS over plain HTTP, and sending it there only invites confusion
+            if let Some(hsts) = headers.strict_transport_security.filter(|_| https) {
+                res.headers_mut().insert(header::STRICT_TRANSPORT_SECURITY, hsts);
+            }
+            
             Ok(res)
         })
     }
@@ -105,29 +151,71 @@ where
 mod handlers {
     use actix_web::{web, Error, HttpResponse};
     use uuid::Uuid;
-    use crate::{auth, db, crypto, ip_controls, mfa, models::{UserRegistration, UserLogin, ApiResponse, NewUser, User, Password, NewPassword, PasswordRequest, PasswordMoveRequest, PasswordResponse, Folder, NewFolder, FolderRequest, Share, ShareRequest, PasswordResetRequest, PasswordResetConfirm, RefreshTokenRequest, ChangePasswordRequest}};
+    use crate::{attachments, auth, breach_check, db, crypto, csv_export::{self, strip_formula_guard}, csv_import::{CsvFormat, detect_csv_format, parse_csv_line, split_csv_records}, email_change, folder_tree, importers, ip_controls, login_challenge, login_lockout, mfa, password_generator, password_health, rekey, sharing::{self, PasswordAccess, PermissionLevel}, trash, webauthn, models::{UserRegistration, UserLogin, ApiResponse, NewUser, User, UserProfile, Password, NewPassword, PasswordRequest, PasswordMoveRequest, FavoriteRequest, BulkMoveRequest, PasswordResponse, TrashedPasswordResponse, PasswordHistoryEntry, NewPasswordHistory, PasswordAttachment, NewPasswordAttachment, CreatedPasswordResponse, Folder, NewFolder, FolderRequest, FolderDeleteQuery, FolderDeleteSummary, Share, ShareRequest, ShareUpdateRequest, PasswordShareEntry, Group, NewGroup, GroupMember, GroupRequest, GroupMemberRequest, DecryptionErrors, DecryptedListResponse, JsonExportDocument, JsonExportFolder, JsonExportEntry, PasswordStrengthRequest, PasswordResetRequest, PasswordResetConfirm, RefreshTokenRequest, ChangePasswordRequest, EmailChangeRequest, EmailChangeConfirm, MfaConfirmRequest, MfaChallengeRequest, MfaDisableRequest, MfaEnrollmentResponse, MfaRecoveryCodesRequest, MfaRecoveryCodesResponse, PasskeyRegistrationStart, PasskeyRegistrationFinish, PasskeyLoginStart, PasskeyLoginFinish, AccountDeletionRequest, NotificationPreferences, NotificationPreferencesUpdate, AccountDeletionSummary, RekeyRequest, ValidationErrors, ValidationErrorResponse}, zero_knowledge};
     use diesel::prelude::*;
     use serde::{Deserialize, Serialize};
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
 
     #[derive(Deserialize)]
     pub struct CsvImportRequest {
         pub csv_data: String,
     }
 
+    #[derive(Deserialize)]
+    pub struct CsvImportQuery {
+        #[serde(default)]
+        pub atomic: bool, // Roll the whole import back if any row fails
+        #[serde(default)]
+        pub skip_existing: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ImportQuery {
+        #[serde(default)]
+        pub skip_existing: bool, // Skip entries whose website and username are already stored
+    }
+
+    #[derive(Serialize, Default)]
+    pub struct CsvImportSummary {
+        pub atomic: bool,
+        pub imported: usize,
+        pub skipped: usize,
+        pub failed: usize,
+        pub folders_created: usize,
+        pub errors: Vec<String>,
+    }
+
     #[derive(Deserialize)]
     pub struct CsvExportRequest {
         pub password: String,
     }
 
-    #[derive(Serialize)]
-    pub struct CsvExportEntry {
-        pub name: String,
-        pub url: String,
-        pub username: String,
-        pub password: String,
-        pub notes: String,
-        pub folder: String,
+    #[derive(Deserialize)]
+    pub struct CreatePasswordQuery {
+        #[serde(default)]
+        pub check_breach: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub struct PasswordListQuery {
+        #[serde(default)]
+        pub favorites_only: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub struct OnePuxImportRequest {
+        pub file_data: String, // base64-encoded .1pux archive
+    }
+
+    #[derive(Deserialize)]
+    pub struct BitwardenJsonImportRequest {
+        pub json_data: String,
+        pub password: Option<String>, // Only for password-protected exports
+    }
+
+    // 400 response listing every invalid field next to the usual top-level message
+    fn validation_error_response(errors: ValidationErrors) -> HttpResponse {
+        HttpResponse::BadRequest().json(ValidationErrorResponse::from(errors))
     }
     
     // User registration handler
@@ -137,109 +225,103 @@ mod handlers {
     ) -> Result<HttpResponse, Error> {
         use crate::schema::users;
         
-        // Validate email format
+        // Validate every field, so the client can show all problems at once
+        let mut errors = ValidationErrors::default();
+        
         let email_regex = regex::Regex::new(r"^[^\s@]+@[^\s@]+\.[^\s@]+$").unwrap();
         if !email_regex.is_match(&user_data.email) {
-            return Ok(HttpResponse::BadRequest().json(
-                ApiResponse::<()>::error("Invalid email format".to_string())
-            ));
+            errors.add("email", "Invalid email format".to_string(), "Invalid email format".to_string());
         }
         
-        // Validate input using auth module
-        match auth::sanitize_username(&user_data.username) {
-            Ok(sanitized_username) => {
-                // Validate password strength
-                match auth::validate_password(&user_data.password) {
-                    Ok(_) => {
-                        // Check if user already exists
-                        let mut conn = db_pool.get().map_err(|e| {
-                            log::error!("Failed to get database connection: {}", e);
-                            actix_web::error::ErrorInternalServerError("Database connection error")
-                        })?;
-                        
-                        use diesel::prelude::*;
-                        
-                        // Check if username already exists
-                        let username_exists = users::table
-                            .filter(users::username.eq(&sanitized_username))
-                            .select(User::as_select())
-                            .first(&mut conn)
-                            .optional()
-                            .map_err(|e| {
-                                log::error!("Database error: {}", e);
-                                actix_web::error::ErrorInternalServerError("Database error")
-                            })?;
-                        
-                        if username_exists.is_some() {
-                            return Ok(HttpResponse::BadRequest().json(
-                                ApiResponse::<()>::error("Username already exists".to_string())
-                            ));
-                        }
-                        
-                        // Check if email already exists
-                        let email_exists = users::table
-                            .filter(users::email.eq(&user_data.email))
-                            .select(User::as_select())
-                            .first(&mut conn)
-                            .optional()
-                            .map_err(|e| {
-                                log::error!("Database error: {}", e);
-                                actix_web::error::ErrorInternalServerError("Database error")
-                            })?;
-                        
-                        if email_exists.is_some() {
-                            return Ok(HttpResponse::BadRequest().json(
-                                ApiResponse::<()>::error("Email already exists".to_string())
-                            ));
-                        }
-                        
-                        // Hash the password
-                        let password_hash = auth::hash_password(&user_data.password);
-                        
-                        // Create new user
-                        let new_user = NewUser {
-                            id: Uuid::new_v4(),
-                            username: sanitized_username,
-                            password_hash: password_hash,
-                            salt: "".to_string(), // In a real app, you'd generate and store a proper salt
-                            mfa_secret: None, // Default to no MFA initially
-                            reset_token: None,
-                            reset_token_expires_at: None,
-                            email: user_data.email.clone(),
-                            auth_method: Some("password".to_string()),
-                            is_sso_user: Some(false),
-                            sso_display_name: None,
-                            sso_avatar_url: None,
-                        };
-                        
-                        // Insert user into database
-                        diesel::insert_into(users::table)
-                            .values(&new_user)
-                            .execute(&mut conn)
-                            .map_err(|e| {
-                                log::error!("Failed to create user in database: {}", e);
-                                actix_web::error::ErrorInternalServerError("Database error")
-                            })?;
-                        
-                        log::info!("User registered successfully: {}", new_user.username);
-                        
-                        Ok(HttpResponse::Ok().json(
-                            ApiResponse::<()>::success("User registered successfully".to_string(), None)
-                        ))
-                    }
-                    Err(error_msg) => {
-                        Ok(HttpResponse::BadRequest().json(
-                            ApiResponse::<()>::error(format!("Password validation failed: {}", error_msg))
-                        ))
-                    }
-                }
-            }
+        let sanitized_username = match auth::sanitize_username(&user_data.username) {
+            Ok(username) => Some(username),
             Err(error_msg) => {
-                Ok(HttpResponse::BadRequest().json(
-                    ApiResponse::<()>::error(format!("Username validation failed: {}", error_msg))
-                ))
+                errors.add("username", format!("Username validation failed: {}", error_msg), error_msg);
+                None
             }
+        };
+        
+        if let Err(error_msg) = auth::validate_password(&user_data.password) {
+            errors.add("password", format!("Password validation failed: {}", error_msg), error_msg);
+        }
+        
+        let sanitized_username = match sanitized_username {
+            Some(username) if errors.is_empty() => username,
+            _ => return Ok(validation_error_response(errors)),
+        };
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Check if username already exists
+        let username_exists = users::table
+            .filter(users::username.eq(&sanitized_username))
+            .select(User::as_select())
+            .first(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if username_exists.is_some() {
+            errors.add("username", "Username already exists".to_string(), "Username already exists".to_string());
+        }
+        
+        // Check if email already exists
+        let email_exists = users::table
+            .filter(users::email.eq(&user_data.email))
+            .select(User::as_select())
+            .first(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if email_exists.is_some() {
+            errors.add("email", "Email already exists".to_string(), "Email already exists".to_string());
+        }
+        
+        if !errors.is_empty() {
+            return Ok(validation_error_response(errors));
         }
+        
+        // Hash the password
+        let password_hash = auth::hash_password(&user_data.password);
+        
+        // Create new user
+        let new_user = NewUser {
+            id: Uuid::new_v4(),
+            username: sanitized_username,
+            password_hash: password_hash,
+            salt: "".to_string(), // In a real app, you'd generate and store a proper salt
+            mfa_secret: None, // Default to no MFA initially
+            reset_token: None,
+            reset_token_expires_at: None,
+            email: user_data.email.clone(),
+            auth_method: Some("password".to_string()),
+            is_sso_user: Some(false),
+            sso_display_name: None,
+            sso_avatar_url: None,
+        };
+        
+        // Insert user into database
+        diesel::insert_into(users::table)
+            .values(&new_user)
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to create user in database: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        log::info!("User registered successfully: {}", new_user.username);
+        
+        Ok(HttpResponse::Ok().json(
+            ApiResponse::<()>::success("User registered successfully".to_string(), None)
+        ))
     }
 
     // User auth verification handler
@@ -288,16 +370,48 @@ mod handlers {
                     }
                 }
             },
-            Err(_) => {
-                Ok(HttpResponse::Unauthorized().json(
-                    ApiResponse::<()>::error("Invalid authentication".to_string())
-                ))
-            }
+            Err(_) => Ok(auth::unauthorized_response()),
         }
     }
     
-    // User logout handler
-    pub async fn logout() -> Result<HttpResponse, Error> {
+    // User logout handler, revoking the token and every other token of its session
+    pub async fn logout(
+        req: actix_web::HttpRequest,
+        db_pool: web::Data<db::DbPool>,
+        token_manager: web::Data<std::sync::Arc<crate::token_management::TokenManager>>,
+    ) -> Result<HttpResponse, Error> {
+        let token = auth::token_from_request(&req);
+        // An invalid or expired token has nothing left to revoke
+        let revoked = if let Some(claims) = token.as_deref().and_then(|token| crate::token_management::decode_enhanced_token(token).ok()) {
+            let revoked = token_manager.revoke_family(&claims.session_id, claims.sub, "logout");
+            if revoked.is_ok() {
+                // The tokens are already dead; this only marks the session ended
+                if let Err(e) = token_manager.revoke_session(claims.sub, &claims.session_id, "logout".to_string()) {
+                    log::warn!("Failed to end session {} on logout: {}", claims.session_id, e);
+                }
+            }
+            Some((claims.sub, revoked))
+        } else if let Some(claims) = token.as_deref().and_then(|token| auth::validate_token(token).ok()) {
+            // Base tokens issued before logins moved to enhanced tokens
+            let revoked = match (&claims.jti, claims.family) {
+                (Some(jti), family) => token_manager
+                    .revoke_token(jti, claims.sub, claims.token_type.clone(), "logout".to_string())
+                    .and_then(|_| family.map_or(Ok(()), |family| token_manager.revoke_family(&family.to_string(), claims.sub, "logout"))),
+                (None, _) => Ok(()),
+            };
+            Some((claims.sub, revoked))
+        } else {
+            None
+        };
+        
+        if let Some((user_id, revoked)) = revoked {
+            if let Err(e) = revoked {
+                log::error!("Failed to revoke token on logout for user {}: {}", user_id, e);
+                return Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to log out".to_string())));
+            }
+            audit_log!(&db_pool, crate::audit::AuditEventType::UserLogout, Some(user_id), &req);
+        }
+        
         // Create an expired cookie to clear the auth token
         let cookie_value = "auth_token=; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=0";
         
@@ -316,11 +430,12 @@ mod handlers {
                     csrf_token: String,
                 }
                 
-                Ok(HttpResponse::Ok().json(
-                    ApiResponse::success("CSRF token generated".to_string(), Some(CsrfResponse {
+                // The cookie is the other half of the double submit checked by CsrfMiddleware
+                Ok(HttpResponse::Ok()
+                    .insert_header(("Set-Cookie", crate::csrf::csrf_cookie(&token)))
+                    .json(ApiResponse::success("CSRF token generated".to_string(), Some(CsrfResponse {
                         csrf_token: token,
-                    }))
-                ))
+                    }))))
             },
             Err(error_msg) => {
                 log::error!("Failed to generate CSRF token: {}", error_msg);
@@ -331,11 +446,118 @@ mod handlers {
         }
     }
 
+    /// Response that ends a successful login: the token pair, with the access token also set as a cookie
+    fn login_success_response(token_pair: auth::TokenPair) -> HttpResponse {
+        // Create HttpOnly cookie for access token (15 minutes)
+        let cookie_value = format!("auth_token={}; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=900", token_pair.access_token);
+        
+        // Prepare response data
+        let response_data = serde_json::json!({
+            "access_token": token_pair.access_token,
+            "refresh_token": token_pair.refresh_token,
+            "expires_in": 900 // 15 minutes in seconds
+        });
+        
+        HttpResponse::Ok()
+            .insert_header(("Set-Cookie", cookie_value))
+            .json(ApiResponse::success("Login successful".to_string(), Some(response_data)))
+    }
+    
+    /// Emails a login code and answers with the challenge the client must complete
+    ///
+    /// When no email can be sent the login is refused rather than let through unverified.
+    async fn start_login_challenge(
+        conn: &mut PgConnection,
+        user: &User,
+        assessment: &crate::enterprise_session_manager::LoginRiskAssessment,
+        client_ip: Option<String>,
+    ) -> Result<HttpResponse, Error> {
+        let email_service = match crate::email::EmailService::new() {
+            Ok(email_service) => email_service,
+            Err(e) => {
+                log::error!("Login of user {} scored risk {} but no login code can be emailed: {}", user.id, assessment.risk_score, e);
+                return Ok(HttpResponse::ServiceUnavailable().json(
+                    ApiResponse::<()>::error("Additional verification is required but can't be sent right now".to_string())
+                ));
+            }
+        };
+        
+        let code = login_challenge::generate_code().map_err(|e| {
+            log::error!("Failed to generate login code: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to start login challenge")
+        })?;
+        let challenge_id = login_challenge::create(conn, user.id, &code, chrono::Utc::now().naive_utc()).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        if let Err(e) = email_service
+            .send_login_challenge_email(&user.email, &user.username, &code, client_ip.as_deref(), login_challenge::CHALLENGE_EXPIRY_MINUTES)
+            .await
+        {
+            log::error!("Failed to send login code to user {}: {}", user.id, e);
+            return Ok(HttpResponse::InternalServerError().json(
+                ApiResponse::<()>::error("Failed to send login code".to_string())
+            ));
+        }
+        
+        log::warn!("Login of user {} scored risk {}; waiting for the emailed code", user.id, assessment.risk_score);
+        Ok(HttpResponse::Unauthorized().json(ApiResponse {
+            success: false,
+            message: "Additional verification required".to_string(),
+            data: Some(serde_json::json!({
+                "challenge_id": challenge_id,
+                "method": "email",
+                "expires_in": login_challenge::CHALLENGE_EXPIRY_MINUTES * 60,
+                "actions_required": assessment.actions_required,
+            })),
+        }))
+    }
+    
+    /// Refuses logins from a blocklisted IP, or one missing from the whitelist, when those lists are enabled
+    ///
+    /// A blocked IP is refused even when whitelisted.
+    fn refuse_blocked_ip(client_ip: Option<std::net::IpAddr>) -> Option<HttpResponse> {
+        let ip = client_ip?;
+        if std::env::var("ENABLE_IP_BLOCKLIST").unwrap_or_else(|_| "false".to_string()) == "true"
+            && ip_controls::IpBlocklist::from_env().is_blocked(&ip)
+        {
+            log::warn!("Login attempt from blocklisted IP: {}", ip);
+            return Some(HttpResponse::Forbidden().json(
+                ApiResponse::<()>::error("Access denied from this IP address".to_string())
+            ));
+        }
+        
+        if std::env::var("ENABLE_IP_WHITELIST").unwrap_or_else(|_| "false".to_string()) == "true"
+            && !ip_controls::IpWhitelist::from_env().is_allowed(&ip)
+        {
+            log::warn!("Login attempt from non-whitelisted IP: {}", ip);
+            return Some(HttpResponse::Forbidden().json(
+                ApiResponse::<()>::error("Access denied from this IP address".to_string())
+            ));
+        }
+        None
+    }
+    
+    /// Refuses a login attempt on an account locked after too many failures
+    fn locked_out_response(remaining: chrono::Duration) -> HttpResponse {
+        let retry_after = remaining.num_seconds().max(1);
+        HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", retry_after.to_string()))
+            .json(ApiResponse {
+                success: false,
+                message: "Too many failed login attempts; try again later".to_string(),
+                data: Some(serde_json::json!({ "retry_after_seconds": retry_after })),
+            })
+    }
+    
     // User login handler with IP-based controls
     pub async fn login(
         req: actix_web::HttpRequest,
         user_data: web::Json<UserLogin>,
         db_pool: web::Data<db::DbPool>,
+        session_manager: web::Data<std::sync::Arc<crate::enterprise_session_manager::EnterpriseSessionManager>>,
+        token_manager: web::Data<std::sync::Arc<crate::token_management::TokenManager>>,
     ) -> Result<HttpResponse, Error> {
         use crate::schema::users;
         use diesel::prelude::*;
@@ -345,17 +567,8 @@ mod handlers {
         // Extract client IP address for logging
         let client_ip = ip_controls::extract_client_ip(&req);
         
-        // Check IP whitelist if enabled
-        if let Some(ip) = client_ip {
-            let whitelist = ip_controls::IpWhitelist::from_env();
-            if std::env::var("ENABLE_IP_WHITELIST").unwrap_or_else(|_| "false".to_string()) == "true" {
-                if !whitelist.is_allowed(&ip) {
-                    log::warn!("Login attempt from non-whitelisted IP: {}", ip);
-                    return Ok(HttpResponse::Forbidden().json(
-                        ApiResponse::<()>::error("Access denied from this IP address".to_string())
-                    ));
-                }
-            }
+        if let Some(response) = refuse_blocked_ip(client_ip) {
+            return Ok(response);
         }
         
         // Validate input
@@ -380,39 +593,91 @@ mod handlers {
                         actix_web::error::ErrorInternalServerError("Database error")
                     })?;
                 
+                // Failures count per account, whichever identifier was typed
+                let lockout_policy = login_lockout::LockoutPolicy::from_env();
+                let now = chrono::Utc::now().naive_utc();
+                let record_failure = |conn: &mut PgConnection, user_id: Uuid| {
+                    let ip_address = client_ip.map(|ip| ip.to_string());
+                    if let Err(e) = login_lockout::record_failure(conn, &lockout_policy, user_id, ip_address, chrono::Utc::now().naive_utc()) {
+                        log::error!("Failed to record failed login for {}: {}", sanitized_username, e);
+                    }
+                };
+                
+                // Refuse locked accounts before checking the password; unknown identifiers are
+                // never refused, so the lockout doesn't reveal which accounts exist
+                if let Some(ref user) = user_result {
+                    let locked_for = login_lockout::check(&mut conn, &lockout_policy, user.id, now).map_err(|e| {
+                        log::error!("Database error: {}", e);
+                        actix_web::error::ErrorInternalServerError("Database error")
+                    })?;
+                    if let Some(remaining) = locked_for {
+                        log::warn!("Login refused for locked account {} ({}s remaining)", sanitized_username, remaining.num_seconds().max(1));
+                        return Ok(locked_out_response(remaining));
+                    }
+                }
+                
                 match user_result {
                     Some(user) => {
                         // Verify password
                         if auth::verify_password(&user_data.password, &user.password_hash) {
+                            // Users who enabled MFA must also present a valid TOTP or recovery code
+                            if let Some(ref mfa_secret) = user.mfa_secret {
+                                let message = match user_data.mfa_code.as_deref() {
+                                    None => Some("MFA code required"),
+                                    Some(code) => {
+                                        let valid = mfa::verify_login_code(&mut conn, user.id, mfa_secret, code).map_err(|e| {
+                                            log::error!("Database error: {}", e);
+                                            actix_web::error::ErrorInternalServerError("Database error")
+                                        })?;
+                                        if valid { None } else { Some("Invalid MFA code") }
+                                    }
+                                };
+                                if let Some(message) = message {
+                                    log::warn!("MFA check failed for user {}: {}", sanitized_username, message);
+                                    if user_data.mfa_code.is_some() {
+                                        record_failure(&mut conn, user.id);
+                                    }
+                                    return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error(message.to_string())));
+                                }
+                            }
+                            
+                            auth::upgrade_legacy_password_hash(&mut conn, user.id, &user_data.password, &user.password_hash);
+                            
+                            // Risky logins need a second factor; users with TOTP have just given one
+                            if user.mfa_secret.is_none() {
+                                let ip_address = client_ip.map(|ip| ip.to_string());
+                                let device_id = crate::token_management::device_id_from_request(&req);
+                                let recent_failures = login_lockout::recent_failures(&mut conn, &lockout_policy, user.id, now).map_err(|e| {
+                                    log::error!("Database error: {}", e);
+                                    actix_web::error::ErrorInternalServerError("Database error")
+                                })?;
+                                match session_manager.assess_login(user.id, device_id.as_deref(), ip_address.as_deref(), recent_failures).await {
+                                    Ok(assessment) if assessment.requires_mfa() => {
+                                        return start_login_challenge(&mut conn, &user, &assessment, ip_address).await;
+                                    }
+                                    Ok(_) => {}
+                                    Err(e) => log::error!("Failed to assess login risk for {}: {}", sanitized_username, e),
+                                }
+                            }
+                            
+                            if let Err(e) = login_lockout::reset(&mut conn, user.id) {
+                                log::error!("Failed to reset failed logins for {}: {}", sanitized_username, e);
+                            }
+                            
                             // Log the IP address for security monitoring
                             if let Some(ip) = client_ip {
                                 log::info!("Successful login for user {} from IP: {}", sanitized_username, ip);
                             }
                             
-                            // Generate JWT token pair
-                            match auth::generate_token_pair(user.id) {
+                            // Start a session and issue its token pair
+                            match token_manager.start_session(&req, user.id) {
                                 Ok(token_pair) => {
                                     log::info!("User {} logged in successfully", sanitized_username);
                                     
                                     // Log successful login
                                     audit_log!(&db_pool, crate::audit::AuditEventType::UserLogin, Some(user.id), &req);
                                     
-                                    // Create HttpOnly cookie for access token (15 minutes)
-                                    let cookie_value = format!("auth_token={}; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=900", token_pair.access_token);
-                                    
-                                    // Prepare response data
-                                    let response_data = serde_json::json!({
-                                        "access_token": token_pair.access_token,
-                                        "refresh_token": token_pair.refresh_token,
-                                        "expires_in": 900 // 15 minutes in seconds
-                                    });
-                                    
-                                    // Future: Add geolocation-based alerts here
-                                    
-                                    Ok(HttpResponse::Ok()
-                                        .insert_header(("Set-Cookie", cookie_value))
-                                        .json(ApiResponse::success("Login successful".to_string(), Some(response_data)))
-                                    )
+                                    Ok(login_success_response(token_pair))
                                 },
                                 Err(e) => {
                                     log::error!("Failed to generate token pair: {}", e);
@@ -423,6 +688,7 @@ mod handlers {
                             }
                         } else {
                             log::warn!("Invalid password for user: {}", sanitized_username);
+                            record_failure(&mut conn, user.id);
                             
                             // Log failed login attempt
                             audit_log!(&db_pool, crate::audit::AuditEventType::LoginFailed, Some(user.id), &req, user.id, format!("Invalid password for user: {}", sanitized_username));
@@ -434,6 +700,7 @@ mod handlers {
                     },
                     None => {
                         log::warn!("User not found: {}", sanitized_username);
+                        record_failure(&mut conn, login_lockout::UNKNOWN_USER);
                         
                         // Log failed login attempt for non-existent user
                         audit_log!(&db_pool, crate::audit::AuditEventType::LoginFailed, None, &req, Uuid::nil(), format!("User not found: {}", sanitized_username));
@@ -453,57 +720,339 @@ mod handlers {
         }
     }
 
-    // Refresh access token
-    pub async fn refresh_token(
-        refresh_data: web::Json<RefreshTokenRequest>,
-        _db_pool: web::Data<db::DbPool>,
+    /// Completes a login that was stepped up with an emailed code
+    pub async fn complete_mfa_challenge(
+        req: actix_web::HttpRequest,
+        challenge_data: web::Json<MfaChallengeRequest>,
+        db_pool: web::Data<db::DbPool>,
+        token_manager: web::Data<std::sync::Arc<crate::token_management::TokenManager>>,
     ) -> Result<HttpResponse, Error> {
-        match auth::refresh_access_token(&refresh_data.refresh_token) {
-            Ok(token_pair) => {
-                // Create HttpOnly cookie for new access token (15 minutes)
-                let cookie_value = format!("auth_token={}; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=900", token_pair.access_token);
-                
-                // Return new token pair and expiration info
-                let response_data = serde_json::json!({
-                    "access_token": token_pair.access_token,
-                    "refresh_token": token_pair.refresh_token,
-                    "expires_in": token_pair.expires_in
-                });
+        use crate::schema::users;
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let invalid = || HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or expired login code".to_string()));
+        
+        // Wrong codes count as failed logins of the account, so the usual lockout caps guessing
+        let user_id = match login_challenge::challenge_user(&mut conn, challenge_data.challenge_id).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })? {
+            Some(user_id) => user_id,
+            None => return Ok(invalid()),
+        };
+        let username = users::table
+            .find(user_id)
+            .select(users::username)
+            .first::<String>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        let lockout_policy = login_lockout::LockoutPolicy::from_env();
+        let now = chrono::Utc::now().naive_utc();
+        let locked_for = login_lockout::check(&mut conn, &lockout_policy, user_id, now).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        if let Some(remaining) = locked_for {
+            return Ok(locked_out_response(remaining));
+        }
+        
+        let outcome = login_challenge::verify(&mut conn, challenge_data.challenge_id, &challenge_data.code, now).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        match outcome {
+            login_challenge::ChallengeOutcome::Verified(user_id) => {
+                if let Err(e) = login_lockout::reset(&mut conn, user_id) {
+                    log::error!("Failed to reset failed logins for {}: {}", username, e);
+                }
                 
-                Ok(HttpResponse::Ok()
-                    .insert_header(("Set-Cookie", cookie_value))
-                    .json(ApiResponse::success("Token refreshed successfully".to_string(), Some(response_data)))
-                )
-            },
-            Err(e) => {
-                log::warn!("Failed to refresh token: {}", e);
-                Ok(HttpResponse::Unauthorized().json(
-                    ApiResponse::<()>::error("Invalid or expired refresh token".to_string())
-                ))
+                match token_manager.start_session(&req, user_id) {
+                    Ok(token_pair) => {
+                        log::info!("User {} logged in after a login challenge", username);
+                        audit_log!(&db_pool, crate::audit::AuditEventType::UserLogin, Some(user_id), &req);
+                        Ok(login_success_response(token_pair))
+                    }
+                    Err(e) => {
+                        log::error!("Failed to generate token pair: {}", e);
+                        Ok(HttpResponse::InternalServerError().json(
+                            ApiResponse::<()>::error("Failed to generate authentication tokens".to_string())
+                        ))
+                    }
+                }
+            }
+            login_challenge::ChallengeOutcome::Invalid | login_challenge::ChallengeOutcome::Expired => {
+                log::warn!("Wrong or expired login code for user {}", username);
+                let ip_address = ip_controls::extract_client_ip(&req).map(|ip| ip.to_string());
+                if let Err(e) = login_lockout::record_failure(&mut conn, &lockout_policy, user_id, ip_address, now) {
+                    log::error!("Failed to record failed login for {}: {}", username, e);
+                }
+                audit_log!(&db_pool, crate::audit::AuditEventType::LoginFailed, Some(user_id), &req, user_id, format!("Invalid login code for user: {}", username));
+                Ok(invalid())
             }
         }
     }
 
-    // Request password reset
-    pub async fn request_password_reset(
-        reset_data: web::Json<PasswordResetRequest>,
+    /// Starts a passkey login, answering with the challenge for the user's authenticator
+    pub async fn start_passkey_login(
+        req: actix_web::HttpRequest,
+        login_data: web::Json<PasskeyLoginStart>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        use crate::schema::users::dsl::*;
+        use crate::schema::users;
+        
+        if let Some(response) = refuse_blocked_ip(ip_controls::extract_client_ip(&req)) {
+            return Ok(response);
+        }
+        
+        let username = match auth::sanitize_username(&login_data.username) {
+            Ok(username) => username,
+            Err(e) => return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
+        };
         
         let mut conn = db_pool.get().map_err(|e| {
-            log::error!("Database connection error: {}", e);
-            actix_web::error::ErrorInternalServerError("Database connection failed")
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Validate email format
-        let email_regex = regex::Regex::new(r"^[^\s@]+@[^\s@]+\.[^\s@]+$").unwrap();
-        if !email_regex.is_match(&reset_data.email) {
-            return Ok(HttpResponse::BadRequest().json(
-                ApiResponse::<()>::error("Invalid email format".to_string())
-            ));
-        }
-        
+        let user_id = users::table
+            .filter(users::username.eq(&username).or(users::email.eq(&username)))
+            .select(users::id)
+            .first::<Uuid>(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        let now = chrono::Utc::now().naive_utc();
+        if let Some(user_id) = user_id {
+            let locked_for = login_lockout::check(&mut conn, &login_lockout::LockoutPolicy::from_env(), user_id, now).map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+            if let Some(remaining) = locked_for {
+                return Ok(locked_out_response(remaining));
+            }
+        }
+        
+        // Unknown users and users without passkeys get the same answer
+        let started = match user_id {
+            Some(user_id) => webauthn::start_authentication(&mut conn, user_id, now),
+            None => Ok(None),
+        };
+        match started {
+            Ok(Some((ceremony_id, options))) => Ok(HttpResponse::Ok().json(ApiResponse::success(
+                "Sign the challenge with your passkey".to_string(),
+                Some(serde_json::json!({
+                    "ceremony_id": ceremony_id,
+                    "options": options,
+                    "expires_in": webauthn::CEREMONY_EXPIRY_MINUTES * 60,
+                }))
+            ))),
+            Ok(None) => Ok(HttpResponse::BadRequest().json(
+                ApiResponse::<()>::error("Passkey login is not available for this account".to_string())
+            )),
+            Err(e) => {
+                log::error!("Failed to start passkey login for {}: {}", username, e);
+                Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to start passkey login".to_string())))
+            }
+        }
+    }
+
+    /// Completes a passkey login with the authenticator's signed challenge
+    ///
+    /// Passkeys are registered requiring user verification, so they stand in for both the
+    /// password and a second factor; no TOTP or emailed code is asked for.
+    pub async fn finish_passkey_login(
+        req: actix_web::HttpRequest,
+        login_data: web::Json<PasskeyLoginFinish>,
+        db_pool: web::Data<db::DbPool>,
+        token_manager: web::Data<std::sync::Arc<crate::token_management::TokenManager>>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::users;
+        
+        if let Some(response) = refuse_blocked_ip(ip_controls::extract_client_ip(&req)) {
+            return Ok(response);
+        }
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let invalid = || HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Passkey login failed or expired".to_string()));
+        
+        // Failed passkey logins count towards the account's lockout like wrong passwords
+        let user_id = match webauthn::ceremony_user(&mut conn, login_data.ceremony_id).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })? {
+            Some(user_id) => user_id,
+            None => return Ok(invalid()),
+        };
+        let username = users::table
+            .find(user_id)
+            .select(users::username)
+            .first::<String>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        let lockout_policy = login_lockout::LockoutPolicy::from_env();
+        let now = chrono::Utc::now().naive_utc();
+        let locked_for = login_lockout::check(&mut conn, &lockout_policy, user_id, now).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        if let Some(remaining) = locked_for {
+            return Ok(locked_out_response(remaining));
+        }
+        
+        match webauthn::finish_authentication(&mut conn, login_data.ceremony_id, &login_data.credential, now) {
+            Ok(user_id) => {
+                if let Err(e) = login_lockout::reset(&mut conn, user_id) {
+                    log::error!("Failed to reset failed logins for {}: {}", username, e);
+                }
+                
+                match token_manager.start_session(&req, user_id) {
+                    Ok(token_pair) => {
+                        log::info!("User {} logged in with a passkey", username);
+                        audit_log!(&db_pool, crate::audit::AuditEventType::UserLogin, Some(user_id), &req);
+                        Ok(login_success_response(token_pair))
+                    }
+                    Err(e) => {
+                        log::error!("Failed to generate token pair: {}", e);
+                        Ok(HttpResponse::InternalServerError().json(
+                            ApiResponse::<()>::error("Failed to generate authentication tokens".to_string())
+                        ))
+                    }
+                }
+            }
+            Err(webauthn::CeremonyError::Expired) | Err(webauthn::CeremonyError::Rejected(_)) => {
+                log::warn!("Passkey login failed for user {}", username);
+                let ip_address = ip_controls::extract_client_ip(&req).map(|ip| ip.to_string());
+                if let Err(e) = login_lockout::record_failure(&mut conn, &lockout_policy, user_id, ip_address, now) {
+                    log::error!("Failed to record failed login for {}: {}", username, e);
+                }
+                audit_log!(&db_pool, crate::audit::AuditEventType::LoginFailed, Some(user_id), &req, user_id, format!("Passkey login failed for user: {}", username));
+                Ok(invalid())
+            }
+            Err(webauthn::CeremonyError::Failed(e)) => {
+                log::error!("Failed to finish passkey login for {}: {}", username, e);
+                Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to finish passkey login".to_string())))
+            }
+        }
+    }
+
+    // Refresh access token, rotating the refresh token
+    pub async fn refresh_token(
+        req: actix_web::HttpRequest,
+        refresh_data: web::Json<RefreshTokenRequest>,
+        db_pool: web::Data<db::DbPool>,
+        token_manager: web::Data<std::sync::Arc<crate::token_management::TokenManager>>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::token_management::RefreshError;
+        
+        let user_agent = req.headers().get("user-agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+        let refreshed = token_manager.refresh_token_pair(
+            &refresh_data.refresh_token,
+            crate::token_management::device_id_from_request(&req),
+            ip_controls::extract_client_ip(&req).map(|ip| ip.to_string()),
+            user_agent,
+        );
+        
+        match refreshed {
+            Ok(token_pair) => {
+                // Create HttpOnly cookie for new access token (15 minutes)
+                let cookie_value = format!("auth_token={}; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=900", token_pair.access_token);
+                
+                // Return new token pair and expiration info
+                let response_data = serde_json::json!({
+                    "access_token": token_pair.access_token,
+                    "refresh_token": token_pair.refresh_token,
+                    "expires_in": token_pair.expires_in
+                });
+                
+                Ok(HttpResponse::Ok()
+                    .insert_header(("Set-Cookie", cookie_value))
+                    .json(ApiResponse::success("Token refreshed successfully".to_string(), Some(response_data)))
+                )
+            },
+            Err(RefreshError::Reused { user_id }) => {
+                audit_log!(&db_pool, crate::audit::AuditEventType::SessionsRevoked, Some(user_id), &req, user_id, "Refresh token reused; session revoked".to_string());
+                Ok(HttpResponse::Unauthorized().json(
+                    ApiResponse::<()>::error("Refresh token was already used; please sign in again".to_string())
+                ))
+            },
+            Err(RefreshError::BindingMismatch) => {
+                log::warn!("Refresh token presented from another device or network");
+                Ok(HttpResponse::Unauthorized().json(ApiResponse {
+                    success: false,
+                    message: "Refresh token was issued to another device or network; please sign in again".to_string(),
+                    data: Some(serde_json::json!({ "error": "refresh_token_binding_mismatch" })),
+                }))
+            },
+            Err(RefreshError::Invalid(reason)) => {
+                log::warn!("Failed to refresh token: {}", reason);
+                Ok(HttpResponse::Unauthorized().json(
+                    ApiResponse::<()>::error("Invalid or expired refresh token".to_string())
+                ))
+            },
+            Err(RefreshError::Failed(e)) => {
+                log::error!("Failed to refresh token: {}", e);
+                Ok(HttpResponse::InternalServerError().json(
+                    ApiResponse::<()>::error("Failed to refresh token".to_string())
+                ))
+            }
+        }
+    }
+
+    // Score a candidate password against the rules register and change-password enforce
+    pub async fn password_strength(
+        strength_data: web::Json<PasswordStrengthRequest>,
+    ) -> Result<HttpResponse, Error> {
+        if strength_data.password.chars().count() > password_health::MAX_STRENGTH_INPUT_CHARS {
+            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(format!(
+                "Password must be at most {} characters to be scored",
+                password_health::MAX_STRENGTH_INPUT_CHARS
+            ))));
+        }
+        
+        let estimate = password_health::estimate_strength(&strength_data.password);
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Password strength estimated".to_string(),
+            Some(estimate)
+        )))
+    }
+
+    // Request password reset
+    pub async fn request_password_reset(
+        reset_data: web::Json<PasswordResetRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::users::dsl::*;
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Database connection error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection failed")
+        })?;
+        
+        // Validate email format
+        let email_regex = regex::Regex::new(r"^[^\s@]+@[^\s@]+\.[^\s@]+$").unwrap();
+        if !email_regex.is_match(&reset_data.email) {
+            return Ok(HttpResponse::BadRequest().json(
+                ApiResponse::<()>::error("Invalid email format".to_string())
+            ));
+        }
+        
         // Find user by email
         let user_result: Result<User, diesel::result::Error> = users
             .filter(email.eq(&reset_data.email))
@@ -524,16 +1073,24 @@ mod handlers {
                 };
                 let token_expiry = auth::generate_reset_token_expiry();
                 
-                // Update user with reset token
-                let update_result = diesel::update(users.filter(id.eq(user.id)))
-                    .set((
-                        crate::schema::users::reset_token.eq(Some(&reset_token_value)),
-                        crate::schema::users::reset_token_expires_at.eq(Some(token_expiry))
-                    ))
-                    .execute(&mut conn);
+                // Update user with reset token, unless one was requested within the cooldown
+                let update_result = crate::password_reset::store_token(
+                    &mut conn,
+                    user.id,
+                    &reset_token_value,
+                    token_expiry,
+                    chrono::Utc::now().naive_utc(),
+                    crate::password_reset::cooldown_from_env(),
+                );
                     
                 match update_result {
-                    Ok(_) => {
+                    Ok(false) => {
+                        log::warn!("Password reset for {} requested again within the cooldown; no email sent", reset_data.email);
+                        Ok(HttpResponse::Ok().json(
+                            ApiResponse::<()>::success("Password reset email sent".to_string(), None)
+                        ))
+                    },
+                    Ok(true) => {
                         log::info!("Password reset token generated for user: {}", reset_data.email);
                         
                         // Send password reset email
@@ -578,9 +1135,12 @@ mod handlers {
     
     // Confirm password reset
     pub async fn confirm_password_reset(
+        req: actix_web::HttpRequest,
         reset_data: web::Json<PasswordResetConfirm>,
         db_pool: web::Data<db::DbPool>,
+        token_manager: web::Data<std::sync::Arc<crate::token_management::TokenManager>>,
     ) -> Result<HttpResponse, Error> {
+        use crate::password_reset::TokenCheck;
         use crate::schema::users::dsl::*;
         
         let mut conn = db_pool.get().map_err(|e| {
@@ -589,51 +1149,69 @@ mod handlers {
         })?;
         
         // Validate new password strength
-        if let Err(e) = auth::validate_password_strength(&reset_data.new_password) {
+        if let Err(e) = auth::validate_password(&reset_data.new_password) {
             return Ok(HttpResponse::BadRequest().json(
                 ApiResponse::<()>::error(e)
             ));
         }
         
-        // Find user by reset token
+        // Find user by the hash of the reset token
         let user_result: Result<User, diesel::result::Error> = users
-            .filter(reset_token.eq(Some(&reset_data.token)))
+            .filter(reset_token.eq(Some(crate::password_reset::hash_token(&reset_data.token))))
             .first(&mut conn);
             
         match user_result {
             Ok(user) => {
                 // Check if token is still valid
-                if let Some(expires_at) = user.reset_token_expires_at {
-                    if !auth::is_reset_token_valid(Some(expires_at)) {
+                let now = chrono::Utc::now().naive_utc();
+                match crate::password_reset::check_token(user.reset_token.as_deref(), user.reset_token_expires_at, &reset_data.token, now) {
+                    TokenCheck::Valid => {}
+                    TokenCheck::Expired => {
                         log::warn!("Expired reset token used for user: {}", user.username);
                         return Ok(HttpResponse::BadRequest().json(
                             ApiResponse::<()>::error("Reset token has expired".to_string())
                         ));
                     }
-                } else {
-                    log::warn!("Reset token without expiry for user: {}", user.username);
-                    return Ok(HttpResponse::BadRequest().json(
-                        ApiResponse::<()>::error("Invalid reset token".to_string())
-                    ));
+                    TokenCheck::Invalid => {
+                        log::warn!("Reset token without expiry for user: {}", user.username);
+                        return Ok(HttpResponse::BadRequest().json(
+                            ApiResponse::<()>::error("Invalid reset token".to_string())
+                        ));
+                    }
                 }
                 
                 // Hash new password
                 let hashed_password = auth::hash_password(&reset_data.new_password);
                 
-                // Update password and clear reset token
-                let update_result = diesel::update(users.filter(id.eq(user.id)))
-                    .set((
-                        crate::schema::users::password_hash.eq(hashed_password),
-                        crate::schema::users::reset_token.eq(None::<String>),
-                        crate::schema::users::reset_token_expires_at.eq(None::<chrono::NaiveDateTime>)
-                    ))
-                    .execute(&mut conn);
-                    
-                match update_result {
-                    Ok(_) => {
+                // Update password and clear reset token, unless a concurrent request used it first
+                match crate::password_reset::consume_token(&mut conn, user.id, &reset_data.token, &hashed_password) {
+                    Ok(true) => {
                         log::info!("Password reset completed for user: {}", user.username);
-                        Ok(HttpResponse::Ok().json(
-                            ApiResponse::<()>::success("Password reset successful".to_string(), None)
+                        
+                        // Sign out every session, including any held by whoever prompted the reset
+                        let sessions_terminated = match token_manager.revoke_all_user_tokens(user.id, "password_reset".to_string()) {
+                            Ok(_) => true,
+                            Err(e) => {
+                                log::error!("Failed to revoke sessions after password reset for user {}: {}", user.id, e);
+                                false
+                            }
+                        };
+                        let (message, details) = if sessions_terminated {
+                            ("Password reset successful", "Password reset; all sessions revoked")
+                        } else {
+                            ("Password reset successful, but existing sessions could not be signed out", "Password reset; revoking sessions failed")
+                        };
+                        audit_log!(&db_pool, crate::audit::AuditEventType::PasswordReset, Some(user.id), &req, user.id, details.to_string());
+                        
+                        Ok(HttpResponse::Ok().json(ApiResponse::success(
+                            message.to_string(),
+                            Some(serde_json::json!({ "sessions_terminated": sessions_terminated }))
+                        )))
+                    },
+                    Ok(false) => {
+                        log::warn!("Reset token for user {} was used by a concurrent request", user.username);
+                        Ok(HttpResponse::BadRequest().json(
+                            ApiResponse::<()>::error("Invalid or expired reset token".to_string())
                         ))
                     },
                     Err(e) => {
@@ -645,35 +1223,28 @@ mod handlers {
                 }
             },
             Err(_) => {
-                log::warn!("Invalid reset token used: {}", reset_data.token);
+                log::warn!("Invalid or already used reset token presented");
                 Ok(HttpResponse::BadRequest().json(
                     ApiResponse::<()>::error("Invalid or expired reset token".to_string())
                 ))
             }
         }
     }
-
+    
     // Change user password handler
     pub async fn change_password(
         req: actix_web::HttpRequest,
         change_data: web::Json<ChangePasswordRequest>,
         db_pool: web::Data<db::DbPool>,
+        token_manager: web::Data<std::sync::Arc<crate::token_management::TokenManager>>,
     ) -> Result<HttpResponse, Error> {
         use crate::schema::users;
         
         // Extract user ID from request
-        let user_id = match auth::extract_user_id_from_request(&req) {
-            Ok(id) => id,
-            Err(e) => {
-                log::warn!("Failed to extract user ID: {}", e);
-                return Ok(HttpResponse::Unauthorized().json(
-                    ApiResponse::<()>::error("Authentication required".to_string())
-                ));
-            }
-        };
+        let user_id = auth::authenticate(&req)?;
 
         // Validate new password strength
-        if let Err(e) = auth::validate_password_strength(&change_data.new_password) {
+        if let Err(e) = auth::validate_password(&change_data.new_password) {
             log::warn!("Password validation failed for user {}: {}", user_id, e);
             return Ok(HttpResponse::BadRequest().json(
                 ApiResponse::<()>::error(e)
@@ -718,6 +1289,14 @@ mod handlers {
             ));
         }
 
+        // A "change" to the same password would defeat rotation
+        if let Err(e) = crate::password_policy::check_not_current(&change_data.new_password, &user.password_hash) {
+            log::warn!("User {} tried to reuse the current password", user_id);
+            return Ok(HttpResponse::BadRequest().json(
+                ApiResponse::<()>::error(e)
+            ));
+        }
+
         // Hash new password
         let new_password_hash = auth::hash_password(&change_data.new_password);
 
@@ -728,9 +1307,36 @@ mod handlers {
         {
             Ok(_) => {
                 log::info!("Password changed successfully for user: {}", user_id);
-                Ok(HttpResponse::Ok().json(
-                    ApiResponse::<()>::success("Password changed successfully".to_string(), None)
-                ))
+                
+                // Sign out other devices, so a session opened with the old password doesn't survive
+                let current_session_id = auth::token_from_request(&req)
+                    .and_then(|token| crate::token_management::decode_enhanced_token(&token).ok())
+                    .map(|claims| claims.session_id);
+                let keep_current = change_data.keep_current_session.unwrap_or(true);
+                let revoked = match (keep_current, current_session_id.as_deref()) {
+                    (true, Some(session_id)) => token_manager.revoke_other_user_tokens(user_id, session_id, "password_changed".to_string()),
+                    _ => token_manager.revoke_all_user_tokens(user_id, "password_changed".to_string()),
+                };
+                let (other_sessions_terminated, sessions_terminated) = match revoked {
+                    Ok(count) => (true, count),
+                    Err(e) => {
+                        log::error!("Failed to revoke sessions after password change for user {}: {}", user_id, e);
+                        (false, 0)
+                    }
+                };
+                
+                let mut response = HttpResponse::Ok();
+                if !keep_current && other_sessions_terminated {
+                    // The current session ended with the others
+                    response.insert_header(("Set-Cookie", "auth_token=; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=0"));
+                }
+                Ok(response.json(ApiResponse::success(
+                    "Password changed successfully".to_string(),
+                    Some(serde_json::json!({
+                        "other_sessions_terminated": other_sessions_terminated,
+                        "sessions_terminated": sessions_terminated,
+                    }))
+                )))
             }
             Err(e) => {
                 log::error!("Failed to update password for user {}: {}", user_id, e);
@@ -741,1335 +1347,4281 @@ mod handlers {
         }
     }
 
-    // Get all passwords for a user
-    pub async fn get_passwords(
+    // Load the authenticated user for the MFA and account handlers
+    fn find_current_user(conn: &mut PgConnection, user_id: Uuid) -> Result<User, HttpResponse> {
+        use crate::schema::users;
+        
+        match users::table.filter(users::id.eq(user_id)).first::<User>(conn) {
+            Ok(user) => Ok(user),
+            Err(diesel::NotFound) => {
+                log::warn!("User not found: {}", user_id);
+                Err(HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found".to_string())))
+            }
+            Err(e) => {
+                log::error!("Database error: {}", e);
+                Err(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error".to_string())))
+            }
+        }
+    }
+
+    // Return the current user's profile
+    pub async fn current_user_profile(
         req: actix_web::HttpRequest,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        use crate::schema::passwords;
+        let user_id = auth::authenticate(&req)?;
         
-        // Extract user ID from request
-        let user_id = match auth::extract_user_id_from_request(&req) {
-            Ok(id) => id,
-            Err(e) => {
-                log::error!("Authentication failed: {}", e);
-                return Err(actix_web::error::ErrorUnauthorized("Authentication failed"));
-            }
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let user = match find_current_user(&mut conn, user_id) {
+            Ok(user) => user,
+            Err(response) => return Ok(response),
         };
         
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Profile retrieved successfully".to_string(),
+            Some(UserProfile::from(user))
+        )))
+    }
+
+    // Start MFA enrollment with a provisional TOTP secret
+    pub async fn enroll_mfa(
+        req: actix_web::HttpRequest,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::users;
+        
+        let user_id = auth::authenticate(&req)?;
+        
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Get passwords that belong to the authenticated user
-        let passwords_list = passwords::table
-            .filter(passwords::user_id.eq(user_id))
-            .select(Password::as_select())
-            .load(&mut conn)
+        let user = match find_current_user(&mut conn, user_id) {
+            Ok(user) => user,
+            Err(response) => return Ok(response),
+        };
+        
+        if user.mfa_secret.is_some() {
+            return Ok(HttpResponse::Conflict().json(ApiResponse::<()>::error("MFA is already enabled; disable it before enrolling again".to_string())));
+        }
+        
+        // The secret only takes effect once confirmed with a valid code
+        let secret = mfa::generate_totp_secret(user_id);
+        let otpauth_uri = match mfa::generate_qr_code_url(&user.username, &secret) {
+            Ok(uri) => uri,
+            Err(e) => {
+                log::error!("Failed to build provisioning URI for user {}: {}", user_id, e);
+                return Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to start MFA enrollment".to_string())));
+            }
+        };
+        
+        diesel::update(users::table.filter(users::id.eq(user_id)))
+            .set(users::mfa_pending_secret.eq(&secret))
+            .execute(&mut conn)
             .map_err(|e| {
                 log::error!("Database error: {}", e);
                 actix_web::error::ErrorInternalServerError("Database error")
             })?;
         
-        // Decrypt passwords and convert to response format
-        let mut decrypted_passwords = Vec::new();
-        for password in passwords_list {
-            match crypto::decrypt_password(&password.encrypted_password) {
-                Ok(decrypted_password) => {
-                    // Decrypt metadata if available, otherwise use unencrypted fields
-                    let website = match &password.encrypted_website {
-                        Some(encrypted_data) => {
-                            crypto::decrypt_metadata(encrypted_data)
-                                .unwrap_or_else(|_| password.website.clone())
-                        }
-                        None => password.website.clone()
-                    };
-                    
-                    let username = match &password.encrypted_username {
-                        Some(encrypted_data) => {
-                            crypto::decrypt_metadata(encrypted_data)
-                                .unwrap_or_else(|_| password.username.clone())
-                        }
-                        None => password.username.clone()
-                    };
-                    
-                    decrypted_passwords.push(PasswordResponse {
-                        id: password.id,
-                        folder_id: password.folder_id,
-                        website,
-                        username,
-                        password: decrypted_password,
-                        user_id: password.user_id,
-                        notes: password.notes,
-                        otp_secret: password.otp_secret,
-                        attachments: password.attachments,
-                    });
-                }
-                Err(e) => {
-                    log::error!("Failed to decrypt password for ID {}: {}", password.id, e);
-                    // Skip this password or return an error - for now, we'll skip
-                    continue;
-                }
-            }
-        }
-
+        log::info!("MFA enrollment started for user: {}", user_id);
         Ok(HttpResponse::Ok().json(ApiResponse::success(
-            "Passwords retrieved successfully".to_string(),
-            Some(decrypted_passwords)
+            "Scan the QR code and confirm with a code to enable MFA".to_string(),
+            Some(MfaEnrollmentResponse {
+                secret: mfa::secret_for_manual_entry(&secret),
+                otpauth_uri,
+            })
         )))
     }
 
-    // Create a new password
-    pub async fn create_password(
+    // Enable MFA once a code from the provisional secret verifies
+    pub async fn confirm_mfa(
         req: actix_web::HttpRequest,
-        password_data: web::Json<PasswordRequest>,
+        confirm_data: web::Json<MfaConfirmRequest>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        // Extract user ID from request
-        let user_id = auth::extract_user_id_from_request(&req).map_err(|e| {
-            actix_web::error::ErrorUnauthorized(e)
-        })?;
-        use crate::schema::passwords;
+        use crate::schema::users;
+        
+        let user_id = auth::authenticate(&req)?;
         
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Sanitize input fields
-        let sanitized_website = match auth::sanitize_website_url(&password_data.website) {
-            Ok(url) => url,
-            Err(error_msg) => {
-                return Ok(HttpResponse::BadRequest().json(
-                    ApiResponse::<()>::error(format!("Website URL validation failed: {}", error_msg))
-                ));
-            }
+        let user = match find_current_user(&mut conn, user_id) {
+            Ok(user) => user,
+            Err(response) => return Ok(response),
         };
         
-        let sanitized_username = match auth::sanitize_username(&password_data.username) {
-            Ok(username) => username,
-            Err(error_msg) => {
-                return Ok(HttpResponse::BadRequest().json(
-                    ApiResponse::<()>::error(format!("Username validation failed: {}", error_msg))
-                ));
+        let pending_secret = match user.mfa_pending_secret {
+            Some(secret) => secret,
+            None => return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("No MFA enrollment in progress".to_string()))),
+        };
+        
+        let confirmed_step = match mfa::current_totp_step(&pending_secret, confirm_data.code.trim()) {
+            Some(step) => step,
+            None => {
+                log::warn!("Invalid MFA confirmation code for user: {}", user_id);
+                return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Invalid MFA code".to_string())));
             }
         };
         
-        let sanitized_notes = match &password_data.notes {
-            Some(notes) => {
-                match auth::sanitize_notes(notes) {
-                    Ok(sanitized) => Some(sanitized),
-                    Err(error_msg) => {
-                        return Ok(HttpResponse::BadRequest().json(
-                            ApiResponse::<()>::error(format!("Notes validation failed: {}", error_msg))
-                        ));
-                    }
-                }
-            },
-            None => None
+        let recovery_codes = match mfa::generate_recovery_codes() {
+            Ok(codes) => codes,
+            Err(e) => {
+                log::error!("Failed to generate recovery codes for user {}: {}", user_id, e);
+                return Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to enable MFA".to_string())));
+            }
         };
         
-        let sanitized_otp_secret = match &password_data.otp_secret {
-            Some(secret) => {
-                match auth::sanitize_otp_secret(secret) {
-                    Ok(otp) => if otp.is_empty() { None } else { Some(otp) },
-                    Err(error_msg) => {
-                        return Ok(HttpResponse::BadRequest().json(
-                            ApiResponse::<()>::error(format!("OTP secret validation failed: {}", error_msg))
-                        ));
-                    }
-                }
-            },
-            None => None
-        };
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            diesel::update(users::table.filter(users::id.eq(user_id)))
+                .set((
+                    users::mfa_secret.eq(Some(&pending_secret)),
+                    users::mfa_pending_secret.eq(None::<String>),
+                    // The confirmation code can't be used again to log in
+                    users::mfa_last_totp_step.eq(Some(confirmed_step as i64)),
+                ))
+                .execute(conn)?;
+            mfa::store_recovery_codes(conn, user_id, &recovery_codes)
+        })
+        .map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
         
-        // Encrypt the password
-        let encrypted_password = crypto::encrypt_password(&password_data.password)
-            .map_err(|e| {
-                log::error!("Encryption error: {}", e);
-                actix_web::error::ErrorInternalServerError("Encryption error")
-            })?;
+        audit_log!(&db_pool, crate::audit::AuditEventType::MfaEnabled, Some(user_id), &req);
+        log::info!("MFA enabled for user: {}", user_id);
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "MFA enabled successfully; store these recovery codes somewhere safe".to_string(),
+            Some(MfaRecoveryCodesResponse { recovery_codes })
+        )))
+    }
+
+    // Disable MFA after re-checking the account password
+    pub async fn disable_mfa(
+        req: actix_web::HttpRequest,
+        disable_data: web::Json<MfaDisableRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::users;
         
-        // Encrypt metadata (website and username)
-        let encrypted_website = crypto::encrypt_metadata(&sanitized_website)
-            .map_err(|e| {
-                log::error!("Website encryption error: {}", e);
-                actix_web::error::ErrorInternalServerError("Metadata encryption error")
-            })?;
+        let user_id = auth::authenticate(&req)?;
         
-        let encrypted_username = crypto::encrypt_metadata(&sanitized_username)
-            .map_err(|e| {
-                log::error!("Username encryption error: {}", e);
-                actix_web::error::ErrorInternalServerError("Metadata encryption error")
-            })?;
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
         
-        let new_password = NewPassword {
-            id: Uuid::new_v4(),
-            folder_id: password_data.folder_id,
-            website: sanitized_website,
-            username: sanitized_username,
-            encrypted_password,
-            user_id,
-            notes: sanitized_notes,
-            otp_secret: sanitized_otp_secret,
-            attachments: password_data.attachments.clone(),
-            encrypted_website: Some(encrypted_website),
-            encrypted_username: Some(encrypted_username),
+        let user = match find_current_user(&mut conn, user_id) {
+            Ok(user) => user,
+            Err(response) => return Ok(response),
         };
         
-        let created_password = diesel::insert_into(passwords::table)
-            .values(&new_password)
-            .returning(Password::as_select())
-            .get_result(&mut conn)
-            .map_err(|e| {
-                log::error!("Database error: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        if !auth::verify_password(&disable_data.password, &user.password_hash) {
+            log::warn!("Invalid password when disabling MFA for user: {}", user_id);
+            return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid password".to_string())));
+        }
         
-        // Log password creation event
-        audit_log!(&db_pool, crate::audit::AuditEventType::PasswordCreated, Some(user_id), &req, created_password.id, format!("Password created for {}", created_password.website));
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            diesel::update(users::table.filter(users::id.eq(user_id)))
+                .set((
+                    users::mfa_secret.eq(None::<String>),
+                    users::mfa_pending_secret.eq(None::<String>),
+                    users::mfa_last_totp_step.eq(None::<i64>),
+                ))
+                .execute(conn)?;
+            mfa::clear_recovery_codes(conn, user_id)
+        })
+        .map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
         
-        Ok(HttpResponse::Ok().json(ApiResponse::success(
-            "Password created successfully".to_string(),
-            Some(created_password)
-        )))
+        audit_log!(&db_pool, crate::audit::AuditEventType::MfaDisabled, Some(user_id), &req);
+        log::info!("MFA disabled for user: {}", user_id);
+        Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("MFA disabled successfully".to_string(), None)))
     }
 
-    // Update a password
-    pub async fn update_password(
+    // Replace all recovery codes after re-checking the account password
+    pub async fn regenerate_recovery_codes(
         req: actix_web::HttpRequest,
-        path: web::Path<Uuid>,
-        password_data: web::Json<PasswordRequest>,
+        regenerate_data: web::Json<MfaRecoveryCodesRequest>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        // Extract user ID from request
-        let user_id = auth::extract_user_id_from_request(&req).map_err(|e| {
-            actix_web::error::ErrorUnauthorized(e)
-        })?;
-        use crate::schema::passwords;
+        let user_id = auth::authenticate(&req)?;
         
-        let password_id = path.into_inner();
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Sanitize input fields
-        let sanitized_website = match auth::sanitize_website_url(&password_data.website) {
-            Ok(url) => url,
-            Err(error_msg) => {
-                return Ok(HttpResponse::BadRequest().json(
-                    ApiResponse::<()>::error(format!("Website URL validation failed: {}", error_msg))
-                ));
-            }
+        let user = match find_current_user(&mut conn, user_id) {
+            Ok(user) => user,
+            Err(response) => return Ok(response),
         };
         
-        let sanitized_username = match auth::sanitize_username(&password_data.username) {
-            Ok(username) => username,
-            Err(error_msg) => {
-                return Ok(HttpResponse::BadRequest().json(
-                    ApiResponse::<()>::error(format!("Username validation failed: {}", error_msg))
-                ));
-            }
-        };
+        if !auth::verify_password(&regenerate_data.password, &user.password_hash) {
+            log::warn!("Invalid password when regenerating recovery codes for user: {}", user_id);
+            return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid password".to_string())));
+        }
         
-        let sanitized_notes = match &password_data.notes {
-            Some(notes) => {
-                match auth::sanitize_notes(notes) {
-                    Ok(sanitized) => Some(sanitized),
-                    Err(error_msg) => {
-                        return Ok(HttpResponse::BadRequest().json(
-                            ApiResponse::<()>::error(format!("Notes validation failed: {}", error_msg))
-                        ));
-                    }
-                }
-            },
-            None => None
-        };
+        if user.mfa_secret.is_none() {
+            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("MFA is not enabled".to_string())));
+        }
         
-        let sanitized_otp_secret = match &password_data.otp_secret {
-            Some(secret) => {
-                match auth::sanitize_otp_secret(secret) {
-                    Ok(otp) => if otp.is_empty() { None } else { Some(otp) },
-                    Err(error_msg) => {
-                        return Ok(HttpResponse::BadRequest().json(
-                            ApiResponse::<()>::error(format!("OTP secret validation failed: {}", error_msg))
-                        ));
-                    }
-                }
-            },
-            None => None
+        let recovery_codes = match mfa::generate_recovery_codes() {
+            Ok(codes) => codes,
+            Err(e) => {
+                log::error!("Failed to generate recovery codes for user {}: {}", user_id, e);
+                return Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to generate recovery codes".to_string())));
+            }
         };
         
-        // Encrypt the password
-        let encrypted_password = crypto::encrypt_password(&password_data.password)
-            .map_err(|e| {
-                log::error!("Encryption error: {}", e);
-                actix_web::error::ErrorInternalServerError("Encryption error")
-            })?;
-        
-        // Encrypt metadata
-        let encrypted_website = crypto::encrypt_metadata(&sanitized_website)
-            .map_err(|e| {
-                log::error!("Website encryption error: {}", e);
-                actix_web::error::ErrorInternalServerError("Encryption error")
-            })?;
-        
-        let encrypted_username = crypto::encrypt_metadata(&sanitized_username)
-            .map_err(|e| {
-                log::error!("Username encryption error: {}", e);
-                actix_web::error::ErrorInternalServerError("Encryption error")
-            })?;
-        
-        // Update password only if it belongs to the authenticated user
-        let rows_affected = diesel::update(
-            passwords::table
-                .filter(passwords::id.eq(password_id))
-                .filter(passwords::user_id.eq(user_id))
-        )
-            .set((
-                passwords::folder_id.eq(password_data.folder_id),
-                passwords::website.eq(&sanitized_website),
-                passwords::username.eq(&sanitized_username),
-                passwords::encrypted_password.eq(encrypted_password),
-                passwords::encrypted_website.eq(Some(encrypted_website)),
-                passwords::encrypted_username.eq(Some(encrypted_username)),
-                passwords::notes.eq(sanitized_notes),
-                passwords::otp_secret.eq(sanitized_otp_secret),
-                passwords::attachments.eq(password_data.attachments.clone()),
-            ))
-            .execute(&mut conn)
+        conn.transaction::<_, diesel::result::Error, _>(|conn| mfa::store_recovery_codes(conn, user_id, &recovery_codes))
             .map_err(|e| {
                 log::error!("Database error: {}", e);
                 actix_web::error::ErrorInternalServerError("Database error")
             })?;
         
-        if rows_affected == 0 {
-            log::warn!("Password not found or access denied for user: {}", user_id);
-            return Err(actix_web::error::ErrorNotFound("Password not found"));
-        }
-        
+        audit_log!(&db_pool, crate::audit::AuditEventType::MfaRecoveryCodesRegenerated, Some(user_id), &req);
+        log::info!("MFA recovery codes regenerated for user: {}", user_id);
         Ok(HttpResponse::Ok().json(ApiResponse::success(
-            "Password updated successfully".to_string(),
-            None::<String>
+            "Recovery codes regenerated; previous codes no longer work".to_string(),
+            Some(MfaRecoveryCodesResponse { recovery_codes })
         )))
     }
 
-    // Move a password to a different folder
-    pub async fn move_password(
+    // Start enrolling a passkey for the current user
+    pub async fn start_passkey_registration(
         req: actix_web::HttpRequest,
-        path: web::Path<Uuid>,
-        move_data: web::Json<PasswordMoveRequest>,
+        start_data: web::Json<PasskeyRegistrationStart>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        // Extract user ID from request (handles both cookies and Authorization header)
-        let user_id = auth::extract_user_id_from_request(&req).map_err(|e| {
-            log::error!("Authentication failed: {}", e);
-            actix_web::error::ErrorUnauthorized("Authentication required")
-        })?;
-        use crate::schema::passwords;
+        let user_id = auth::authenticate(&req)?;
         
-        let password_id = path.into_inner();
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Update only the folder_id without re-encrypting the password
-        let rows_affected = diesel::update(
-            passwords::table
-                .filter(passwords::id.eq(password_id))
-                .filter(passwords::user_id.eq(user_id))
-        )
-            .set(passwords::folder_id.eq(move_data.folder_id))
-            .execute(&mut conn)
-            .map_err(|e| {
-                log::error!("Database error: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        let user = match find_current_user(&mut conn, user_id) {
+            Ok(user) => user,
+            Err(response) => return Ok(response),
+        };
         
-        if rows_affected == 0 {
-            log::warn!("Password not found or access denied for user: {}", user_id);
-            return Err(actix_web::error::ErrorNotFound("Password not found"));
+        // A passkey logs in without the password, so a stolen access token alone mustn't add one
+        if !auth::verify_password(&start_data.current_password, &user.password_hash) {
+            log::warn!("Invalid password when starting passkey registration for user: {}", user_id);
+            return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid password".to_string())));
         }
         
-        Ok(HttpResponse::Ok().json(ApiResponse::success(
-            "Password moved successfully".to_string(),
-            None::<String>
-        )))
+        match webauthn::start_registration(&mut conn, user.id, &user.username, &user.username, chrono::Utc::now().naive_utc()) {
+            Ok((ceremony_id, options)) => Ok(HttpResponse::Ok().json(ApiResponse::success(
+                "Create the passkey on your device to finish registration".to_string(),
+                Some(serde_json::json!({
+                    "ceremony_id": ceremony_id,
+                    "options": options,
+                    "expires_in": webauthn::CEREMONY_EXPIRY_MINUTES * 60,
+                }))
+            ))),
+            Err(e) => {
+                log::error!("Failed to start passkey registration for user {}: {}", user_id, e);
+                Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to start passkey registration".to_string())))
+            }
+        }
     }
 
-    // Delete a password
-    pub async fn delete_password(
+    // Store the passkey created by the authenticator
+    pub async fn finish_passkey_registration(
         req: actix_web::HttpRequest,
-        path: web::Path<Uuid>,
+        registration_data: web::Json<PasskeyRegistrationFinish>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        // Extract user ID from request (handles both cookies and Authorization header)
-        let user_id = auth::extract_user_id_from_request(&req).map_err(|e| {
-            log::error!("Authentication failed: {}", e);
-            actix_web::error::ErrorUnauthorized("Authentication required")
-        })?;
-        use crate::schema::passwords;
+        let user_id = auth::authenticate(&req)?;
         
-        let password_id = path.into_inner();
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Delete password only if it belongs to the authenticated user
-        let deleted_rows = diesel::delete(
-            passwords::table
-                .filter(passwords::id.eq(password_id))
-                .filter(passwords::user_id.eq(user_id))
-        )
-            .execute(&mut conn)
-            .map_err(|e| {
-                log::error!("Database error: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
-        
-        if deleted_rows == 0 {
-            log::warn!("Password not found or access denied for user: {}", user_id);
-            return Err(actix_web::error::ErrorNotFound("Password not found"));
+        let name = webauthn::passkey_name(registration_data.name.as_deref());
+        let registered = webauthn::finish_registration(
+            &mut conn,
+            user_id,
+            registration_data.ceremony_id,
+            &name,
+            &registration_data.credential,
+            chrono::Utc::now().naive_utc(),
+        );
+        match registered {
+            Ok(passkey_id) => {
+                audit_log!(&db_pool, crate::audit::AuditEventType::PasskeyRegistered, Some(user_id), &req, passkey_id, format!("Passkey registered: {}", name));
+                log::info!("Passkey registered for user: {}", user_id);
+                Ok(HttpResponse::Ok().json(ApiResponse::success(
+                    "Passkey registered".to_string(),
+                    Some(serde_json::json!({ "id": passkey_id, "name": name }))
+                )))
+            }
+            Err(webauthn::CeremonyError::Expired) => Ok(HttpResponse::BadRequest().json(
+                ApiResponse::<()>::error("Passkey registration expired; please start again".to_string())
+            )),
+            Err(webauthn::CeremonyError::Rejected(message)) => {
+                log::warn!("Passkey registration rejected for user {}: {}", user_id, message);
+                Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(message)))
+            }
+            Err(webauthn::CeremonyError::Failed(e)) => {
+                log::error!("Failed to finish passkey registration for user {}: {}", user_id, e);
+                Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to register passkey".to_string())))
+            }
         }
-        
-        // Log password deletion event
-        audit_log!(&db_pool, crate::audit::AuditEventType::PasswordDeleted, Some(user_id), &req, password_id, format!("Password deleted: {}", password_id));
-        
-        Ok(HttpResponse::Ok().json(ApiResponse::success(
-            "Password deleted successfully".to_string(),
-            None::<String>
-        )))
     }
 
-    // Get all folders for a user
-    pub async fn get_folders(
+    // List the current user's passkeys
+    pub async fn list_passkeys(
         req: actix_web::HttpRequest,
-        db_pool: web::Data<db::DbPool>,
+        read_pool: web::Data<db::ReadPool>,
     ) -> Result<HttpResponse, Error> {
-        // Extract user ID from request (supports both cookies and Authorization header)
-        let user_id = auth::extract_user_id_from_request(&req).map_err(|e| {
-            log::error!("Authentication failed: {}", e);
-            actix_web::error::ErrorUnauthorized("Authentication failed")
-        })?;
-        use crate::schema::folders;
+        let user_id = auth::authenticate(&req)?;
         
-        let mut conn = db_pool.get().map_err(|e| {
+        let mut conn = read_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        let folders_list = folders::table
-            .filter(folders::user_id.eq(user_id))
-            .load::<Folder>(&mut conn)
-            .map_err(|e| {
-                log::error!("Database error: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        let passkeys = webauthn::list_passkeys(&mut conn, user_id).map_err(|e| {
+            log::error!("Database error loading passkeys: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
         
-        Ok(HttpResponse::Ok().json(ApiResponse::success(
-            "Folders retrieved successfully".to_string(),
-            Some(folders_list)
-        )))
+        Ok(HttpResponse::Ok().json(ApiResponse::success("Passkeys retrieved successfully".to_string(), Some(passkeys))))
     }
-
-    // Create a new folder
-    pub async fn create_folder(
+    
+    // Remove one of the current user's passkeys
+    pub async fn delete_passkey(
         req: actix_web::HttpRequest,
-        folder_data: web::Json<FolderRequest>,
+        path: web::Path<Uuid>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        // Extract user ID from request (supports both cookies and Authorization header)
-        let user_id = auth::extract_user_id_from_request(&req).map_err(|e| {
-            log::error!("Authentication failed: {}", e);
-            actix_web::error::ErrorUnauthorized("Authentication failed")
-        })?;
-        use crate::schema::folders;
+        let user_id = auth::authenticate(&req)?;
         
+        let passkey_id = path.into_inner();
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        let folder_name = folder_data.name.as_ref()
-            .ok_or_else(|| actix_web::error::ErrorBadRequest("Folder name is required"))?;
-        
-        let new_folder = NewFolder {
-            id: Uuid::new_v4(),
-            user_id,
-            parent_folder_id: folder_data.parent_folder_id,
-            name: folder_name.clone(),
-        };
-        
-        let created_folder = diesel::insert_into(folders::table)
-            .values(&new_folder)
-            .get_result::<Folder>(&mut conn)
-            .map_err(|e| {
-                log::error!("Database error: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        let deleted = webauthn::delete_passkey(&mut conn, user_id, passkey_id).map_err(|e| {
+            log::error!("Database error deleting passkey: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
         
-        Ok(HttpResponse::Ok().json(ApiResponse::success(
-            "Folder created successfully".to_string(),
-            Some(created_folder)
-        )))
+        match deleted {
+            Some(name) => {
+                audit_log!(&db_pool, crate::audit::AuditEventType::PasskeyRemoved, Some(user_id), &req, passkey_id, format!("Passkey removed: {}", name));
+                log::info!("Passkey {} removed by user: {}", passkey_id, user_id);
+                Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("Passkey removed".to_string(), None)))
+            }
+            None => Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Passkey not found".to_string()))),
+        }
     }
-
-    // Delete a folder
-    pub async fn delete_folder(
+    
+    // Start moving the current user's account to a new email address
+    pub async fn request_email_change(
         req: actix_web::HttpRequest,
-        path: web::Path<Uuid>,
+        change_data: web::Json<EmailChangeRequest>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        // Extract user ID from request (supports both cookies and Authorization header)
-        let user_id = auth::extract_user_id_from_request(&req).map_err(|e| {
-            log::error!("Authentication failed: {}", e);
-            actix_web::error::ErrorUnauthorized("Authentication failed")
-        })?;
-        use crate::schema::{folders, passwords, shares};
+        let user_id = auth::authenticate(&req)?;
+        
+        let new_email = change_data.new_email.trim();
+        let email_regex = regex::Regex::new(r"^[^\s@]+@[^\s@]+\.[^\s@]+$").unwrap();
+        if !email_regex.is_match(new_email) {
+            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Invalid email format".to_string())));
+        }
         
-        let folder_id = path.into_inner();
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // First, verify the folder exists and belongs to the user
-        let folder = folders::table
-            .filter(folders::id.eq(folder_id))
-            .filter(folders::user_id.eq(user_id))
-            .first::<Folder>(&mut conn)
-            .optional()
-            .map_err(|e| {
-                log::error!("Database error: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        let user = match find_current_user(&mut conn, user_id) {
+            Ok(user) => user,
+            Err(response) => return Ok(response),
+        };
         
-        if folder.is_none() {
-            log::warn!("Folder not found or access denied for user: {}", user_id);
-            return Err(actix_web::error::ErrorNotFound("Folder not found"));
+        if email_change::managed_by_sso(&user) {
+            return Ok(HttpResponse::Conflict().json(
+                ApiResponse::<()>::error("This account's email is managed by its single sign-on provider".to_string())
+            ));
         }
         
-        let folder = folder.unwrap();
+        if !auth::verify_password(&change_data.current_password, &user.password_hash) {
+            log::warn!("Invalid password when changing email for user: {}", user_id);
+            return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid password".to_string())));
+        }
         
-        // Move all passwords from this folder to its parent folder
-        diesel::update(
-            passwords::table
-                .filter(passwords::folder_id.eq(folder_id))
-                .filter(passwords::user_id.eq(user_id))
-        )
-            .set(passwords::folder_id.eq(folder.parent_folder_id))
-            .execute(&mut conn)
-            .map_err(|e| {
-                log::error!("Failed to move passwords: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        if new_email == user.email {
+            return Ok(HttpResponse::BadRequest().json(
+                ApiResponse::<()>::error("New email must be different from the current email".to_string())
+            ));
+        }
         
-        // Move all subfolders to the parent folder
-        diesel::update(
-            folders::table
-                .filter(folders::parent_folder_id.eq(folder_id))
-                .filter(folders::user_id.eq(user_id))
-        )
-            .set(folders::parent_folder_id.eq(folder.parent_folder_id))
-            .execute(&mut conn)
-            .map_err(|e| {
-                log::error!("Failed to move subfolders: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        let in_use = email_change::email_in_use(&mut conn, new_email, user_id).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        if in_use {
+            return Ok(HttpResponse::Conflict().json(ApiResponse::<()>::error("Email already exists".to_string())));
+        }
         
-        // Delete any shares for this folder
-        diesel::delete(
-            shares::table
-                .filter(shares::folder_id.eq(folder_id))
-        )
-            .execute(&mut conn)
-            .map_err(|e| {
-                log::error!("Failed to delete folder shares: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        // Without email delivery the change could never be confirmed
+        let email_service = match crate::email::EmailService::new() {
+            Ok(email_service) => email_service,
+            Err(e) => {
+                log::error!("Failed to initialize email service: {}", e);
+                return Ok(HttpResponse::ServiceUnavailable().json(
+                    ApiResponse::<()>::error("Email changes are unavailable because email delivery isn't configured".to_string())
+                ));
+            }
+        };
         
-        // Finally, delete the folder
-        let deleted_rows = diesel::delete(
-            folders::table
-                .filter(folders::id.eq(folder_id))
-                .filter(folders::user_id.eq(user_id))
-        )
-            .execute(&mut conn)
-            .map_err(|e| {
-                log::error!("Database error: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        let token = match auth::generate_password_reset_token() {
+            Ok(token) => token,
+            Err(e) => {
+                log::error!("Failed to generate email change token: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to start email change".to_string())));
+            }
+        };
+        email_change::store_pending(&mut conn, user_id, new_email, &token, chrono::Utc::now().naive_utc()).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
         
-        if deleted_rows == 0 {
-            log::warn!("Failed to delete folder for user: {}", user_id);
-            return Err(actix_web::error::ErrorInternalServerError("Failed to delete folder"));
+        if let Err(e) = email_service
+            .send_email_change_email(new_email, &user.username, &token, email_change::EMAIL_CHANGE_EXPIRY_MINUTES)
+            .await
+        {
+            log::error!("Failed to send email change confirmation for user {}: {}", user_id, e);
+            return Ok(HttpResponse::InternalServerError().json(
+                ApiResponse::<()>::error("Failed to send confirmation email".to_string())
+            ));
         }
         
-        log::info!("Folder {} deleted successfully by user {}", folder_id, user_id);
+        log::info!("Email change requested for user: {}", user_id);
         Ok(HttpResponse::Ok().json(ApiResponse::success(
-            "Folder deleted successfully".to_string(),
-            None::<String>
+            "Follow the link sent to the new address to confirm the change".to_string(),
+            Some(serde_json::json!({
+                "pending_email": new_email,
+                "expires_in": email_change::EMAIL_CHANGE_EXPIRY_MINUTES * 60,
+            }))
         )))
     }
 
-    // Update a folder
-    pub async fn update_folder(
+    // Switch the account to its pending email once the emailed link is followed
+    pub async fn confirm_email_change(
         req: actix_web::HttpRequest,
-        path: web::Path<Uuid>,
-        folder_data: web::Json<FolderRequest>,
+        confirm_data: web::Json<EmailChangeConfirm>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        // Extract user ID from request (supports both cookies and Authorization header)
-        let user_id = auth::extract_user_id_from_request(&req).map_err(|e| {
-            log::error!("Authentication failed: {}", e);
-            actix_web::error::ErrorUnauthorized("Authentication failed")
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
-        use crate::schema::folders;
         
-        let folder_id = path.into_inner();
+        let outcome = email_change::confirm(&mut conn, confirm_data.token.trim(), chrono::Utc::now().naive_utc()).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        match outcome {
+            email_change::ConfirmOutcome::Changed { user_id, old_email, new_email } => {
+                audit_log!(&db_pool, crate::audit::AuditEventType::EmailChanged, Some(user_id), &req, user_id, format!("Email changed from {} to {}", old_email, new_email));
+                log::info!("Email changed for user: {}", user_id);
+                Ok(HttpResponse::Ok().json(ApiResponse::success(
+                    "Email changed successfully".to_string(),
+                    Some(serde_json::json!({ "email": new_email }))
+                )))
+            }
+            email_change::ConfirmOutcome::Expired => Ok(HttpResponse::BadRequest().json(
+                ApiResponse::<()>::error("Confirmation link has expired; please request the change again".to_string())
+            )),
+            email_change::ConfirmOutcome::Invalid => Ok(HttpResponse::BadRequest().json(
+                ApiResponse::<()>::error("Invalid confirmation link".to_string())
+            )),
+            email_change::ConfirmOutcome::EmailTaken => Ok(HttpResponse::Conflict().json(
+                ApiResponse::<()>::error("Email already exists".to_string())
+            )),
+        }
+    }
+
+    // Delete the current user's account and everything they own
+    pub async fn delete_account(
+        req: actix_web::HttpRequest,
+        deletion_data: web::Json<AccountDeletionRequest>,
+        db_pool: web::Data<db::DbPool>,
+        token_manager: web::Data<std::sync::Arc<crate::token_management::TokenManager>>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{folders, oauth_accounts, passwords, revoked_tokens, session_security_events, shares, users};
+        
+        let user_id = auth::authenticate(&req)?;
+        
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Update folder only if it belongs to the authenticated user
-        let updated_rows = if let Some(ref name) = folder_data.name {
-            // Update name only
-            diesel::update(
-                folders::table
-                    .filter(folders::id.eq(folder_id))
-                    .filter(folders::user_id.eq(user_id))
-            )
-                .set(folders::name.eq(name))
-                .execute(&mut conn)
-                .map_err(|e| {
-                    log::error!("Database error: {}", e);
-                    actix_web::error::ErrorInternalServerError("Database error")
-                })?
-        } else if folder_data.parent_folder_id.is_some() {
-            // Update parent_folder_id
-            diesel::update(
-                folders::table
-                    .filter(folders::id.eq(folder_id))
-                    .filter(folders::user_id.eq(user_id))
-            )
-                .set(folders::parent_folder_id.eq(folder_data.parent_folder_id))
-                .execute(&mut conn)
-                .map_err(|e| {
-                    log::error!("Database error: {}", e);
-                    actix_web::error::ErrorInternalServerError("Database error")
-                })?
-        } else {
-            return Err(actix_web::error::ErrorBadRequest("No fields to update"));
+        let user = match find_current_user(&mut conn, user_id) {
+            Ok(user) => user,
+            Err(response) => return Ok(response),
         };
         
-        if updated_rows == 0 {
-            log::warn!("Folder not found or access denied for user: {}", user_id);
-            return Err(actix_web::error::ErrorNotFound("Folder not found"));
+        if !auth::verify_password(&deletion_data.current_password, &user.password_hash) {
+            log::warn!("Invalid password when deleting account for user: {}", user_id);
+            return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Current password is incorrect".to_string())));
         }
         
-        // Fetch the updated folder to return it
-        let updated_folder = folders::table
-            .filter(folders::id.eq(folder_id))
-            .filter(folders::user_id.eq(user_id))
-            .first::<Folder>(&mut conn)
-            .map_err(|e| {
-                log::error!("Database error: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        // Sessions are ended only once the deletion has committed, so a failed deletion leaves them be
+        let sessions = token_manager.get_user_sessions(user_id);
         
-        Ok(HttpResponse::Ok().json(ApiResponse::success(
-            "Folder updated successfully".to_string(),
-            Some(updated_folder)
-        )))
+        let summary = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            // Keep orphaned-record cleanup from running while rows are half deleted
+            db::lock_account_maintenance_shared(conn)?;
+            
+            // Owned shares and shares received, so nobody is left pointing at deleted rows
+            let deleted_shares = diesel::delete(
+                shares::table.filter(shares::user_id.eq(user_id).or(shares::shared_with_user_id.eq(user_id)))
+            )
+                .execute(conn)?;
+            
+            let deleted_passwords = diesel::delete(passwords::table.filter(passwords::user_id.eq(user_id)))
+                .execute(conn)?;
+            let deleted_folders = diesel::delete(folders::table.filter(folders::user_id.eq(user_id)))
+                .execute(conn)?;
+            diesel::delete(oauth_accounts::table.filter(oauth_accounts::user_id.eq(user_id)))
+                .execute(conn)?;
+            
+            // References that don't cascade
+            diesel::update(revoked_tokens::table.filter(revoked_tokens::revoked_by_user_id.eq(user_id)))
+                .set(revoked_tokens::revoked_by_user_id.eq(None::<Uuid>))
+                .execute(conn)?;
+            diesel::update(session_security_events::table.filter(session_security_events::resolved_by.eq(user_id)))
+                .set(session_security_events::resolved_by.eq(None::<Uuid>))
+                .execute(conn)?;
+            
+            // Sessions, groups, recovery codes and history cascade with the user row
+            diesel::delete(users::table.filter(users::id.eq(user_id)))
+                .execute(conn)?;
+            
+            Ok(AccountDeletionSummary { deleted_passwords, deleted_folders, deleted_shares })
+        })
+        .map_err(|e| {
+            log::error!("Failed to delete account {}: {}", user_id, e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        token_manager.end_deleted_user_sessions(user_id, &sessions);
+        
+        audit_log!(&db_pool, crate::audit::AuditEventType::AccountDeleted, Some(user_id), &req);
+        log::info!(
+            "Account {} deleted: {} passwords, {} folders, {} shares",
+            user_id, summary.deleted_passwords, summary.deleted_folders, summary.deleted_shares
+        );
+        Ok(HttpResponse::Ok()
+            .insert_header(("Set-Cookie", "auth_token=; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=0"))
+            .json(ApiResponse::success("Account deleted successfully".to_string(), Some(summary))))
     }
 
-    // Generate OTP code for a password entry
-    pub async fn generate_otp(
+    pub async fn get_notification_preferences(
         req: actix_web::HttpRequest,
-        path: web::Path<Uuid>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        // Extract user ID from request (supports both cookies and Authorization header)
-        let user_id = auth::extract_user_id_from_request(&req).map_err(|e| {
-            log::error!("Authentication failed: {}", e);
-            actix_web::error::ErrorUnauthorized("Authentication failed")
-        })?;
-        let password_id = path.into_inner();
-        
-        use crate::schema::passwords;
+        let user_id = auth::authenticate(&req)?;
         
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Get the password entry and verify ownership
-        let password = passwords::table
-            .filter(passwords::id.eq(password_id))
-            .filter(passwords::user_id.eq(user_id))
-            .first::<Password>(&mut conn)
-            .optional()
-            .map_err(|e| {
-                log::error!("Database error: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?
-            .ok_or_else(|| {
-                log::warn!("Password not found or access denied for user: {}", user_id);
-                actix_web::error::ErrorNotFound("Password not found")
-            })?;
-        
-        // Check if password has OTP secret
-        if let Some(otp_secret) = &password.otp_secret {
-            match mfa::generate_totp_code(otp_secret) {
-                Ok(code) => {
-                    Ok(HttpResponse::Ok().json(ApiResponse::success(
-                        "OTP code generated successfully".to_string(),
-                        Some(serde_json::json!({
-                            "otp_code": code,
-                            "expires_in": 30
-                        }))
-                    )))
-                }
-                Err(e) => {
-                    log::error!("Failed to generate OTP code: {}", e);
-                    Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(
-                        "Failed to generate OTP code".to_string()
-                    )))
-                }
+        match load_notification_preferences(&mut conn, user_id) {
+            Ok(preferences) => Ok(HttpResponse::Ok().json(ApiResponse::success("Notification preferences retrieved".to_string(), Some(preferences)))),
+            Err(diesel::NotFound) => Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found".to_string()))),
+            Err(e) => {
+                log::error!("Failed to load notification preferences for user {}: {}", user_id, e);
+                Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error".to_string())))
             }
-        } else {
-            Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(
-                "No OTP secret configured for this password".to_string()
-            )))
         }
     }
-
-    // Share password handler - temporarily disabled
-    pub async fn share_password(
+    
+    pub async fn update_notification_preferences(
         req: actix_web::HttpRequest,
-        path: web::Path<Uuid>,
-        share_data: web::Json<ShareRequest>,
+        update: web::Json<NotificationPreferencesUpdate>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        use crate::schema::{passwords, users, shares};
-        use crate::models::NewShare;
-        use diesel::prelude::*;
-        use chrono::{Utc, Duration};
+        use crate::schema::users;
         
-        // Extract user ID from JWT token
-        let current_user_id = match auth::extract_user_id_from_request(&req) {
-            Ok(user_uuid) => user_uuid,
+        let user_id = auth::authenticate(&req)?;
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let result = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            if let Some(new_device_alerts) = update.new_device_alerts {
+                diesel::update(users::table.find(user_id))
+                    .set(users::new_device_alerts.eq(new_device_alerts))
+                    .execute(conn)?;
+            }
+            if let Some(share_notifications) = update.share_notifications {
+                diesel::update(users::table.find(user_id))
+                    .set(users::share_notifications.eq(share_notifications))
+                    .execute(conn)?;
+            }
+            load_notification_preferences(conn, user_id)
+        });
+        
+        match result {
+            Ok(preferences) => Ok(HttpResponse::Ok().json(ApiResponse::success("Notification preferences updated".to_string(), Some(preferences)))),
+            Err(diesel::NotFound) => Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found".to_string()))),
             Err(e) => {
-                log::error!("Failed to extract user ID: {}", e);
-                return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or missing token".to_string())));
+                log::error!("Failed to update notification preferences for user {}: {}", user_id, e);
+                Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error".to_string())))
             }
-        };
+        }
+    }
+    
+    fn load_notification_preferences(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<NotificationPreferences> {
+        use crate::schema::users;
         
-        let password_id = path.into_inner();
+        users::table
+            .find(user_id)
+            .select(NotificationPreferences::as_select())
+            .first(conn)
+    }
+
+    // Get all passwords for a user
+    pub async fn get_passwords(
+        req: actix_web::HttpRequest,
+        query: web::Query<PasswordListQuery>,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::passwords;
         
-        let mut conn = db_pool.get().map_err(|e| {
+        // Extract user ID from request
+        let user_id = auth::authenticate(&req)?;
+        
+        let mut conn = read_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Verify the password exists and belongs to the current user
-        let password_exists = passwords::table
-            .filter(passwords::id.eq(password_id))
-            .filter(passwords::user_id.eq(current_user_id))
-            .first::<Password>(&mut conn)
-            .optional()
+        // Get passwords that belong to the authenticated user, leaving out the trash
+        let mut passwords_query = passwords::table
+            .filter(passwords::user_id.eq(user_id))
+            .filter(passwords::deleted_at.is_null())
+            .into_boxed();
+        if query.favorites_only {
+            passwords_query = passwords_query.filter(passwords::is_favorite.eq(true));
+        }
+        let passwords_list = passwords_query
+            .select(Password::as_select())
+            .load(&mut conn)
             .map_err(|e| {
-                log::error!("Database error checking password ownership: {}", e);
+                log::error!("Database error: {}", e);
                 actix_web::error::ErrorInternalServerError("Database error")
             })?;
         
-        if password_exists.is_none() {
-            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Password not found or access denied".to_string())));
-        }
-        
-        // Find the recipient user by username
-        let recipient_user = users::table
-            .filter(users::username.eq(&share_data.recipient_username))
-            .first::<User>(&mut conn)
-            .optional()
-            .map_err(|e| {
-                log::error!("Database error finding recipient user: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
-        
-        let recipient_user = match recipient_user {
-            Some(user) => user,
-            None => {
-                return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Recipient user not found".to_string())));
+        // Decrypt passwords and convert to response format
+        let mut decrypted_passwords = Vec::new();
+        let mut decryption_errors = DecryptionErrors::default();
+        for password in passwords_list {
+            let password_id = password.id;
+            match password_response(password) {
+                Ok(response) => decrypted_passwords.push(response),
+                Err(e) => {
+                    log::error!("Failed to decrypt password for ID {}: {}", password_id, e);
+                    // Skip this password but report it so the client can warn the user
+                    decryption_errors.record(password_id);
+                }
             }
-        };
-        
-        // Prevent sharing with yourself
-        if recipient_user.id == current_user_id {
-            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Cannot share with yourself".to_string())));
         }
-        
-        // Check if already shared with this user
-        let existing_share = shares::table
-            .filter(shares::password_id.eq(password_id))
-            .filter(shares::shared_with_user_id.eq(recipient_user.id))
-            .first::<Share>(&mut conn)
-            .optional()
-            .map_err(|e| {
-                log::error!("Database error checking existing share: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
-        
-        if existing_share.is_some() {
-            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Password already shared with this user".to_string())));
+
+        if decryption_errors.count > 0 {
+            log::warn!("{} passwords for user {} could not be decrypted", decryption_errors.count, user_id);
         }
-        
-        // Calculate expiration date
-        let expires_at = share_data.expiration_days.map(|days| {
-            (Utc::now() + Duration::days(days as i64)).naive_utc()
-        });
-        
-        // Create new share
-        let new_share = NewShare {
-            id: Uuid::new_v4(),
-            password_id: Some(password_id),
-            folder_id: None,
-            user_id: current_user_id,
-            shared_with_user_id: recipient_user.id,
-            permission_level: share_data.permission_level.clone(),
-            expires_at,
-            created_at: Utc::now().naive_utc(),
+
+        Ok(HttpResponse::Ok().json(DecryptedListResponse {
+            response: ApiResponse::success(
+                "Passwords retrieved successfully".to_string(),
+                Some(decrypted_passwords)
+            ),
+            decryption_errors,
+        }))
+    }
+
+    // Decrypt a stored entry into its response form
+    fn password_response(password: Password) -> Result<PasswordResponse, String> {
+        let (decrypted_password, client_encrypted) = open_stored_password(&password)?;
+        
+        // Decrypt metadata if available, otherwise use unencrypted fields
+        let website = match &password.encrypted_website {
+            Some(encrypted_data) => {
+                crypto::decrypt_metadata(encrypted_data)
+                    .unwrap_or_else(|_| password.website.clone())
+            }
+            None => password.website.clone()
         };
         
-        // Insert share into database
-        diesel::insert_into(shares::table)
-            .values(&new_share)
-            .execute(&mut conn)
-            .map_err(|e| {
-                log::error!("Failed to create password share: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
-        
-        log::info!("Password {} shared successfully with user {} by user {}", password_id, recipient_user.username, current_user_id);
+        let username = match &password.encrypted_username {
+            Some(encrypted_data) => {
+                crypto::decrypt_metadata(encrypted_data)
+                    .unwrap_or_else(|_| password.username.clone())
+            }
+            None => password.username.clone()
+        };
         
-        Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("Password shared successfully".to_string(), None)))
+        Ok(PasswordResponse {
+            id: password.id,
+            folder_id: password.folder_id,
+            website,
+            username,
+            password: decrypted_password,
+            user_id: password.user_id,
+            notes: password.notes,
+            otp_secret: password.otp_secret,
+            attachments: password.attachments,
+            is_client_encrypted: password.is_client_encrypted,
+            is_favorite: password.is_favorite,
+            client_encrypted,
+        })
     }
 
-    // Share folder handler
-    pub async fn share_folder(
+    // Decrypt a stored password; client-encrypted entries come back as the blob the client sent
+    fn open_stored_password(password: &Password) -> Result<(String, Option<zero_knowledge::EncryptedData>), String> {
+        if password.is_client_encrypted {
+            zero_knowledge::decode_client_blob(&password.encrypted_password).map(|blob| (String::new(), Some(blob)))
+        } else {
+            crypto::decrypt_password(&password.encrypted_password).map(|decrypted_password| (decrypted_password, None))
+        }
+    }
+    
+    // Create a new password
+    pub async fn create_password(
         req: actix_web::HttpRequest,
-        path: web::Path<Uuid>,
-        share_data: web::Json<ShareRequest>,
+        query: web::Query<CreatePasswordQuery>,
+        password_data: web::Json<PasswordRequest>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        use crate::schema::{folders, users, shares};
-        use crate::models::NewShare;
-        use diesel::prelude::*;
-        use chrono::{Utc, Duration};
-        
-        // Extract user ID from JWT token
-        let current_user_id = match auth::extract_user_id_from_request(&req) {
-            Ok(user_uuid) => user_uuid,
-            Err(e) => {
-                log::error!("Failed to extract user ID: {}", e);
-                return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or missing token".to_string())));
-            }
-        };
-        
-        let folder_id = path.into_inner();
+        // Extract user ID from request
+        let user_id = auth::authenticate(&req)?;
+        use crate::schema::passwords;
         
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Verify the folder exists and belongs to the current user
-        let folder_exists = folders::table
-            .filter(folders::id.eq(folder_id))
-            .filter(folders::user_id.eq(current_user_id))
-            .first::<Folder>(&mut conn)
-            .optional()
-            .map_err(|e| {
-                log::error!("Database error checking folder ownership: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        let PasswordFields { website: sanitized_website, username: sanitized_username, notes: sanitized_notes, otp_secret: sanitized_otp_secret, client_blob } =
+            match sanitize_password_fields(&password_data) {
+                Ok(fields) => fields,
+                Err(errors) => return Ok(validation_error_response(errors)),
+            };
         
-        if folder_exists.is_none() {
-            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Folder not found or access denied".to_string())));
-        }
+        // Encrypt the password, unless the client already did and the server must never see it
+        let is_client_encrypted = client_blob.is_some();
+        let encrypted_password = match client_blob {
+            Some(stored) => stored,
+            None => crypto::encrypt_password(&password_data.password)
+                .map_err(|e| {
+                    log::error!("Encryption error: {}", e);
+                    actix_web::error::ErrorInternalServerError("Encryption error")
+                })?,
+        };
         
-        // Find the recipient user by username
-        let recipient_user = users::table
-            .filter(users::username.eq(&share_data.recipient_username))
-            .first::<User>(&mut conn)
-            .optional()
+        // Encrypt metadata (website and username)
+        let encrypted_website = crypto::encrypt_metadata(&sanitized_website)
             .map_err(|e| {
-                log::error!("Database error finding recipient user: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
+                log::error!("Website encryption error: {}", e);
+                actix_web::error::ErrorInternalServerError("Metadata encryption error")
             })?;
         
-        let recipient_user = match recipient_user {
-            Some(user) => user,
-            None => {
-                return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Recipient user not found".to_string())));
-            }
-        };
-        
-        // Prevent sharing with yourself
-        if recipient_user.id == current_user_id {
-            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Cannot share with yourself".to_string())));
-        }
-        
-        // Check if already shared with this user
-        let existing_share = shares::table
-            .filter(shares::folder_id.eq(folder_id))
-            .filter(shares::shared_with_user_id.eq(recipient_user.id))
-            .first::<Share>(&mut conn)
-            .optional()
+        let encrypted_username = crypto::encrypt_metadata(&sanitized_username)
             .map_err(|e| {
-                log::error!("Database error checking existing share: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
+                log::error!("Username encryption error: {}", e);
+                actix_web::error::ErrorInternalServerError("Metadata encryption error")
             })?;
         
-        if existing_share.is_some() {
-            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Folder already shared with this user".to_string())));
+        // Optionally make sure every ciphertext decrypts back before it is stored
+        if crypto::verify_on_write_enabled() {
+            let password_check = if is_client_encrypted {
+                Ok(())
+            } else {
+                crypto::verify_round_trip(&encrypted_password, &password_data.password)
+            };
+            password_check
+                .and_then(|_| crypto::verify_round_trip(&encrypted_website, &sanitized_website))
+                .and_then(|_| crypto::verify_round_trip(&encrypted_username, &sanitized_username))
+                .map_err(|e| {
+                    log::error!("Encryption verification failed: {}", e);
+                    actix_web::error::ErrorInternalServerError("Encryption verification failed")
+                })?;
         }
         
-        // Calculate expiration date
-        let expires_at = share_data.expiration_days.map(|days| {
-            (Utc::now() + Duration::days(days as i64)).naive_utc()
-        });
-        
-        // Create new share
-        let new_share = NewShare {
+        let new_password = NewPassword {
             id: Uuid::new_v4(),
-            password_id: None,
-            folder_id: Some(folder_id),
-            user_id: current_user_id,
-            shared_with_user_id: recipient_user.id,
-            permission_level: share_data.permission_level.clone(),
-            expires_at,
-            created_at: Utc::now().naive_utc(),
+            folder_id: password_data.folder_id,
+            website: sanitized_website,
+            username: sanitized_username,
+            encrypted_password,
+            user_id,
+            notes: sanitized_notes,
+            otp_secret: sanitized_otp_secret,
+            attachments: password_data.attachments.clone(),
+            encrypted_website: Some(encrypted_website),
+            encrypted_username: Some(encrypted_username),
+            is_client_encrypted,
         };
         
-        // Insert share into database
-        diesel::insert_into(shares::table)
-            .values(&new_share)
-            .execute(&mut conn)
+        let created_password = diesel::insert_into(passwords::table)
+            .values(&new_password)
+            .returning(Password::as_select())
+            .get_result(&mut conn)
             .map_err(|e| {
-                log::error!("Failed to create folder share: {}", e);
+                log::error!("Database error: {}", e);
                 actix_web::error::ErrorInternalServerError("Database error")
             })?;
         
-        log::info!("Folder {} shared successfully with user {} by user {}", folder_id, recipient_user.username, current_user_id);
+        // Log password creation event
+        audit_log!(&db_pool, crate::audit::AuditEventType::PasswordCreated, Some(user_id), &req, created_password.id, format!("Password created for {}", created_password.website));
         
-        Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("Folder shared successfully".to_string(), None)))
+        // Checked after saving so a slow or unreachable breach API never blocks the save
+        // The plaintext of a client-encrypted password is never available to check
+        let breach_count = if query.check_breach && !is_client_encrypted {
+            breach_check::check_password_breach(&password_data.password).await
+        } else {
+            None
+        };
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Password created successfully".to_string(),
+            Some(CreatedPasswordResponse {
+                password: created_password,
+                breach_count,
+            })
+        )))
     }
 
-    // Get shared items handler
-    pub async fn get_shared_items(
-        req: actix_web::HttpRequest,
-        db_pool: web::Data<db::DbPool>,
-    ) -> Result<HttpResponse, Error> {
-        use crate::schema::shares;
-        use diesel::prelude::*;
-        use chrono::Utc;
+    // Validated and encrypted field updates shared by owner and shared-recipient edits
+    #[derive(AsChangeset)]
+    #[diesel(table_name = crate::schema::passwords, treat_none_as_null = true)]
+    struct PasswordChanges {
+        website: String,
+        username: String,
+        encrypted_password: Vec<u8>,
+        encrypted_website: Option<Vec<u8>>,
+        encrypted_username: Option<Vec<u8>>,
+        notes: Option<String>,
+        otp_secret: Option<String>,
+        attachments: Option<serde_json::Value>,
+        updated_at: chrono::NaiveDateTime,
+        is_client_encrypted: bool,
+    }
+
+    // Sanitized plain fields of a password request
+    struct PasswordFields {
+        website: String,
+        username: String,
+        notes: Option<String>,
+        otp_secret: Option<String>,
+        client_blob: Option<Vec<u8>>, // Stored form of a client-encrypted password
+    }
+
+    // Sanitize the fields of a password request, collecting every field that is invalid
+    fn sanitize_password_fields(password_data: &PasswordRequest) -> Result<PasswordFields, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
         
-        // Extract user ID from JWT token
-        let current_user_id = match auth::extract_user_id_from_request(&req) {
-            Ok(user_uuid) => user_uuid,
-            Err(e) => {
-                log::error!("Failed to extract user ID: {}", e);
-                return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or missing token".to_string())));
-            }
+        let website = auth::sanitize_website_url(&password_data.website).unwrap_or_else(|error_msg| {
+            errors.add("website", format!("Website URL validation failed: {}", error_msg), error_msg);
+            String::new()
+        });
+        
+        let username = auth::sanitize_username(&password_data.username).unwrap_or_else(|error_msg| {
+            errors.add("username", format!("Username validation failed: {}", error_msg), error_msg);
+            String::new()
+        });
+        
+        let notes = match &password_data.notes {
+            Some(notes) => auth::sanitize_notes(notes).map(Some).unwrap_or_else(|error_msg| {
+                errors.add("notes", format!("Notes validation failed: {}", error_msg), error_msg);
+                None
+            }),
+            None => None
         };
         
-        let mut conn = db_pool.get().map_err(|e| {
-            log::error!("Failed to get database connection: {}", e);
-            actix_web::error::ErrorInternalServerError("Database connection error")
-        })?;
+        let otp_secret = match &password_data.otp_secret {
+            Some(secret) => match auth::sanitize_otp_secret(secret) {
+                Ok(otp) => if otp.is_empty() { None } else { Some(otp) },
+                Err(error_msg) => {
+                    errors.add("otp_secret", format!("OTP secret validation failed: {}", error_msg), error_msg);
+                    None
+                }
+            },
+            None => None
+        };
         
-        // Get all shares where the current user is the recipient
-        let mut all_shares = shares::table
-            .filter(shares::shared_with_user_id.eq(current_user_id))
-            .load::<Share>(&mut conn)
-            .map_err(|e| {
-                log::error!("Database error retrieving shares: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        let client_blob = match &password_data.client_encrypted {
+            Some(blob) => zero_knowledge::encode_client_blob(blob).map(Some).unwrap_or_else(|error_msg| {
+                errors.add("client_encrypted", error_msg.clone(), error_msg);
+                None
+            }),
+            None => None
+        };
         
-        // Filter out expired shares
-        let now = Utc::now().naive_utc();
-        all_shares.retain(|share| {
-            match share.expires_at {
-                Some(expiry) => expiry > now,
-                None => true, // Never expires
-            }
-        });
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(PasswordFields { website, username, notes, otp_secret, client_blob })
+    }
+
+    // Sanitize and encrypt an edit request, returning an error response if it is invalid
+    fn prepare_password_changes(password_data: &PasswordRequest) -> Result<PasswordChanges, HttpResponse> {
+        let fields = sanitize_password_fields(password_data).map_err(validation_error_response)?;
         
-        // Remove expired shares from database (cleanup)
-        let expired_share_ids: Vec<Uuid> = shares::table
-            .filter(shares::shared_with_user_id.eq(current_user_id))
-            .filter(shares::expires_at.is_not_null())
-            .filter(shares::expires_at.lt(now))
-            .select(shares::id)
-            .load::<Uuid>(&mut conn)
-            .unwrap_or_default();
-        
-        if !expired_share_ids.is_empty() {
-            diesel::delete(shares::table.filter(shares::id.eq_any(&expired_share_ids)))
-                .execute(&mut conn)
-                .map_err(|e| {
-                    log::error!("Failed to cleanup expired shares: {}", e);
-                    // Don't fail the request for cleanup errors
-                })
-                .ok();
-            
-            log::info!("Cleaned up {} expired shares for user {}", expired_share_ids.len(), current_user_id);
+        let encryption_error = |e: String| {
+            log::error!("Encryption error: {}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Encryption error".to_string()))
+        };
+        
+        // Encrypt the password and metadata; client-encrypted passwords are stored as sent
+        let is_client_encrypted = fields.client_blob.is_some();
+        let encrypted_password = match fields.client_blob {
+            Some(stored) => stored,
+            None => crypto::encrypt_password(&password_data.password).map_err(encryption_error)?,
+        };
+        let encrypted_website = crypto::encrypt_metadata(&fields.website).map_err(encryption_error)?;
+        let encrypted_username = crypto::encrypt_metadata(&fields.username).map_err(encryption_error)?;
+        
+        Ok(PasswordChanges {
+            website: fields.website,
+            username: fields.username,
+            encrypted_password,
+            encrypted_website: Some(encrypted_website),
+            encrypted_username: Some(encrypted_username),
+            notes: fields.notes,
+            otp_secret: fields.otp_secret,
+            attachments: password_data.attachments.clone(),
+            updated_at: chrono::Utc::now().naive_utc(),
+            is_client_encrypted,
+        })
+    }
+
+    // Most recent history entries returned for a password
+    const PASSWORD_HISTORY_LIMIT: i64 = 20;
+
+    // Keep the previous encrypted value before a password is overwritten
+    fn record_password_history(
+        conn: &mut PgConnection,
+        password_id: Uuid,
+        previous_encrypted: Vec<u8>,
+        new_password: &str,
+    ) -> QueryResult<()> {
+        use crate::schema::password_history;
+        
+        // Edits that only touch metadata shouldn't fill the history with identical values
+        if crypto::decrypt_password(&previous_encrypted).is_ok_and(|previous| previous == new_password) {
+            return Ok(());
         }
         
-        log::info!("Retrieved {} shared items for user {}", all_shares.len(), current_user_id);
-        Ok(HttpResponse::Ok().json(ApiResponse::success("Shared items retrieved successfully".to_string(), Some(all_shares))))
+        diesel::insert_into(password_history::table)
+            .values(&NewPasswordHistory {
+                id: Uuid::new_v4(),
+                password_id,
+                encrypted_password: previous_encrypted,
+                changed_at: chrono::Utc::now().naive_utc(),
+            })
+            .execute(conn)?;
+        Ok(())
     }
-    
-    // Get shared passwords with decrypted content
-    pub async fn get_shared_passwords(
+
+    // Update a password
+    pub async fn update_password(
         req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        password_data: web::Json<PasswordRequest>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        use crate::schema::shares;
-        use diesel::prelude::*;
-        use chrono::Utc;
-        
-        // Extract user ID from JWT token
-        let current_user_id = match auth::extract_user_id_from_request(&req) {
-            Ok(user_uuid) => user_uuid,
-            Err(e) => {
-                log::error!("Failed to extract user ID: {}", e);
-                return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or missing token".to_string())));
-            }
-        };
+        // Extract user ID from request
+        let user_id = auth::authenticate(&req)?;
+        use crate::schema::passwords;
         
+        let password_id = path.into_inner();
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Get all password shares where the current user is the recipient and not expired
-        let now = Utc::now().naive_utc();
-        let password_shares = shares::table
-            .inner_join(crate::schema::passwords::table)
-            .filter(shares::shared_with_user_id.eq(current_user_id))
-            .filter(shares::password_id.is_not_null())
-            .filter(shares::expires_at.is_null().or(shares::expires_at.gt(now)))
-            .select((shares::all_columns, crate::schema::passwords::all_columns))
-            .load::<(Share, Password)>(&mut conn)
-            .map_err(|e| {
-                log::error!("Database error retrieving shared passwords: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        let changes = match prepare_password_changes(&password_data) {
+            Ok(changes) => changes,
+            Err(response) => return Ok(response),
+        };
         
-        // Decrypt passwords and prepare response
-        let mut decrypted_passwords = Vec::new();
-        for (share, password) in password_shares {
-            match crypto::decrypt_password(&password.encrypted_password) {
-                Ok(decrypted_password) => {
-                    let password_response = serde_json::json!({
-                        "id": password.id,
-                        "website": password.website,
-                        "username": password.username,
-                        "password": decrypted_password,
-                        "notes": password.notes,
-                        "otp_secret": password.otp_secret,
-                        "folder_id": password.folder_id,
-                        "shared_by": share.user_id,
-                        "permission_level": share.permission_level,
-                        "expires_at": share.expires_at
-                    });
-                    decrypted_passwords.push(password_response);
-                },
+        // Update password only if it belongs to the authenticated user
+        let rows_affected = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let previous = passwords::table
+                .filter(passwords::id.eq(password_id))
+                .filter(passwords::user_id.eq(user_id))
+                .filter(passwords::deleted_at.is_null())
+                .select((passwords::encrypted_password, passwords::is_client_encrypted))
+                .for_update()
+                .first::<(Vec<u8>, bool)>(conn)
+                .optional()?;
+            let Some((previous, previous_client_encrypted)) = previous else { return Ok(0) };
+            // History is decrypted and rekeyed by the server, so it only keeps server-encrypted values
+            if !previous_client_encrypted {
+                record_password_history(conn, password_id, previous, &password_data.password)?;
+            }
+            
+            diesel::update(
+                passwords::table
+                    .filter(passwords::id.eq(password_id))
+                    .filter(passwords::user_id.eq(user_id))
+            )
+                .set((passwords::folder_id.eq(password_data.folder_id), &changes))
+                .execute(conn)
+        })
+        .map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        if rows_affected == 0 {
+            log::warn!("Password not found or access denied for user: {}", user_id);
+            return Err(actix_web::error::ErrorNotFound("Password not found"));
+        }
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Password updated successfully".to_string(),
+            None::<String>
+        )))
+    }
+
+    // Get the previous values of a password, newest first
+    pub async fn get_password_history(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{password_history, passwords};
+        
+        let user_id = auth::authenticate(&req)?;
+        
+        let password_id = path.into_inner();
+        let mut conn = read_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Only the owner may see previous values
+        let history = password_history::table
+            .inner_join(passwords::table)
+            .filter(passwords::id.eq(password_id))
+            .filter(passwords::user_id.eq(user_id))
+            .order(password_history::changed_at.desc())
+            .limit(PASSWORD_HISTORY_LIMIT)
+            .select((password_history::id, password_history::encrypted_password, password_history::changed_at))
+            .load::<(Uuid, Vec<u8>, chrono::NaiveDateTime)>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if history.is_empty() {
+            let owned = diesel::select(diesel::dsl::exists(
+                passwords::table
+                    .filter(passwords::id.eq(password_id))
+                    .filter(passwords::user_id.eq(user_id))
+            ))
+                .get_result::<bool>(&mut conn)
+                .map_err(|e| {
+                    log::error!("Database error: {}", e);
+                    actix_web::error::ErrorInternalServerError("Database error")
+                })?;
+            if !owned {
+                return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Password not found".to_string())));
+            }
+        }
+        
+        let mut entries = Vec::new();
+        let mut decryption_errors = DecryptionErrors::default();
+        for (id, encrypted_password, changed_at) in history {
+            match crypto::decrypt_password(&encrypted_password) {
+                Ok(password) => entries.push(PasswordHistoryEntry { id, password, changed_at }),
                 Err(e) => {
-                    log::error!("Failed to decrypt shared password {}: {}", password.id, e);
-                    // Skip this password but continue with others
+                    log::error!("Failed to decrypt history entry {}: {}", id, e);
+                    decryption_errors.record(id);
                 }
             }
         }
         
-        log::info!("Retrieved {} shared passwords for user {}", decrypted_passwords.len(), current_user_id);
-        Ok(HttpResponse::Ok().json(ApiResponse::success("Shared passwords retrieved successfully".to_string(), Some(decrypted_passwords))))
+        Ok(HttpResponse::Ok().json(DecryptedListResponse {
+            response: ApiResponse::success("Password history retrieved successfully".to_string(), Some(entries)),
+            decryption_errors,
+        }))
+    }
+
+    /// Resolves the caller's access to a password, or the response ending the request
+    fn attachment_access(conn: &mut PgConnection, user_id: Uuid, password_id: Uuid, needed: PasswordAccess) -> Result<(), HttpResponse> {
+        let access = sharing::resolve_password_access(conn, user_id, password_id).map_err(|e| {
+            log::error!("Database error resolving password access: {}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error".to_string()))
+        })?;
+        
+        if access == PasswordAccess::None {
+            return Err(HttpResponse::NotFound().json(ApiResponse::<()>::error("Password not found".to_string())));
+        }
+        if access < needed {
+            log::warn!("User {} lacks access to change attachments of password {}", user_id, password_id);
+            return Err(HttpResponse::Forbidden().json(ApiResponse::<()>::error("Only the owner can change attachments".to_string())));
+        }
+        Ok(())
     }
     
-    // Remove a share (unshare)
-    pub async fn remove_share(
+    // List the files attached to a password
+    pub async fn list_attachments(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::password_attachments;
+        
+        let user_id = auth::authenticate(&req)?;
+        
+        let password_id = path.into_inner();
+        let mut conn = read_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        if let Err(response) = attachment_access(&mut conn, user_id, password_id, PasswordAccess::Read) {
+            return Ok(response);
+        }
+        
+        let attachments = password_attachments::table
+            .filter(password_attachments::password_id.eq(password_id))
+            .order(password_attachments::created_at.asc())
+            .select(PasswordAttachment::as_select())
+            .load::<PasswordAttachment>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success("Attachments retrieved successfully".to_string(), Some(attachments))))
+    }
+    
+    // Attach a file to a password from a multipart upload
+    pub async fn upload_attachment(
         req: actix_web::HttpRequest,
         path: web::Path<Uuid>,
+        mut payload: actix_multipart::Multipart,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        use crate::schema::shares;
-        use diesel::prelude::*;
+        use futures_util::StreamExt;
         
-        // Extract user ID from JWT token
-        let current_user_id = match auth::extract_user_id_from_request(&req) {
-            Ok(user_uuid) => user_uuid,
-            Err(e) => {
-                log::error!("Failed to extract user ID: {}", e);
-                return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or missing token".to_string())));
+        let user_id = auth::authenticate(&req)?;
+        
+        let password_id = path.into_inner();
+        let limits = attachments::AttachmentLimits::from_env();
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Check before reading the body, so nobody uploads megabytes only to be turned away
+        if let Err(response) = attachment_access(&mut conn, user_id, password_id, PasswordAccess::Owner) {
+            return Ok(response);
+        }
+        
+        // The first part carrying a file name is the attachment; other parts are ignored
+        let mut upload = None;
+        while let Some(field) = payload.next().await {
+            let mut field = field.map_err(|e| {
+                log::warn!("Malformed attachment upload: {}", e);
+                actix_web::error::ErrorBadRequest("Malformed multipart body")
+            })?;
+            let filename = match field.content_disposition().and_then(|cd| cd.get_filename()) {
+                Some(filename) => attachments::sanitize_filename(filename),
+                None => continue,
+            };
+            let content_type = field
+                .content_type()
+                .map(|mime| mime.essence_str().to_string())
+                .unwrap_or_else(|| "application/octet-stream".to_string());
+            
+            let mut content = Vec::new();
+            while let Some(chunk) = field.next().await {
+                let chunk = chunk.map_err(|e| {
+                    log::warn!("Malformed attachment upload: {}", e);
+                    actix_web::error::ErrorBadRequest("Malformed multipart body")
+                })?;
+                if (content.len() + chunk.len()) as i64 > limits.max_file_bytes {
+                    return Ok(HttpResponse::PayloadTooLarge().json(ApiResponse::<()>::error(
+                        format!("Attachments can be at most {} bytes", limits.max_file_bytes)
+                    )));
+                }
+                content.extend_from_slice(&chunk);
             }
+            upload = Some((filename, content_type, content));
+            break;
+        }
+        
+        let (filename, content_type, content) = match upload {
+            Some(upload) => upload,
+            None => return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("No file was uploaded".to_string()))),
         };
         
-        let share_id = path.into_inner();
+        let size_bytes = content.len() as i64;
+        let encrypted_content = crypto::encrypt_attachment(content).map_err(|e| {
+            log::error!("Failed to encrypt attachment: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to encrypt attachment")
+        })?;
         
-        let mut conn = db_pool.get().map_err(|e| {
+        let new_attachment = NewPasswordAttachment {
+            id: Uuid::new_v4(),
+            password_id,
+            user_id,
+            filename,
+            content_type,
+            size_bytes,
+            encrypted_content,
+        };
+        let stored = attachments::insert_within_quota(&mut conn, &new_attachment, limits.quota_bytes).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        if !stored {
+            return Ok(HttpResponse::PayloadTooLarge().json(ApiResponse::<()>::error(
+                format!("Attachment storage quota of {} bytes exceeded", limits.quota_bytes)
+            )));
+        }
+        
+        audit_log!(&db_pool, crate::audit::AuditEventType::AttachmentAdded, Some(user_id), &req, new_attachment.id, format!("Attachment added to password: {}", password_id));
+        
+        Ok(HttpResponse::Created().json(ApiResponse::success(
+            "Attachment uploaded successfully".to_string(),
+            Some(PasswordAttachment {
+                id: new_attachment.id,
+                password_id,
+                filename: new_attachment.filename,
+                content_type: new_attachment.content_type,
+                size_bytes,
+                created_at: chrono::Utc::now().naive_utc(),
+            })
+        )))
+    }
+    
+    // Download the decrypted content of an attachment
+    pub async fn download_attachment(
+        req: actix_web::HttpRequest,
+        path: web::Path<(Uuid, Uuid)>,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+        use crate::schema::password_attachments;
+        
+        let user_id = auth::authenticate(&req)?;
+        
+        let (password_id, attachment_id) = path.into_inner();
+        let mut conn = read_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Verify the share exists and the user has permission to remove it
-        // (either the owner who shared it or the recipient)
-        let share = shares::table
-            .filter(shares::id.eq(share_id))
-            .filter(shares::user_id.eq(current_user_id).or(shares::shared_with_user_id.eq(current_user_id)))
-            .first::<Share>(&mut conn)
+        if let Err(response) = attachment_access(&mut conn, user_id, password_id, PasswordAccess::Read) {
+            return Ok(response);
+        }
+        
+        let attachment = password_attachments::table
+            .filter(password_attachments::id.eq(attachment_id))
+            .filter(password_attachments::password_id.eq(password_id))
+            .select((password_attachments::filename, password_attachments::content_type, password_attachments::encrypted_content))
+            .first::<(String, String, Vec<u8>)>(&mut conn)
             .optional()
             .map_err(|e| {
-                log::error!("Database error checking share ownership: {}", e);
+                log::error!("Database error: {}", e);
                 actix_web::error::ErrorInternalServerError("Database error")
             })?;
+        let (filename, content_type, encrypted_content) = match attachment {
+            Some(attachment) => attachment,
+            None => return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Attachment not found".to_string()))),
+        };
         
-        if share.is_none() {
-            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Share not found or access denied".to_string())));
+        let content = crypto::decrypt_attachment(&encrypted_content).map_err(|e| {
+            log::error!("Failed to decrypt attachment {}: {}", attachment_id, e);
+            actix_web::error::ErrorInternalServerError("Failed to decrypt attachment")
+        })?;
+        
+        // Always a download, so uploaded HTML can't run in the app's origin
+        Ok(HttpResponse::Ok()
+            .content_type(content_type)
+            .insert_header(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(filename)],
+            })
+            .insert_header(("X-Content-Type-Options", "nosniff"))
+            .insert_header(("Cache-Control", "no-store"))
+            .body(content))
+    }
+    
+    // Remove an attachment from a password
+    pub async fn delete_attachment(
+        req: actix_web::HttpRequest,
+        path: web::Path<(Uuid, Uuid)>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::password_attachments;
+        
+        let user_id = auth::authenticate(&req)?;
+        
+        let (password_id, attachment_id) = path.into_inner();
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        if let Err(response) = attachment_access(&mut conn, user_id, password_id, PasswordAccess::Owner) {
+            return Ok(response);
         }
         
-        // Delete the share
-        diesel::delete(shares::table.filter(shares::id.eq(share_id)))
+        let deleted_rows = diesel::delete(
+            password_attachments::table
+                .filter(password_attachments::id.eq(attachment_id))
+                .filter(password_attachments::password_id.eq(password_id))
+        )
             .execute(&mut conn)
             .map_err(|e| {
-                log::error!("Failed to remove share: {}", e);
+                log::error!("Database error: {}", e);
                 actix_web::error::ErrorInternalServerError("Database error")
             })?;
         
-        log::info!("Share {} removed successfully by user {}", share_id, current_user_id);
-        Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("Share removed successfully".to_string(), None)))
+        if deleted_rows == 0 {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Attachment not found".to_string())));
+        }
+        
+        audit_log!(&db_pool, crate::audit::AuditEventType::AttachmentDeleted, Some(user_id), &req, attachment_id, format!("Attachment removed from password: {}", password_id));
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Attachment deleted successfully".to_string(),
+            None::<String>
+        )))
     }
 
-    // CSV Export handler
-    pub async fn export_csv(
+    // Move a password to a different folder
+    pub async fn move_password(
         req: actix_web::HttpRequest,
-        export_data: web::Json<CsvExportRequest>,
+        path: web::Path<Uuid>,
+        move_data: web::Json<PasswordMoveRequest>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        use crate::schema::{passwords, folders, users};
-        
-        // Authenticate user
-        let current_user_id = match auth::extract_user_id_from_request(&req) {
-            Ok(user_id) => user_id,
-            Err(_) => {
-                return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or missing token".to_string())));
-            }
-        };
+        // Extract user ID from request (handles both cookies and Authorization header)
+        let user_id = auth::authenticate(&req)?;
+        use crate::schema::passwords;
         
+        let password_id = path.into_inner();
         let mut conn = db_pool.get().map_err(|e| {
             log::error!("Failed to get database connection: {}", e);
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Verify user password before allowing export
-        let user = users::table
-            .filter(users::id.eq(current_user_id))
-            .first::<User>(&mut conn)
+        // Update only the folder_id without re-encrypting the password
+        let rows_affected = diesel::update(
+            passwords::table
+                .filter(passwords::id.eq(password_id))
+                .filter(passwords::user_id.eq(user_id))
+                .filter(passwords::deleted_at.is_null())
+        )
+            .set(passwords::folder_id.eq(move_data.folder_id))
+            .execute(&mut conn)
             .map_err(|e| {
                 log::error!("Database error: {}", e);
                 actix_web::error::ErrorInternalServerError("Database error")
             })?;
         
-        if !auth::verify_password(&export_data.password, &user.password_hash) {
-            return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid password".to_string())));
+        if rows_affected == 0 {
+            log::warn!("Password not found or access denied for user: {}", user_id);
+            return Err(actix_web::error::ErrorNotFound("Password not found"));
         }
         
-        // Get all passwords for the user
-        let user_passwords = passwords::table
-            .filter(passwords::user_id.eq(current_user_id))
-            .load::<Password>(&mut conn)
-            .map_err(|e| {
-                log::error!("Database error: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Password moved successfully".to_string(),
+            None::<String>
+        )))
+    }
+
+    // Star or unstar a password
+    pub async fn set_password_favorite(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        favorite_data: web::Json<FavoriteRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::passwords;
         
-        // Get all folders for the user to map folder names
-        let user_folders = folders::table
-            .filter(folders::user_id.eq(current_user_id))
-            .load::<Folder>(&mut conn)
+        let user_id = auth::authenticate(&req)?;
+        
+        let password_id = path.into_inner();
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Only the owner's flag exists, so recipients of a share can't star it
+        let rows_affected = diesel::update(
+            passwords::table
+                .filter(passwords::id.eq(password_id))
+                .filter(passwords::user_id.eq(user_id))
+                .filter(passwords::deleted_at.is_null())
+        )
+            .set(passwords::is_favorite.eq(favorite_data.is_favorite))
+            .execute(&mut conn)
             .map_err(|e| {
                 log::error!("Database error: {}", e);
                 actix_web::error::ErrorInternalServerError("Database error")
             })?;
         
-        // Create folder lookup map
-        let folder_map: HashMap<Uuid, String> = user_folders
-            .into_iter()
-            .map(|f| (f.id, f.name))
-            .collect();
-        
-        // Convert passwords to CSV format
-        let mut csv_entries = Vec::new();
-        for password in user_passwords {
-            // Decrypt password
-            let decrypted_password = match crypto::decrypt_password(&password.encrypted_password) {
-                Ok(pwd) => pwd,
-                Err(e) => {
-                    log::error!("Failed to decrypt password: {}", e);
-                    continue; // Skip this entry if decryption fails
-                }
-            };
-            
-            let folder_name = password.folder_id
-                .and_then(|id| folder_map.get(&id))
-                .map(|name| name.clone())
-                .unwrap_or_else(|| "No Folder".to_string());
-            
-            csv_entries.push(CsvExportEntry {
-                name: password.website.clone(),
-                url: password.website,
-                username: password.username,
-                password: decrypted_password,
-                notes: password.notes.unwrap_or_default(),
-                folder: folder_name,
-            });
+        if rows_affected == 0 {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Password not found".to_string())));
         }
         
-        // Generate CSV content
-        let mut csv_content = String::from("name,url,username,password,notes,folder\n");
-        for entry in csv_entries {
-            csv_content.push_str(&format!(
-                "\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\"\n",
-                entry.name.replace('"', "\"\""),
-                entry.url.replace('"', "\"\""),
-                entry.username.replace('"', "\"\""),
-                entry.password.replace('"', "\"\""),
-                entry.notes.replace('"', "\"\""),
-                entry.folder.replace('"', "\"\"")
-            ));
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Favorite updated successfully".to_string(),
+            Some(serde_json::json!({ "is_favorite": favorite_data.is_favorite }))
+        )))
+    }
+
+    // Move a password to the trash
+    pub async fn delete_password(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        // Extract user ID from request (handles both cookies and Authorization header)
+        let user_id = auth::authenticate(&req)?;
+        use crate::schema::passwords;
+        
+        let password_id = path.into_inner();
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Trash the password only if it belongs to the authenticated user and isn't trashed yet
+        let deleted_rows = diesel::update(
+            passwords::table
+                .filter(passwords::id.eq(password_id))
+                .filter(passwords::user_id.eq(user_id))
+                .filter(passwords::deleted_at.is_null())
+        )
+            .set(passwords::deleted_at.eq(Some(chrono::Utc::now().naive_utc())))
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if deleted_rows == 0 {
+            log::warn!("Password not found or access denied for user: {}", user_id);
+            return Err(actix_web::error::ErrorNotFound("Password not found"));
+        }
+        
+        // Log password deletion event
+        audit_log!(&db_pool, crate::audit::AuditEventType::PasswordDeleted, Some(user_id), &req, password_id, format!("Password moved to trash: {}", password_id));
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Password moved to trash".to_string(),
+            None::<String>
+        )))
+    }
+
+    /// Most ids accepted by one bulk password request
+    const MAX_BULK_PASSWORD_IDS: usize = 500;
+
+    // Checks a bulk request's ids, returning them without duplicates or an error response
+    fn bulk_password_ids(ids: &[Uuid]) -> Result<Vec<Uuid>, HttpResponse> {
+        if ids.is_empty() {
+            return Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error("No password ids given".to_string())));
+        }
+        if ids.len() > MAX_BULK_PASSWORD_IDS {
+            return Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error(
+                format!("At most {} password ids can be given at once", MAX_BULK_PASSWORD_IDS)
+            )));
+        }
+
+        let mut unique = ids.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+        Ok(unique)
+    }
+
+    // Move several passwords to the trash at once
+    pub async fn bulk_delete_passwords(
+        req: actix_web::HttpRequest,
+        password_ids: web::Json<Vec<Uuid>>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::passwords;
+        
+        let user_id = auth::authenticate(&req)?;
+        
+        let requested = match bulk_password_ids(&password_ids) {
+            Ok(ids) => ids,
+            Err(response) => return Ok(response),
+        };
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Shares stay in place so a restore brings them back
+        let deleted = diesel::update(
+            passwords::table
+                .filter(passwords::id.eq_any(&requested))
+                .filter(passwords::user_id.eq(user_id))
+                .filter(passwords::deleted_at.is_null())
+        )
+            .set(passwords::deleted_at.eq(Some(chrono::Utc::now().naive_utc())))
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to bulk delete passwords for user {}: {}", user_id, e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        audit_log!(&db_pool, crate::audit::AuditEventType::PasswordsBulkDeleted, Some(user_id), &req, user_id, format!("Bulk delete of {} passwords ({} requested)", deleted, requested.len()));
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            format!("{} passwords moved to trash", deleted),
+            Some(serde_json::json!({ "deleted": deleted, "requested": requested.len() }))
+        )))
+    }
+
+    // Move several passwords into a folder (or out of any folder) at once
+    pub async fn bulk_move_passwords(
+        req: actix_web::HttpRequest,
+        move_data: web::Json<BulkMoveRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{folders, passwords};
+        
+        let user_id = auth::authenticate(&req)?;
+        
+        let requested = match bulk_password_ids(&move_data.password_ids) {
+            Ok(ids) => ids,
+            Err(response) => return Ok(response),
+        };
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        if let Some(folder_id) = move_data.folder_id {
+            let folder_owned = diesel::select(diesel::dsl::exists(
+                folders::table
+                    .filter(folders::id.eq(folder_id))
+                    .filter(folders::user_id.eq(user_id))
+            ))
+                .get_result::<bool>(&mut conn)
+                .map_err(|e| {
+                    log::error!("Database error checking folder ownership: {}", e);
+                    actix_web::error::ErrorInternalServerError("Database error")
+                })?;
+            
+            if !folder_owned {
+                return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Folder not found or access denied".to_string())));
+            }
+        }
+        
+        // Update only the folder_id without re-encrypting the passwords
+        let moved = diesel::update(
+            passwords::table
+                .filter(passwords::id.eq_any(&requested))
+                .filter(passwords::user_id.eq(user_id))
+                .filter(passwords::deleted_at.is_null())
+        )
+            .set(passwords::folder_id.eq(move_data.folder_id))
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to bulk move passwords for user {}: {}", user_id, e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            format!("{} passwords moved", moved),
+            Some(serde_json::json!({ "moved": moved, "requested": requested.len() }))
+        )))
+    }
+
+    // List the passwords in the trash, most recently deleted first
+    pub async fn get_trash(
+        req: actix_web::HttpRequest,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::passwords;
+        
+        let user_id = auth::authenticate(&req)?;
+        
+        let mut conn = read_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let trashed = passwords::table
+            .filter(passwords::user_id.eq(user_id))
+            .filter(passwords::deleted_at.is_not_null())
+            .order(passwords::deleted_at.desc())
+            .select(Password::as_select())
+            .load(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        let retention = chrono::Duration::days(trash::retention_days());
+        let mut entries = Vec::new();
+        let mut decryption_errors = DecryptionErrors::default();
+        for password in trashed {
+            let password_id = password.id;
+            let Some(deleted_at) = password.deleted_at else { continue };
+            match password_response(password) {
+                Ok(response) => entries.push(TrashedPasswordResponse {
+                    password: response,
+                    deleted_at,
+                    purge_after: deleted_at + retention,
+                }),
+                Err(e) => {
+                    log::error!("Failed to decrypt trashed password {}: {}", password_id, e);
+                    decryption_errors.record(password_id);
+                }
+            }
+        }
+        
+        Ok(HttpResponse::Ok().json(DecryptedListResponse {
+            response: ApiResponse::success("Trash retrieved successfully".to_string(), Some(entries)),
+            decryption_errors,
+        }))
+    }
+
+    // Take a password back out of the trash
+    pub async fn restore_password(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::passwords;
+        
+        let user_id = auth::authenticate(&req)?;
+        
+        let password_id = path.into_inner();
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let restored = diesel::update(
+            passwords::table
+                .filter(passwords::id.eq(password_id))
+                .filter(passwords::user_id.eq(user_id))
+                .filter(passwords::deleted_at.is_not_null())
+        )
+            .set(passwords::deleted_at.eq(None::<chrono::NaiveDateTime>))
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if restored == 0 {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Password not found in trash".to_string())));
+        }
+        
+        audit_log!(&db_pool, crate::audit::AuditEventType::PasswordRestored, Some(user_id), &req, password_id, format!("Password restored from trash: {}", password_id));
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Password restored successfully".to_string(),
+            None::<String>
+        )))
+    }
+
+    // Permanently delete trashed passwords that are past the retention period
+    pub async fn purge_trash(
+        req: actix_web::HttpRequest,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        let user_id = auth::authenticate(&req)?;
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let retention_days = trash::retention_days();
+        let cutoff = trash::purge_cutoff(chrono::Utc::now().naive_utc(), retention_days);
+        let purged = trash::purge_expired(&mut conn, Some(user_id), cutoff).map_err(|e| {
+            log::error!("Failed to purge trash for user {}: {}", user_id, e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        audit_log!(&db_pool, crate::audit::AuditEventType::TrashPurged, Some(user_id), &req, user_id, format!("Purged {} trashed passwords older than {} days", purged, retention_days));
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            format!("{} passwords purged", purged),
+            Some(serde_json::json!({ "purged": purged, "retention_days": retention_days }))
+        )))
+    }
+
+    // Generate a random password
+    pub async fn generate_password(
+        req: actix_web::HttpRequest,
+        options: web::Json<password_generator::GeneratorOptions>,
+    ) -> Result<HttpResponse, Error> {
+        auth::authenticate(&req)?;
+
+        match password_generator::generate(&options) {
+            Ok(generated) => Ok(HttpResponse::Ok().json(ApiResponse::success(
+                "Password generated successfully".to_string(),
+                Some(generated)
+            ))),
+            Err(e) => Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
+        }
+    }
+
+    // Report groups of entries that share a password, without revealing it
+    pub async fn get_duplicate_passwords(
+        req: actix_web::HttpRequest,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        let user_id = auth::authenticate(&req)?;
+        
+        let mut conn = read_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let (entries, undecryptable) = password_health::load_analysis_entries(&mut conn, user_id).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        let groups = password_health::find_duplicates(&entries);
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Duplicate password report generated".to_string(),
+            Some(serde_json::json!({
+                "total": entries.len(),
+                "groups": groups,
+                "undecryptable": undecryptable,
+            }))
+        )))
+    }
+
+    // Report entries with weak passwords and why, without revealing them
+    pub async fn get_weak_passwords(
+        req: actix_web::HttpRequest,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        let user_id = auth::authenticate(&req)?;
+        
+        let mut conn = read_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let (entries, undecryptable) = password_health::load_analysis_entries(&mut conn, user_id).map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        let weak = password_health::find_weak(&entries);
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Weak password report generated".to_string(),
+            Some(serde_json::json!({
+                "total": entries.len(),
+                "weak": weak,
+                "undecryptable": undecryptable,
+            }))
+        )))
+    }
+
+    // Get the cached password health report, starting a scan if none exists yet
+    pub async fn get_password_health(
+        req: actix_web::HttpRequest,
+        db_pool: web::Data<db::DbPool>,
+        health_service: web::Data<std::sync::Arc<password_health::PasswordHealthService>>,
+    ) -> Result<HttpResponse, Error> {
+        let user_id = auth::authenticate(&req)?;
+
+        let status = health_service.status(user_id);
+        if status.report.is_none() {
+            if !status.scanning {
+                health_service.start_scan(user_id, db_pool.get_ref().clone());
+            }
+            return Ok(HttpResponse::Accepted().json(ApiResponse::success(
+                "Password health scan in progress".to_string(),
+                Some(password_health::HealthStatus { report: None, scanning: true })
+            )));
+        }
+
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Password health report retrieved".to_string(),
+            Some(status)
+        )))
+    }
+
+    // Trigger a background refresh of the password health report
+    pub async fn rescan_password_health(
+        req: actix_web::HttpRequest,
+        db_pool: web::Data<db::DbPool>,
+        health_service: web::Data<std::sync::Arc<password_health::PasswordHealthService>>,
+    ) -> Result<HttpResponse, Error> {
+        let user_id = auth::authenticate(&req)?;
+
+        let message = if health_service.start_scan(user_id, db_pool.get_ref().clone()) {
+            "Password health scan started"
+        } else {
+            "Password health scan already in progress"
+        };
+
+        Ok(HttpResponse::Accepted().json(ApiResponse::success(
+            message.to_string(),
+            Some(health_service.status(user_id))
+        )))
+    }
+
+    // Get all folders for a user
+    pub async fn get_folders(
+        req: actix_web::HttpRequest,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        // Extract user ID from request (supports both cookies and Authorization header)
+        let user_id = auth::authenticate(&req)?;
+        use crate::schema::folders;
+        
+        let mut conn = read_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let folders_list = folders::table
+            .filter(folders::user_id.eq(user_id))
+            .load::<Folder>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Folders retrieved successfully".to_string(),
+            Some(folders_list)
+        )))
+    }
+
+    // Create a new folder
+    pub async fn create_folder(
+        req: actix_web::HttpRequest,
+        folder_data: web::Json<FolderRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        // Extract user ID from request (supports both cookies and Authorization header)
+        let user_id = auth::authenticate(&req)?;
+        use crate::schema::folders;
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let folder_name = match folder_data.name.as_deref().map(auth::sanitize_folder_name) {
+            Some(Ok(name)) => name,
+            Some(Err(error_msg)) => {
+                let mut errors = ValidationErrors::default();
+                errors.add("name", format!("Folder name validation failed: {}", error_msg), error_msg);
+                return Ok(validation_error_response(errors));
+            }
+            None => {
+                let mut errors = ValidationErrors::default();
+                errors.add("name", "Folder name is required".to_string(), "Folder name is required".to_string());
+                return Ok(validation_error_response(errors));
+            }
+        };
+        
+        let new_folder = NewFolder {
+            id: Uuid::new_v4(),
+            user_id,
+            parent_folder_id: folder_data.parent_folder_id,
+            name: folder_name,
+        };
+        
+        let created_folder = diesel::insert_into(folders::table)
+            .values(&new_folder)
+            .get_result::<Folder>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Folder created successfully".to_string(),
+            Some(created_folder)
+        )))
+    }
+
+    // Delete a folder
+    pub async fn delete_folder(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        query: web::Query<FolderDeleteQuery>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        // Extract user ID from request (supports both cookies and Authorization header)
+        let user_id = auth::authenticate(&req)?;
+        use crate::schema::{folders, passwords, shares};
+        
+        let folder_id = path.into_inner();
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // First, verify the folder exists and belongs to the user
+        let folder = folders::table
+            .filter(folders::id.eq(folder_id))
+            .filter(folders::user_id.eq(user_id))
+            .first::<Folder>(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if folder.is_none() {
+            log::warn!("Folder not found or access denied for user: {}", user_id);
+            return Err(actix_web::error::ErrorNotFound("Folder not found"));
+        }
+        
+        let folder = folder.unwrap();
+        
+        if query.recursive {
+            return delete_folder_tree(&mut conn, user_id, folder_id);
+        }
+        
+        // Move all passwords from this folder to its parent folder
+        diesel::update(
+            passwords::table
+                .filter(passwords::folder_id.eq(folder_id))
+                .filter(passwords::user_id.eq(user_id))
+        )
+            .set(passwords::folder_id.eq(folder.parent_folder_id))
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to move passwords: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        // Move all subfolders to the parent folder
+        diesel::update(
+            folders::table
+                .filter(folders::parent_folder_id.eq(folder_id))
+                .filter(folders::user_id.eq(user_id))
+        )
+            .set(folders::parent_folder_id.eq(folder.parent_folder_id))
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to move subfolders: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        // Delete any shares for this folder
+        diesel::delete(
+            shares::table
+                .filter(shares::folder_id.eq(folder_id))
+        )
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to delete folder shares: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        // Finally, delete the folder
+        let deleted_rows = diesel::delete(
+            folders::table
+                .filter(folders::id.eq(folder_id))
+                .filter(folders::user_id.eq(user_id))
+        )
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if deleted_rows == 0 {
+            log::warn!("Failed to delete folder for user: {}", user_id);
+            return Err(actix_web::error::ErrorInternalServerError("Failed to delete folder"));
+        }
+        
+        log::info!("Folder {} deleted successfully by user {}", folder_id, user_id);
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Folder deleted successfully".to_string(),
+            None::<String>
+        )))
+    }
+
+    // Delete a folder with all its subfolders, their passwords and any shares of them
+    fn delete_folder_tree(conn: &mut PgConnection, user_id: Uuid, folder_id: Uuid) -> Result<HttpResponse, Error> {
+        use crate::schema::{folders, passwords, shares};
+        
+        let summary = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let parents = folder_tree::load_parent_map(conn, user_id)?;
+            let folder_ids = folder_tree::subtree_ids(folder_id, &parents);
+            
+            let password_ids = passwords::table
+                .filter(passwords::user_id.eq(user_id))
+                .filter(passwords::folder_id.eq_any(&folder_ids))
+                .select(passwords::id)
+                .load::<Uuid>(conn)?;
+            
+            // Nothing references these rows with ON DELETE CASCADE, so delete dependents first
+            diesel::delete(
+                shares::table.filter(
+                    shares::folder_id.eq_any(&folder_ids).or(shares::password_id.eq_any(&password_ids))
+                )
+            )
+                .execute(conn)?;
+            
+            let deleted_passwords = diesel::delete(passwords::table.filter(passwords::id.eq_any(&password_ids)))
+                .execute(conn)?;
+            
+            let deleted_folders = diesel::delete(
+                folders::table
+                    .filter(folders::user_id.eq(user_id))
+                    .filter(folders::id.eq_any(&folder_ids))
+            )
+                .execute(conn)?;
+            
+            Ok(FolderDeleteSummary { deleted_folders, deleted_passwords })
+        })
+        .map_err(|e| {
+            log::error!("Failed to delete folder tree: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        log::info!(
+            "Folder {} deleted recursively by user {}: {} folders, {} passwords",
+            folder_id, user_id, summary.deleted_folders, summary.deleted_passwords
+        );
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Folder deleted successfully".to_string(),
+            Some(summary)
+        )))
+    }
+
+    // Update a folder
+    pub async fn update_folder(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        folder_data: web::Json<FolderRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        // Extract user ID from request (supports both cookies and Authorization header)
+        let user_id = auth::authenticate(&req)?;
+        use crate::schema::folders;
+        
+        let folder_id = path.into_inner();
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Update folder only if it belongs to the authenticated user
+        let updated_rows = if let Some(ref name) = folder_data.name {
+            let name = match auth::sanitize_folder_name(name) {
+                Ok(name) => name,
+                Err(error_msg) => {
+                    let mut errors = ValidationErrors::default();
+                    errors.add("name", format!("Folder name validation failed: {}", error_msg), error_msg);
+                    return Ok(validation_error_response(errors));
+                }
+            };
+            
+            // Update name only
+            diesel::update(
+                folders::table
+                    .filter(folders::id.eq(folder_id))
+                    .filter(folders::user_id.eq(user_id))
+            )
+                .set(folders::name.eq(name))
+                .execute(&mut conn)
+                .map_err(|e| {
+                    log::error!("Database error: {}", e);
+                    actix_web::error::ErrorInternalServerError("Database error")
+                })?
+        } else if let Some(parent_folder_id) = folder_data.parent_folder_id {
+            // Reject moves that would make the folder its own ancestor
+            let parents = folder_tree::load_parent_map(&mut conn, user_id).map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+            if !parents.contains_key(&folder_id) {
+                log::warn!("Folder not found or access denied for user: {}", user_id);
+                return Err(actix_web::error::ErrorNotFound("Folder not found"));
+            }
+            if let Err(message) = folder_tree::validate_reparent(folder_id, parent_folder_id, &parents) {
+                return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(message)));
+            }
+            
+            // Update parent_folder_id
+            diesel::update(
+                folders::table
+                    .filter(folders::id.eq(folder_id))
+                    .filter(folders::user_id.eq(user_id))
+            )
+                .set(folders::parent_folder_id.eq(Some(parent_folder_id)))
+                .execute(&mut conn)
+                .map_err(|e| {
+                    log::error!("Database error: {}", e);
+                    actix_web::error::ErrorInternalServerError("Database error")
+                })?
+        } else {
+            return Err(actix_web::error::ErrorBadRequest("No fields to update"));
+        };
+        
+        if updated_rows == 0 {
+            log::warn!("Folder not found or access denied for user: {}", user_id);
+            return Err(actix_web::error::ErrorNotFound("Folder not found"));
+        }
+        
+        // Fetch the updated folder to return it
+        let updated_folder = folders::table
+            .filter(folders::id.eq(folder_id))
+            .filter(folders::user_id.eq(user_id))
+            .first::<Folder>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Folder updated successfully".to_string(),
+            Some(updated_folder)
+        )))
+    }
+
+    // Generate OTP code for a password entry
+    pub async fn generate_otp(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        // Extract user ID from request (supports both cookies and Authorization header)
+        let user_id = auth::authenticate(&req)?;
+        let password_id = path.into_inner();
+        
+        use crate::schema::passwords;
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Get the password entry and verify ownership
+        let password = passwords::table
+            .filter(passwords::id.eq(password_id))
+            .filter(passwords::user_id.eq(user_id))
+            .filter(passwords::deleted_at.is_null())
+            .first::<Password>(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?
+            .ok_or_else(|| {
+                log::warn!("Password not found or access denied for user: {}", user_id);
+                actix_web::error::ErrorNotFound("Password not found")
+            })?;
+        
+        // Check if password has OTP secret
+        if let Some(otp_secret) = &password.otp_secret {
+            match mfa::generate_totp_code(otp_secret) {
+                Ok(totp) => {
+                    Ok(HttpResponse::Ok().json(ApiResponse::success(
+                        "OTP code generated successfully".to_string(),
+                        Some(serde_json::json!({
+                            "otp_code": totp.code,
+                            "expires_in": totp.expires_in,
+                            "next_otp_code": totp.next_code
+                        }))
+                    )))
+                }
+                Err(e) => {
+                    log::error!("Failed to generate OTP code: {}", e);
+                    Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(
+                        "Failed to generate OTP code".to_string()
+                    )))
+                }
+            }
+        } else {
+            Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(
+                "No OTP secret configured for this password".to_string()
+            )))
+        }
+    }
+
+    // Recipient of a share: either a single user or a group
+    enum ShareRecipient {
+        User(Box<User>),
+        Group(Group),
+    }
+
+    impl ShareRecipient {
+        fn kind(&self) -> &'static str {
+            match self {
+                ShareRecipient::User(_) => "user",
+                ShareRecipient::Group(_) => "group",
+            }
+        }
+
+        fn name(&self) -> &str {
+            match self {
+                ShareRecipient::User(user) => &user.username,
+                ShareRecipient::Group(group) => &group.name,
+            }
+        }
+
+        fn user_id(&self) -> Option<Uuid> {
+            match self {
+                ShareRecipient::User(user) => Some(user.id),
+                ShareRecipient::Group(_) => None,
+            }
+        }
+
+        fn group_id(&self) -> Option<Uuid> {
+            match self {
+                ShareRecipient::User(_) => None,
+                ShareRecipient::Group(group) => Some(group.id),
+            }
+        }
+    }
+
+    // Explain why a new share would only duplicate access the recipient already has
+    fn already_has_access(recipient: &ShareRecipient, item: &str, existing: sharing::ExistingAccess) -> HttpResponse {
+        let message = match existing {
+            sharing::ExistingAccess::Direct => format!("{} already shared with this {}", item, recipient.kind()),
+            sharing::ExistingAccess::Folder => format!(
+                "This {} already has access to the {} through a shared folder",
+                recipient.kind(),
+                item.to_lowercase(),
+            ),
+        };
+        HttpResponse::BadRequest().json(ApiResponse::<()>::error(message))
+    }
+
+    // Resolve the recipient of a share request, returning an error response if it is invalid
+    fn resolve_share_recipient(
+        conn: &mut PgConnection,
+        current_user_id: Uuid,
+        share_data: &ShareRequest,
+    ) -> Result<ShareRecipient, HttpResponse> {
+        use crate::schema::{groups, users};
+
+        match (&share_data.recipient_username, share_data.group_id) {
+            (Some(recipient_username), None) => {
+                // Find the recipient user by username
+                let recipient_user = users::table
+                    .filter(users::username.eq(recipient_username))
+                    .first::<User>(conn)
+                    .optional()
+                    .map_err(|e| {
+                        log::error!("Database error finding recipient user: {}", e);
+                        HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error".to_string()))
+                    })?;
+
+                let recipient_user = recipient_user.ok_or_else(|| {
+                    HttpResponse::BadRequest().json(ApiResponse::<()>::error("Recipient user not found".to_string()))
+                })?;
+
+                // Prevent sharing with yourself
+                if recipient_user.id == current_user_id {
+                    return Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Cannot share with yourself".to_string())));
+                }
+
+                Ok(ShareRecipient::User(Box::new(recipient_user)))
+            }
+            (None, Some(group_id)) => {
+                // Only the group owner or its members may share with a group
+                let member_group_ids = sharing::user_group_ids(conn, current_user_id).map_err(|e| {
+                    log::error!("Database error loading group memberships: {}", e);
+                    HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error".to_string()))
+                })?;
+
+                let group = groups::table
+                    .filter(groups::id.eq(group_id))
+                    .filter(groups::owner_id.eq(current_user_id).or(groups::id.eq_any(&member_group_ids)))
+                    .first::<Group>(conn)
+                    .optional()
+                    .map_err(|e| {
+                        log::error!("Database error finding recipient group: {}", e);
+                        HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error".to_string()))
+                    })?;
+
+                group.map(ShareRecipient::Group).ok_or_else(|| {
+                    HttpResponse::BadRequest().json(ApiResponse::<()>::error("Recipient group not found".to_string()))
+                })
+            }
+            _ => Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Specify either recipient_username or group_id".to_string()))),
+        }
+    }
+
+    // Email a user recipient about a new share unless they opted out; never fails the share
+    fn notify_share_recipient(conn: &mut PgConnection, sharer_id: Uuid, recipient: &ShareRecipient, item_kind: &'static str) {
+        use crate::schema::users;
+
+        // Group shares reach many people at once, so only direct shares send an email
+        let ShareRecipient::User(recipient_user) = recipient else {
+            return;
+        };
+        if !recipient_user.share_notifications {
+            return;
+        }
+
+        let sharer_username = match users::table.find(sharer_id).select(users::username).first::<String>(conn) {
+            Ok(username) => username,
+            Err(e) => {
+                log::error!("Failed to load sharer {} for share notification: {}", sharer_id, e);
+                return;
+            }
+        };
+        let to_email = recipient_user.email.clone();
+        let username = recipient_user.username.clone();
+
+        actix_web::rt::spawn(async move {
+            let result = match crate::email::EmailService::new() {
+                Ok(email_service) => email_service.send_share_notification(&to_email, &username, &sharer_username, item_kind).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                log::error!("Failed to send share notification to {}: {}", to_email, e);
+            }
+        });
+    }
+
+    // Share password handler - temporarily disabled
+    pub async fn share_password(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        share_data: web::Json<ShareRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{passwords, shares};
+        use crate::models::NewShare;
+        use diesel::prelude::*;
+        use chrono::{Utc, Duration};
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let password_id = path.into_inner();
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Verify the password exists and belongs to the current user
+        let password_exists = passwords::table
+            .filter(passwords::id.eq(password_id))
+            .filter(passwords::user_id.eq(current_user_id))
+            .filter(passwords::deleted_at.is_null())
+            .first::<Password>(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error checking password ownership: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if password_exists.is_none() {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Password not found or access denied".to_string())));
+        }
+        
+        // Resolve the recipient (a single user or a group)
+        let recipient = match resolve_share_recipient(&mut conn, current_user_id, &share_data) {
+            Ok(recipient) => recipient,
+            Err(response) => return Ok(response),
+        };
+        
+        // Check whether the recipient can already reach it, directly or through a shared folder
+        let folder_id = password_exists.and_then(|password| password.folder_id);
+        let existing = sharing::find_existing_access(
+            &mut conn,
+            current_user_id,
+            recipient.user_id(),
+            recipient.group_id(),
+            Some(password_id),
+            folder_id,
+            share_data.permission_level,
+        ).map_err(|e| {
+            log::error!("Database error checking existing share: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        if let Some(existing) = existing {
+            return Ok(already_has_access(&recipient, "Password", existing));
+        }
+        
+        // Calculate expiration date
+        let expires_at = share_data.expiration_days.map(|days| {
+            (Utc::now() + Duration::days(days as i64)).naive_utc()
+        });
+        
+        // Create new share
+        let new_share = NewShare {
+            id: Uuid::new_v4(),
+            password_id: Some(password_id),
+            folder_id: None,
+            user_id: current_user_id,
+            shared_with_user_id: recipient.user_id(),
+            permission_level: share_data.permission_level.as_str().to_string(),
+            expires_at,
+            created_at: Utc::now().naive_utc(),
+            group_id: recipient.group_id(),
+        };
+        
+        // Insert share into database
+        diesel::insert_into(shares::table)
+            .values(&new_share)
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to create password share: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        log::info!("Password {} shared successfully with {} {} by user {}", password_id, recipient.kind(), recipient.name(), current_user_id);
+        notify_share_recipient(&mut conn, current_user_id, &recipient, "password");
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("Password shared successfully".to_string(), None)))
+    }
+
+    // Share folder handler
+    pub async fn share_folder(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        share_data: web::Json<ShareRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{folders, shares};
+        use crate::models::NewShare;
+        use diesel::prelude::*;
+        use chrono::{Utc, Duration};
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let folder_id = path.into_inner();
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Verify the folder exists and belongs to the current user
+        let folder_exists = folders::table
+            .filter(folders::id.eq(folder_id))
+            .filter(folders::user_id.eq(current_user_id))
+            .first::<Folder>(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error checking folder ownership: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if folder_exists.is_none() {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Folder not found or access denied".to_string())));
+        }
+        
+        // Resolve the recipient (a single user or a group)
+        let recipient = match resolve_share_recipient(&mut conn, current_user_id, &share_data) {
+            Ok(recipient) => recipient,
+            Err(response) => return Ok(response),
+        };
+        
+        // Check whether the recipient can already reach it, directly or through a parent folder
+        let existing = sharing::find_existing_access(
+            &mut conn,
+            current_user_id,
+            recipient.user_id(),
+            recipient.group_id(),
+            None,
+            Some(folder_id),
+            share_data.permission_level,
+        ).map_err(|e| {
+            log::error!("Database error checking existing share: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        if let Some(existing) = existing {
+            return Ok(already_has_access(&recipient, "Folder", existing));
+        }
+        
+        // Calculate expiration date
+        let expires_at = share_data.expiration_days.map(|days| {
+            (Utc::now() + Duration::days(days as i64)).naive_utc()
+        });
+        
+        // Create new share
+        let new_share = NewShare {
+            id: Uuid::new_v4(),
+            password_id: None,
+            folder_id: Some(folder_id),
+            user_id: current_user_id,
+            shared_with_user_id: recipient.user_id(),
+            permission_level: share_data.permission_level.as_str().to_string(),
+            expires_at,
+            created_at: Utc::now().naive_utc(),
+            group_id: recipient.group_id(),
+        };
+        
+        // Insert share into database
+        diesel::insert_into(shares::table)
+            .values(&new_share)
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to create folder share: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        log::info!("Folder {} shared successfully with {} {} by user {}", folder_id, recipient.kind(), recipient.name(), current_user_id);
+        notify_share_recipient(&mut conn, current_user_id, &recipient, "folder");
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("Folder shared successfully".to_string(), None)))
+    }
+
+    // List who the current user's password is shared with
+    pub async fn get_password_shares(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{groups, passwords, shares, users};
+        
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let password_id = path.into_inner();
+        let mut conn = read_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Only the owner may see who else has access
+        let owned = diesel::select(diesel::dsl::exists(
+            passwords::table
+                .filter(passwords::id.eq(password_id))
+                .filter(passwords::user_id.eq(current_user_id))
+        ))
+            .get_result::<bool>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        if !owned {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Password not found".to_string())));
+        }
+        
+        let now = chrono::Utc::now().naive_utc();
+        let entries: Vec<PasswordShareEntry> = shares::table
+            .left_join(users::table.on(shares::shared_with_user_id.eq(users::id.nullable())))
+            .left_join(groups::table)
+            .filter(shares::password_id.eq(password_id))
+            .order(shares::created_at.asc())
+            .select((
+                shares::id,
+                users::username.nullable(),
+                shares::group_id,
+                groups::name.nullable(),
+                shares::permission_level,
+                shares::expires_at,
+                shares::created_at,
+            ))
+            .load::<PasswordShareEntry>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error retrieving shares: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?
+            .into_iter()
+            .filter(|entry| sharing::share_is_active(entry.expires_at, now))
+            .collect();
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            "Password shares retrieved successfully".to_string(),
+            Some(serde_json::json!({
+                "count": entries.len(),
+                "shares": entries,
+            }))
+        )))
+    }
+    
+    // Get shared items handler
+    pub async fn get_shared_items(
+        req: actix_web::HttpRequest,
+        db_pool: web::Data<db::DbPool>,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::shares;
+        use diesel::prelude::*;
+        use chrono::Utc;
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let mut conn = read_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Resolve the groups the user currently belongs to
+        let group_ids = sharing::user_group_ids(&mut conn, current_user_id).map_err(|e| {
+            log::error!("Database error loading group memberships: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        // Get all shares where the current user is the recipient, directly or through a group
+        let mut all_shares = shares::table
+            .filter(shares::shared_with_user_id.eq(current_user_id).or(shares::group_id.eq_any(&group_ids)))
+            .filter(shares::user_id.ne(current_user_id))
+            .load::<Share>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error retrieving shares: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        // Filter out expired shares
+        let now = Utc::now().naive_utc();
+        let share_count = all_shares.len();
+        all_shares.retain(|share| sharing::share_is_active(share.expires_at, now));
+        
+        // Expired shares showed up, so purge them (for everyone, not just this recipient)
+        if all_shares.len() < share_count {
+            // Cleanup is a write, so it goes to the primary rather than the read pool
+            let purged = db_pool.get()
+                .map_err(|e| e.to_string())
+                .and_then(|mut write_conn| sharing::purge_expired_shares(&mut write_conn).map_err(|e| e.to_string()));
+            match purged {
+                Ok(count) => log::info!("Cleaned up {} expired shares", count),
+                // Don't fail the request for cleanup errors
+                Err(e) => log::error!("Failed to cleanup expired shares: {}", e),
+            }
+        }
+        
+        log::info!("Retrieved {} shared items for user {}", all_shares.len(), current_user_id);
+        Ok(HttpResponse::Ok().json(ApiResponse::success("Shared items retrieved successfully".to_string(), Some(all_shares))))
+    }
+    
+    // Get shared passwords with decrypted content
+    pub async fn get_shared_passwords(
+        req: actix_web::HttpRequest,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::shares;
+        use diesel::prelude::*;
+        use chrono::Utc;
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let mut conn = read_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Get all password shares where the current user is the recipient (directly or via
+        // a group they currently belong to) and not expired
+        let now = Utc::now().naive_utc();
+        let group_ids = sharing::user_group_ids(&mut conn, current_user_id).map_err(|e| {
+            log::error!("Database error loading group memberships: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        let password_shares = shares::table
+            .inner_join(crate::schema::passwords::table)
+            .filter(shares::shared_with_user_id.eq(current_user_id).or(shares::group_id.eq_any(&group_ids)))
+            .filter(shares::user_id.ne(current_user_id))
+            .filter(shares::password_id.is_not_null())
+            // Same boundary as `sharing::share_is_active`: a share lapses at its expiry instant
+            .filter(shares::expires_at.is_null().or(shares::expires_at.gt(now)))
+            // Entries in the owner's trash stay hidden from recipients until restored
+            .filter(crate::schema::passwords::deleted_at.is_null())
+            .select((shares::all_columns, crate::schema::passwords::all_columns))
+            .load::<(Share, Password)>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error retrieving shared passwords: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        // Decrypt passwords and prepare response
+        let mut decrypted_passwords = Vec::new();
+        let mut decryption_errors = DecryptionErrors::default();
+        for (share, password) in password_shares {
+            match open_stored_password(&password) {
+                Ok((decrypted_password, client_encrypted)) => {
+                    let password_response = serde_json::json!({
+                        "id": password.id,
+                        "website": password.website,
+                        "username": password.username,
+                        "password": decrypted_password,
+                        "is_client_encrypted": password.is_client_encrypted,
+                        "client_encrypted": client_encrypted,
+                        "notes": password.notes,
+                        "otp_secret": password.otp_secret,
+                        "folder_id": password.folder_id,
+                        "shared_by": share.user_id,
+                        "group_id": share.group_id,
+                        "can_edit": sharing::allows_write(&share.permission_level),
+                        "permission_level": share.permission_level,
+                        "expires_at": share.expires_at
+                    });
+                    decrypted_passwords.push(password_response);
+                },
+                Err(e) => {
+                    log::error!("Failed to decrypt shared password {}: {}", password.id, e);
+                    // Skip this password but report it and continue with others
+                    decryption_errors.record(password.id);
+                }
+            }
+        }
+        
+        log::info!("Retrieved {} shared passwords for user {}", decrypted_passwords.len(), current_user_id);
+        Ok(HttpResponse::Ok().json(DecryptedListResponse {
+            response: ApiResponse::success("Shared passwords retrieved successfully".to_string(), Some(decrypted_passwords)),
+            decryption_errors,
+        }))
+    }
+    
+    // List the passwords in a folder shared with the current user, including its subfolders
+    pub async fn get_shared_folder_passwords(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::passwords;
+        
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let folder_id = path.into_inner();
+        let mut conn = read_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // The folder itself or any folder above it may carry the share
+        let folder = sharing::resolve_folder_access(&mut conn, current_user_id, folder_id).map_err(|e| {
+            log::error!("Database error resolving folder access: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        let folder = match folder {
+            Some(folder) if folder.access != PasswordAccess::None => folder,
+            _ => return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Shared folder not found or access denied".to_string()))),
+        };
+        let can_edit = folder.access >= PasswordAccess::Write;
+        let permission_level = if can_edit { PermissionLevel::Write } else { PermissionLevel::Read };
+        
+        let folder_ids = folder_tree::subtree_ids(folder_id, &folder.parents);
+        let folder_passwords = passwords::table
+            .filter(passwords::user_id.eq(folder.owner_id))
+            .filter(passwords::folder_id.eq_any(&folder_ids))
+            // Entries in the owner's trash stay hidden from recipients until restored
+            .filter(passwords::deleted_at.is_null())
+            .load::<Password>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error retrieving shared folder passwords: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        let mut decrypted_passwords = Vec::new();
+        let mut decryption_errors = DecryptionErrors::default();
+        for password in folder_passwords {
+            match open_stored_password(&password) {
+                Ok((decrypted_password, client_encrypted)) => {
+                    decrypted_passwords.push(serde_json::json!({
+                        "id": password.id,
+                        "website": password.website,
+                        "username": password.username,
+                        "password": decrypted_password,
+                        "is_client_encrypted": password.is_client_encrypted,
+                        "client_encrypted": client_encrypted,
+                        "notes": password.notes,
+                        "otp_secret": password.otp_secret,
+                        "folder_id": password.folder_id,
+                        "shared_by": folder.owner_id,
+                        "can_edit": can_edit,
+                        "permission_level": permission_level,
+                    }));
+                },
+                Err(e) => {
+                    log::error!("Failed to decrypt shared password {}: {}", password.id, e);
+                    decryption_errors.record(password.id);
+                }
+            }
+        }
+        
+        log::info!("Retrieved {} passwords in shared folder {} for user {}", decrypted_passwords.len(), folder_id, current_user_id);
+        Ok(HttpResponse::Ok().json(DecryptedListResponse {
+            response: ApiResponse::success("Shared folder passwords retrieved successfully".to_string(), Some(decrypted_passwords)),
+            decryption_errors,
+        }))
+    }
+    
+    // Update a password shared with the current user, if the share allows writes
+    pub async fn update_shared_password(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        password_data: web::Json<PasswordRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::passwords;
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let password_id = path.into_inner();
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let access = sharing::resolve_password_access(&mut conn, current_user_id, password_id).map_err(|e| {
+            log::error!("Database error resolving password access: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        match access {
+            PasswordAccess::None => {
+                return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Shared password not found or access denied".to_string())));
+            }
+            PasswordAccess::Read => {
+                log::warn!("User {} attempted to edit read-only shared password {}", current_user_id, password_id);
+                return Ok(HttpResponse::Forbidden().json(ApiResponse::<()>::error("This share does not allow editing".to_string())));
+            }
+            PasswordAccess::Write | PasswordAccess::Owner => {}
+        }
+        
+        let changes = match prepare_password_changes(&password_data) {
+            Ok(changes) => changes,
+            Err(response) => return Ok(response),
+        };
+        
+        // The folder belongs to the owner's vault, so recipients can't move the entry
+        let rows_affected = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let previous = passwords::table
+                .filter(passwords::id.eq(password_id))
+                .select((passwords::encrypted_password, passwords::is_client_encrypted))
+                .for_update()
+                .first::<(Vec<u8>, bool)>(conn)
+                .optional()?;
+            let Some((previous, previous_client_encrypted)) = previous else { return Ok(0) };
+            // History is decrypted and rekeyed by the server, so it only keeps server-encrypted values
+            if !previous_client_encrypted {
+                record_password_history(conn, password_id, previous, &password_data.password)?;
+            }
+            
+            diesel::update(passwords::table.filter(passwords::id.eq(password_id)))
+                .set(&changes)
+                .execute(conn)
+        })
+        .map_err(|e| {
+            log::error!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        if rows_affected == 0 {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Shared password not found or access denied".to_string())));
+        }
+        
+        log::info!("Shared password {} updated by user {}", password_id, current_user_id);
+        Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("Shared password updated successfully".to_string(), None)))
+    }
+    
+    // Change the permission or expiry of a share the current user created
+    pub async fn update_share(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        update_data: web::Json<ShareUpdateRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::shares;
+        
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let share_id = path.into_inner();
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let not_found = || HttpResponse::NotFound().json(ApiResponse::<()>::error("Share not found or access denied".to_string()));
+        
+        let share = match shares::table
+            .filter(shares::id.eq(share_id))
+            .first::<Share>(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error retrieving share: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })? {
+            Some(share) => share,
+            None => return Ok(not_found()),
+        };
+        
+        let changes = match sharing::plan_share_update(
+            &share,
+            current_user_id,
+            update_data.permission_level,
+            update_data.expiration_days,
+            chrono::Utc::now().naive_utc(),
+        ) {
+            Ok(changes) => changes,
+            Err(sharing::ShareUpdateError::NotFound) => {
+                if share.shared_with_user_id == Some(current_user_id) {
+                    log::warn!("User {} attempted to change share {} they received", current_user_id, share_id);
+                }
+                return Ok(not_found());
+            }
+            Err(sharing::ShareUpdateError::Invalid(message)) => {
+                return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(message)));
+            }
+        };
+        
+        // Guard on the sharer again so the update can't apply to a share that changed hands
+        let updated = diesel::update(
+            shares::table
+                .filter(shares::id.eq(share_id))
+                .filter(shares::user_id.eq(current_user_id))
+        )
+            .set(&changes)
+            .get_result::<Share>(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Failed to update share: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        match updated {
+            Some(share) => {
+                log::info!("Share {} updated by user {}", share_id, current_user_id);
+                Ok(HttpResponse::Ok().json(ApiResponse::success("Share updated successfully".to_string(), Some(share))))
+            }
+            None => Ok(not_found()),
+        }
+    }
+    
+    // Remove a share (unshare)
+    pub async fn remove_share(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::shares;
+        use diesel::prelude::*;
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let share_id = path.into_inner();
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Verify the share exists and the user has permission to remove it
+        // (either the owner who shared it or the recipient)
+        let share = shares::table
+            .filter(shares::id.eq(share_id))
+            .filter(shares::user_id.eq(current_user_id).or(shares::shared_with_user_id.eq(current_user_id)))
+            .first::<Share>(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error checking share ownership: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if share.is_none() {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Share not found or access denied".to_string())));
+        }
+        
+        // Delete the share
+        diesel::delete(shares::table.filter(shares::id.eq(share_id)))
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to remove share: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        log::info!("Share {} removed successfully by user {}", share_id, current_user_id);
+        Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("Share removed successfully".to_string(), None)))
+    }
+
+    // Create a group
+    pub async fn create_group(
+        req: actix_web::HttpRequest,
+        group_data: web::Json<GroupRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::groups;
+        use chrono::Utc;
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let name = group_data.name.trim();
+        if name.is_empty() || name.len() > 100 {
+            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Group name must be between 1 and 100 characters".to_string())));
+        }
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let new_group = NewGroup {
+            id: Uuid::new_v4(),
+            owner_id: current_user_id,
+            name: name.to_string(),
+            created_at: Utc::now().naive_utc(),
+        };
+        
+        let group = diesel::insert_into(groups::table)
+            .values(&new_group)
+            .get_result::<Group>(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to create group: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        log::info!("Group {} created by user {}", group.id, current_user_id);
+        Ok(HttpResponse::Ok().json(ApiResponse::success("Group created successfully".to_string(), Some(group))))
+    }
+    
+    // List groups the user owns or belongs to
+    pub async fn get_groups(
+        req: actix_web::HttpRequest,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::groups;
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let member_group_ids = sharing::user_group_ids(&mut conn, current_user_id).map_err(|e| {
+            log::error!("Database error loading group memberships: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        let user_groups = groups::table
+            .filter(groups::owner_id.eq(current_user_id).or(groups::id.eq_any(&member_group_ids)))
+            .order(groups::name.asc())
+            .load::<Group>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error retrieving groups: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success("Groups retrieved successfully".to_string(), Some(user_groups))))
+    }
+    
+    // Delete a group (owner only); group shares are removed by cascade
+    pub async fn delete_group(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::groups;
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let group_id = path.into_inner();
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let deleted_count = diesel::delete(
+            groups::table
+                .filter(groups::id.eq(group_id))
+                .filter(groups::owner_id.eq(current_user_id))
+        )
+        .execute(&mut conn)
+        .map_err(|e| {
+            log::error!("Failed to delete group: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        if deleted_count == 0 {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Group not found or access denied".to_string())));
+        }
+        
+        log::info!("Group {} deleted by user {}", group_id, current_user_id);
+        Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("Group deleted successfully".to_string(), None)))
+    }
+    
+    // List members of a group (visible to the owner and members)
+    pub async fn get_group_members(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{groups, group_members, users};
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let group_id = path.into_inner();
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let member_group_ids = sharing::user_group_ids(&mut conn, current_user_id).map_err(|e| {
+            log::error!("Database error loading group memberships: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        let group = groups::table
+            .filter(groups::id.eq(group_id))
+            .filter(groups::owner_id.eq(current_user_id).or(groups::id.eq_any(&member_group_ids)))
+            .first::<Group>(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error retrieving group: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if group.is_none() {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Group not found or access denied".to_string())));
+        }
+        
+        let members = group_members::table
+            .inner_join(users::table)
+            .filter(group_members::group_id.eq(group_id))
+            .select((group_members::user_id, users::username, group_members::added_at))
+            .order(users::username.asc())
+            .load::<(Uuid, String, chrono::NaiveDateTime)>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error retrieving group members: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?
+            .into_iter()
+            .map(|(user_id, username, added_at)| serde_json::json!({
+                "user_id": user_id,
+                "username": username,
+                "added_at": added_at
+            }))
+            .collect::<Vec<_>>();
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success("Group members retrieved successfully".to_string(), Some(members))))
+    }
+    
+    // Add a member to a group (owner only)
+    pub async fn add_group_member(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        member_data: web::Json<GroupMemberRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{groups, group_members, users};
+        use chrono::Utc;
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let group_id = path.into_inner();
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Verify the group exists and belongs to the current user
+        let group = groups::table
+            .filter(groups::id.eq(group_id))
+            .filter(groups::owner_id.eq(current_user_id))
+            .first::<Group>(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error checking group ownership: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if group.is_none() {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Group not found or access denied".to_string())));
+        }
+        
+        // Find the new member by username
+        let member_user = users::table
+            .filter(users::username.eq(&member_data.username))
+            .first::<User>(&mut conn)
+            .optional()
+            .map_err(|e| {
+                log::error!("Database error finding user: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        let member_user = match member_user {
+            Some(user) => user,
+            None => {
+                return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("User not found".to_string())));
+            }
+        };
+        
+        let new_member = GroupMember {
+            group_id,
+            user_id: member_user.id,
+            added_at: Utc::now().naive_utc(),
+        };
+        
+        let inserted = diesel::insert_into(group_members::table)
+            .values(&new_member)
+            .on_conflict_do_nothing()
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to add group member: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        if inserted == 0 {
+            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("User is already a member of this group".to_string())));
+        }
+        
+        log::info!("User {} added to group {} by user {}", member_user.id, group_id, current_user_id);
+        Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("Member added successfully".to_string(), None)))
+    }
+    
+    // Remove a member from a group (owner, or the member leaving the group)
+    pub async fn remove_group_member(
+        req: actix_web::HttpRequest,
+        path: web::Path<(Uuid, Uuid)>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{groups, group_members};
+        
+        // Extract user ID from JWT token
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let (group_id, member_id) = path.into_inner();
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        if member_id != current_user_id {
+            let is_owner = groups::table
+                .filter(groups::id.eq(group_id))
+                .filter(groups::owner_id.eq(current_user_id))
+                .first::<Group>(&mut conn)
+                .optional()
+                .map_err(|e| {
+                    log::error!("Database error checking group ownership: {}", e);
+                    actix_web::error::ErrorInternalServerError("Database error")
+                })?
+                .is_some();
+            
+            if !is_owner {
+                return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Group not found or access denied".to_string())));
+            }
+        }
+        
+        // Membership is resolved on every read, so removing it cuts access to group shares immediately
+        let deleted_count = diesel::delete(
+            group_members::table
+                .filter(group_members::group_id.eq(group_id))
+                .filter(group_members::user_id.eq(member_id))
+        )
+        .execute(&mut conn)
+        .map_err(|e| {
+            log::error!("Failed to remove group member: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+        
+        if deleted_count == 0 {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("Member not found".to_string())));
+        }
+        
+        log::info!("User {} removed from group {} by user {}", member_id, group_id, current_user_id);
+        Ok(HttpResponse::Ok().json(ApiResponse::<()>::success("Member removed successfully".to_string(), None)))
+    }
+
+    // List the authenticated user's own audit events
+    pub async fn get_audit_events(
+        req: actix_web::HttpRequest,
+        query: web::Query<crate::audit::AuditEventQuery>,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        let user_id = auth::authenticate(&req)?;
+        
+        if let Some(event_type) = &query.event_type {
+            if !crate::audit::is_known_event_type(event_type) {
+                return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(format!("Unknown event type: {}", event_type))));
+            }
+        }
+        if let (Some(from), Some(to)) = (query.from, query.to) {
+            if from > to {
+                return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("'from' must not be after 'to'".to_string())));
+            }
+        }
+        
+        let events = crate::audit::query_user_events(&read_pool, user_id, &query).await.map_err(|e| {
+            log::error!("Failed to query audit events: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to load audit events")
+        })?;
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success("Audit events retrieved".to_string(), Some(events))))
+    }
+
+    // Re-encrypt all stored secrets under a new data key, or verify them under the current one (admin only)
+    pub async fn rekey_data(
+        req: actix_web::HttpRequest,
+        rekey_data: web::Json<RekeyRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        let user_id = match auth::require_admin(&req) {
+            Ok(id) => id,
+            Err(response) => return Ok(response),
+        };
+        
+        let RekeyRequest { old_key_env, new_key_env, dry_run } = rekey_data.into_inner();
+        let old_key_env = old_key_env.unwrap_or_else(|| "ENCRYPTION_KEY".to_string());
+        let key_envs = [Some(&old_key_env), new_key_env.as_ref()];
+        // Only key variables may be named, so the endpoint can't be used to probe other secrets
+        if key_envs.iter().flatten().any(|name| !name.starts_with("ENCRYPTION_KEY")) {
+            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Key sources must be environment variables named ENCRYPTION_KEY*".to_string())));
+        }
+        
+        let old_key = match rekey::RekeyKey::from_env_var(&old_key_env) {
+            Ok(key) => key,
+            Err(e) => return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
+        };
+        let new_key = match (dry_run, new_key_env) {
+            (true, _) => None,
+            (false, None) => return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("new_key_env is required unless dry_run is set".to_string()))),
+            (false, Some(new_key_env)) => match rekey::RekeyKey::from_env_var(&new_key_env) {
+                Ok(key) if key.fingerprint() == old_key.fingerprint() => {
+                    return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("The new key is the same as the old key".to_string())));
+                }
+                Ok(key) => Some(key),
+                Err(e) => return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
+            },
+        };
+        
+        let pool = db_pool.get_ref().clone();
+        let outcome = actix_web::rt::task::spawn_blocking(move || -> Result<Option<rekey::RekeyReport>, String> {
+            let mut conn = pool.get().map_err(|e| format!("Failed to get database connection: {}", e))?;
+            if !db::try_lock_rekey(&mut conn).map_err(|e| format!("Failed to take rekey lock: {}", e))? {
+                return Ok(None);
+            }
+            let report = match &new_key {
+                Some(new_key) => rekey::rekey_all(&mut conn, &old_key, new_key),
+                None => rekey::verify_all(&mut conn, &old_key),
+            };
+            db::unlock_rekey(&mut conn).map_err(|e| format!("Failed to release rekey lock: {}", e))?;
+            report.map(Some).map_err(|e| format!("Database error: {}", e))
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|outcome| outcome)
+        .map_err(|e| {
+            log::error!("Rekey failed: {}", e);
+            actix_web::error::ErrorInternalServerError("Rekey failed")
+        })?;
+        
+        let Some(report) = outcome else {
+            return Ok(HttpResponse::Conflict().json(ApiResponse::<()>::error("A rekey is already running".to_string())));
+        };
+        
+        if report.dry_run {
+            let message = if report.completed {
+                "All encrypted values decrypt under the current key"
+            } else {
+                "Some encrypted values failed to decrypt under the current key"
+            };
+            return Ok(HttpResponse::Ok().json(ApiResponse::success(message.to_string(), Some(report))));
+        }
+        
+        audit_log!(&db_pool, crate::audit::AuditEventType::EncryptionKeyRotated, Some(user_id), &req);
+        if report.completed {
+            log::info!("Data encryption key rotated by user {}", user_id);
+            Ok(HttpResponse::Ok().json(ApiResponse::success(
+                "Rekey completed; switch ENCRYPTION_KEY to the new key and restart the server".to_string(),
+                Some(report),
+            )))
+        } else {
+            log::warn!("Rekey by user {} stopped at a value that decrypts under neither key", user_id);
+            Ok(HttpResponse::UnprocessableEntity().json(ApiResponse {
+                success: false,
+                message: "Rekey stopped at a value that decrypts under neither key; fix it and run again to resume".to_string(),
+                data: Some(report),
+            }))
+        }
+    }
+
+    // Terminate every session of another user, e.g. after their account was compromised (admin only)
+    pub async fn revoke_user_sessions(
+        req: actix_web::HttpRequest,
+        path: web::Path<Uuid>,
+        db_pool: web::Data<db::DbPool>,
+        token_manager: web::Data<std::sync::Arc<crate::token_management::TokenManager>>,
+        session_manager: web::Data<std::sync::Arc<crate::enterprise_session_manager::EnterpriseSessionManager>>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::users;
+        
+        let admin_id = match auth::require_admin(&req) {
+            Ok(id) => id,
+            Err(response) => return Ok(response),
+        };
+        
+        let target_user_id = path.into_inner();
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let exists = diesel::select(diesel::dsl::exists(users::table.filter(users::id.eq(target_user_id))))
+            .get_result::<bool>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        if !exists {
+            return Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found".to_string())));
+        }
+        
+        // Each revoked session records a security event resolved by the admin
+        let terminated = match session_manager.revoke_user_sessions(target_user_id, "admin_revoked", Some(admin_id)).await {
+            Ok(count) => count,
+            Err(e) => {
+                log::error!("Failed to revoke sessions of user {}: {}", target_user_id, e);
+                return Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke sessions".to_string())));
+            }
+        };
+        
+        // Sessions the token manager still caches
+        if let Err(e) = token_manager.revoke_all_user_tokens(target_user_id, "admin_revoked".to_string()) {
+            log::error!("Failed to revoke tokens of user {}: {}", target_user_id, e);
+            return Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke sessions".to_string())));
+        }
+        
+        audit_log!(&db_pool, crate::audit::AuditEventType::SessionsRevoked, Some(admin_id), &req, target_user_id, format!("Admin {} revoked {} sessions of user {}", admin_id, terminated, target_user_id));
+        log::warn!("Admin {} revoked {} sessions of user {}", admin_id, terminated, target_user_id);
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(
+            format!("{} sessions terminated", terminated),
+            Some(serde_json::json!({ "sessions_terminated": terminated }))
+        )))
+    }
+
+    // Verify the tamper-evident audit log chain (admin only)
+    pub async fn verify_audit_chain(
+        req: actix_web::HttpRequest,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        if let Err(response) = auth::require_admin(&req) {
+            return Ok(response);
+        }
+        
+        let verification = crate::audit::verify_audit_chain(&db_pool).await.map_err(|e| {
+            log::error!("Failed to verify audit chain: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to verify audit chain")
+        })?;
+        
+        let message = if verification.valid {
+            "Audit log chain verified"
+        } else {
+            "Audit log chain is broken"
+        };
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(message.to_string(), Some(verification))))
+    }
+
+    // CSV Export handler
+    pub async fn export_csv(
+        req: actix_web::HttpRequest,
+        export_data: web::Json<CsvExportRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::folders;
+        
+        // Authenticate user
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Verify user password before allowing export
+        if !verify_export_password(&mut conn, current_user_id, &export_data.password)? {
+            return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid password".to_string())));
+        }
+        
+        // Get all folders for the user to map folder names
+        let folder_names: HashMap<Uuid, String> = folders::table
+            .filter(folders::user_id.eq(current_user_id))
+            .select((folders::id, folders::name))
+            .load::<(Uuid, String)>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?
+            .into_iter()
+            .collect();
+        drop(conn);
+        
+        log::info!("CSV export started for user {}", current_user_id);
+        
+        Ok(HttpResponse::Ok()
+            .content_type("text/csv")
+            .append_header(("Content-Disposition", "attachment; filename=\"passq_export.csv\""))
+            .streaming(csv_export::password_csv_stream(db_pool.get_ref().clone(), current_user_id, folder_names)))
+    }
+    
+    // Export the authenticated user's audit events as CSV
+    pub async fn export_audit_events(
+        req: actix_web::HttpRequest,
+        range: web::Json<crate::audit::AuditExportRequest>,
+        read_pool: web::Data<db::ReadPool>,
+    ) -> Result<HttpResponse, Error> {
+        let user_id = auth::authenticate(&req)?;
+        
+        if let (Some(from), Some(to)) = (range.from, range.to) {
+            if from > to {
+                return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("'from' must not be after 'to'".to_string())));
+            }
+        }
+        
+        log::info!("Audit log export started for user {}", user_id);
+        
+        Ok(HttpResponse::Ok()
+            .content_type("text/csv")
+            .append_header(("Content-Disposition", "attachment; filename=\"passq_audit_log.csv\""))
+            .streaming(crate::audit::user_events_csv_stream(db::DbPool::clone(&read_pool), user_id, range.into_inner())))
+    }
+    
+    // Check the account password that gates every export
+    fn verify_export_password(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        password: &str,
+    ) -> Result<bool, Error> {
+        use crate::schema::users;
+        
+        let user = users::table
+            .filter(users::id.eq(user_id))
+            .first::<User>(conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        Ok(auth::verify_password(password, &user.password_hash))
+    }
+    
+    // JSON Export handler
+    pub async fn export_json(
+        req: actix_web::HttpRequest,
+        export_data: web::Json<CsvExportRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{passwords, folders};
+        
+        // Authenticate user
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Verify user password before allowing export
+        if !verify_export_password(&mut conn, current_user_id, &export_data.password)? {
+            return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid password".to_string())));
+        }
+        
+        let user_folders = folders::table
+            .filter(folders::user_id.eq(current_user_id))
+            .load::<Folder>(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        let user_passwords = passwords::table
+            .filter(passwords::user_id.eq(current_user_id))
+            .filter(passwords::deleted_at.is_null())
+            // Only the client holding the key can export client-encrypted entries
+            .filter(passwords::is_client_encrypted.eq(false))
+            .select(Password::as_select())
+            .load(&mut conn)
+            .map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+        
+        let mut entries = Vec::new();
+        let mut decryption_errors = DecryptionErrors::default();
+        for password in user_passwords {
+            let decrypted_password = match crypto::decrypt_password(&password.encrypted_password) {
+                Ok(pwd) => pwd,
+                Err(e) => {
+                    log::error!("Failed to decrypt password for ID {}: {}", password.id, e);
+                    decryption_errors.record(password.id);
+                    continue;
+                }
+            };
+            
+            // Decrypt metadata if available, otherwise use unencrypted fields
+            let website = password.encrypted_website.as_deref()
+                .and_then(|data| crypto::decrypt_metadata(data).ok())
+                .unwrap_or(password.website);
+            let username = password.encrypted_username.as_deref()
+                .and_then(|data| crypto::decrypt_metadata(data).ok())
+                .unwrap_or(password.username);
+            
+            entries.push(JsonExportEntry {
+                id: password.id,
+                folder_id: password.folder_id,
+                website,
+                username,
+                password: decrypted_password,
+                notes: password.notes,
+                otp_secret: password.otp_secret,
+                attachments: password.attachments,
+                created_at: Some(password.created_at),
+                updated_at: Some(password.updated_at),
+            });
+        }
+        
+        if decryption_errors.count > 0 {
+            log::warn!("JSON export for user {} skipped {} entries that failed to decrypt", current_user_id, decryption_errors.count);
+        }
+        
+        let document = JsonExportDocument {
+            version: 1,
+            exported_at: chrono::Utc::now().naive_utc(),
+            folders: user_folders
+                .into_iter()
+                .map(|folder| JsonExportFolder {
+                    id: folder.id,
+                    name: folder.name,
+                    parent_folder_id: folder.parent_folder_id,
+                })
+                .collect(),
+            passwords: entries,
+        };
+        
+        audit_log!(&db_pool, crate::audit::AuditEventType::DataExport, Some(current_user_id), &req, current_user_id, format!("JSON export of {} passwords", document.passwords.len()));
+        log::info!("JSON export completed for user {}", current_user_id);
+        
+        Ok(HttpResponse::Ok()
+            .append_header(("Content-Disposition", "attachment; filename=\"passq_export.json\""))
+            .json(document))
+    }
+    
+    // CSV Import handler
+    pub async fn import_csv(
+        req: actix_web::HttpRequest,
+        query: web::Query<CsvImportQuery>,
+        import_data: web::Json<CsvImportRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{passwords, folders};
+        
+        // Authenticate user
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        // Parse CSV data
+        let records = split_csv_records(&import_data.csv_data);
+        if records.is_empty() {
+            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Empty CSV data".to_string())));
+        }
+        
+        // Detect CSV format from header
+        let format = detect_csv_format(&records[0].1);
+        log::info!("Detected CSV format: {:?}", format);
+        
+        let atomic = query.atomic;
+        let mut summary = CsvImportSummary { atomic, ..Default::default() };
+        let mut errors = Vec::new();
+        
+        // Each row runs in its own savepoint inside one transaction, so a failed insert neither
+        // aborts the rest of a best-effort import nor survives a rolled back atomic one
+        let outcome = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            // Get existing folders for the user
+            let user_folders = folders::table
+                .filter(folders::user_id.eq(current_user_id))
+                .load::<Folder>(conn)?;
+        
+            let mut folder_map: HashMap<String, Uuid> = user_folders
+                .into_iter()
+                .map(|f| (f.name.clone(), f.id))
+                .collect();
+            // Folders created by this import and how many entries landed in each
+            let mut new_folders: HashMap<Uuid, usize> = HashMap::new();
+            let mut existing = if query.skip_existing { Some(existing_entry_keys(conn, current_user_id)?) } else { None };
+        
+            for (line_num, record) in records.iter().skip(1) {
+                let fields = parse_csv_line(record);
+            
+                // Extract data based on detected format
+                let (name, url, username, password, notes, folder_name) = match format {
+                    CsvFormat::Bitwarden => {
+                        // Bitwarden format: folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp
+                        if fields.len() < 10 {
+                            errors.push(format!("Line {}: Invalid Bitwarden format - expected at least 10 fields, got {}", line_num, fields.len()));
+                            continue;
+                        }
+                        let folder = fields.first().unwrap_or(&String::new()).clone();
+                        let name = fields.get(3).unwrap_or(&String::new()).clone();
+                        let notes = fields.get(4).unwrap_or(&String::new()).clone();
+                        let url = fields.get(7).unwrap_or(&String::new()).clone();
+                        let username = fields.get(8).unwrap_or(&String::new()).clone();
+                        let password = fields.get(9).unwrap_or(&String::new()).clone();
+                        (name, url, username, password, notes, if folder.is_empty() { "No Folder".to_string() } else { folder })
+                    },
+                    CsvFormat::LastPass => {
+                        if fields.len() < 7 {
+                            errors.push(format!("Line {}: Invalid LastPass format", line_num));
+                            continue;
+                        }
+                        let url = fields.first().unwrap_or(&String::new()).clone();
+                        let username = fields.get(1).unwrap_or(&String::new()).clone();
+                        let password = fields.get(2).unwrap_or(&String::new()).clone();
+                        let notes = fields.get(3).unwrap_or(&String::new()).clone();
+                        let name = fields.get(4).unwrap_or(&String::new()).clone();
+                        let folder = fields.get(5).unwrap_or(&String::new()).clone();
+                        (name, url, username, password, notes, if folder.is_empty() { "No Folder".to_string() } else { folder })
+                    },
+                    CsvFormat::OnePassword => {
+                        if fields.len() < 9 {
+                            errors.push(format!("Line {}: Invalid 1Password format", line_num));
+                            continue;
+                        }
+                        let name = fields.first().unwrap_or(&String::new()).clone();
+                        let url = fields.get(1).unwrap_or(&String::new()).clone();
+                        let username = fields.get(2).unwrap_or(&String::new()).clone();
+                        let password = fields.get(3).unwrap_or(&String::new()).clone();
+                        let notes = fields.get(8).unwrap_or(&String::new()).clone();
+                        let tags = fields.get(7).unwrap_or(&String::new()).clone();
+                        let folder = if tags.is_empty() { "No Folder".to_string() } else { tags.split(',').next().unwrap_or("No Folder").to_string() };
+                        (name, url, username, password, notes, folder)
+                    },
+                    CsvFormat::Chrome => {
+                        if fields.len() < 4 {
+                            errors.push(format!("Line {}: Invalid Chrome format", line_num));
+                            continue;
+                        }
+                        let name = fields.first().unwrap_or(&String::new()).clone();
+                        let url = fields.get(1).unwrap_or(&String::new()).clone();
+                        let username = fields.get(2).unwrap_or(&String::new()).clone();
+                        let password = fields.get(3).unwrap_or(&String::new()).clone();
+                        let notes = String::new();
+                        let folder = "No Folder".to_string();
+                        (name, url, username, password, notes, folder)
+                    },
+                    CsvFormat::ChromeWithNote => {
+                        if fields.len() < 4 {
+                            errors.push(format!("Line {}: Invalid Chrome format", line_num));
+                            continue;
+                        }
+                        let name = fields.first().unwrap_or(&String::new()).clone();
+                        let url = fields.get(1).unwrap_or(&String::new()).clone();
+                        let username = fields.get(2).unwrap_or(&String::new()).clone();
+                        let password = fields.get(3).unwrap_or(&String::new()).clone();
+                        let notes = fields.get(4).unwrap_or(&String::new()).clone();
+                        let folder = "No Folder".to_string();
+                        (name, url, username, password, notes, folder)
+                    },
+                    CsvFormat::Firefox => {
+                        if fields.len() < 3 {
+                            errors.push(format!("Line {}: Invalid Firefox format", line_num));
+                            continue;
+                        }
+                        let url = fields.first().unwrap_or(&String::new()).clone();
+                        let username = fields.get(1).unwrap_or(&String::new()).clone();
+                        let password = fields.get(2).unwrap_or(&String::new()).clone();
+                        let name = if url.is_empty() { "Firefox Entry".to_string() } else { url.clone() };
+                        let notes = String::new();
+                        let folder = "No Folder".to_string();
+                        (name, url, username, password, notes, folder)
+                    },
+                    CsvFormat::Dashlane => {
+                        if fields.len() < 4 {
+                            errors.push(format!("Line {}: Invalid Dashlane format", line_num));
+                            continue;
+                        }
+                        let username = fields.first().unwrap_or(&String::new()).clone();
+                        let password = fields.get(1).unwrap_or(&String::new()).clone();
+                        let url = fields.get(2).unwrap_or(&String::new()).clone();
+                        let name = fields.get(3).unwrap_or(&String::new()).clone();
+                        let notes = fields.get(4).unwrap_or(&String::new()).clone();
+                        let folder = "No Folder".to_string();
+                        (name, url, username, password, notes, folder)
+                    },
+                    CsvFormat::KeePass => {
+                        if fields.len() < 3 {
+                            errors.push(format!("Line {}: Invalid KeePass format", line_num));
+                            continue;
+                        }
+                        let name = fields.first().unwrap_or(&String::new()).clone();
+                        let username = fields.get(1).unwrap_or(&String::new()).clone();
+                        let password = fields.get(2).unwrap_or(&String::new()).clone();
+                        let url = fields.get(3).unwrap_or(&String::new()).clone();
+                        let notes = fields.get(4).unwrap_or(&String::new()).clone();
+                        let folder = "No Folder".to_string();
+                        (name, url, username, password, notes, folder)
+                    },
+                    CsvFormat::Kaspersky => {
+                        if fields.len() < 4 {
+                            errors.push(format!("Line {}: Invalid Kaspersky format", line_num));
+                            continue;
+                        }
+                        let name = fields.first().unwrap_or(&String::new()).clone();
+                        let url = fields.get(1).unwrap_or(&String::new()).clone();
+                        let username = fields.get(2).unwrap_or(&String::new()).clone();
+                        let password = fields.get(3).unwrap_or(&String::new()).clone();
+                        let notes = fields.get(4).unwrap_or(&String::new()).clone();
+                        let folder = "No Folder".to_string();
+                        (name, url, username, password, notes, folder)
+                    },
+                    CsvFormat::Enpass => {
+                        if fields.len() < 4 {
+                            errors.push(format!("Line {}: Invalid Enpass format", line_num));
+                            continue;
+                        }
+                        let name = fields.first().unwrap_or(&String::new()).clone();
+                        let username = fields.get(1).unwrap_or(&String::new()).clone();
+                        let password = fields.get(2).unwrap_or(&String::new()).clone();
+                        let url = fields.get(3).unwrap_or(&String::new()).clone();
+                        let notes = fields.get(4).unwrap_or(&String::new()).clone();
+                        let folder = "No Folder".to_string();
+                        (name, url, username, password, notes, folder)
+                    },
+                    CsvFormat::PassQ => {
+                        if fields.len() < 4 {
+                            errors.push(format!("Line {}: Invalid PassQ format (need at least name,url,username,password)", line_num));
+                            continue;
+                        }
+                        // Our own export guards formula-like cells with a leading `'`
+                        let field = |index: usize| fields.get(index).map(|value| strip_formula_guard(value).to_string());
+                        let name = field(0).unwrap_or_default();
+                        let url = field(1).unwrap_or_default();
+                        let username = field(2).unwrap_or_default();
+                        let password = field(3).unwrap_or_default();
+                        let notes = field(4).unwrap_or_default();
+                        let folder_name = field(5).unwrap_or_else(|| String::from("No Folder"));
+                        (name, url, username, password, notes, folder_name)
+                    }
+                };
+            
+                // Only some exporters carry a TOTP column; a seed we can't use is dropped, not the entry
+                let otp_column = match format {
+                    CsvFormat::Bitwarden => fields.get(10),
+                    CsvFormat::OnePassword => fields.get(4),
+                    _ => None,
+                };
+                let otp_secret = match otp_column.and_then(|value| importers::extract_totp_secret(value)).map(|secret| auth::sanitize_otp_secret(&secret)) {
+                    Some(Ok(secret)) if !secret.is_empty() => Some(secret),
+                    Some(Err(e)) => {
+                        log::warn!("Line {}: Ignoring unusable OTP secret: {}", line_num, e);
+                        None
+                    }
+                    _ => None,
+                };
+            
+                // Skip entries without essential data
+                if name.is_empty() && url.is_empty() {
+                    errors.push(format!("Line {}: Missing both name and URL", line_num));
+                    continue;
+                }
+            
+                if username.is_empty() && password.is_empty() {
+                    errors.push(format!("Line {}: Missing both username and password", line_num));
+                    continue;
+                }
+            
+                // Use name as website if URL is empty, or URL as name if name is empty
+                 let final_name = if name.is_empty() { url.clone() } else { name.clone() };
+                 let final_url = if url.is_empty() { final_name.clone() } else { url.clone() };
+            
+                if let Some(existing) = &existing {
+                    if existing.contains(&importers::entry_key(&final_url, &username)) {
+                        summary.skipped += 1;
+                        continue;
+                    }
+                }
+            
+                // Get or create folder
+                let folder_id = if folder_name == "No Folder" || folder_name.is_empty() {
+                    None
+                } else {
+                    match folder_map.get(&folder_name) {
+                        Some(id) => Some(*id),
+                        None => {
+                            // Create new folder
+                            let new_folder = NewFolder {
+                                id: Uuid::new_v4(),
+                                user_id: current_user_id,
+                                parent_folder_id: None,
+                                name: folder_name.clone(),
+                            };
+                        
+                            match conn.transaction(|conn| diesel::insert_into(folders::table).values(&new_folder).execute(conn)) {
+                                Ok(_) => {
+                                    folder_map.insert(folder_name, new_folder.id);
+                                    new_folders.insert(new_folder.id, 0);
+                                    Some(new_folder.id)
+                                }
+                                Err(e) => {
+                                    log::error!("Failed to create folder: {}", e);
+                                    errors.push(format!("Line {}: Failed to create folder", line_num));
+                                    continue;
+                                }
+                            }
+                        }
+                    }
+                };
+            
+                // Encrypt password
+                let encrypted_password = match crypto::encrypt_password(&password) {
+                    Ok(encrypted) => encrypted,
+                    Err(e) => {
+                        log::error!("Failed to encrypt password: {}", e);
+                        errors.push(format!("Line {}: Failed to encrypt password", line_num));
+                        continue;
+                    }
+                };
+            
+                // Encrypt metadata
+                let encrypted_website = match crypto::encrypt_metadata(&final_url) {
+                    Ok(encrypted) => encrypted,
+                    Err(e) => {
+                        log::error!("Failed to encrypt website: {}", e);
+                        errors.push(format!("Line {}: Failed to encrypt website", line_num));
+                        continue;
+                    }
+                };
+            
+                let encrypted_username = match crypto::encrypt_metadata(&username) {
+                    Ok(encrypted) => encrypted,
+                    Err(e) => {
+                        log::error!("Failed to encrypt username: {}", e);
+                        errors.push(format!("Line {}: Failed to encrypt username", line_num));
+                        continue;
+                    }
+                };
+            
+                // Create new password entry
+                let new_password = NewPassword {
+                    id: Uuid::new_v4(),
+                    user_id: current_user_id,
+                    folder_id,
+                    website: final_url,
+                    username,
+                    encrypted_password,
+                    notes: if notes.is_empty() { None } else { Some(notes) },
+                    otp_secret,
+                    attachments: None,
+                    encrypted_website: Some(encrypted_website),
+                    encrypted_username: Some(encrypted_username),
+                    is_client_encrypted: false,
+                };
+            
+                match conn.transaction(|conn| diesel::insert_into(passwords::table).values(&new_password).execute(conn)) {
+                    Ok(_) => {
+                        summary.imported += 1;
+                        if let Some(existing) = existing.as_mut() {
+                            existing.insert(importers::entry_key(&new_password.website, &new_password.username));
+                        }
+                        if let Some(count) = folder_id.and_then(|id| new_folders.get_mut(&id)) {
+                            *count += 1;
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("Failed to insert password: {}", e);
+                        errors.push(format!("Line {}: Failed to save password", line_num));
+                    }
+                }
+            }
+        
+            if atomic && !errors.is_empty() {
+                return Err(diesel::result::Error::RollbackTransaction);
+            }
+        
+            // Folders whose every entry failed would otherwise linger empty and pile up on retries
+            let empty_folders: Vec<Uuid> = new_folders.iter().filter(|(_, count)| **count == 0).map(|(id, _)| *id).collect();
+            if !empty_folders.is_empty() {
+                diesel::delete(folders::table.filter(folders::id.eq_any(&empty_folders))).execute(conn)?;
+            }
+            summary.folders_created = new_folders.len() - empty_folders.len();
+            Ok(())
+        });
+        
+        summary.failed = errors.len();
+        summary.errors = errors;
+        match outcome {
+            Ok(()) => {}
+            Err(diesel::result::Error::RollbackTransaction) => {
+                log::info!("Atomic CSV import for user {} rolled back: {} rows failed", current_user_id, summary.failed);
+                summary.imported = 0;
+                summary.skipped = 0;
+                summary.folders_created = 0;
+                return Ok(HttpResponse::UnprocessableEntity().json(ApiResponse {
+                    success: false,
+                    message: format!("Import rolled back: {} rows failed: {}", summary.failed, summary.errors.join("; ")),
+                    data: Some(summary),
+                }));
+            }
+            Err(e) => {
+                log::error!("CSV import failed for user {}: {}", current_user_id, e);
+                return Ok(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Import failed; nothing was imported".to_string())));
+            }
+        }
+        
+        log::info!("CSV import completed for user {}: {} imported, {} skipped, {} errors", current_user_id, summary.imported, summary.skipped, summary.failed);
+        
+        let skipped = if summary.skipped > 0 { format!(" ({} already existed)", summary.skipped) } else { String::new() };
+        let message = if summary.errors.is_empty() {
+            format!("Successfully imported {} passwords{}", summary.imported, skipped)
+        } else {
+            format!("Imported {} passwords{} with {} errors: {}", summary.imported, skipped, summary.failed, summary.errors.join("; "))
+        };
+        
+        Ok(HttpResponse::Ok().json(ApiResponse::success(message, Some(summary))))
+    }
+    
+    // 1Password .1pux import handler
+    pub async fn import_1password(
+        req: actix_web::HttpRequest,
+        query: web::Query<ImportQuery>,
+        import_data: web::Json<OnePuxImportRequest>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use base64::Engine;
+        
+        // Authenticate user
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let archive = match base64::engine::general_purpose::STANDARD.decode(import_data.file_data.trim()) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("file_data must be a base64-encoded .1pux file".to_string())));
+            }
+        };
+        
+        let items = match importers::parse_1pux(&archive) {
+            Ok(items) => items,
+            Err(e) => return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
+        };
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let summary = save_imported_items(&mut conn, current_user_id, items, query.skip_existing)?;
+        log::info!("1Password import completed for user {}: {} imported, {} skipped, {} failed", current_user_id, summary.imported, summary.skipped, summary.failed);
+        
+        let message = format!("Imported {} passwords ({} skipped, {} failed)", summary.imported, summary.skipped, summary.failed);
+        Ok(HttpResponse::Ok().json(ApiResponse::success(message, Some(summary))))
+    }
+    
+    // PassQ JSON import handler, restoring the folder hierarchy of a JSON export
+    pub async fn import_json(
+        req: actix_web::HttpRequest,
+        query: web::Query<ImportQuery>,
+        document: web::Json<JsonExportDocument>,
+        db_pool: web::Data<db::DbPool>,
+    ) -> Result<HttpResponse, Error> {
+        use crate::schema::{passwords, folders};
+        
+        // Authenticate user
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let mut conn = db_pool.get().map_err(|e| {
+            log::error!("Failed to get database connection: {}", e);
+            actix_web::error::ErrorInternalServerError("Database connection error")
+        })?;
+        
+        let document = document.into_inner();
+        let mut summary = importers::ImportSummary::default();
+        let mut existing = if query.skip_existing {
+            Some(existing_entry_keys(&mut conn, current_user_id).map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?)
+        } else {
+            None
+        };
+        
+        // First pass: create every folder at the top level, mapping exported ids to new ones
+        let mut folder_map: HashMap<Uuid, Uuid> = HashMap::new();
+        for folder in &document.folders {
+            let new_folder = NewFolder {
+                id: Uuid::new_v4(),
+                user_id: current_user_id,
+                parent_folder_id: None,
+                name: folder.name.clone(),
+            };
+            
+            match diesel::insert_into(folders::table).values(&new_folder).execute(&mut conn) {
+                Ok(_) => {
+                    folder_map.insert(folder.id, new_folder.id);
+                    summary.folders_created += 1;
+                }
+                Err(e) => {
+                    log::error!("Failed to create folder: {}", e);
+                    summary.folder_errors.push(format!("Folder '{}': Failed to create folder", folder.name));
+                }
+            }
+        }
+        
+        // Second pass: restore parent links between the newly created folders
+        let (links, link_errors) = importers::resolve_folder_parents(&document.folders);
+        summary.folder_errors.extend(link_errors);
+        for (folder_id, parent_id) in links {
+            let (Some(new_id), Some(new_parent_id)) = (folder_map.get(&folder_id), folder_map.get(&parent_id)) else {
+                continue;
+            };
+            
+            if let Err(e) = diesel::update(folders::table.filter(folders::id.eq(new_id)))
+                .set(folders::parent_folder_id.eq(new_parent_id))
+                .execute(&mut conn) {
+                log::error!("Failed to set folder parent: {}", e);
+                summary.folder_errors.push(format!("Folder {}: Failed to restore parent folder", folder_id));
+            }
+        }
+        
+        for (index, entry) in document.passwords.into_iter().enumerate() {
+            if entry.website.is_empty() {
+                summary.failed(index, entry.website, "Missing website".to_string());
+                continue;
+            }
+            if entry.username.is_empty() && entry.password.is_empty() {
+                summary.failed(index, entry.website, "Missing both username and password".to_string());
+                continue;
+            }
+            
+            if let Some(existing) = &existing {
+                if existing.contains(&importers::entry_key(&entry.website, &entry.username)) {
+                    summary.skipped(index, entry.website, "Already exists".to_string());
+                    continue;
+                }
+            }
+            
+            let notes = match entry.notes.as_deref().map(auth::sanitize_notes).transpose() {
+                Ok(notes) => notes.filter(|n| !n.is_empty()),
+                Err(e) => {
+                    summary.failed(index, entry.website, e);
+                    continue;
+                }
+            };
+            
+            let otp_secret = match entry.otp_secret.as_deref().map(auth::sanitize_otp_secret).transpose() {
+                Ok(secret) => secret.filter(|s| !s.is_empty()),
+                Err(e) => {
+                    summary.failed(index, entry.website, e);
+                    continue;
+                }
+            };
+            
+            // Entries whose folder wasn't exported (or failed to import) land at the top level
+            let folder_id = entry.folder_id.and_then(|id| folder_map.get(&id).copied());
+            
+            let encrypted = crypto::encrypt_password(&entry.password)
+                .and_then(|password| Ok((password, crypto::encrypt_metadata(&entry.website)?, crypto::encrypt_metadata(&entry.username)?)));
+            let (encrypted_password, encrypted_website, encrypted_username) = match encrypted {
+                Ok(encrypted) => encrypted,
+                Err(e) => {
+                    log::error!("Failed to encrypt imported entry: {}", e);
+                    summary.failed(index, entry.website, "Failed to encrypt entry".to_string());
+                    continue;
+                }
+            };
+            
+            let new_password = NewPassword {
+                id: Uuid::new_v4(),
+                user_id: current_user_id,
+                folder_id,
+                website: entry.website.clone(),
+                username: entry.username,
+                encrypted_password,
+                notes,
+                otp_secret,
+                attachments: entry.attachments,
+                encrypted_website: Some(encrypted_website),
+                encrypted_username: Some(encrypted_username),
+                is_client_encrypted: false,
+            };
+            
+            match diesel::insert_into(passwords::table).values(&new_password).execute(&mut conn) {
+                Ok(_) => {
+                    if let Some(existing) = existing.as_mut() {
+                        existing.insert(importers::entry_key(&new_password.website, &new_password.username));
+                    }
+                    summary.imported(index, entry.website, new_password.id)
+                }
+                Err(e) => {
+                    log::error!("Failed to insert password: {}", e);
+                    summary.failed(index, entry.website, "Failed to save password".to_string());
+                }
+            }
         }
         
-        log::info!("CSV export completed for user {}", current_user_id);
+        audit_log!(&db_pool, crate::audit::AuditEventType::DataImport, Some(current_user_id), &req, current_user_id, format!("JSON import of {} passwords", summary.imported));
+        log::info!("JSON import completed for user {}: {} folders, {} imported, {} skipped, {} failed", current_user_id, summary.folders_created, summary.imported, summary.skipped, summary.failed);
         
-        Ok(HttpResponse::Ok()
-            .content_type("text/csv")
-            .append_header(("Content-Disposition", "attachment; filename=\"passq_export.csv\""))
-            .body(csv_content))
+        let message = format!(
+            "Imported {} folders and {} passwords ({} skipped, {} failed, {} folder errors)",
+            summary.folders_created, summary.imported, summary.skipped, summary.failed, summary.folder_errors.len()
+        );
+        Ok(HttpResponse::Ok().json(ApiResponse::success(message, Some(summary))))
     }
     
-    // CSV Import handler
-    #[derive(Debug, Clone)]
-    enum CsvFormat {
-        PassQ,      // name,url,username,password,notes,folder
-        Bitwarden,  // folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp
-        LastPass,   // url,username,password,extra,name,grouping,fav (or with totp)
-        OnePassword, // Title,Website,Username,Password,One-time password,Favorite status,Archived status,Tags,Notes
-        Chrome,     // name,url,username,password
-        Firefox,    // url,username,password,httpRealm,formActionOrigin,guid,timeCreated,timeLastUsed,timePasswordChanged
-        Dashlane,   // Username,Password,Website,Title,Note
-        KeePass,    // Account,Login Name,Password,Web Site,Comments
-        Kaspersky,  // Name,Website,Login,Password,Comment
-    }
-
-    fn detect_csv_format(header_line: &str) -> CsvFormat {
-        let headers = parse_csv_line(header_line);
-        let headers_lower: Vec<String> = headers.iter().map(|h| h.to_lowercase()).collect();
-        
-        // Check for Bitwarden format
-        if headers_lower.contains(&"folder".to_string()) && 
-           headers_lower.contains(&"favorite".to_string()) && 
-           headers_lower.contains(&"login_uri".to_string()) && 
-           headers_lower.contains(&"login_username".to_string()) && 
-           headers_lower.contains(&"login_password".to_string()) {
-            return CsvFormat::Bitwarden;
-        }
-        
-        // Check for LastPass format
-        if headers_lower.contains(&"url".to_string()) && 
-           headers_lower.contains(&"username".to_string()) && 
-           headers_lower.contains(&"password".to_string()) && 
-           headers_lower.contains(&"grouping".to_string()) && 
-           headers_lower.contains(&"extra".to_string()) {
-            return CsvFormat::LastPass;
-        }
-        
-        // Check for 1Password format
-        if headers_lower.contains(&"title".to_string()) && 
-           headers_lower.contains(&"website".to_string()) && 
-           headers_lower.contains(&"username".to_string()) && 
-           headers_lower.contains(&"password".to_string()) && 
-           headers_lower.contains(&"one-time password".to_string()) {
-            return CsvFormat::OnePassword;
-        }
-        
-        // Check for Firefox format
-        if headers_lower.contains(&"url".to_string()) && 
-           headers_lower.contains(&"username".to_string()) && 
-           headers_lower.contains(&"password".to_string()) && 
-           headers_lower.contains(&"httprealm".to_string()) && 
-           headers_lower.contains(&"formactionorigin".to_string()) {
-            return CsvFormat::Firefox;
-        }
-        
-        // Check for KeePass format
-        if headers_lower.contains(&"account".to_string()) && 
-           headers_lower.contains(&"login name".to_string()) && 
-           headers_lower.contains(&"password".to_string()) && 
-           headers_lower.contains(&"web site".to_string()) {
-            return CsvFormat::KeePass;
-        }
-        
-        // Check for Dashlane format
-        if headers_lower.contains(&"username".to_string()) && 
-           headers_lower.contains(&"password".to_string()) && 
-           headers_lower.contains(&"website".to_string()) && 
-           headers_lower.contains(&"title".to_string()) && 
-           headers_lower.contains(&"note".to_string()) {
-            return CsvFormat::Dashlane;
-        }
-        
-        // Check for Kaspersky format
-        if headers_lower.contains(&"name".to_string()) && 
-           headers_lower.contains(&"website".to_string()) && 
-           headers_lower.contains(&"login".to_string()) && 
-           headers_lower.contains(&"password".to_string()) && 
-           headers_lower.contains(&"comment".to_string()) {
-            return CsvFormat::Kaspersky;
-        }
-        
-        // Check for Chrome format (simple: name,url,username,password)
-        if headers_lower.len() == 4 && 
-           headers_lower.contains(&"name".to_string()) && 
-           headers_lower.contains(&"url".to_string()) && 
-           headers_lower.contains(&"username".to_string()) && 
-           headers_lower.contains(&"password".to_string()) {
-            return CsvFormat::Chrome;
-        }
-        
-        // Default to PassQ format
-        CsvFormat::PassQ
-    }
-
-    pub async fn import_csv(
+    // Bitwarden JSON import handler
+    pub async fn import_bitwarden_json(
         req: actix_web::HttpRequest,
-        import_data: web::Json<CsvImportRequest>,
+        query: web::Query<ImportQuery>,
+        import_data: web::Json<BitwardenJsonImportRequest>,
         db_pool: web::Data<db::DbPool>,
     ) -> Result<HttpResponse, Error> {
-        use crate::schema::{passwords, folders};
-        
         // Authenticate user
-        let current_user_id = match auth::extract_user_id_from_request(&req) {
-            Ok(user_id) => user_id,
-            Err(_) => {
-                return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or missing token".to_string())));
-            }
+        let current_user_id = auth::authenticate(&req)?;
+        
+        let items = match importers::parse_bitwarden_json(&import_data.json_data, import_data.password.as_deref()) {
+            Ok(items) => items,
+            Err(e) => return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
         };
         
         let mut conn = db_pool.get().map_err(|e| {
@@ -2077,307 +5629,193 @@ mod handlers {
             actix_web::error::ErrorInternalServerError("Database connection error")
         })?;
         
-        // Parse CSV data
-        let lines: Vec<&str> = import_data.csv_data.lines().collect();
-        if lines.is_empty() {
-            return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Empty CSV data".to_string())));
-        }
+        let summary = save_imported_items(&mut conn, current_user_id, items, query.skip_existing)?;
+        log::info!("Bitwarden import completed for user {}: {} imported, {} skipped, {} failed", current_user_id, summary.imported, summary.skipped, summary.failed);
         
-        // Detect CSV format from header
-        let format = detect_csv_format(lines[0]);
-        log::info!("Detected CSV format: {:?}", format);
+        let message = format!("Imported {} passwords ({} skipped, {} failed)", summary.imported, summary.skipped, summary.failed);
+        Ok(HttpResponse::Ok().json(ApiResponse::success(message, Some(summary))))
+    }
+    
+    // Save parsed import items, creating nested folders on demand
+    fn save_imported_items(
+        conn: &mut PgConnection,
+        current_user_id: Uuid,
+        items: Vec<importers::ParsedItem>,
+        skip_existing: bool,
+    ) -> Result<importers::ImportSummary, Error> {
+        use crate::schema::{passwords, folders};
+        use importers::ParsedItem;
         
-        let mut imported_count = 0;
-        let mut errors = Vec::new();
+        let mut summary = importers::ImportSummary::default();
         
-        // Get existing folders for the user
-        let user_folders = folders::table
+        // Existing folders keyed by (parent, name) so nested paths reuse them
+        let mut folder_map: HashMap<(Option<Uuid>, String), Uuid> = folders::table
             .filter(folders::user_id.eq(current_user_id))
-            .load::<Folder>(&mut conn)
+            .load::<Folder>(conn)
             .map_err(|e| {
                 log::error!("Database error: {}", e);
                 actix_web::error::ErrorInternalServerError("Database error")
-            })?;
-        
-        let mut folder_map: HashMap<String, Uuid> = user_folders
+            })?
             .into_iter()
-            .map(|f| (f.name.clone(), f.id))
+            .map(|f| ((f.parent_folder_id, f.name), f.id))
             .collect();
         
-        for (line_num, line) in lines.iter().skip(1).enumerate() {
-            if line.trim().is_empty() {
-                continue;
-            }
-            
-            let fields = parse_csv_line(line);
-            
-            // Extract data based on detected format
-            let (name, url, username, password, notes, folder_name) = match format {
-                CsvFormat::Bitwarden => {
-                    // Bitwarden format: folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp
-                    if fields.len() < 10 {
-                        errors.push(format!("Line {}: Invalid Bitwarden format - expected at least 10 fields, got {}", line_num + 2, fields.len()));
-                        continue;
-                    }
-                    let folder = fields.get(0).unwrap_or(&String::new()).clone();
-                    let name = fields.get(3).unwrap_or(&String::new()).clone();
-                    let notes = fields.get(4).unwrap_or(&String::new()).clone();
-                    let url = fields.get(7).unwrap_or(&String::new()).clone();
-                    let username = fields.get(8).unwrap_or(&String::new()).clone();
-                    let password = fields.get(9).unwrap_or(&String::new()).clone();
-                    (name, url, username, password, notes, if folder.is_empty() { "No Folder".to_string() } else { folder })
-                },
-                CsvFormat::LastPass => {
-                    if fields.len() < 7 {
-                        errors.push(format!("Line {}: Invalid LastPass format", line_num + 2));
-                        continue;
-                    }
-                    let url = fields.get(0).unwrap_or(&String::new()).clone();
-                    let username = fields.get(1).unwrap_or(&String::new()).clone();
-                    let password = fields.get(2).unwrap_or(&String::new()).clone();
-                    let notes = fields.get(3).unwrap_or(&String::new()).clone();
-                    let name = fields.get(4).unwrap_or(&String::new()).clone();
-                    let folder = fields.get(5).unwrap_or(&String::new()).clone();
-                    (name, url, username, password, notes, if folder.is_empty() { "No Folder".to_string() } else { folder })
-                },
-                CsvFormat::OnePassword => {
-                    if fields.len() < 9 {
-                        errors.push(format!("Line {}: Invalid 1Password format", line_num + 2));
-                        continue;
-                    }
-                    let name = fields.get(0).unwrap_or(&String::new()).clone();
-                    let url = fields.get(1).unwrap_or(&String::new()).clone();
-                    let username = fields.get(2).unwrap_or(&String::new()).clone();
-                    let password = fields.get(3).unwrap_or(&String::new()).clone();
-                    let notes = fields.get(8).unwrap_or(&String::new()).clone();
-                    let tags = fields.get(7).unwrap_or(&String::new()).clone();
-                    let folder = if tags.is_empty() { "No Folder".to_string() } else { tags.split(',').next().unwrap_or("No Folder").to_string() };
-                    (name, url, username, password, notes, folder)
-                },
-                CsvFormat::Chrome => {
-                    if fields.len() < 4 {
-                        errors.push(format!("Line {}: Invalid Chrome format", line_num + 2));
-                        continue;
-                    }
-                    let name = fields.get(0).unwrap_or(&String::new()).clone();
-                    let url = fields.get(1).unwrap_or(&String::new()).clone();
-                    let username = fields.get(2).unwrap_or(&String::new()).clone();
-                    let password = fields.get(3).unwrap_or(&String::new()).clone();
-                    let notes = String::new();
-                    let folder = "No Folder".to_string();
-                    (name, url, username, password, notes, folder)
-                },
-                CsvFormat::Firefox => {
-                    if fields.len() < 3 {
-                        errors.push(format!("Line {}: Invalid Firefox format", line_num + 2));
-                        continue;
-                    }
-                    let url = fields.get(0).unwrap_or(&String::new()).clone();
-                    let username = fields.get(1).unwrap_or(&String::new()).clone();
-                    let password = fields.get(2).unwrap_or(&String::new()).clone();
-                    let name = if url.is_empty() { "Firefox Entry".to_string() } else { url.clone() };
-                    let notes = String::new();
-                    let folder = "No Folder".to_string();
-                    (name, url, username, password, notes, folder)
-                },
-                CsvFormat::Dashlane => {
-                    if fields.len() < 4 {
-                        errors.push(format!("Line {}: Invalid Dashlane format", line_num + 2));
-                        continue;
-                    }
-                    let username = fields.get(0).unwrap_or(&String::new()).clone();
-                    let password = fields.get(1).unwrap_or(&String::new()).clone();
-                    let url = fields.get(2).unwrap_or(&String::new()).clone();
-                    let name = fields.get(3).unwrap_or(&String::new()).clone();
-                    let notes = fields.get(4).unwrap_or(&String::new()).clone();
-                    let folder = "No Folder".to_string();
-                    (name, url, username, password, notes, folder)
-                },
-                CsvFormat::KeePass => {
-                    if fields.len() < 3 {
-                        errors.push(format!("Line {}: Invalid KeePass format", line_num + 2));
-                        continue;
-                    }
-                    let name = fields.get(0).unwrap_or(&String::new()).clone();
-                    let username = fields.get(1).unwrap_or(&String::new()).clone();
-                    let password = fields.get(2).unwrap_or(&String::new()).clone();
-                    let url = fields.get(3).unwrap_or(&String::new()).clone();
-                    let notes = fields.get(4).unwrap_or(&String::new()).clone();
-                    let folder = "No Folder".to_string();
-                    (name, url, username, password, notes, folder)
-                },
-                CsvFormat::Kaspersky => {
-                    if fields.len() < 4 {
-                        errors.push(format!("Line {}: Invalid Kaspersky format", line_num + 2));
-                        continue;
-                    }
-                    let name = fields.get(0).unwrap_or(&String::new()).clone();
-                    let url = fields.get(1).unwrap_or(&String::new()).clone();
-                    let username = fields.get(2).unwrap_or(&String::new()).clone();
-                    let password = fields.get(3).unwrap_or(&String::new()).clone();
-                    let notes = fields.get(4).unwrap_or(&String::new()).clone();
-                    let folder = "No Folder".to_string();
-                    (name, url, username, password, notes, folder)
-                },
-                CsvFormat::PassQ => {
-                    if fields.len() < 4 {
-                        errors.push(format!("Line {}: Invalid PassQ format (need at least name,url,username,password)", line_num + 2));
-                        continue;
-                    }
-                    let name = fields.get(0).unwrap_or(&String::new()).clone();
-                    let url = fields.get(1).unwrap_or(&String::new()).clone();
-                    let username = fields.get(2).unwrap_or(&String::new()).clone();
-                    let password = fields.get(3).unwrap_or(&String::new()).clone();
-                    let notes = fields.get(4).unwrap_or(&String::new()).clone();
-                    let folder_name = fields.get(5).unwrap_or(&String::from("No Folder")).clone();
-                    (name, url, username, password, notes, folder_name)
+        let mut existing = if skip_existing {
+            Some(existing_entry_keys(conn, current_user_id).map_err(|e| {
+                log::error!("Database error: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?)
+        } else {
+            None
+        };
+        
+        for (index, item) in items.into_iter().enumerate() {
+            let entry = match item {
+                ParsedItem::Entry(entry) => entry,
+                ParsedItem::Skipped { name, reason } => {
+                    summary.skipped(index, name, reason);
+                    continue;
                 }
             };
             
             // Skip entries without essential data
-            if name.is_empty() && url.is_empty() {
-                errors.push(format!("Line {}: Missing both name and URL", line_num + 2));
+            if entry.name.is_empty() && entry.url.is_empty() {
+                summary.failed(index, entry.name, "Missing both name and URL".to_string());
                 continue;
             }
-            
-            if username.is_empty() && password.is_empty() {
-                errors.push(format!("Line {}: Missing both username and password", line_num + 2));
+            if entry.username.is_empty() && entry.password.is_empty() {
+                summary.failed(index, entry.name, "Missing both username and password".to_string());
                 continue;
             }
             
-            // Use name as website if URL is empty, or URL as name if name is empty
-             let final_name = if name.is_empty() { url.clone() } else { name.clone() };
-             let final_url = if url.is_empty() { final_name.clone() } else { url.clone() };
+            let final_name = if entry.name.is_empty() { entry.url.clone() } else { entry.name.clone() };
+            let final_url = if entry.url.is_empty() { final_name.clone() } else { entry.url.clone() };
             
-            // Get or create folder
-            let folder_id = if folder_name == "No Folder" || folder_name.is_empty() {
-                None
-            } else {
-                match folder_map.get(&folder_name) {
-                    Some(id) => Some(*id),
-                    None => {
-                        // Create new folder
-                        let new_folder = NewFolder {
-                            id: Uuid::new_v4(),
-                            user_id: current_user_id,
-                            parent_folder_id: None,
-                            name: folder_name.clone(),
-                        };
-                        
-                        match diesel::insert_into(folders::table)
-                            .values(&new_folder)
-                            .execute(&mut conn) {
-                            Ok(_) => {
-                                folder_map.insert(folder_name, new_folder.id);
-                                Some(new_folder.id)
-                            }
-                            Err(e) => {
-                                log::error!("Failed to create folder: {}", e);
-                                errors.push(format!("Line {}: Failed to create folder", line_num + 2));
-                                continue;
-                            }
-                        }
-                    }
+            let notes = match auth::sanitize_notes(&entry.notes_with_custom_fields()) {
+                Ok(notes) => notes,
+                Err(e) => {
+                    summary.failed(index, final_name, e);
+                    continue;
                 }
             };
             
-            // Encrypt password
-            let encrypted_password = match crypto::encrypt_password(&password) {
-                Ok(encrypted) => encrypted,
+            let otp_secret = match entry.otp_secret.as_deref().map(auth::sanitize_otp_secret).transpose() {
+                Ok(secret) => secret.filter(|s| !s.is_empty()),
                 Err(e) => {
-                    log::error!("Failed to encrypt password: {}", e);
-                    errors.push(format!("Line {}: Failed to encrypt password", line_num + 2));
+                    summary.failed(index, final_name, e);
                     continue;
                 }
             };
             
-            // Encrypt metadata
-            let encrypted_website = match crypto::encrypt_metadata(&final_url) {
-                Ok(encrypted) => encrypted,
-                Err(e) => {
-                    log::error!("Failed to encrypt website: {}", e);
-                    errors.push(format!("Line {}: Failed to encrypt website", line_num + 2));
+            if let Some(existing) = &existing {
+                if existing.contains(&importers::entry_key(&final_url, &entry.username)) {
+                    summary.skipped(index, final_name, "Already exists".to_string());
                     continue;
                 }
-            };
+            }
+            
+            // Walk the folder path, creating any missing level
+            let mut folder_id = None;
+            let mut folder_error = None;
+            for folder_name in &entry.folder_path {
+                let key = (folder_id, folder_name.clone());
+                if let Some(id) = folder_map.get(&key) {
+                    folder_id = Some(*id);
+                    continue;
+                }
+                
+                let new_folder = NewFolder {
+                    id: Uuid::new_v4(),
+                    user_id: current_user_id,
+                    parent_folder_id: folder_id,
+                    name: folder_name.clone(),
+                };
+                
+                match diesel::insert_into(folders::table).values(&new_folder).execute(conn) {
+                    Ok(_) => {
+                        folder_map.insert(key, new_folder.id);
+                        summary.folders_created += 1;
+                        folder_id = Some(new_folder.id);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to create folder: {}", e);
+                        folder_error = Some("Failed to create folder".to_string());
+                        break;
+                    }
+                }
+            }
+            if let Some(e) = folder_error {
+                summary.failed(index, final_name, e);
+                continue;
+            }
             
-            let encrypted_username = match crypto::encrypt_metadata(&username) {
+            let encrypted = crypto::encrypt_password(&entry.password)
+                .and_then(|password| Ok((password, crypto::encrypt_metadata(&final_url)?, crypto::encrypt_metadata(&entry.username)?)));
+            let (encrypted_password, encrypted_website, encrypted_username) = match encrypted {
                 Ok(encrypted) => encrypted,
                 Err(e) => {
-                    log::error!("Failed to encrypt username: {}", e);
-                    errors.push(format!("Line {}: Failed to encrypt username", line_num + 2));
+                    log::error!("Failed to encrypt imported entry: {}", e);
+                    summary.failed(index, final_name, "Failed to encrypt entry".to_string());
                     continue;
                 }
             };
             
-            // Create new password entry
             let new_password = NewPassword {
                 id: Uuid::new_v4(),
                 user_id: current_user_id,
                 folder_id,
                 website: final_url,
-                username,
+                username: entry.username,
                 encrypted_password,
                 notes: if notes.is_empty() { None } else { Some(notes) },
-                otp_secret: None,
+                otp_secret,
                 attachments: None,
                 encrypted_website: Some(encrypted_website),
                 encrypted_username: Some(encrypted_username),
+                is_client_encrypted: false,
             };
             
-            match diesel::insert_into(passwords::table)
-                .values(&new_password)
-                .execute(&mut conn) {
-                Ok(_) => imported_count += 1,
+            match diesel::insert_into(passwords::table).values(&new_password).execute(conn) {
+                Ok(_) => {
+                    if let Some(existing) = existing.as_mut() {
+                        existing.insert(importers::entry_key(&new_password.website, &new_password.username));
+                    }
+                    summary.imported(index, final_name, new_password.id)
+                }
                 Err(e) => {
                     log::error!("Failed to insert password: {}", e);
-                    errors.push(format!("Line {}: Failed to save password", line_num + 2));
+                    summary.failed(index, final_name, "Failed to save password".to_string());
                 }
             }
         }
         
-        log::info!("CSV import completed for user {}: {} imported, {} errors", current_user_id, imported_count, errors.len());
-        
-        let message = if errors.is_empty() {
-            format!("Successfully imported {} passwords", imported_count)
-        } else {
-            format!("Imported {} passwords with {} errors: {}", imported_count, errors.len(), errors.join("; "))
-        };
-        
-        Ok(HttpResponse::Ok().json(ApiResponse::success(message, Some(imported_count))))
+        Ok(summary)
     }
     
-    // Helper function to parse CSV line with quoted fields
-    fn parse_csv_line(line: &str) -> Vec<String> {
-        let mut fields = Vec::new();
-        let mut current_field = String::new();
-        let mut in_quotes = false;
-        let mut chars = line.chars().peekable();
-        
-        while let Some(ch) = chars.next() {
-            match ch {
-                '"' => {
-                    if in_quotes && chars.peek() == Some(&'"') {
-                        // Escaped quote
-                        current_field.push('"');
-                        chars.next(); // Skip the second quote
-                    } else {
-                        in_quotes = !in_quotes;
-                    }
-                }
-                ',' if !in_quotes => {
-                    fields.push(current_field.trim().to_string());
-                    current_field.clear();
-                }
-                _ => {
-                    current_field.push(ch);
-                }
-            }
-        }
+    // Decrypted website and username keys of the user's live entries, for imports that skip existing ones
+    fn existing_entry_keys(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<HashSet<(String, String)>> {
+        use crate::schema::passwords;
+        
+        let rows = passwords::table
+            .filter(passwords::user_id.eq(user_id))
+            .filter(passwords::deleted_at.is_null())
+            .select((passwords::website, passwords::username, passwords::encrypted_website, passwords::encrypted_username))
+            .load::<(String, String, Option<Vec<u8>>, Option<Vec<u8>>)>(conn)?;
         
-        fields.push(current_field.trim().to_string());
-        fields
+        Ok(rows
+            .into_iter()
+            .map(|(website, username, encrypted_website, encrypted_username)| {
+                let website = encrypted_website.as_deref()
+                    .and_then(|data| crypto::decrypt_metadata(data).ok())
+                    .unwrap_or(website);
+                let username = encrypted_username.as_deref()
+                    .and_then(|data| crypto::decrypt_metadata(data).ok())
+                    .unwrap_or(username);
+                importers::entry_key(&website, &username)
+            })
+            .collect())
     }
+
 }
 
 #[actix_web::main]
@@ -2386,17 +5824,52 @@ async fn main() -> std::io::Result<()> {
     dotenv().ok();
     
     // Initialize logging
-    env_logger::init();
+    logging::init();
     log::info!("Starting Passq backend server");
     
+    // Refuse to start rather than sign tokens with a missing or guessable key
+    if let Err(e) = auth::check_jwt_secret(env::var("JWT_SECRET").ok().as_deref()) {
+        log::error!(
+            "Fatal: {}. Set JWT_SECRET to a random value of at least {} bytes, e.g. the output of `openssl rand -base64 48`.",
+            e,
+            auth::MIN_JWT_SECRET_BYTES
+        );
+        std::process::exit(1);
+    }
+    
+    // Fix the Argon2id cost for new password hashes (ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, ARGON2_PARALLELISM)
+    let argon2_params = auth::init_argon2_params().expect("Invalid Argon2 parameters");
+    log::info!(
+        "Password hashing uses Argon2id with {} KiB memory, {} iterations and parallelism {}",
+        argon2_params.m_cost(), argon2_params.t_cost(), argon2_params.p_cost()
+    );
+    
     // Initialize database connection pool
     let db_pool = db::establish_connection();
     log::info!("Database connection pool established");
     
+    // Initialize read pool (replica when DATABASE_READ_URL is set, otherwise the primary)
+    let read_pool = db::establish_read_connection(&db_pool);
+    
     // Initialize token manager
     let token_manager = std::sync::Arc::new(token_management::TokenManager::new(db_pool.clone()));
     log::info!("Token manager initialized");
     
+    // Initialize enterprise session manager
+    let session_manager = std::sync::Arc::new(
+        enterprise_session_manager::EnterpriseSessionManager::new(db_pool.clone())
+            .with_read_pool(&read_pool)
+    );
+    
+    // Periodic cleanup of expired tokens, session data and shares
+    cleanup::spawn(db_pool.clone(), token_manager.clone(), session_manager.clone());
+    
+    // Shared request quota counters (enforced when ENABLE_REQUEST_QUOTAS=true)
+    let quota_store = std::sync::Arc::new(quotas::QuotaStore::new());
+    
+    // Cached per-user password health reports, refreshed by background scans
+    let health_service = std::sync::Arc::new(password_health::PasswordHealthService::new());
+    
     // Get port from environment or default to 8080
     let port = env::var("PORT")
         .unwrap_or_else(|_| "8080".to_string())
@@ -2405,19 +5878,16 @@ async fn main() -> std::io::Result<()> {
 
     println!("Starting server on http://0.0.0.0:{}", port);
 
+    // How long in-flight requests get to finish after SIGTERM/SIGINT before workers are stopped
+    let shutdown_timeout = shutdown_timeout_secs(env::var("SHUTDOWN_TIMEOUT_SECS").ok().as_deref());
+    let shutdown_token_manager = token_manager.clone();
+
+    // Allowed origins are read once and shared by every worker
+    let cors_config = cors::CorsConfig::from_env();
+    let security_headers = security_headers::SecurityHeaders::from_env();
+
     HttpServer::new(move || {
-        let cors = Cors::default()
-            .allowed_origin("http://localhost:3000")  // React development server
-            .allowed_origin("http://127.0.0.1:3000")  // Alternative localhost
-            .allowed_origin("http://localhost:8080")  // Alternative frontend port
-            .allowed_origin("https://passq.app")      // Production domain (update as needed)
-            .allowed_origin_fn(|origin, _req_head| {
-                // Allow Chrome extension origins
-                origin.as_bytes().starts_with(b"chrome-extension://")
-            })
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
-            .allowed_headers(vec!["Content-Type", "Authorization", "Accept"])
-            .supports_credentials();
+        let cors = cors_config.build();
 
         // Rate limiting configuration
         let auth_governor_conf = GovernorConfigBuilder::default()
@@ -2431,14 +5901,25 @@ async fn main() -> std::io::Result<()> {
             .burst_size(50) // Allow burst of 50 requests
             .finish()
             .unwrap();
+        
+        // Structured exports of whole vaults can exceed the default 2MB JSON limit
+        let import_json = models::json_config().limit(20 * 1024 * 1024);
             
         App::new()
-            .wrap(CspMiddleware)
+            .wrap(quotas::QuotaMiddleware::new(quota_store.clone()))
+            .wrap(csrf::CsrfMiddleware)
+            .wrap(CspMiddleware::new(security_headers.clone()))
+            .wrap(request_id::RequestIdMiddleware)
             .wrap(cors)
-            .wrap(Logger::default())
+            // The default format plus the request id, to find a request's other log lines
+            .wrap(Logger::new(r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T request_id=%{X-Request-Id}o"#))
             .wrap(Governor::new(&general_governor_conf))
             .app_data(web::Data::new(db_pool.clone()))
+            .app_data(web::Data::new(read_pool.clone()))
             .app_data(web::Data::new(token_manager.clone()))
+            .app_data(web::Data::new(session_manager.clone()))
+            .app_data(web::Data::new(health_service.clone()))
+            .app_data(models::json_config())
             .service(
                 web::resource("/register")
                     .wrap(Governor::new(&auth_governor_conf))
@@ -2449,10 +5930,19 @@ async fn main() -> std::io::Result<()> {
                     .wrap(Governor::new(&auth_governor_conf))
                     .route(web::post().to(handlers::login))
             )
+            .service(
+                web::resource("/auth/mfa-challenge")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::post().to(handlers::complete_mfa_challenge))
+            )
             .service(
                 web::resource("/auth/verify")
                     .route(web::get().to(handlers::verify_auth))
             )
+            .service(
+                web::resource("/auth/me")
+                    .route(web::get().to(handlers::current_user_profile))
+            )
             .service(
                 web::resource("/auth/logout")
                     .route(web::post().to(handlers::logout))
@@ -2462,6 +5952,10 @@ async fn main() -> std::io::Result<()> {
                     .route(web::get().to(handlers::get_csrf_token))
             )
             // Password reset endpoints
+            .service(
+                web::resource("/auth/password-strength")
+                    .route(web::post().to(handlers::password_strength))
+            )
             .service(
                 web::resource("/auth/password-reset/request")
                     .wrap(Governor::new(&auth_governor_conf))
@@ -2484,6 +5978,75 @@ async fn main() -> std::io::Result<()> {
                     .wrap(Governor::new(&auth_governor_conf))
                     .route(web::post().to(handlers::change_password))
             )
+            .service(
+                web::resource("/auth/email")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::put().to(handlers::request_email_change))
+            )
+            .service(
+                web::resource("/auth/email/confirm")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::post().to(handlers::confirm_email_change))
+            )
+            // MFA enrollment endpoints
+            .service(
+                web::resource("/auth/mfa/enroll")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::post().to(handlers::enroll_mfa))
+            )
+            .service(
+                web::resource("/auth/mfa/confirm")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::post().to(handlers::confirm_mfa))
+            )
+            .service(
+                web::resource("/auth/mfa/disable")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::post().to(handlers::disable_mfa))
+            )
+            .service(
+                web::resource("/auth/account")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::delete().to(handlers::delete_account))
+            )
+            .service(
+                web::resource("/auth/notifications")
+                    .route(web::get().to(handlers::get_notification_preferences))
+                    .route(web::put().to(handlers::update_notification_preferences))
+            )
+            .service(
+                web::resource("/auth/mfa/recovery-codes/regenerate")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::post().to(handlers::regenerate_recovery_codes))
+            )
+            .service(
+                web::resource("/auth/webauthn/register/start")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::post().to(handlers::start_passkey_registration))
+            )
+            .service(
+                web::resource("/auth/webauthn/register/finish")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::post().to(handlers::finish_passkey_registration))
+            )
+            .service(
+                web::resource("/auth/webauthn/login/start")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::post().to(handlers::start_passkey_login))
+            )
+            .service(
+                web::resource("/auth/webauthn/login/finish")
+                    .wrap(Governor::new(&auth_governor_conf))
+                    .route(web::post().to(handlers::finish_passkey_login))
+            )
+            .service(
+                web::resource("/auth/webauthn/credentials")
+                    .route(web::get().to(handlers::list_passkeys))
+            )
+            .service(
+                web::resource("/auth/webauthn/credentials/{id}")
+                    .route(web::delete().to(handlers::delete_passkey))
+            )
             // Token management endpoints
             .service(
                 web::resource("/auth/token/refresh")
@@ -2499,6 +6062,31 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/auth/sessions")
                     .route(web::post().to(token_management::manage_sessions))
             )
+            .service(
+                web::resource("/auth/sessions/{id}/name")
+                    .route(web::put().to(enterprise_session_manager::rename_session))
+            )
+            .service(
+                web::resource("/auth/devices")
+                    .route(web::get().to(enterprise_session_manager::list_devices))
+            )
+            .service(
+                web::resource("/auth/devices/trust/confirm")
+                    .route(web::post().to(enterprise_session_manager::confirm_device_trust))
+            )
+            .service(
+                web::resource("/auth/devices/{fingerprint}/trust")
+                    .route(web::post().to(enterprise_session_manager::trust_device))
+            )
+            .service(
+                web::resource("/auth/devices/{fingerprint}")
+                    .route(web::delete().to(enterprise_session_manager::forget_device))
+            )
+            .service(
+                web::resource("/auth/session-limits")
+                    .route(web::get().to(enterprise_session_manager::get_session_limits))
+                    .route(web::put().to(enterprise_session_manager::update_session_limits))
+            )
             .service(
                 web::resource("/auth/analytics")
                     .route(web::get().to(token_management::get_token_analytics))
@@ -2510,6 +6098,7 @@ async fn main() -> std::io::Result<()> {
             // Enterprise session management endpoints
             .service(
                 web::resource("/auth/enterprise/sessions")
+                    .route(web::get().to(enterprise_session_manager::list_enterprise_sessions))
                     .route(web::post().to(enterprise_session_manager::create_enterprise_session))
             )
             .service(
@@ -2520,6 +6109,10 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/auth/enterprise/analytics")
                     .route(web::get().to(enterprise_session_manager::get_enterprise_analytics))
             )
+            .service(
+                web::resource("/auth/enterprise/session-limits/simulate")
+                    .route(web::post().to(enterprise_session_manager::simulate_session_limits))
+            )
             .service(
                 web::resource("/auth/enterprise/cleanup")
                     .route(web::post().to(enterprise_session_manager::cleanup_enterprise_data))
@@ -2576,6 +6169,39 @@ async fn main() -> std::io::Result<()> {
                     .route(web::get().to(handlers::get_passwords))
                     .route(web::post().to(handlers::create_password))
             )
+            .service(
+                web::resource("/passwords/generate")
+                    .route(web::post().to(handlers::generate_password))
+            )
+            .service(
+                web::resource("/passwords/health")
+                    .route(web::get().to(handlers::get_password_health))
+            )
+            .service(
+                web::resource("/passwords/health/rescan")
+                    .route(web::post().to(handlers::rescan_password_health))
+            )
+            .service(
+                web::resource("/passwords/analysis/duplicates")
+                    .route(web::get().to(handlers::get_duplicate_passwords))
+            )
+            .service(
+                web::resource("/passwords/analysis/weak")
+                    .route(web::get().to(handlers::get_weak_passwords))
+            )
+            .service(
+                web::resource("/passwords/bulk-delete")
+                    .route(web::post().to(handlers::bulk_delete_passwords))
+            )
+            .service(
+                web::resource("/passwords/bulk-move")
+                    .route(web::post().to(handlers::bulk_move_passwords))
+            )
+            .service(
+                web::resource("/passwords/trash")
+                    .route(web::get().to(handlers::get_trash))
+                    .route(web::delete().to(handlers::purge_trash))
+            )
             .service(
                 web::resource("/passwords/{id}")
                     .route(web::put().to(handlers::update_password))
@@ -2585,10 +6211,36 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/passwords/{id}/move")
                     .route(web::put().to(handlers::move_password))
             )
+            .service(
+                web::resource("/passwords/{id}/favorite")
+                    .route(web::put().to(handlers::set_password_favorite))
+            )
             .service(
                 web::resource("/passwords/{id}/otp")
                     .route(web::get().to(handlers::generate_otp))
             )
+            .service(
+                web::resource("/passwords/{id}/attachments")
+                    .route(web::get().to(handlers::list_attachments))
+                    .route(web::post().to(handlers::upload_attachment))
+            )
+            .service(
+                web::resource("/passwords/{id}/attachments/{attachment_id}")
+                    .route(web::get().to(handlers::download_attachment))
+                    .route(web::delete().to(handlers::delete_attachment))
+            )
+            .service(
+                web::resource("/passwords/{id}/history")
+                    .route(web::get().to(handlers::get_password_history))
+            )
+            .service(
+                web::resource("/passwords/{id}/shares")
+                    .route(web::get().to(handlers::get_password_shares))
+            )
+            .service(
+                web::resource("/passwords/{id}/restore")
+                    .route(web::post().to(handlers::restore_password))
+            )
             // Folder endpoints
             .service(
                 web::resource("/folders")
@@ -2617,21 +6269,115 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/shared/passwords")
                     .route(web::get().to(handlers::get_shared_passwords))
             )
+            .service(
+                web::resource("/shared/passwords/{id}")
+                    .route(web::put().to(handlers::update_shared_password))
+            )
+            .service(
+                web::resource("/shared/folders/{id}/passwords")
+                    .route(web::get().to(handlers::get_shared_folder_passwords))
+            )
             .service(
                 web::resource("/shares/{id}")
+                    .route(web::put().to(handlers::update_share))
                     .route(web::delete().to(handlers::remove_share))
             )
+            // Group endpoints
+            .service(
+                web::resource("/groups")
+                    .route(web::get().to(handlers::get_groups))
+                    .route(web::post().to(handlers::create_group))
+            )
+            .service(
+                web::resource("/groups/{id}")
+                    .route(web::delete().to(handlers::delete_group))
+            )
+            .service(
+                web::resource("/groups/{id}/members")
+                    .route(web::get().to(handlers::get_group_members))
+                    .route(web::post().to(handlers::add_group_member))
+            )
+            .service(
+                web::resource("/groups/{id}/members/{user_id}")
+                    .route(web::delete().to(handlers::remove_group_member))
+            )
+            .service(
+                web::resource("/admin/rekey")
+                    .route(web::post().to(handlers::rekey_data))
+            )
+            .service(
+                web::resource("/admin/users/{id}/revoke-sessions")
+                    .route(web::post().to(handlers::revoke_user_sessions))
+            )
+            // Audit endpoints
+            .service(
+                web::resource("/audit/events")
+                    .route(web::get().to(handlers::get_audit_events))
+            )
+            .service(
+                web::resource("/audit/export")
+                    .route(web::post().to(handlers::export_audit_events))
+            )
+            .service(
+                web::resource("/audit/verify")
+                    .route(web::get().to(handlers::verify_audit_chain))
+            )
             // CSV endpoints
             .service(
                 web::resource("/export/csv")
                     .route(web::post().to(handlers::export_csv))
             )
+            .service(
+                web::resource("/export/json")
+                    .route(web::post().to(handlers::export_json))
+            )
             .service(
                 web::resource("/import/csv")
                     .route(web::post().to(handlers::import_csv))
             )
+            .service(
+                web::resource("/import/1password")
+                    .app_data(import_json.clone())
+                    .route(web::post().to(handlers::import_1password))
+            )
+            .service(
+                web::resource("/import/json")
+                    .app_data(import_json.clone())
+                    .route(web::post().to(handlers::import_json))
+            )
+            .service(
+                web::resource("/import/bitwarden-json")
+                    .app_data(import_json.clone())
+                    .route(web::post().to(handlers::import_bitwarden_json))
+            )
     })
     .bind(("0.0.0.0", port))?
+    // Actix stops accepting connections on SIGTERM/SIGINT and drains in-flight requests
+    .shutdown_timeout(shutdown_timeout)
     .run()
-    .await
+    .await?;
+
+    log::info!("Server stopped, cleaning up expired tokens before exit");
+    match actix_web::rt::task::spawn_blocking(move || shutdown_token_manager.cleanup_expired_tokens()).await {
+        Ok(Ok(count)) => log::info!("Removed {} expired token revocations on shutdown", count),
+        Ok(Err(e)) => log::error!("Token cleanup on shutdown failed: {}", e),
+        Err(e) => log::error!("Token cleanup task on shutdown failed: {}", e),
+    }
+    log::info!("Passq backend server shut down");
+
+    Ok(())
+}
+
+/// Default grace period for in-flight requests on shutdown
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
+fn shutdown_timeout_secs(value: Option<&str>) -> u64 {
+    match value.map(|v| v.trim().parse::<u64>()) {
+        Some(Ok(secs)) => secs,
+        Some(Err(_)) => {
+            log::warn!("Invalid SHUTDOWN_TIMEOUT_SECS, using {} seconds", DEFAULT_SHUTDOWN_TIMEOUT_SECS);
+            DEFAULT_SHUTDOWN_TIMEOUT_SECS
+        }
+        None => DEFAULT_SHUTDOWN_TIMEOUT_SECS,
+    }
 }
@@ -1,44 +1,97 @@
 //! Multi-Factor Authentication module
 
+use diesel::prelude::*;
+use diesel::PgConnection;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::env;
+use std::sync::OnceLock;
 use totp_rs::{Algorithm, Secret, TOTP};
 use uuid::Uuid;
 use log;
+use crate::models::NewMfaRecoveryCode;
+
+/// Number of recovery codes issued at a time
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// 32 unambiguous characters, so each random byte maps to one without bias
+const RECOVERY_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+/// Codes carry 50 random bits, so a low bcrypt cost keeps login fast without weakening them
+const RECOVERY_CODE_BCRYPT_COST: u32 = 8;
 
 /// Generates a TOTP secret for a user
-#[allow(dead_code)]
 pub fn generate_totp_secret(_user_id: Uuid) -> String {
     let secret = Secret::generate_secret(); // Not a Result, no expect
     secret.to_string()
 }
 
+/// Returns the secret as authenticator apps expect it for manual entry
+///
+/// The stored secret string itself is the TOTP key, so apps need its base32 encoding.
+pub fn secret_for_manual_entry(secret: &str) -> String {
+    Secret::Raw(secret.as_bytes().to_vec()).to_encoded().to_string()
+}
+
 /// Generates a QR code provisioning URL for the authenticator app
-#[allow(dead_code)]
-pub fn generate_qr_code_url(user_id: Uuid, secret: &str) -> String {
+pub fn generate_qr_code_url(account_name: &str, secret: &str) -> Result<String, String> {
     let totp = TOTP::new(
         Algorithm::SHA1,
         6,                          // digits
         1,                          // skew
         30,                         // step
         secret.as_bytes().to_vec(),
-        Some("MyApp".to_string()),  // issuer
-        format!("user_{}", user_id) // account name
-    ).expect("Failed to create TOTP instance");
+        Some("PassQ".to_string()),  // issuer
+        account_name.replace(':', "_") // account name (':' separates issuer and account)
+    ).map_err(|e| {
+        log::error!("Failed to create TOTP instance: {}", e);
+        "Failed to create TOTP instance".to_string()
+    })?;
+
+    Ok(totp.get_url())
+}
+
+/// Most digits and longest step accepted from an `otpauth://` URI
+const MAX_TOTP_DIGITS: usize = 8;
+const MAX_TOTP_PERIOD: u64 = 300;
 
-    totp.get_url()
+/// A generated TOTP code, the seconds left before its step ends and the code that follows it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TotpCode {
+    pub code: String,
+    pub expires_in: u64,
+    pub next_code: String,
 }
 
-/// Generates a TOTP code from a secret
-pub fn generate_totp_code(secret: &str) -> Result<String, String> {
+/// Builds the TOTP generator for a password entry's stored OTP secret
+///
+/// A bare secret is used as-is with SHA1, 6 digits and a 30-second step. An `otpauth://totp/`
+/// URI carries a base32 secret and may set `digits`, `period` and `algorithm` (SHA1, SHA256, SHA512).
+pub fn totp_for_secret(secret: &str) -> Result<TOTP, String> {
     if secret.is_empty() {
         return Err("Empty TOTP secret provided".to_string());
     }
 
+    if secret.starts_with("otpauth://") {
+        let totp = TOTP::from_url_unchecked(secret).map_err(|e| {
+            log::warn!("Invalid otpauth URI: {}", e);
+            "Invalid otpauth URI".to_string()
+        })?;
+        if !(6..=MAX_TOTP_DIGITS).contains(&totp.digits) {
+            return Err(format!("TOTP digits must be between 6 and {}", MAX_TOTP_DIGITS));
+        }
+        if !(1..=MAX_TOTP_PERIOD).contains(&totp.step) {
+            return Err(format!("TOTP period must be between 1 and {} seconds", MAX_TOTP_PERIOD));
+        }
+        return Ok(totp);
+    }
+
     // Validate secret format - check if it's a valid base32 string
     if secret.len() < 16 || secret.chars().any(|c| !c.is_ascii_alphanumeric()) {
         return Err("Invalid TOTP secret format".to_string());
     }
 
-    match TOTP::new(
+    TOTP::new(
         Algorithm::SHA1,
         6,
         1,
@@ -46,60 +99,326 @@ pub fn generate_totp_code(secret: &str) -> Result<String, String> {
         secret.as_bytes().to_vec(),
         Some("MyApp".to_string()),
         "account".to_string(),
-    ) {
-        Ok(totp) => {
-            match totp.generate_current() {
-                Ok(code) => Ok(code),
-                Err(e) => {
-                    log::error!("Failed to generate TOTP code: {}", e);
-                    Err("Failed to generate TOTP code".to_string())
-                }
-            }
-        }
-        Err(e) => {
-            log::error!("Failed to create TOTP instance: {}", e);
-            Err("Failed to create TOTP instance".to_string())
+    ).map_err(|e| {
+        log::error!("Failed to create TOTP instance: {}", e);
+        "Failed to create TOTP instance".to_string()
+    })
+}
+
+/// Generates the TOTP code for a stored secret at the given Unix time
+pub fn generate_totp_code_at(secret: &str, time: u64) -> Result<TotpCode, String> {
+    let totp = totp_for_secret(secret)?;
+    let next_step = totp.next_step(time);
+    Ok(TotpCode {
+        code: totp.generate(time),
+        expires_in: next_step - time,
+        next_code: totp.generate(next_step),
+    })
+}
+
+/// Generates the current TOTP code for a stored secret
+pub fn generate_totp_code(secret: &str) -> Result<TotpCode, String> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|_| "System clock is before the Unix epoch".to_string())?;
+    generate_totp_code_at(secret, now.as_secs())
+}
+
+/// Steps on either side of the current one whose codes are accepted when MFA_TOTP_SKEW_STEPS isn't set
+pub const DEFAULT_TOTP_SKEW_STEPS: u64 = 1;
+
+/// Widest window MFA_TOTP_SKEW_STEPS may set; each extra step gives guessers another valid code
+const MAX_TOTP_SKEW_STEPS: u64 = 10;
+
+/// Seconds per step of account TOTP secrets
+const ACCOUNT_TOTP_STEP: u64 = 30;
+
+/// How many steps before and after the current one a login code may come from
+pub fn totp_skew_steps() -> u64 {
+    static SKEW: OnceLock<u64> = OnceLock::new();
+    *SKEW.get_or_init(|| parse_skew_steps(env::var("MFA_TOTP_SKEW_STEPS").ok().as_deref()))
+}
+
+fn parse_skew_steps(value: Option<&str>) -> u64 {
+    match value.map(|v| v.trim().parse::<u64>()) {
+        Some(Ok(steps)) if steps <= MAX_TOTP_SKEW_STEPS => steps,
+        Some(_) => {
+            log::warn!("Invalid MFA_TOTP_SKEW_STEPS, using {}", DEFAULT_TOTP_SKEW_STEPS);
+            DEFAULT_TOTP_SKEW_STEPS
         }
+        None => DEFAULT_TOTP_SKEW_STEPS,
     }
 }
 
-/// Verifies a TOTP code
-pub fn verify_totp_code(secret: &str, code: &str) -> bool {
+/// Finds the time step a code for an account secret belongs to, looking `skew` steps either side of `time`
+pub fn totp_step_at(secret: &str, code: &str, time: u64, skew: u64) -> Option<u64> {
     if secret.is_empty() || code.is_empty() {
         log::warn!("Empty TOTP secret or code provided");
-        return false;
+        return None;
     }
 
     // Validate secret format - check if it's a valid base32 string
     if secret.len() < 16 || secret.chars().any(|c| !c.is_ascii_alphanumeric()) {
         log::warn!("Invalid TOTP secret format");
-        return false;
+        return None;
     }
 
-    match TOTP::new(
+    // Each step is checked on its own so the caller learns which one matched
+    let totp = match TOTP::new(
         Algorithm::SHA1,
         6,
-        1,
-        30,
+        0,
+        ACCOUNT_TOTP_STEP,
         secret.as_bytes().to_vec(),
         Some("MyApp".to_string()),
         "account".to_string(),
     ) {
-        Ok(totp) => {
-            match totp.check_current(code) {
-                Ok(result) => {
-                    log::info!("TOTP verification successful");
-                    result
-                }
-                Err(e) => {
-                    log::warn!("TOTP verification failed: {}", e);
-                    false
-                }
-            }
-        }
+        Ok(totp) => totp,
         Err(e) => {
             log::error!("Failed to create TOTP instance: {}", e);
-            false
+            return None;
         }
+    };
+
+    let current = time / ACCOUNT_TOTP_STEP;
+    (current.saturating_sub(skew)..=current + skew).find(|step| totp.check(code, step * ACCOUNT_TOTP_STEP))
+}
+
+/// Finds the time step of a TOTP code for an account secret at the current time
+pub fn current_totp_step(secret: &str, code: &str) -> Option<u64> {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok()?;
+    let step = totp_step_at(secret, code, now.as_secs(), totp_skew_steps());
+    if step.is_none() {
+        log::warn!("TOTP verification failed");
+    }
+    step
+}
+
+/// Records a TOTP step as used unless it, or a later one, was used before; returns whether it was new
+///
+/// Checked and recorded in one statement, so a code can't log in twice even from concurrent requests.
+pub fn record_totp_step(conn: &mut PgConnection, user_id: Uuid, step: u64) -> QueryResult<bool> {
+    use crate::schema::users;
+
+    let step = step as i64;
+    let updated = diesel::update(
+        users::table
+            .filter(users::id.eq(user_id))
+            .filter(users::mfa_last_totp_step.is_null().or(users::mfa_last_totp_step.lt(step))),
+    )
+    .set(users::mfa_last_totp_step.eq(Some(step)))
+    .execute(conn)?;
+
+    Ok(updated > 0)
+}
+
+/// Generates a fresh set of recovery codes formatted as `XXXXX-XXXXX`
+pub fn generate_recovery_codes() -> Result<Vec<String>, String> {
+    let rng = SystemRandom::new();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; RECOVERY_CODE_LENGTH];
+            rng.fill(&mut bytes)
+                .map_err(|_| "Failed to generate recovery codes".to_string())?;
+            let chars: String = bytes
+                .iter()
+                .map(|b| RECOVERY_CODE_ALPHABET[(*b as usize) % RECOVERY_CODE_ALPHABET.len()] as char)
+                .collect();
+            let (first, second) = chars.split_at(RECOVERY_CODE_LENGTH / 2);
+            Ok(format!("{}-{}", first, second))
+        })
+        .collect()
+}
+
+/// Canonical form of a recovery code as typed by the user
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Replaces a user's recovery codes with hashes of the given codes
+///
+/// Callers should run this inside the transaction that enables MFA or regenerates the codes.
+pub fn store_recovery_codes(conn: &mut PgConnection, user_id: Uuid, codes: &[String]) -> QueryResult<()> {
+    use crate::schema::mfa_recovery_codes;
+
+    diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(user_id)))
+        .execute(conn)?;
+
+    let mut new_codes = Vec::with_capacity(codes.len());
+    for code in codes {
+        let code_hash = bcrypt::hash(normalize_recovery_code(code), RECOVERY_CODE_BCRYPT_COST).map_err(|e| {
+            log::error!("Failed to hash recovery code: {}", e);
+            diesel::result::Error::RollbackTransaction
+        })?;
+        new_codes.push(NewMfaRecoveryCode { id: Uuid::new_v4(), user_id, code_hash });
+    }
+
+    diesel::insert_into(mfa_recovery_codes::table)
+        .values(&new_codes)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Deletes all of a user's recovery codes
+pub fn clear_recovery_codes(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<usize> {
+    use crate::schema::mfa_recovery_codes;
+
+    diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(user_id)))
+        .execute(conn)
+}
+
+/// Marks a matching unused recovery code as consumed; returns whether one matched
+pub fn consume_recovery_code(conn: &mut PgConnection, user_id: Uuid, code: &str) -> QueryResult<bool> {
+    use crate::schema::mfa_recovery_codes;
+
+    let normalized = normalize_recovery_code(code);
+    if normalized.len() != RECOVERY_CODE_LENGTH {
+        return Ok(false);
+    }
+
+    let unused = mfa_recovery_codes::table
+        .filter(mfa_recovery_codes::user_id.eq(user_id))
+        .filter(mfa_recovery_codes::used_at.is_null())
+        .select((mfa_recovery_codes::id, mfa_recovery_codes::code_hash))
+        .load::<(Uuid, String)>(conn)?;
+
+    let matching_id = match unused.iter().find(|(_, code_hash)| bcrypt::verify(&normalized, code_hash).unwrap_or(false)) {
+        Some((id, _)) => *id,
+        None => return Ok(false),
+    };
+
+    // Guard on used_at so two concurrent logins can't both spend the same code
+    let consumed = diesel::update(
+        mfa_recovery_codes::table
+            .filter(mfa_recovery_codes::id.eq(matching_id))
+            .filter(mfa_recovery_codes::used_at.is_null()),
+    )
+    .set(mfa_recovery_codes::used_at.eq(chrono::Utc::now().naive_utc()))
+    .execute(conn)?;
+
+    if consumed == 1 {
+        log::warn!("MFA recovery code used for user: {}", user_id);
+    }
+    Ok(consumed == 1)
+}
+
+/// Verifies a login MFA code as a TOTP code, falling back to the user's recovery codes
+pub fn verify_login_code(conn: &mut PgConnection, user_id: Uuid, secret: &str, code: &str) -> QueryResult<bool> {
+    let code = code.trim();
+    if let Some(step) = current_totp_step(secret, code) {
+        if record_totp_step(conn, user_id, step)? {
+            return Ok(true);
+        }
+        log::warn!("Replayed TOTP code for user: {}", user_id);
+        return Ok(false);
+    }
+    consume_recovery_code(conn, user_id, code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enrollment_url_matches_verification() {
+        let secret = generate_totp_secret(Uuid::new_v4());
+        let url = generate_qr_code_url("alice", &secret).unwrap();
+        assert!(url.starts_with("otpauth://totp/PassQ:alice?"));
+        assert!(url.contains(&format!("secret={}", secret_for_manual_entry(&secret).trim_end_matches('='))));
+
+        // A code from an app that scanned the URL must verify against the stored secret
+        let app = TOTP::from_url(&url).unwrap();
+        let code = app.generate_current().unwrap();
+        assert!(current_totp_step(&secret, &code).is_some());
+    }
+
+    // RFC 6238 appendix B SHA1 secret; its code for t=59 falls in step 1 (t=30..59)
+    const RFC6238_SHA1_SECRET: &str = "12345678901234567890";
+    const STEP_ONE_CODE: &str = "287082";
+
+    #[test]
+    fn test_parse_skew_steps() {
+        assert_eq!(parse_skew_steps(None), DEFAULT_TOTP_SKEW_STEPS);
+        assert_eq!(parse_skew_steps(Some(" 2 ")), 2);
+        assert_eq!(parse_skew_steps(Some("0")), 0);
+        assert_eq!(parse_skew_steps(Some("11")), DEFAULT_TOTP_SKEW_STEPS);
+        assert_eq!(parse_skew_steps(Some("-1")), DEFAULT_TOTP_SKEW_STEPS);
+    }
+
+    #[test]
+    fn test_totp_step_boundaries() {
+        let step_at = |time, skew| totp_step_at(RFC6238_SHA1_SECRET, STEP_ONE_CODE, time, skew);
+
+        // Without skew only step 1 itself accepts the code
+        assert_eq!(step_at(29, 0), None);
+        assert_eq!(step_at(30, 0), Some(1));
+        assert_eq!(step_at(59, 0), Some(1));
+        assert_eq!(step_at(60, 0), None);
+
+        // One step of skew accepts it from the start of step 0 to the end of step 2
+        assert_eq!(step_at(0, 1), Some(1));
+        assert_eq!(step_at(29, 1), Some(1));
+        assert_eq!(step_at(89, 1), Some(1));
+        assert_eq!(step_at(90, 1), None);
+        assert_eq!(step_at(119, 2), Some(1));
+        assert_eq!(step_at(120, 2), None);
+
+        assert_eq!(totp_step_at(RFC6238_SHA1_SECRET, "000000", 59, 1), None);
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes().unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        for code in &codes {
+            assert_eq!(code.len(), RECOVERY_CODE_LENGTH + 1);
+            assert_eq!(normalize_recovery_code(code).len(), RECOVERY_CODE_LENGTH);
+            assert!(normalize_recovery_code(code).bytes().all(|b| RECOVERY_CODE_ALPHABET.contains(&b)));
+        }
+        assert_eq!(normalize_recovery_code(" abcde-fghjk "), "ABCDEFGHJK");
+    }
+
+    // RFC 6238 appendix B, SHA256 key as base32
+    const RFC6238_SHA256_URI: &str = "otpauth://totp/Example:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA&algorithm=SHA256&digits=8&period=30";
+
+    #[test]
+    fn test_totp_sha256_eight_digits() {
+        let vectors = [
+            (59, "46119246"),
+            (1111111109, "68084774"),
+            (1111111111, "67062674"),
+            (1234567890, "91819424"),
+            (2000000000, "90698825"),
+            (20000000000, "77737706"),
+        ];
+        for (time, expected) in vectors {
+            assert_eq!(generate_totp_code_at(RFC6238_SHA256_URI, time).unwrap().code, expected);
+        }
+
+        // The next code is the one the following step will produce
+        let current = generate_totp_code_at(RFC6238_SHA256_URI, 1111111109).unwrap();
+        assert_eq!(current.expires_in, 1);
+        assert_eq!(current.next_code, "67062674");
+    }
+
+    #[test]
+    fn test_totp_period_and_legacy_secret() {
+        // Bare secrets keep the standard settings: RFC 6238 SHA1 vector truncated to 6 digits
+        let legacy = generate_totp_code_at("12345678901234567890", 59).unwrap();
+        assert_eq!(legacy.code, "287082");
+        assert_eq!(legacy.expires_in, 1);
+
+        let sixty = RFC6238_SHA256_URI.replace("period=30", "period=60");
+        assert_eq!(generate_totp_code_at(&sixty, 59).unwrap().expires_in, 1);
+        assert_eq!(generate_totp_code_at(&sixty, 60).unwrap().expires_in, 60);
+        assert_eq!(generate_totp_code_at(&sixty, 61).unwrap().expires_in, 59);
+
+        assert!(totp_for_secret(&RFC6238_SHA256_URI.replace("digits=8", "digits=10")).is_err());
+        assert!(totp_for_secret(&RFC6238_SHA256_URI.replace("period=30", "period=0")).is_err());
+        assert!(totp_for_secret(&RFC6238_SHA256_URI.replace("SHA256", "MD5")).is_err());
     }
 }
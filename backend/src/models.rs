@@ -1,7 +1,10 @@
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 use chrono;
+use crate::sharing::PermissionLevel;
+use crate::zero_knowledge::EncryptedData;
 
 #[derive(Queryable, Selectable, Serialize, Deserialize, Debug)]
 #[diesel(table_name = crate::schema::users)]
@@ -19,6 +22,45 @@ pub struct User {
     pub is_sso_user: Option<bool>,
     pub sso_display_name: Option<String>,
     pub sso_avatar_url: Option<String>,
+    pub is_admin: bool,
+    #[serde(skip_serializing)]
+    pub mfa_pending_secret: Option<String>, // Set during enrollment until confirmed
+    pub new_device_alerts: bool,
+    pub share_notifications: bool,
+    pub reset_requested_at: Option<chrono::NaiveDateTime>,
+    #[serde(skip_serializing)]
+    pub mfa_last_totp_step: Option<i64>,
+    pub pending_email: Option<String>, // Set until the new address is confirmed
+    #[serde(skip_serializing)]
+    pub email_change_token: Option<String>,
+    #[serde(skip_serializing)]
+    pub email_change_expires_at: Option<chrono::NaiveDateTime>,
+}
+
+/// What the client may know about the signed-in user; never carries secrets or hashes
+#[derive(Serialize)]
+pub struct UserProfile {
+    pub username: String,
+    pub email: String,
+    pub auth_method: Option<String>,
+    pub is_sso_user: bool,
+    pub sso_display_name: Option<String>,
+    pub sso_avatar_url: Option<String>,
+    pub mfa_enabled: bool,
+}
+
+impl From<User> for UserProfile {
+    fn from(user: User) -> Self {
+        Self {
+            username: user.username,
+            email: user.email,
+            auth_method: user.auth_method,
+            is_sso_user: user.is_sso_user.unwrap_or(false),
+            sso_display_name: user.sso_display_name,
+            sso_avatar_url: user.sso_avatar_url,
+            mfa_enabled: user.mfa_secret.is_some(),
+        }
+    }
 }
 
 #[derive(Insertable)]
@@ -61,6 +103,13 @@ pub struct UserRegistration {
 pub struct UserLogin {
     pub username: String,
     pub password: String,
+    #[serde(default)]
+    pub mfa_code: Option<String>, // Required once the user has enabled MFA
+}
+
+#[derive(Deserialize)]
+pub struct PasswordStrengthRequest {
+    pub password: String,
 }
 
 #[derive(Deserialize)]
@@ -83,6 +132,116 @@ pub struct RefreshTokenRequest {
 pub struct ChangePasswordRequest {
     pub current_password: String,
     pub new_password: String,
+    /// Keep the session making the request signed in (default); other sessions always end
+    pub keep_current_session: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct EmailChangeRequest {
+    pub new_email: String,
+    pub current_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct EmailChangeConfirm {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct AccountDeletionRequest {
+    pub current_password: String,
+}
+
+#[derive(Serialize)]
+pub struct AccountDeletionSummary {
+    pub deleted_passwords: usize,
+    pub deleted_folders: usize,
+    pub deleted_shares: usize,
+}
+
+/// Which optional emails the user receives
+#[derive(Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::users)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NotificationPreferences {
+    pub new_device_alerts: bool,
+    pub share_notifications: bool,
+}
+
+/// Omitted fields are left unchanged
+#[derive(Deserialize)]
+pub struct NotificationPreferencesUpdate {
+    pub new_device_alerts: Option<bool>,
+    pub share_notifications: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct MfaConfirmRequest {
+    pub code: String,
+}
+
+/// Answer to the step-up challenge of a high-risk login
+#[derive(Deserialize)]
+pub struct MfaChallengeRequest {
+    pub challenge_id: Uuid,
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct MfaDisableRequest {
+    pub password: String,
+}
+
+/// Re-authentication required before enrolling a passkey
+#[derive(Deserialize)]
+pub struct PasskeyRegistrationStart {
+    pub current_password: String,
+}
+
+/// Authenticator response finishing a passkey registration
+#[derive(Deserialize)]
+pub struct PasskeyRegistrationFinish {
+    pub ceremony_id: Uuid,
+    /// Label shown for the passkey, e.g. the device it lives on
+    pub name: Option<String>,
+    pub credential: webauthn_rs::prelude::RegisterPublicKeyCredential,
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyLoginStart {
+    pub username: String,
+}
+
+/// Signed challenge finishing a passkey login
+#[derive(Deserialize)]
+pub struct PasskeyLoginFinish {
+    pub ceremony_id: Uuid,
+    pub credential: webauthn_rs::prelude::PublicKeyCredential,
+}
+
+#[derive(Deserialize)]
+pub struct MfaRecoveryCodesRequest {
+    pub password: String,
+}
+
+#[derive(Serialize)]
+pub struct MfaEnrollmentResponse {
+    pub secret: String, // Base32 secret for manual entry
+    pub otpauth_uri: String,
+}
+
+// Plaintext recovery codes, only ever returned once
+#[derive(Serialize)]
+pub struct MfaRecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::mfa_recovery_codes)]
+pub struct NewMfaRecoveryCode {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub code_hash: String,
 }
 
 // Password models
@@ -101,6 +260,11 @@ pub struct Password {
     pub attachments: Option<serde_json::Value>,
     pub encrypted_website: Option<Vec<u8>>,
     pub encrypted_username: Option<Vec<u8>>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    pub is_client_encrypted: bool,
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+    pub is_favorite: bool,
 }
 
 #[derive(Insertable, Deserialize)]
@@ -117,6 +281,7 @@ pub struct NewPassword {
     pub attachments: Option<serde_json::Value>,
     pub encrypted_website: Option<Vec<u8>>,
     pub encrypted_username: Option<Vec<u8>>,
+    pub is_client_encrypted: bool,
 }
 
 #[derive(Deserialize)]
@@ -124,7 +289,9 @@ pub struct PasswordRequest {
     pub folder_id: Option<Uuid>,
     pub website: String,
     pub username: String,
-    pub password: String,
+    #[serde(default)]
+    pub password: String, // Ignored when client_encrypted is set
+    pub client_encrypted: Option<EncryptedData>, // Encrypted by the client; stored verbatim
     pub notes: Option<String>,
     pub otp_secret: Option<String>,
     pub attachments: Option<serde_json::Value>,
@@ -135,6 +302,59 @@ pub struct PasswordMoveRequest {
     pub folder_id: Option<Uuid>,
 }
 
+#[derive(Deserialize)]
+pub struct FavoriteRequest {
+    pub is_favorite: bool,
+}
+
+#[derive(Deserialize)]
+pub struct BulkMoveRequest {
+    pub password_ids: Vec<Uuid>,
+    pub folder_id: Option<Uuid>, // None moves the passwords out of any folder
+}
+
+// Metadata of a file attached to a password; the content is only loaded for downloads
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = crate::schema::password_attachments)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PasswordAttachment {
+    pub id: Uuid,
+    pub password_id: Uuid,
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::password_attachments)]
+pub struct NewPasswordAttachment {
+    pub id: Uuid,
+    pub password_id: Uuid,
+    pub user_id: Uuid,
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub encrypted_content: Vec<u8>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::password_history)]
+pub struct NewPasswordHistory {
+    pub id: Uuid,
+    pub password_id: Uuid,
+    pub encrypted_password: Vec<u8>,
+    pub changed_at: chrono::NaiveDateTime,
+}
+
+// A decrypted previous value of a password
+#[derive(Serialize, Debug)]
+pub struct PasswordHistoryEntry {
+    pub id: Uuid,
+    pub password: String,
+    pub changed_at: chrono::NaiveDateTime,
+}
+
 // Response struct for decrypted passwords
 #[derive(Serialize, Debug)]
 pub struct PasswordResponse {
@@ -142,11 +362,33 @@ pub struct PasswordResponse {
     pub folder_id: Option<Uuid>,
     pub website: String,
     pub username: String,
-    pub password: String, // Decrypted password
+    pub password: String, // Decrypted password, empty for client-encrypted entries
     pub user_id: Uuid,
     pub notes: Option<String>,
     pub otp_secret: Option<String>,
     pub attachments: Option<serde_json::Value>,
+    pub is_client_encrypted: bool,
+    pub is_favorite: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_encrypted: Option<EncryptedData>,
+}
+
+// A trashed password with when it was deleted and when it will be purged
+#[derive(Serialize, Debug)]
+pub struct TrashedPasswordResponse {
+    #[serde(flatten)]
+    pub password: PasswordResponse,
+    pub deleted_at: chrono::NaiveDateTime,
+    pub purge_after: chrono::NaiveDateTime,
+}
+
+// Created password with the optional breach lookup result
+#[derive(Serialize, Debug)]
+pub struct CreatedPasswordResponse {
+    #[serde(flatten)]
+    pub password: Password,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub breach_count: Option<u64>,
 }
 
 // Folder models
@@ -173,6 +415,26 @@ pub struct FolderRequest {
     pub name: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct RekeyRequest {
+    pub old_key_env: Option<String>, // Defaults to ENCRYPTION_KEY
+    pub new_key_env: Option<String>, // Not needed for a dry run
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize)]
+pub struct FolderDeleteQuery {
+    #[serde(default)]
+    pub recursive: bool, // Delete subfolders and their passwords instead of moving them up
+}
+
+#[derive(Serialize)]
+pub struct FolderDeleteSummary {
+    pub deleted_folders: usize,
+    pub deleted_passwords: usize,
+}
+
 // Share models
 #[derive(Queryable, Selectable, Serialize, Debug)]
 #[diesel(table_name = crate::schema::shares)]
@@ -182,10 +444,11 @@ pub struct Share {
     pub password_id: Option<Uuid>,
     pub folder_id: Option<Uuid>,
     pub user_id: Uuid,
-    pub shared_with_user_id: Uuid,
+    pub shared_with_user_id: Option<Uuid>,
     pub permission_level: String,
     pub expires_at: Option<chrono::NaiveDateTime>,
     pub created_at: chrono::NaiveDateTime,
+    pub group_id: Option<Uuid>,
 }
 
 #[derive(Insertable, Deserialize)]
@@ -195,19 +458,89 @@ pub struct NewShare {
     pub password_id: Option<Uuid>,
     pub folder_id: Option<Uuid>,
     pub user_id: Uuid,
-    pub shared_with_user_id: Uuid,
+    pub shared_with_user_id: Option<Uuid>,
     pub permission_level: String,
     pub expires_at: Option<chrono::NaiveDateTime>,
     pub created_at: chrono::NaiveDateTime,
+    pub group_id: Option<Uuid>,
 }
 
 #[derive(Deserialize)]
 pub struct ShareRequest {
-    pub recipient_username: String,
-    pub permission_level: String, // "view" or "edit"
+    pub recipient_username: Option<String>,
+    pub group_id: Option<Uuid>, // Share with a group instead of a single user
+    pub permission_level: PermissionLevel,
     pub expiration_days: Option<i32>, // None for never expires
 }
 
+/// Omitted fields are left unchanged; `"expiration_days": null` removes the expiry
+#[derive(Deserialize)]
+pub struct ShareUpdateRequest {
+    pub permission_level: Option<PermissionLevel>,
+    #[serde(default, deserialize_with = "present")]
+    pub expiration_days: Option<Option<i32>>,
+}
+
+/// Tells an explicit `null` (`Some(None)`) apart from a missing field (`None`)
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// An active share of a password, as its owner sees it
+#[derive(Queryable, Serialize)]
+pub struct PasswordShareEntry {
+    pub id: Uuid,
+    pub recipient_username: Option<String>,
+    pub group_id: Option<Uuid>,
+    pub group_name: Option<String>,
+    pub permission_level: String,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+// Group models
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = crate::schema::groups)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Group {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub name: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::groups)]
+pub struct NewGroup {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub name: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Insertable, Serialize, Debug)]
+#[diesel(table_name = crate::schema::group_members)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct GroupMember {
+    pub group_id: Uuid,
+    pub user_id: Uuid,
+    pub added_at: chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct GroupRequest {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct GroupMemberRequest {
+    pub username: String,
+}
+
 #[derive(Serialize)]
 pub struct ApiResponse<T> {
     pub success: bool,
@@ -233,6 +566,111 @@ impl<T> ApiResponse<T> {
     }
 }
 
+/// JSON body settings that answer unreadable bodies with the usual `ApiResponse` error
+///
+/// Actix would otherwise reply with a plain-text 400, e.g. for an unknown `permission_level`.
+pub fn json_config() -> actix_web::web::JsonConfig {
+    actix_web::web::JsonConfig::default().error_handler(|err, _req| {
+        use actix_web::error::JsonPayloadError;
+
+        let mut response = match err {
+            JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => actix_web::HttpResponse::PayloadTooLarge(),
+            JsonPayloadError::ContentType => actix_web::HttpResponse::UnsupportedMediaType(),
+            _ => actix_web::HttpResponse::BadRequest(),
+        };
+        let response = response.json(ApiResponse::<()>::error(format!("Invalid request body: {}", err)));
+        actix_web::error::InternalError::from_response(err, response).into()
+    })
+}
+
+// Field-level validation failures collected from a request
+#[derive(Default, Debug)]
+pub struct ValidationErrors {
+    message: Option<String>,
+    fields: BTreeMap<String, String>,
+}
+
+impl ValidationErrors {
+    /// Records why a field is invalid; the summary of the first failure becomes the top-level message
+    pub fn add(&mut self, field: &str, summary: String, message: String) {
+        self.message.get_or_insert(summary);
+        self.fields.entry(field.to_string()).or_insert(message);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+// Error response carrying a `field -> message` map next to the usual top-level message
+#[derive(Serialize)]
+pub struct ValidationErrorResponse {
+    #[serde(flatten)]
+    pub response: ApiResponse<()>,
+    pub errors: BTreeMap<String, String>,
+}
+
+impl From<ValidationErrors> for ValidationErrorResponse {
+    fn from(errors: ValidationErrors) -> Self {
+        Self {
+            response: ApiResponse::error(errors.message.unwrap_or_else(|| "Validation failed".to_string())),
+            errors: errors.fields,
+        }
+    }
+}
+
+// Summary of list entries that could not be decrypted
+#[derive(Serialize, Default, Debug)]
+pub struct DecryptionErrors {
+    pub count: usize,
+    pub ids: Vec<Uuid>,
+}
+
+impl DecryptionErrors {
+    pub fn record(&mut self, id: Uuid) {
+        self.count += 1;
+        self.ids.push(id);
+    }
+}
+
+// List response that reports decryption failures alongside the entries that succeeded
+#[derive(Serialize)]
+pub struct DecryptedListResponse<T> {
+    #[serde(flatten)]
+    pub response: ApiResponse<Vec<T>>,
+    pub decryption_errors: DecryptionErrors,
+}
+
+// Structured JSON export document, also accepted by the JSON import
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonExportDocument {
+    pub version: u32,
+    pub exported_at: chrono::NaiveDateTime,
+    pub folders: Vec<JsonExportFolder>,
+    pub passwords: Vec<JsonExportEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonExportFolder {
+    pub id: Uuid,
+    pub name: String,
+    pub parent_folder_id: Option<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonExportEntry {
+    pub id: Uuid,
+    pub folder_id: Option<Uuid>,
+    pub website: String,
+    pub username: String,
+    pub password: String,
+    pub notes: Option<String>,
+    pub otp_secret: Option<String>,
+    pub attachments: Option<serde_json::Value>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
 // TODO: Fix LoginHistory model type mappings
 // #[derive(Queryable, Selectable, Serialize, Deserialize, Debug)]
 // #[diesel(table_name = crate::schema::login_history)]
@@ -268,4 +706,66 @@ impl<T> ApiResponse<T> {
 //     pub user_agent: Option<String>,
 //     pub login_time: chrono::DateTime<chrono::Utc>,
 //     pub is_suspicious: bool,
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation_error_response_shape() {
+        let mut errors = ValidationErrors::default();
+        assert!(errors.is_empty());
+        errors.add("username", "Username validation failed: Username too long".to_string(), "Username too long".to_string());
+        errors.add("email", "Invalid email format".to_string(), "Invalid email format".to_string());
+        errors.add("username", "Username already exists".to_string(), "Username already exists".to_string());
+
+        let body = serde_json::to_value(ValidationErrorResponse::from(errors)).unwrap();
+        assert_eq!(body, serde_json::json!({
+            "success": false,
+            "message": "Username validation failed: Username too long",
+            "data": null,
+            "errors": {
+                "email": "Invalid email format",
+                "username": "Username too long"
+            }
+        }));
+    }
+
+    #[test]
+    fn test_share_update_request_null_expiry() {
+        let parse = |body: &str| serde_json::from_str::<ShareUpdateRequest>(body).unwrap().expiration_days;
+        assert_eq!(parse(r#"{"permission_level": "edit"}"#), None);
+        assert_eq!(parse(r#"{"expiration_days": null}"#), Some(None));
+        assert_eq!(parse(r#"{"expiration_days": 30}"#), Some(Some(30)));
+    }
+
+    #[actix_web::test]
+    async fn test_json_errors_use_the_response_envelope() {
+        use actix_web::{http::StatusCode, test as actix_test, web, App, HttpResponse};
+
+        async fn update(_update: web::Json<ShareUpdateRequest>) -> HttpResponse {
+            HttpResponse::Ok().finish()
+        }
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(json_config())
+                .route("/", web::put().to(update))
+                .service(web::resource("/small").app_data(json_config().limit(8)).route(web::put().to(update))),
+        )
+        .await;
+
+        let put = |uri: &str, body: &'static str| {
+            actix_test::TestRequest::put().uri(uri).insert_header(("Content-Type", "application/json")).set_payload(body).to_request()
+        };
+        let res = actix_test::call_service(&app, put("/", r#"{"permission_level": "admin"}"#)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["success"], false);
+        assert!(body["message"].as_str().unwrap().contains("unknown variant `admin`"));
+
+        let res = actix_test::call_service(&app, put("/small", r#"{"permission_level": "read"}"#)).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["success"], false);
+    }
+}
@@ -72,6 +72,7 @@ pub struct OAuthConfig {
 pub enum OAuthProvider {
     Microsoft,
     Google,
+    Oidc, // Generic OpenID Connect provider configured through OIDC_* env vars
 }
 
 impl OAuthProvider {
@@ -79,6 +80,7 @@ impl OAuthProvider {
         match self {
             OAuthProvider::Microsoft => "microsoft",
             OAuthProvider::Google => "google",
+            OAuthProvider::Oidc => "oidc",
         }
     }
 
@@ -87,6 +89,7 @@ impl OAuthProvider {
         match s.to_lowercase().as_str() {
             "microsoft" => Some(OAuthProvider::Microsoft),
             "google" => Some(OAuthProvider::Google),
+            "oidc" => Some(OAuthProvider::Oidc),
             _ => None,
         }
     }
@@ -110,6 +113,14 @@ impl OAuthProvider {
                 user_info_url: "https://www.googleapis.com/oauth2/v2/userinfo".to_string(),
                 scopes: vec!["openid".to_string(), "profile".to_string(), "email".to_string()],
             },
+            OAuthProvider::Oidc => OAuthConfig {
+                client_id: std::env::var("OIDC_CLIENT_ID").unwrap_or_default(),
+                client_secret: std::env::var("OIDC_CLIENT_SECRET").unwrap_or_default(),
+                auth_url: std::env::var("OIDC_AUTH_URL").unwrap_or_default(),
+                token_url: std::env::var("OIDC_TOKEN_URL").unwrap_or_default(),
+                user_info_url: std::env::var("OIDC_USERINFO_URL").unwrap_or_default(),
+                scopes: vec!["openid".to_string(), "profile".to_string(), "email".to_string()],
+            },
         }
     }
 }
@@ -0,0 +1,196 @@
+//! Password generator module for cryptographically random passwords
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+pub const MIN_LENGTH: usize = 8;
+pub const MAX_LENGTH: usize = 128;
+
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!@#$%^&*()-_=+[]{};:,.<>?/~";
+
+/// Characters easily confused with one another when read or typed
+const AMBIGUOUS: &str = "Il1O0o";
+
+/// Options accepted by the generator, defaulting to a 20 character mixed password
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GeneratorOptions {
+    pub length: usize,
+    pub include_uppercase: bool,
+    pub include_lowercase: bool,
+    pub include_digits: bool,
+    pub include_symbols: bool,
+    pub exclude_ambiguous: bool,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        Self {
+            length: 20,
+            include_uppercase: true,
+            include_lowercase: true,
+            include_digits: true,
+            include_symbols: true,
+            exclude_ambiguous: false,
+        }
+    }
+}
+
+impl GeneratorOptions {
+    /// Returns the character set of each enabled class
+    fn character_classes(&self) -> Vec<Vec<char>> {
+        [
+            (self.include_uppercase, UPPERCASE),
+            (self.include_lowercase, LOWERCASE),
+            (self.include_digits, DIGITS),
+            (self.include_symbols, SYMBOLS),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, chars)| {
+            chars
+                .chars()
+                .filter(|c| !self.exclude_ambiguous || !AMBIGUOUS.contains(*c))
+                .collect()
+        })
+        .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeneratedPassword {
+    pub password: String,
+    pub entropy_bits: f64,
+}
+
+/// Estimates the entropy of a password drawn uniformly from a character pool
+pub fn entropy_bits(length: usize, pool_size: usize) -> f64 {
+    if pool_size < 2 {
+        return 0.0;
+    }
+    length as f64 * (pool_size as f64).log2()
+}
+
+/// Estimates the entropy of an existing password from the generator classes it draws on
+///
+/// Characters outside the generator's classes count as their own class of 32.
+pub fn estimate_entropy_bits(password: &str) -> f64 {
+    let class_sizes = [UPPERCASE, LOWERCASE, DIGITS, SYMBOLS]
+        .into_iter()
+        .filter(|class| password.chars().any(|c| class.contains(c)))
+        .map(|class| class.chars().count());
+    let has_other = password.chars().any(|c| ![UPPERCASE, LOWERCASE, DIGITS, SYMBOLS].iter().any(|class| class.contains(c)));
+
+    let pool_size = class_sizes.sum::<usize>() + if has_other { 32 } else { 0 };
+    entropy_bits(password.chars().count(), pool_size)
+}
+
+/// Returns a uniformly distributed index below `bound`
+fn random_index(rng: &SystemRandom, bound: usize) -> Result<usize, String> {
+    let bound = bound as u32;
+    // Reject values past the last full multiple of bound to avoid modulo bias
+    let zone = u32::MAX - (u32::MAX % bound);
+    loop {
+        let mut bytes = [0u8; 4];
+        rng.fill(&mut bytes)
+            .map_err(|_| "Failed to generate random bytes".to_string())?;
+        let value = u32::from_le_bytes(bytes);
+        if value < zone {
+            return Ok((value % bound) as usize);
+        }
+    }
+}
+
+/// Generates a password containing at least one character from each enabled class
+pub fn generate(options: &GeneratorOptions) -> Result<GeneratedPassword, String> {
+    if options.length < MIN_LENGTH || options.length > MAX_LENGTH {
+        return Err(format!("Length must be between {} and {}", MIN_LENGTH, MAX_LENGTH));
+    }
+
+    let classes = options.character_classes();
+    if classes.is_empty() {
+        return Err("At least one character class must be enabled".to_string());
+    }
+
+    let pool: Vec<char> = classes.iter().flatten().copied().collect();
+    let rng = SystemRandom::new();
+
+    let mut password = Vec::with_capacity(options.length);
+    for class in &classes {
+        password.push(class[random_index(&rng, class.len())?]);
+    }
+    while password.len() < options.length {
+        password.push(pool[random_index(&rng, pool.len())?]);
+    }
+
+    // Shuffle so the guaranteed characters don't always lead
+    for i in (1..password.len()).rev() {
+        let j = random_index(&rng, i + 1)?;
+        password.swap(i, j);
+    }
+
+    Ok(GeneratedPassword {
+        password: password.into_iter().collect(),
+        entropy_bits: entropy_bits(options.length, pool.len()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_includes_each_enabled_class() {
+        let options = GeneratorOptions { length: MIN_LENGTH, ..Default::default() };
+        for _ in 0..50 {
+            let generated = generate(&options).unwrap();
+            assert_eq!(generated.password.chars().count(), MIN_LENGTH);
+            for class in [UPPERCASE, LOWERCASE, DIGITS, SYMBOLS] {
+                assert!(generated.password.chars().any(|c| class.contains(c)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_respects_options() {
+        let options = GeneratorOptions {
+            length: 64,
+            include_symbols: false,
+            exclude_ambiguous: true,
+            ..Default::default()
+        };
+        let generated = generate(&options).unwrap();
+        assert!(generated.password.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert!(!generated.password.chars().any(|c| AMBIGUOUS.contains(c)));
+        assert!((generated.entropy_bits - entropy_bits(64, 56)).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_estimate_entropy_matches_generator() {
+        let options = GeneratorOptions { length: 32, ..Default::default() };
+        let generated = generate(&options).unwrap();
+        assert!((estimate_entropy_bits(&generated.password) - generated.entropy_bits).abs() < f64::EPSILON);
+
+        assert!((estimate_entropy_bits("abcdefgh") - entropy_bits(8, 26)).abs() < f64::EPSILON);
+        assert!((estimate_entropy_bits("Ab1") - entropy_bits(3, 62)).abs() < f64::EPSILON);
+        assert!((estimate_entropy_bits("ünï") - entropy_bits(3, 58)).abs() < f64::EPSILON);
+        assert_eq!(estimate_entropy_bits(""), 0.0);
+    }
+
+    #[test]
+    fn test_generate_rejects_impossible_requests() {
+        let no_classes = GeneratorOptions {
+            include_uppercase: false,
+            include_lowercase: false,
+            include_digits: false,
+            include_symbols: false,
+            ..Default::default()
+        };
+        assert!(generate(&no_classes).is_err());
+        assert!(generate(&GeneratorOptions { length: 7, ..Default::default() }).is_err());
+        assert!(generate(&GeneratorOptions { length: 129, ..Default::default() }).is_err());
+    }
+}
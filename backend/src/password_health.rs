@@ -0,0 +1,436 @@
+//! Password health module for vault strength scans
+//!
+//! Scoring a large vault is slow, so scans run off the request path and the latest
+//! report is cached per user until the next rescan.
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use ring::digest;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+use crate::{crypto, db, password_generator, password_policy};
+use crate::models::Password;
+
+/// Default age after which a password is reported as old
+const DEFAULT_MAX_AGE_DAYS: i64 = 365;
+
+/// zxcvbn scores below this are reported as weak
+const MIN_ACCEPTABLE_SCORE: u8 = 3;
+
+/// Returns the zxcvbn score (0–4) of a password
+pub fn score_password(password: &str) -> u8 {
+    zxcvbn::zxcvbn(password, &[]).score().into()
+}
+
+/// Longest input scored by `estimate_strength`; zxcvbn gets slow on very long inputs
+pub const MAX_STRENGTH_INPUT_CHARS: usize = 256;
+
+/// Strength of a candidate account password next to the requirements the server enforces
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct StrengthEstimate {
+    pub score: u8,
+    pub entropy_bits: f64,
+    pub meets_requirements: bool,
+    pub unmet_requirements: Vec<String>,
+}
+
+/// Scores a password the way register and change-password will judge it
+pub fn estimate_strength(password: &str) -> StrengthEstimate {
+    let unmet_requirements = password_policy::current().unmet_requirements(password);
+    StrengthEstimate {
+        score: score_password(password),
+        entropy_bits: password_generator::estimate_entropy_bits(password),
+        meets_requirements: unmet_requirements.is_empty(),
+        unmet_requirements,
+    }
+}
+
+/// SHA-256 of a plaintext, so entries can be grouped without keeping the passwords around
+fn password_fingerprint(password: &str) -> Vec<u8> {
+    digest::digest(&digest::SHA256, password.as_bytes()).as_ref().to_vec()
+}
+
+/// Entries sharing a category in a health report
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct HealthCategory {
+    pub count: usize,
+    pub ids: Vec<Uuid>,
+}
+
+impl HealthCategory {
+    fn add(&mut self, id: Uuid) {
+        self.count += 1;
+        self.ids.push(id);
+    }
+}
+
+/// Cached result of a vault health scan
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub generated_at: NaiveDateTime,
+    pub total: usize,
+    pub weak: HealthCategory,
+    pub reused: HealthCategory,
+    pub old: HealthCategory,
+    pub undecryptable: HealthCategory,
+}
+
+/// A decrypted vault entry to analyze
+pub struct HealthEntry {
+    pub id: Uuid,
+    pub password: Option<String>, // None when the entry could not be decrypted
+    pub updated_at: NaiveDateTime,
+}
+
+/// Builds a health report from decrypted entries
+pub fn analyze(entries: &[HealthEntry], now: NaiveDateTime, max_age_days: i64) -> HealthReport {
+    let mut report = HealthReport {
+        generated_at: now,
+        total: entries.len(),
+        weak: HealthCategory::default(),
+        reused: HealthCategory::default(),
+        old: HealthCategory::default(),
+        undecryptable: HealthCategory::default(),
+    };
+
+    // Group by a hash of the plaintext so it is never kept around longer than needed
+    let mut by_hash: HashMap<Vec<u8>, Vec<Uuid>> = HashMap::new();
+
+    for entry in entries {
+        if now.signed_duration_since(entry.updated_at).num_days() >= max_age_days {
+            report.old.add(entry.id);
+        }
+
+        let password = match &entry.password {
+            Some(password) => password,
+            None => {
+                report.undecryptable.add(entry.id);
+                continue;
+            }
+        };
+
+        if score_password(password) < MIN_ACCEPTABLE_SCORE {
+            report.weak.add(entry.id);
+        }
+
+        by_hash.entry(password_fingerprint(password)).or_default().push(entry.id);
+    }
+
+    let mut reused: Vec<Uuid> = by_hash
+        .into_values()
+        .filter(|ids| ids.len() > 1)
+        .flatten()
+        .collect();
+    reused.sort();
+    for id in reused {
+        report.reused.add(id);
+    }
+
+    report
+}
+
+/// A vault entry identified by its site and login, never its password
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct EntrySummary {
+    pub id: Uuid,
+    pub website: String,
+    pub username: String,
+}
+
+/// A decrypted vault entry for the on-demand analysis reports
+pub struct AnalysisEntry {
+    pub summary: EntrySummary,
+    pub password: String,
+}
+
+/// Entries that all use the same password
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DuplicateGroup {
+    pub count: usize,
+    pub entries: Vec<EntrySummary>,
+}
+
+/// Groups entries sharing a password, largest groups first
+pub fn find_duplicates(entries: &[AnalysisEntry]) -> Vec<DuplicateGroup> {
+    let mut by_hash: HashMap<Vec<u8>, Vec<EntrySummary>> = HashMap::new();
+    for entry in entries {
+        by_hash.entry(password_fingerprint(&entry.password)).or_default().push(entry.summary.clone());
+    }
+
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| {
+            group.sort_by(|a, b| (&a.website, &a.username).cmp(&(&b.website, &b.username)));
+            DuplicateGroup { count: group.len(), entries: group }
+        })
+        .collect();
+    groups.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.entries[0].website.cmp(&b.entries[0].website)));
+    groups
+}
+
+/// An entry whose password should be rotated, with why
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct WeakEntry {
+    #[serde(flatten)]
+    pub entry: EntrySummary,
+    pub score: u8,
+    pub entropy_bits: f64,
+    pub reasons: Vec<String>,
+}
+
+/// Lists entries that fail the strength rules or score below the acceptable level, weakest first
+pub fn find_weak(entries: &[AnalysisEntry]) -> Vec<WeakEntry> {
+    let mut weak: Vec<WeakEntry> = entries
+        .iter()
+        .filter_map(|entry| {
+            let score = score_password(&entry.password);
+            let mut reasons = password_policy::current().unmet_requirements(&entry.password);
+            if score < MIN_ACCEPTABLE_SCORE {
+                reasons.insert(0, format!("Easy to guess (strength score {} of 4)", score));
+            }
+            if reasons.is_empty() {
+                return None;
+            }
+
+            Some(WeakEntry {
+                entry: entry.summary.clone(),
+                score,
+                entropy_bits: password_generator::estimate_entropy_bits(&entry.password),
+                reasons,
+            })
+        })
+        .collect();
+
+    weak.sort_by(|a, b| a.score.cmp(&b.score).then_with(|| a.entropy_bits.total_cmp(&b.entropy_bits)));
+    weak
+}
+
+/// Decrypts a user's server-encrypted entries, returning them with the ids that failed to decrypt
+pub fn load_analysis_entries(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<(Vec<AnalysisEntry>, Vec<Uuid>)> {
+    use crate::schema::passwords;
+
+    let rows = passwords::table
+        .filter(passwords::user_id.eq(user_id))
+        .filter(passwords::deleted_at.is_null())
+        .filter(passwords::is_client_encrypted.eq(false))
+        .select(Password::as_select())
+        .load(conn)?;
+
+    let mut entries = Vec::with_capacity(rows.len());
+    let mut undecryptable = Vec::new();
+    for row in rows {
+        let password = match crypto::decrypt_password(&row.encrypted_password) {
+            Ok(password) => password,
+            Err(_) => {
+                undecryptable.push(row.id);
+                continue;
+            }
+        };
+
+        // Decrypt metadata if available, otherwise use unencrypted fields
+        let website = row.encrypted_website.as_deref()
+            .and_then(|encrypted| crypto::decrypt_metadata(encrypted).ok())
+            .unwrap_or(row.website);
+        let username = row.encrypted_username.as_deref()
+            .and_then(|encrypted| crypto::decrypt_metadata(encrypted).ok())
+            .unwrap_or(row.username);
+
+        entries.push(AnalysisEntry { summary: EntrySummary { id: row.id, website, username }, password });
+    }
+
+    Ok((entries, undecryptable))
+}
+
+/// Cached report and scan state for one user
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HealthStatus {
+    pub report: Option<HealthReport>,
+    pub scanning: bool,
+}
+
+/// Runs vault health scans in the background and caches the latest report per user
+#[derive(Default)]
+pub struct PasswordHealthService {
+    reports: Mutex<HashMap<Uuid, HealthStatus>>,
+}
+
+impl PasswordHealthService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the cached status for a user
+    pub fn status(&self, user_id: Uuid) -> HealthStatus {
+        self.reports.lock().unwrap().get(&user_id).cloned().unwrap_or_default()
+    }
+
+    /// Starts a background scan unless one is already running; returns whether it started
+    pub fn start_scan(self: &Arc<Self>, user_id: Uuid, db_pool: db::DbPool) -> bool {
+        {
+            let mut reports = self.reports.lock().unwrap();
+            let status = reports.entry(user_id).or_default();
+            if status.scanning {
+                return false;
+            }
+            status.scanning = true;
+        }
+
+        let service = self.clone();
+        actix_web::rt::spawn(async move {
+            let result = actix_web::rt::task::spawn_blocking(move || scan_vault(&db_pool, user_id)).await;
+
+            let mut reports = service.reports.lock().unwrap();
+            let status = reports.entry(user_id).or_default();
+            status.scanning = false;
+            match result {
+                Ok(Ok(report)) => {
+                    log::info!("Password health scan completed for user {}: {} entries", user_id, report.total);
+                    status.report = Some(report);
+                }
+                Ok(Err(e)) => log::error!("Password health scan failed for user {}: {}", user_id, e),
+                Err(e) => log::error!("Password health scan task failed for user {}: {}", user_id, e),
+            }
+        });
+
+        true
+    }
+}
+
+/// Decrypts and scores every password in a user's vault
+fn scan_vault(db_pool: &db::DbPool, user_id: Uuid) -> Result<HealthReport, String> {
+    use crate::schema::passwords;
+
+    let mut conn = db_pool.get().map_err(|e| format!("Failed to get database connection: {}", e))?;
+
+    let rows = passwords::table
+        .filter(passwords::user_id.eq(user_id))
+        .filter(passwords::deleted_at.is_null())
+        // The server can't see client-encrypted passwords, so they aren't scored
+        .filter(passwords::is_client_encrypted.eq(false))
+        .select((passwords::id, passwords::encrypted_password, passwords::updated_at))
+        .load::<(Uuid, Vec<u8>, NaiveDateTime)>(&mut conn)
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    let entries: Vec<HealthEntry> = rows
+        .into_iter()
+        .map(|(id, encrypted_password, updated_at)| HealthEntry {
+            id,
+            password: crypto::decrypt_password(&encrypted_password).ok(),
+            updated_at,
+        })
+        .collect();
+
+    let max_age_days = env::var("PASSWORD_MAX_AGE_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(DEFAULT_MAX_AGE_DAYS);
+
+    Ok(analyze(&entries, Utc::now().naive_utc(), max_age_days))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_score_password() {
+        assert!(score_password("password") < MIN_ACCEPTABLE_SCORE);
+        assert!(score_password("correct-horse-battery-staple-92!") >= MIN_ACCEPTABLE_SCORE);
+    }
+
+    #[test]
+    fn test_estimate_strength() {
+        let weak = estimate_strength("password");
+        assert!(weak.score < MIN_ACCEPTABLE_SCORE);
+        assert!(!weak.meets_requirements);
+        assert_eq!(weak.unmet_requirements, password_policy::current().unmet_requirements("password"));
+        assert_eq!(weak.unmet_requirements.len(), 3);
+
+        let strong = estimate_strength("Correct-Horse-Battery-Staple-92");
+        assert!(strong.score >= MIN_ACCEPTABLE_SCORE);
+        assert!(strong.meets_requirements);
+        assert!(strong.unmet_requirements.is_empty());
+        assert!(strong.entropy_bits > weak.entropy_bits);
+        assert!(crate::auth::validate_password("Correct-Horse-Battery-Staple-92").is_ok());
+    }
+
+    #[test]
+    fn test_analyze() {
+        let now = Utc::now().naive_utc();
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let entries = vec![
+            HealthEntry { id: ids[0], password: Some("password".to_string()), updated_at: now },
+            HealthEntry { id: ids[1], password: Some("Tr0ub4dor&3-horse-staple".to_string()), updated_at: now - Duration::days(400) },
+            HealthEntry { id: ids[2], password: Some("Tr0ub4dor&3-horse-staple".to_string()), updated_at: now },
+            HealthEntry { id: ids[3], password: None, updated_at: now },
+        ];
+
+        let report = analyze(&entries, now, 365);
+        assert_eq!(report.total, 4);
+        assert_eq!(report.weak.ids, vec![ids[0]]);
+        assert_eq!(report.old.ids, vec![ids[1]]);
+        assert_eq!(report.undecryptable.ids, vec![ids[3]]);
+
+        let mut reused = vec![ids[1], ids[2]];
+        reused.sort();
+        assert_eq!(report.reused.ids, reused);
+    }
+
+    fn analysis_entry(website: &str, password: &str) -> AnalysisEntry {
+        AnalysisEntry {
+            summary: EntrySummary { id: Uuid::new_v4(), website: website.to_string(), username: "alice".to_string() },
+            password: password.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let entries = vec![
+            analysis_entry("https://c.example", "shared-secret-1"),
+            analysis_entry("https://a.example", "shared-secret-1"),
+            analysis_entry("https://b.example", "unique-secret"),
+            analysis_entry("https://d.example", "shared-secret-2"),
+            analysis_entry("https://e.example", "shared-secret-2"),
+            analysis_entry("https://f.example", "shared-secret-2"),
+        ];
+
+        let groups = find_duplicates(&entries);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].count, 3);
+        assert_eq!(groups[1].count, 2);
+        let websites: Vec<&str> = groups[1].entries.iter().map(|e| e.website.as_str()).collect();
+        assert_eq!(websites, vec!["https://a.example", "https://c.example"]);
+
+        let json = serde_json::to_string(&groups).unwrap();
+        assert!(!json.contains("shared-secret"));
+    }
+
+    #[test]
+    fn test_find_weak() {
+        let entries = vec![
+            analysis_entry("https://a.example", "password"),
+            analysis_entry("https://b.example", "correct-Horse-battery-staple-92!"),
+            analysis_entry("https://c.example", "qrxv-tmwp-jzkd-hbns"),
+        ];
+
+        let weak = find_weak(&entries);
+        assert_eq!(weak.len(), 2);
+        assert_eq!(weak[0].entry.website, "https://a.example");
+        assert!(weak[0].reasons[0].starts_with("Easy to guess"));
+        assert!(weak[0].reasons.contains(&"Password must contain at least one digit".to_string()));
+
+        // Strong by zxcvbn but missing required classes still needs rotating
+        assert_eq!(weak[1].entry.website, "https://c.example");
+        assert!(weak[1].score >= MIN_ACCEPTABLE_SCORE);
+        assert!(!weak[1].reasons.is_empty());
+
+        let json = serde_json::to_value(&weak).unwrap();
+        assert_eq!(json[0]["website"], "https://a.example");
+        assert!(!json.to_string().contains("\"password\""));
+    }
+}
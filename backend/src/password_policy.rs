@@ -0,0 +1,181 @@
+//! Password policy module for the rules account passwords must meet
+//!
+//! Registration, password changes and resets all check passwords against the same policy,
+//! read once from the environment so an organization can require e.g. longer passwords.
+
+use std::env;
+use std::sync::OnceLock;
+
+/// Longest password accepted, whatever the policy
+pub const MAX_PASSWORD_CHARS: usize = 128;
+
+const DEFAULT_MIN_LENGTH: usize = 8;
+
+/// What an account password must contain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_special: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: DEFAULT_MIN_LENGTH,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_special: true,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Loads PASSWORD_MIN_LENGTH and the PASSWORD_REQUIRE_UPPERCASE, _LOWERCASE, _DIGIT and
+    /// _SPECIAL flags
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let flag = |name: &str, default: bool| parse_flag(name, env::var(name).ok().as_deref(), default);
+
+        Self {
+            min_length: parse_min_length(env::var("PASSWORD_MIN_LENGTH").ok().as_deref()),
+            require_uppercase: flag("PASSWORD_REQUIRE_UPPERCASE", defaults.require_uppercase),
+            require_lowercase: flag("PASSWORD_REQUIRE_LOWERCASE", defaults.require_lowercase),
+            require_digit: flag("PASSWORD_REQUIRE_DIGIT", defaults.require_digit),
+            require_special: flag("PASSWORD_REQUIRE_SPECIAL", defaults.require_special),
+        }
+    }
+
+    /// Lists every rule the password fails, in the order they are checked
+    pub fn unmet_requirements(&self, password: &str) -> Vec<String> {
+        let length = password.chars().count();
+        let mut issues = Vec::new();
+        if length < self.min_length {
+            issues.push(format!("Password must be at least {} characters long", self.min_length));
+        }
+        if length > MAX_PASSWORD_CHARS {
+            issues.push(format!("Password must be at most {} characters long", MAX_PASSWORD_CHARS));
+        }
+        if self.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+            issues.push("Password must contain at least one uppercase letter".to_string());
+        }
+        if self.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+            issues.push("Password must contain at least one lowercase letter".to_string());
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            issues.push("Password must contain at least one digit".to_string());
+        }
+        if self.require_special && !password.chars().any(|c| !c.is_alphanumeric()) {
+            issues.push("Password must contain at least one special character".to_string());
+        }
+        issues
+    }
+
+    /// Checks the password, failing with the first rule it breaks
+    pub fn validate(&self, password: &str) -> Result<(), String> {
+        match self.unmet_requirements(password).into_iter().next() {
+            Some(issue) => Err(issue),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Rejects a new password that is the same as the current one, given the current hash
+pub fn check_not_current(new_password: &str, current_hash: &str) -> Result<(), String> {
+    if crate::auth::verify_password(new_password, current_hash) {
+        return Err("New password must be different from the current password".to_string());
+    }
+    Ok(())
+}
+
+/// The policy configured for this process
+pub fn current() -> &'static PasswordPolicy {
+    static POLICY: OnceLock<PasswordPolicy> = OnceLock::new();
+    POLICY.get_or_init(PasswordPolicy::from_env)
+}
+
+fn parse_min_length(value: Option<&str>) -> usize {
+    match value.map(|v| v.trim().parse::<usize>()) {
+        Some(Ok(length)) if (1..=MAX_PASSWORD_CHARS).contains(&length) => length,
+        Some(_) => {
+            log::warn!("Invalid PASSWORD_MIN_LENGTH, using {}", DEFAULT_MIN_LENGTH);
+            DEFAULT_MIN_LENGTH
+        }
+        None => DEFAULT_MIN_LENGTH,
+    }
+}
+
+fn parse_flag(name: &str, value: Option<&str>, default: bool) -> bool {
+    match value.map(|v| v.trim()) {
+        Some("true") => true,
+        Some("false") => false,
+        Some(_) => {
+            log::warn!("Invalid {}, using {}", name, default);
+            default
+        }
+        None => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_min_length() {
+        assert_eq!(parse_min_length(None), DEFAULT_MIN_LENGTH);
+        assert_eq!(parse_min_length(Some(" 12 ")), 12);
+        assert_eq!(parse_min_length(Some("0")), DEFAULT_MIN_LENGTH);
+        assert_eq!(parse_min_length(Some("500")), DEFAULT_MIN_LENGTH);
+        assert_eq!(parse_min_length(Some("long")), DEFAULT_MIN_LENGTH);
+    }
+
+    #[test]
+    fn test_parse_flag() {
+        assert!(parse_flag("TEST", None, true));
+        assert!(!parse_flag("TEST", Some("false"), true));
+        assert!(parse_flag("TEST", Some(" true "), false));
+        assert!(!parse_flag("TEST", Some("yes"), false));
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let policy = PasswordPolicy::default();
+        assert!(policy.validate("Str0ng!pass").is_ok());
+        assert_eq!(policy.validate("Sh0rt!").unwrap_err(), "Password must be at least 8 characters long");
+        assert_eq!(policy.unmet_requirements("password"), vec![
+            "Password must contain at least one uppercase letter".to_string(),
+            "Password must contain at least one digit".to_string(),
+            "Password must contain at least one special character".to_string(),
+        ]);
+        assert!(policy.validate(&format!("Aa1!{}", "a".repeat(MAX_PASSWORD_CHARS))).is_err());
+        // Length counts characters, not bytes
+        assert!(policy.validate("Ää1!ääää").is_ok());
+    }
+
+    #[test]
+    fn test_check_not_current() {
+        let current_hash = crate::auth::hash_password("Str0ng!pass");
+        assert_eq!(
+            check_not_current("Str0ng!pass", &current_hash).unwrap_err(),
+            "New password must be different from the current password"
+        );
+        assert!(check_not_current("Str0ng!pass2", &current_hash).is_ok());
+    }
+
+    #[test]
+    fn test_custom_policy() {
+        let policy = PasswordPolicy {
+            min_length: 12,
+            require_uppercase: false,
+            require_lowercase: true,
+            require_digit: false,
+            require_special: false,
+        };
+        assert!(policy.validate("correcthorsebattery").is_ok());
+        assert_eq!(policy.validate("Str0ng!pass").unwrap_err(), "Password must be at least 12 characters long");
+    }
+}
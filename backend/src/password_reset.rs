@@ -0,0 +1,178 @@
+//! Password reset module for issuing reset tokens
+//!
+//! Each email gets at most one reset email per cooldown period. Requests within the cooldown
+//! get the same neutral response as any other, they just don't send another email. Only a
+//! hash of each token is stored, and a token stops working once it has been used.
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use ring::digest;
+use std::env;
+use uuid::Uuid;
+
+/// Minutes between reset emails when PASSWORD_RESET_COOLDOWN_MINUTES isn't set
+pub const DEFAULT_COOLDOWN_MINUTES: i64 = 5;
+
+/// How long after one reset request another one is ignored; zero disables the cooldown
+pub fn cooldown_from_env() -> Duration {
+    Duration::minutes(parse_cooldown_minutes(env::var("PASSWORD_RESET_COOLDOWN_MINUTES").ok().as_deref()))
+}
+
+fn parse_cooldown_minutes(value: Option<&str>) -> i64 {
+    match value.map(|v| v.trim().parse::<i64>()) {
+        Some(Ok(minutes)) if minutes >= 0 => minutes,
+        Some(_) => {
+            log::warn!("Invalid PASSWORD_RESET_COOLDOWN_MINUTES, using {} minutes", DEFAULT_COOLDOWN_MINUTES);
+            DEFAULT_COOLDOWN_MINUTES
+        }
+        None => DEFAULT_COOLDOWN_MINUTES,
+    }
+}
+
+/// How a presented reset token compares to the one stored for a user
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenCheck {
+    Valid,
+    Expired,
+    Invalid,
+}
+
+/// The form a reset token is stored and looked up in
+pub fn hash_token(token: &str) -> String {
+    let digest = digest::digest(&digest::SHA256, token.as_bytes());
+    hex::encode(digest.as_ref())
+}
+
+/// Checks a presented token against the stored hash and expiry
+pub fn check_token(stored_hash: Option<&str>, expires_at: Option<NaiveDateTime>, presented: &str, now: NaiveDateTime) -> TokenCheck {
+    match (stored_hash, expires_at) {
+        (Some(stored_hash), Some(expires_at)) if stored_hash == hash_token(presented) => {
+            if now < expires_at {
+                TokenCheck::Valid
+            } else {
+                TokenCheck::Expired
+            }
+        }
+        _ => TokenCheck::Invalid,
+    }
+}
+
+/// Stores a new reset token unless the user requested one within the cooldown
+///
+/// The cooldown is checked in the same statement that records the request, so concurrent
+/// requests can't both get through. Returns whether the token was stored.
+pub fn store_token(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    token: &str,
+    expires_at: NaiveDateTime,
+    now: NaiveDateTime,
+    cooldown: Duration,
+) -> QueryResult<bool> {
+    use crate::schema::users;
+
+    let cutoff = now - cooldown;
+    let updated = diesel::update(
+        users::table
+            .filter(users::id.eq(user_id))
+            .filter(users::reset_requested_at.is_null().or(users::reset_requested_at.le(cutoff))),
+    )
+    .set((
+        users::reset_token.eq(Some(hash_token(token))),
+        users::reset_token_expires_at.eq(Some(expires_at)),
+        users::reset_requested_at.eq(Some(now)),
+    ))
+    .execute(conn)?;
+
+    Ok(updated > 0)
+}
+
+/// Sets the new password and clears the reset token it was authorized by
+///
+/// Only succeeds while the token is still stored, so of two concurrent requests with the same
+/// token only one changes the password. Returns whether the password was changed.
+pub fn consume_token(conn: &mut PgConnection, user_id: Uuid, token: &str, new_password_hash: &str) -> QueryResult<bool> {
+    use crate::schema::users;
+
+    let updated = diesel::update(
+        users::table
+            .filter(users::id.eq(user_id))
+            .filter(users::reset_token.eq(hash_token(token))),
+    )
+    .set((
+        users::password_hash.eq(new_password_hash),
+        users::reset_token.eq(None::<String>),
+        users::reset_token_expires_at.eq(None::<NaiveDateTime>),
+    ))
+    .execute(conn)?;
+
+    Ok(updated > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cooldown_minutes() {
+        assert_eq!(parse_cooldown_minutes(None), DEFAULT_COOLDOWN_MINUTES);
+        assert_eq!(parse_cooldown_minutes(Some(" 30 ")), 30);
+        assert_eq!(parse_cooldown_minutes(Some("0")), 0);
+        assert_eq!(parse_cooldown_minutes(Some("-1")), DEFAULT_COOLDOWN_MINUTES);
+        assert_eq!(parse_cooldown_minutes(Some("soon")), DEFAULT_COOLDOWN_MINUTES);
+    }
+
+    fn now() -> NaiveDateTime {
+        chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap().naive_utc()
+    }
+
+    #[test]
+    fn test_token_is_hashed_at_rest() {
+        let token = "3f1c9a0b7e2d4c6a8b0e1f2d3c4b5a69788796a5b4c3d2e1f0a9b8c7d6e5f4a3";
+        let stored = hash_token(token);
+        assert_ne!(stored, token);
+        assert_eq!(stored.len(), 64);
+        assert_eq!(stored, hash_token(token));
+
+        // Someone who can read the stored value still can't use it as a token
+        let expires_at = Some(now() + Duration::minutes(15));
+        assert_eq!(check_token(Some(&stored), expires_at, token, now()), TokenCheck::Valid);
+        assert_eq!(check_token(Some(&stored), expires_at, &stored, now()), TokenCheck::Invalid);
+    }
+
+    #[test]
+    fn test_check_token() {
+        let token = "reset-token";
+        let stored = hash_token(token);
+        let expires_at = Some(now() + Duration::minutes(15));
+
+        assert_eq!(check_token(Some(&stored), expires_at, "other-token", now()), TokenCheck::Invalid);
+        assert_eq!(check_token(Some(&stored), None, token, now()), TokenCheck::Invalid);
+        assert_eq!(check_token(Some(&stored), expires_at, token, now() + Duration::minutes(15)), TokenCheck::Expired);
+    }
+
+    #[test]
+    fn test_used_token_is_rejected() {
+        use crate::schema::users;
+
+        let Some(mut conn) = crate::db::test_connection(
+            "CREATE TEMPORARY TABLE users (id UUID PRIMARY KEY, password_hash VARCHAR NOT NULL, \
+             reset_token VARCHAR, reset_token_expires_at TIMESTAMP, reset_requested_at TIMESTAMP)",
+        ) else {
+            return;
+        };
+        let user_id = Uuid::new_v4();
+        diesel::insert_into(users::table)
+            .values((users::id.eq(user_id), users::password_hash.eq("old-hash")))
+            .execute(&mut conn)
+            .unwrap();
+        assert!(store_token(&mut conn, user_id, "reset-token", now() + Duration::minutes(15), now(), Duration::zero()).unwrap());
+
+        assert!(consume_token(&mut conn, user_id, "reset-token", "new-hash").unwrap());
+        assert!(!consume_token(&mut conn, user_id, "reset-token", "attacker-hash").unwrap());
+
+        let password_hash: String = users::table.find(user_id).select(users::password_hash).first(&mut conn).unwrap();
+        assert_eq!(password_hash, "new-hash");
+    }
+}
@@ -0,0 +1,373 @@
+//! Request quota module for per-IP and per-user limits by endpoint class
+//!
+//! Complements the governor burst limiting with per-minute quotas for reads, writes
+//! and exports, plus a daily quota per authenticated user.
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{http::Method, Error, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use crate::{auth, ip_controls};
+use crate::models::ApiResponse;
+use crate::token_management::decode_enhanced_token;
+
+const MINUTE: Duration = Duration::from_secs(60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Category of endpoint a request counts against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointClass {
+    Read,
+    Write,
+    Export,
+}
+
+impl EndpointClass {
+    /// Classifies a request by method and path
+    pub fn classify(method: &Method, path: &str) -> Self {
+        if path.starts_with("/export/") || path == "/audit/export" {
+            EndpointClass::Export
+        } else if method == Method::GET || method == Method::HEAD || method == Method::OPTIONS {
+            EndpointClass::Read
+        } else {
+            EndpointClass::Write
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EndpointClass::Read => "reads",
+            EndpointClass::Write => "writes",
+            EndpointClass::Export => "exports",
+        }
+    }
+}
+
+/// Per-minute limits for each endpoint class plus a daily per-user limit
+#[derive(Debug, Clone)]
+pub struct QuotaConfig {
+    pub reads_per_minute: u32,
+    pub writes_per_minute: u32,
+    pub exports_per_minute: u32,
+    pub daily_per_user: u32,
+}
+
+impl QuotaConfig {
+    /// Loads quotas from QUOTA_* environment variables
+    pub fn from_env() -> Self {
+        Self {
+            reads_per_minute: env_u32("QUOTA_READS_PER_MINUTE", 600),
+            writes_per_minute: env_u32("QUOTA_WRITES_PER_MINUTE", 120),
+            exports_per_minute: env_u32("QUOTA_EXPORTS_PER_MINUTE", 5),
+            daily_per_user: env_u32("QUOTA_DAILY_PER_USER", 20_000),
+        }
+    }
+
+    /// Returns the per-minute limit for a class
+    pub fn limit_for(&self, class: EndpointClass) -> u32 {
+        match class {
+            EndpointClass::Read => self.reads_per_minute,
+            EndpointClass::Write => self.writes_per_minute,
+            EndpointClass::Export => self.exports_per_minute,
+        }
+    }
+}
+
+fn env_u32(name: &str, default: u32) -> u32 {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            log::warn!("Invalid value for {}: {}, using default {}", name, value, default);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// Details of the quota a rejected request exceeded
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct QuotaExceeded {
+    pub quota: String,
+    pub limit: u32,
+    pub window_seconds: u64,
+    pub retry_after_seconds: u64,
+}
+
+struct Counter {
+    count: u32,
+    expires_at: Instant,
+}
+
+/// Shared fixed-window counters keyed by subject and endpoint class
+#[derive(Default)]
+pub struct QuotaStore {
+    counters: Mutex<HashMap<String, Counter>>,
+}
+
+impl QuotaStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks every quota applying to a request, counting it against each
+    ///
+    /// All quotas are checked before any is counted, so a request refused by one quota
+    /// doesn't use up the others.
+    pub fn check(
+        &self,
+        config: &QuotaConfig,
+        class: EndpointClass,
+        ip: Option<&str>,
+        user_id: Option<&str>,
+        now: Instant,
+    ) -> Result<(), QuotaExceeded> {
+        let class_limit = config.limit_for(class);
+        let mut checks = Vec::new();
+
+        if let Some(ip) = ip {
+            checks.push((format!("ip:{}:{}", ip, class.as_str()), format!("{}_per_ip", class.as_str()), class_limit, MINUTE));
+        }
+        if let Some(user_id) = user_id {
+            checks.push((format!("user:{}:{}", user_id, class.as_str()), format!("{}_per_user", class.as_str()), class_limit, MINUTE));
+            checks.push((format!("user:{}:daily", user_id), "daily_per_user".to_string(), config.daily_per_user, DAY));
+        }
+        // A limit of 0 disables that quota
+        checks.retain(|(_, _, limit, _)| *limit > 0);
+
+        let mut counters = self.counters.lock().unwrap();
+
+        // Drop expired windows so the map doesn't grow without bound
+        if counters.len() > 10_000 {
+            counters.retain(|_, counter| counter.expires_at > now);
+        }
+
+        for (key, quota, limit, window) in &checks {
+            let Some(counter) = counters.get(key).filter(|counter| counter.expires_at > now) else {
+                continue;
+            };
+            if counter.count >= *limit {
+                return Err(QuotaExceeded {
+                    quota: quota.clone(),
+                    limit: *limit,
+                    window_seconds: window.as_secs(),
+                    retry_after_seconds: counter.expires_at.saturating_duration_since(now).as_secs().max(1),
+                });
+            }
+        }
+
+        for (key, _, _, window) in checks {
+            let counter = counters.entry(key).or_insert(Counter { count: 0, expires_at: now + window });
+            if counter.expires_at <= now {
+                counter.count = 0;
+                counter.expires_at = now + window;
+            }
+            counter.count += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// The user a request is counted against, taken from its access token
+///
+/// Only the signature and expiry are checked; the handler authenticates the request fully, so the
+/// session checks and their database work aren't repeated here.
+fn quota_user_id(req: &actix_web::HttpRequest) -> Option<Uuid> {
+    let token = auth::token_from_request(req)?;
+    let claims = decode_enhanced_token(&token).ok()?;
+    (claims.token_type == "access").then_some(claims.sub)
+}
+
+/// Middleware enforcing request quotas, enabled with ENABLE_REQUEST_QUOTAS=true
+pub struct QuotaMiddleware {
+    store: Arc<QuotaStore>,
+    config: Rc<QuotaConfig>,
+    enabled: bool,
+}
+
+impl QuotaMiddleware {
+    pub fn new(store: Arc<QuotaStore>) -> Self {
+        let enabled = env::var("ENABLE_REQUEST_QUOTAS").unwrap_or_else(|_| "false".to_string()) == "true";
+        Self {
+            store,
+            config: Rc::new(QuotaConfig::from_env()),
+            enabled,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for QuotaMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = QuotaMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(QuotaMiddlewareService {
+            service: Rc::new(service),
+            store: self.store.clone(),
+            config: self.config.clone(),
+            enabled: self.enabled,
+        }))
+    }
+}
+
+pub struct QuotaMiddlewareService<S> {
+    service: Rc<S>,
+    store: Arc<QuotaStore>,
+    config: Rc<QuotaConfig>,
+    enabled: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for QuotaMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        if self.enabled {
+            let class = EndpointClass::classify(req.method(), req.path());
+            let ip = ip_controls::extract_client_ip(req.request()).map(|ip| ip.to_string());
+            let user_id = quota_user_id(req.request()).map(|id| id.to_string());
+
+            if let Err(exceeded) = self.store.check(&self.config, class, ip.as_deref(), user_id.as_deref(), Instant::now()) {
+                log::warn!("Request quota {} exceeded (ip: {:?}, user: {:?})", exceeded.quota, ip, user_id);
+                let response = HttpResponse::TooManyRequests()
+                    .insert_header(("Retry-After", exceeded.retry_after_seconds.to_string()))
+                    .json(ApiResponse {
+                        success: false,
+                        message: format!("Request quota exceeded: {}", exceeded.quota),
+                        data: Some(exceeded),
+                    });
+                return Box::pin(async move { Ok(req.into_response(response).map_into_right_body()) });
+            }
+        }
+
+        Box::pin(async move {
+            let res = service.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> QuotaConfig {
+        QuotaConfig {
+            reads_per_minute: 3,
+            writes_per_minute: 2,
+            exports_per_minute: 1,
+            daily_per_user: 4,
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(EndpointClass::classify(&Method::GET, "/passwords"), EndpointClass::Read);
+        assert_eq!(EndpointClass::classify(&Method::POST, "/passwords"), EndpointClass::Write);
+        assert_eq!(EndpointClass::classify(&Method::DELETE, "/folders/1"), EndpointClass::Write);
+        assert_eq!(EndpointClass::classify(&Method::POST, "/export/csv"), EndpointClass::Export);
+        assert_eq!(EndpointClass::classify(&Method::POST, "/audit/export"), EndpointClass::Export);
+        assert_eq!(EndpointClass::classify(&Method::GET, "/audit/events"), EndpointClass::Read);
+    }
+
+    #[test]
+    fn test_quota_user_id_reads_access_tokens_only() {
+        std::env::set_var("JWT_SECRET", "test-jwt-secret");
+        let user_id = Uuid::new_v4();
+        let pair = crate::token_management::encode_enhanced_token_pair(user_id, "session", None, None).unwrap().tokens;
+        let with_token = |token: &str| {
+            actix_web::test::TestRequest::default()
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_http_request()
+        };
+
+        assert_eq!(quota_user_id(&with_token(&pair.access_token)), Some(user_id));
+        assert_eq!(quota_user_id(&with_token(&pair.refresh_token)), None);
+        assert_eq!(quota_user_id(&with_token("not-a-token")), None);
+        assert_eq!(quota_user_id(&actix_web::test::TestRequest::default().to_http_request()), None);
+    }
+
+    #[test]
+    fn test_per_ip_quota_and_window_expiry() {
+        let store = QuotaStore::new();
+        let config = config();
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            assert!(store.check(&config, EndpointClass::Write, Some("10.0.0.1"), None, now).is_ok());
+        }
+        let exceeded = store.check(&config, EndpointClass::Write, Some("10.0.0.1"), None, now).unwrap_err();
+        assert_eq!(exceeded.quota, "writes_per_ip");
+        assert_eq!(exceeded.limit, 2);
+
+        // Other classes and IPs are counted separately
+        assert!(store.check(&config, EndpointClass::Read, Some("10.0.0.1"), None, now).is_ok());
+        assert!(store.check(&config, EndpointClass::Write, Some("10.0.0.2"), None, now).is_ok());
+
+        // The window resets after a minute
+        assert!(store.check(&config, EndpointClass::Write, Some("10.0.0.1"), None, now + MINUTE).is_ok());
+    }
+
+    #[test]
+    fn test_daily_user_quota() {
+        let store = QuotaStore::new();
+        let config = config();
+        let now = Instant::now();
+
+        for i in 0..4 {
+            // Spread requests across minutes so only the daily quota applies
+            let at = now + MINUTE * i;
+            assert!(store.check(&config, EndpointClass::Read, None, Some("user"), at).is_ok());
+        }
+        let exceeded = store.check(&config, EndpointClass::Read, None, Some("user"), now + MINUTE * 5).unwrap_err();
+        assert_eq!(exceeded.quota, "daily_per_user");
+        assert_eq!(exceeded.window_seconds, DAY.as_secs());
+    }
+
+    #[test]
+    fn test_refused_requests_are_not_counted() {
+        let store = QuotaStore::new();
+        let config = config();
+        let now = Instant::now();
+
+        for i in 0..4 {
+            assert!(store.check(&config, EndpointClass::Read, None, Some("user"), now + MINUTE * i).is_ok());
+        }
+
+        // Refused by the daily quota, so the IP's read quota is left untouched
+        let at = now + MINUTE * 5;
+        for _ in 0..5 {
+            let exceeded = store.check(&config, EndpointClass::Read, Some("10.0.0.1"), Some("user"), at).unwrap_err();
+            assert_eq!(exceeded.quota, "daily_per_user");
+        }
+        for _ in 0..3 {
+            assert!(store.check(&config, EndpointClass::Read, Some("10.0.0.1"), None, at).is_ok());
+        }
+        assert_eq!(store.check(&config, EndpointClass::Read, Some("10.0.0.1"), None, at).unwrap_err().quota, "reads_per_ip");
+    }
+}
@@ -0,0 +1,462 @@
+//! Rekey module for rotating the data-encryption key
+//!
+//! Every encrypted column is decrypted with the old key and re-encrypted with the new one in
+//! batches. Each batch commits together with a cursor in `rekey_progress`, so an interrupted
+//! run resumes after the last committed batch. Values that already decrypt under the new key
+//! are left alone, which makes re-running a finished or partial rekey safe.
+
+use crate::crypto;
+use crate::key_management;
+use diesel::prelude::*;
+use diesel::PgConnection;
+use ring::aead;
+use serde::Serialize;
+use uuid::Uuid;
+
+const BATCH_SIZE: i64 = 500;
+/// Attachments are up to megabytes each, so they are moved a few at a time
+const ATTACHMENT_BATCH_SIZE: i64 = 20;
+const MAX_REPORTED_FAILURES: usize = 20;
+
+/// An encryption key together with a fingerprint identifying it in progress records
+pub struct RekeyKey {
+    key: aead::LessSafeKey,
+    fingerprint: String,
+}
+
+impl RekeyKey {
+    /// Loads the key held in the named environment variable
+    pub fn from_env_var(var_name: &str) -> Result<Self, String> {
+        key_management::key_from_env_var(var_name).map(Self::from_key)
+    }
+
+    fn from_key(key: aead::LessSafeKey) -> Self {
+        let fingerprint = key_fingerprint(&key);
+        Self { key, fingerprint }
+    }
+
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+}
+
+/// Key check value: the tag of an empty message sealed under a fixed zero nonce
+///
+/// The fixed nonce is only ever used for this one constant message, so it reveals nothing
+/// beyond whether two keys are equal.
+fn key_fingerprint(key: &aead::LessSafeKey) -> String {
+    let nonce = aead::Nonce::assume_unique_for_key([0u8; 12]);
+    let mut empty = Vec::new();
+    match key.seal_in_place_append_tag(nonce, aead::Aad::from(b"passq-rekey-fingerprint"), &mut empty) {
+        Ok(()) => hex::encode(&empty[..8]),
+        Err(_) => String::new(),
+    }
+}
+
+/// Outcome of moving one stored value to the new key
+#[derive(Debug, PartialEq)]
+enum Rekeyed {
+    Reencrypted(Vec<u8>),
+    AlreadyRekeyed,
+}
+
+fn rekey_value(ciphertext: &[u8], old: &aead::LessSafeKey, new: &aead::LessSafeKey) -> Result<Rekeyed, String> {
+    match crypto::decrypt(ciphertext.to_vec(), old) {
+        Ok(plaintext) => crypto::encrypt(plaintext, new).map(Rekeyed::Reencrypted),
+        // Left over from an interrupted batch or written by a server already on the new key
+        Err(_) if crypto::decrypt(ciphertext.to_vec(), new).is_ok() => Ok(Rekeyed::AlreadyRekeyed),
+        Err(e) => Err(e),
+    }
+}
+
+/// A row's encrypted values, in the order of its table's `columns`
+struct EncryptedRow {
+    id: Uuid,
+    values: Vec<Option<Vec<u8>>>,
+}
+
+/// A table holding values encrypted with the data key
+struct EncryptedTable {
+    name: &'static str,
+    columns: &'static [&'static str],
+    load_batch: fn(&mut PgConnection, Uuid) -> QueryResult<Vec<EncryptedRow>>,
+    store: fn(&mut PgConnection, &EncryptedRow) -> QueryResult<usize>,
+}
+
+const ENCRYPTED_TABLES: &[EncryptedTable] = &[
+    EncryptedTable {
+        name: "passwords",
+        columns: &["encrypted_password", "encrypted_website", "encrypted_username"],
+        load_batch: load_passwords_batch,
+        store: store_password,
+    },
+    EncryptedTable {
+        name: "password_history",
+        columns: &["encrypted_password"],
+        load_batch: load_password_history_batch,
+        store: store_password_history,
+    },
+    EncryptedTable {
+        name: "password_attachments",
+        columns: &["encrypted_content"],
+        load_batch: load_password_attachments_batch,
+        store: store_password_attachment,
+    },
+];
+
+/// Loads the next batch of passwords with ids above the cursor, locked for update
+fn load_passwords_batch(conn: &mut PgConnection, after: Uuid) -> QueryResult<Vec<EncryptedRow>> {
+    use crate::schema::passwords;
+
+    let rows = passwords::table
+        .filter(passwords::id.gt(after))
+        .order(passwords::id.asc())
+        .limit(BATCH_SIZE)
+        .select((
+            passwords::id,
+            passwords::is_client_encrypted,
+            passwords::encrypted_password,
+            passwords::encrypted_website,
+            passwords::encrypted_username,
+        ))
+        .for_update()
+        .load::<(Uuid, bool, Vec<u8>, Option<Vec<u8>>, Option<Vec<u8>>)>(conn)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(id, client_encrypted, password, website, username)| password_row(id, client_encrypted, password, website, username))
+        .collect())
+}
+
+/// A client-encrypted blob is under the client's key, not the data key, so it is left out and
+/// only the server-encrypted website and username are moved
+fn password_row(id: Uuid, client_encrypted: bool, password: Vec<u8>, website: Option<Vec<u8>>, username: Option<Vec<u8>>) -> EncryptedRow {
+    let password = if client_encrypted { None } else { Some(password) };
+    EncryptedRow { id, values: vec![password, website, username] }
+}
+
+fn store_password(conn: &mut PgConnection, row: &EncryptedRow) -> QueryResult<usize> {
+    use crate::schema::passwords;
+
+    let target = passwords::table.find(row.id);
+    let website = passwords::encrypted_website.eq(row.values[1].clone());
+    let username = passwords::encrypted_username.eq(row.values[2].clone());
+    match &row.values[0] {
+        Some(password) => diesel::update(target)
+            .set((passwords::encrypted_password.eq(password), website, username))
+            .execute(conn),
+        None => diesel::update(target).set((website, username)).execute(conn),
+    }
+}
+
+/// Loads the next batch of password history entries with ids above the cursor, locked for update
+fn load_password_history_batch(conn: &mut PgConnection, after: Uuid) -> QueryResult<Vec<EncryptedRow>> {
+    use crate::schema::password_history;
+
+    let rows = password_history::table
+        .filter(password_history::id.gt(after))
+        .order(password_history::id.asc())
+        .limit(BATCH_SIZE)
+        .select((password_history::id, password_history::encrypted_password))
+        .for_update()
+        .load::<(Uuid, Vec<u8>)>(conn)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(id, password)| EncryptedRow { id, values: vec![Some(password)] })
+        .collect())
+}
+
+fn store_password_history(conn: &mut PgConnection, row: &EncryptedRow) -> QueryResult<usize> {
+    use crate::schema::password_history;
+
+    diesel::update(password_history::table.find(row.id))
+        .set(password_history::encrypted_password.eq(row.values[0].clone().unwrap_or_default()))
+        .execute(conn)
+}
+
+/// Loads the next batch of attachments with ids above the cursor, locked for update
+fn load_password_attachments_batch(conn: &mut PgConnection, after: Uuid) -> QueryResult<Vec<EncryptedRow>> {
+    use crate::schema::password_attachments;
+
+    let rows = password_attachments::table
+        .filter(password_attachments::id.gt(after))
+        .order(password_attachments::id.asc())
+        .limit(ATTACHMENT_BATCH_SIZE)
+        .select((password_attachments::id, password_attachments::encrypted_content))
+        .for_update()
+        .load::<(Uuid, Vec<u8>)>(conn)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(id, content)| EncryptedRow { id, values: vec![Some(content)] })
+        .collect())
+}
+
+fn store_password_attachment(conn: &mut PgConnection, row: &EncryptedRow) -> QueryResult<usize> {
+    use crate::schema::password_attachments;
+
+    diesel::update(password_attachments::table.find(row.id))
+        .set(password_attachments::encrypted_content.eq(row.values[0].clone().unwrap_or_default()))
+        .execute(conn)
+}
+
+/// Per-table counts from a rekey or dry run
+#[derive(Serialize, Debug, Default)]
+pub struct TableRekeyReport {
+    pub table: String,
+    pub rows_checked: i64,
+    pub values_rekeyed: i64,
+    pub values_already_rekeyed: i64,
+    pub values_failed: i64,
+    pub resumed_after: Option<Uuid>,
+}
+
+/// A stored value that could not be decrypted
+#[derive(Serialize, Debug)]
+pub struct RekeyFailure {
+    pub table: String,
+    pub row_id: Uuid,
+    pub column: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RekeyReport {
+    pub dry_run: bool,
+    pub completed: bool,
+    pub tables: Vec<TableRekeyReport>,
+    pub failures: Vec<RekeyFailure>,
+}
+
+/// Checks that every encrypted value decrypts under the given key, without writing anything
+pub fn verify_all(conn: &mut PgConnection, key: &RekeyKey) -> QueryResult<RekeyReport> {
+    let mut report = RekeyReport { dry_run: true, completed: true, tables: Vec::new(), failures: Vec::new() };
+
+    for table in ENCRYPTED_TABLES {
+        let mut table_report = TableRekeyReport { table: table.name.to_string(), ..Default::default() };
+        let mut cursor = Uuid::nil();
+
+        loop {
+            let batch = conn.transaction(|conn| (table.load_batch)(conn, cursor))?;
+            let Some(last) = batch.last() else { break };
+            cursor = last.id;
+
+            for row in &batch {
+                table_report.rows_checked += 1;
+                for (column, value) in table.columns.iter().zip(&row.values) {
+                    let Some(value) = value else { continue };
+                    if let Err(error) = crypto::decrypt(value.clone(), &key.key) {
+                        table_report.values_failed += 1;
+                        report.completed = false;
+                        if report.failures.len() < MAX_REPORTED_FAILURES {
+                            report.failures.push(RekeyFailure {
+                                table: table.name.to_string(),
+                                row_id: row.id,
+                                column: column.to_string(),
+                                error,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        report.tables.push(table_report);
+    }
+
+    Ok(report)
+}
+
+/// Re-encrypts every encrypted value from the old key to the new one, resuming an interrupted run
+///
+/// Stops at the first value that decrypts under neither key, leaving its batch uncommitted.
+pub fn rekey_all(conn: &mut PgConnection, old: &RekeyKey, new: &RekeyKey) -> QueryResult<RekeyReport> {
+    let mut report = RekeyReport { dry_run: false, completed: true, tables: Vec::new(), failures: Vec::new() };
+
+    for table in ENCRYPTED_TABLES {
+        let (table_report, failure) = rekey_table(conn, table, old, new)?;
+        report.tables.push(table_report);
+        if let Some(failure) = failure {
+            report.completed = false;
+            report.failures.push(failure);
+            break;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Moves a row's values to the new key in place, returning whether any of them changed
+fn rekey_row(
+    table: &EncryptedTable,
+    row: &mut EncryptedRow,
+    old: &aead::LessSafeKey,
+    new: &aead::LessSafeKey,
+    report: &mut TableRekeyReport,
+) -> Result<bool, RekeyFailure> {
+    report.rows_checked += 1;
+    let mut changed = false;
+    for (column, value) in table.columns.iter().zip(row.values.iter_mut()) {
+        let Some(ciphertext) = value else { continue };
+        match rekey_value(ciphertext, old, new) {
+            Ok(Rekeyed::Reencrypted(reencrypted)) => {
+                *ciphertext = reencrypted;
+                report.values_rekeyed += 1;
+                changed = true;
+            }
+            Ok(Rekeyed::AlreadyRekeyed) => report.values_already_rekeyed += 1,
+            Err(error) => {
+                return Err(RekeyFailure {
+                    table: table.name.to_string(),
+                    row_id: row.id,
+                    column: column.to_string(),
+                    error,
+                });
+            }
+        }
+    }
+    Ok(changed)
+}
+
+fn rekey_table(
+    conn: &mut PgConnection,
+    table: &EncryptedTable,
+    old: &RekeyKey,
+    new: &RekeyKey,
+) -> QueryResult<(TableRekeyReport, Option<RekeyFailure>)> {
+    use crate::schema::rekey_progress;
+
+    // Only resume a cursor recorded for the same target key
+    let resumed_after = rekey_progress::table
+        .find(table.name)
+        .filter(rekey_progress::key_fingerprint.eq(&new.fingerprint))
+        .select(rekey_progress::last_id)
+        .first::<Option<Uuid>>(conn)
+        .optional()?
+        .flatten();
+
+    let mut table_report = TableRekeyReport { table: table.name.to_string(), resumed_after, ..Default::default() };
+    let mut cursor = resumed_after.unwrap_or(Uuid::nil());
+
+    loop {
+        let mut batch_report = TableRekeyReport::default();
+        let outcome = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let mut batch = (table.load_batch)(conn, cursor)?;
+            let Some(last_id) = batch.last().map(|row| row.id) else { return Ok(None) };
+
+            for row in &mut batch {
+                match rekey_row(table, row, &old.key, &new.key, &mut batch_report) {
+                    Ok(true) => {
+                        (table.store)(conn, row)?;
+                    }
+                    Ok(false) => {}
+                    Err(failure) => return Ok(Some(Err(failure))),
+                }
+            }
+
+            let now = chrono::Utc::now().naive_utc();
+            diesel::insert_into(rekey_progress::table)
+                .values((
+                    rekey_progress::table_name.eq(table.name),
+                    rekey_progress::key_fingerprint.eq(&new.fingerprint),
+                    rekey_progress::last_id.eq(Some(last_id)),
+                    rekey_progress::rows_processed.eq(batch_report.rows_checked),
+                    rekey_progress::updated_at.eq(now),
+                ))
+                .on_conflict(rekey_progress::table_name)
+                .do_update()
+                .set((
+                    rekey_progress::key_fingerprint.eq(&new.fingerprint),
+                    rekey_progress::last_id.eq(Some(last_id)),
+                    rekey_progress::rows_processed.eq(rekey_progress::rows_processed + batch_report.rows_checked),
+                    rekey_progress::updated_at.eq(now),
+                ))
+                .execute(conn)?;
+
+            Ok(Some(Ok(last_id)))
+        });
+
+        // A failed batch rolled back, so only count committed ones
+        match outcome {
+            Ok(None) => break,
+            Ok(Some(Ok(last_id))) => {
+                cursor = last_id;
+                table_report.rows_checked += batch_report.rows_checked;
+                table_report.values_rekeyed += batch_report.values_rekeyed;
+                table_report.values_already_rekeyed += batch_report.values_already_rekeyed;
+            }
+            Ok(Some(Err(failure))) => {
+                table_report.values_failed += 1;
+                return Ok((table_report, Some(failure)));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Finished tables start from scratch next time; a full pass is safe since rekeyed values are skipped
+    diesel::delete(rekey_progress::table.find(table.name)).execute(conn)?;
+
+    Ok((table_report, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key(byte: u8) -> aead::LessSafeKey {
+        aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_256_GCM, &[byte; 32]).unwrap())
+    }
+
+    #[test]
+    fn test_rekey_value_moves_to_new_key() {
+        let (old, new, other) = (test_key(1), test_key(2), test_key(3));
+        let ciphertext = crypto::encrypt(b"hunter2".to_vec(), &old).unwrap();
+
+        let Rekeyed::Reencrypted(reencrypted) = rekey_value(&ciphertext, &old, &new).unwrap() else {
+            panic!("value under the old key should be re-encrypted");
+        };
+        assert_eq!(crypto::decrypt(reencrypted.clone(), &new).unwrap(), b"hunter2");
+        assert!(crypto::decrypt(reencrypted.clone(), &old).is_err());
+
+        // Running again over an already moved value leaves it alone
+        assert_eq!(rekey_value(&reencrypted, &old, &new).unwrap(), Rekeyed::AlreadyRekeyed);
+        assert!(rekey_value(&ciphertext, &other, &new).is_err());
+    }
+
+    #[test]
+    fn test_rekey_mixed_password_rows() {
+        let (old, new) = (test_key(1), test_key(2));
+        let seal = |value: &[u8]| crypto::encrypt(value.to_vec(), &old).unwrap();
+        let client_blob = b"client ciphertext, not under the data key".to_vec();
+
+        let mut server_row = password_row(Uuid::new_v4(), false, seal(b"hunter2"), Some(seal(b"example.com")), Some(seal(b"alice")));
+        let mut client_row = password_row(Uuid::new_v4(), true, client_blob, Some(seal(b"example.org")), Some(seal(b"bob")));
+        let mut bare_client_row = password_row(Uuid::new_v4(), true, b"blob".to_vec(), None, None);
+
+        let mut report = TableRekeyReport::default();
+        let passwords = &ENCRYPTED_TABLES[0];
+        assert!(rekey_row(passwords, &mut server_row, &old, &new, &mut report).unwrap());
+        assert!(rekey_row(passwords, &mut client_row, &old, &new, &mut report).unwrap());
+        assert!(!rekey_row(passwords, &mut bare_client_row, &old, &new, &mut report).unwrap());
+
+        let open = |value: &Option<Vec<u8>>| crypto::decrypt(value.clone().unwrap(), &new).unwrap();
+        assert_eq!(open(&server_row.values[0]), b"hunter2");
+        assert_eq!(open(&server_row.values[1]), b"example.com");
+        // The client blob is never loaded, so it can't be rewritten; its server-side columns move
+        assert_eq!(client_row.values[0], None);
+        assert_eq!(open(&client_row.values[1]), b"example.org");
+        assert_eq!(open(&client_row.values[2]), b"bob");
+        assert_eq!(bare_client_row.values, vec![None, None, None]);
+
+        assert_eq!(report.rows_checked, 3);
+        assert_eq!(report.values_rekeyed, 5);
+    }
+
+    #[test]
+    fn test_key_fingerprint() {
+        assert_eq!(RekeyKey::from_key(test_key(1)).fingerprint(), RekeyKey::from_key(test_key(1)).fingerprint());
+        assert_ne!(RekeyKey::from_key(test_key(1)).fingerprint(), RekeyKey::from_key(test_key(2)).fingerprint());
+        assert_eq!(RekeyKey::from_key(test_key(1)).fingerprint().len(), 16);
+    }
+}
@@ -0,0 +1,171 @@
+//! Request id module for correlating a request across logs
+//!
+//! Every request gets an id: the caller's `X-Request-Id` if it is a plausible id, otherwise a
+//! fresh UUID. The id is stored in the request extensions, echoed in the response header, and
+//! attached to the access log, audit entries and server error logs of the request.
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use uuid::Uuid;
+
+/// Header the id is read from and echoed in
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest caller-supplied id that is kept
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The id of the current request
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestId(HeaderValue);
+
+impl RequestId {
+    /// Keeps the caller's id if it is short and made of id characters, so it can't forge log lines
+    pub fn from_header(value: Option<&HeaderValue>) -> Self {
+        match value {
+            Some(value) if is_valid_id(value.as_bytes()) => Self(value.clone()),
+            _ => Self::generate(),
+        }
+    }
+
+    fn generate() -> Self {
+        let mut buf = [0u8; uuid::fmt::Hyphenated::LENGTH];
+        let id = Uuid::new_v4().hyphenated().encode_lower(&mut buf);
+        Self(HeaderValue::from_str(id).expect("a UUID is a valid header value"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever holds ASCII, see is_valid_id
+        self.0.to_str().unwrap_or_default()
+    }
+}
+
+fn is_valid_id(id: &[u8]) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.iter().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+}
+
+/// The id the middleware assigned to a request, if it ran
+pub fn of(req: &HttpRequest) -> Option<String> {
+    req.extensions().get::<RequestId>().map(|id| id.as_str().to_string())
+}
+
+/// Middleware assigning request ids
+pub struct RequestIdMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIdMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestIdMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let request_id = RequestId::from_header(req.headers().get(&REQUEST_ID_HEADER));
+        req.extensions_mut().insert(request_id.clone());
+
+        Box::pin(async move {
+            let mut res = match service.call(req).await {
+                Ok(res) => res,
+                Err(e) => {
+                    log::error!(request_id = request_id.as_str(); "Request failed: {}", e);
+                    return Err(e);
+                }
+            };
+
+            if res.status().is_server_error() {
+                log::error!(
+                    request_id = request_id.as_str();
+                    "{} {} failed with {} (request {})",
+                    res.request().method(), res.request().path(), res.status(), request_id.as_str()
+                );
+            }
+            res.headers_mut().insert(REQUEST_ID_HEADER, request_id.0);
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test as actix_test, web, App, HttpResponse};
+
+    async fn echo_request_id(req: HttpRequest) -> HttpResponse {
+        HttpResponse::Ok().body(of(&req).unwrap_or_default())
+    }
+
+    async fn call(req: actix_test::TestRequest) -> (Option<String>, String) {
+        let app = actix_test::init_service(
+            App::new()
+                .wrap(RequestIdMiddleware)
+                .route("/", web::get().to(echo_request_id)),
+        )
+        .await;
+        let res = actix_test::call_service(&app, req.to_request()).await;
+        let header = res.headers().get(&REQUEST_ID_HEADER).map(|v| v.to_str().unwrap().to_string());
+        let body = String::from_utf8(actix_test::read_body(res).await.to_vec()).unwrap();
+        (header, body)
+    }
+
+    #[test]
+    fn test_is_valid_id() {
+        assert!(is_valid_id(b"7f9c2ba4-e88f-4c3a-9d2e-0b1c7a6d5e4f"));
+        assert!(is_valid_id(b"trace:abc_123.4"));
+        assert!(!is_valid_id(b""));
+        assert!(!is_valid_id(b"id with spaces"));
+        assert!(!is_valid_id(b"id\"}{\"level\":\"ERROR"));
+        assert!(!is_valid_id("x".repeat(MAX_REQUEST_ID_LEN + 1).as_bytes()));
+    }
+
+    #[actix_web::test]
+    async fn test_incoming_id_is_kept() {
+        let (header, body) = call(actix_test::TestRequest::get().uri("/").insert_header(("X-Request-Id", "support-ticket-42"))).await;
+        assert_eq!(header.as_deref(), Some("support-ticket-42"));
+        assert_eq!(body, "support-ticket-42");
+    }
+
+    #[actix_web::test]
+    async fn test_missing_or_invalid_id_is_generated() {
+        let (header, body) = call(actix_test::TestRequest::get().uri("/")).await;
+        let header = header.unwrap();
+        assert!(Uuid::parse_str(&header).is_ok());
+        assert_eq!(body, header);
+
+        let (header, _) = call(actix_test::TestRequest::get().uri("/").insert_header(("X-Request-Id", "not valid"))).await;
+        assert!(Uuid::parse_str(&header.unwrap()).is_ok());
+    }
+}
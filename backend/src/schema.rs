@@ -11,6 +11,18 @@ diesel::table! {
         details -> Nullable<Text>,
         timestamp -> Timestamp,
         integrity_hash -> Varchar,
+        sequence -> Int8,
+        chain_hash -> Nullable<Varchar>,
+        request_id -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    failed_login_attempts (id) {
+        id -> Uuid,
+        ip_address -> Nullable<Varchar>,
+        attempted_at -> Timestamp,
+        user_id -> Uuid,
     }
 }
 
@@ -23,6 +35,34 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    group_members (group_id, user_id) {
+        group_id -> Uuid,
+        user_id -> Uuid,
+        added_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    groups (id) {
+        id -> Uuid,
+        owner_id -> Uuid,
+        name -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    login_challenges (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        code_hash -> Varchar,
+        attempts -> Int4,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     login_history (id) {
         id -> Uuid,
@@ -47,6 +87,38 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    mfa_recovery_codes (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    password_attachments (id) {
+        id -> Uuid,
+        password_id -> Uuid,
+        user_id -> Uuid,
+        filename -> Varchar,
+        content_type -> Varchar,
+        size_bytes -> Int8,
+        encrypted_content -> Bytea,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    password_history (id) {
+        id -> Uuid,
+        password_id -> Uuid,
+        encrypted_password -> Bytea,
+        changed_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     passwords (id) {
         id -> Uuid,
@@ -60,6 +132,21 @@ diesel::table! {
         attachments -> Nullable<Jsonb>,
         encrypted_website -> Nullable<Bytea>,
         encrypted_username -> Nullable<Bytea>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        is_client_encrypted -> Bool,
+        deleted_at -> Nullable<Timestamp>,
+        is_favorite -> Bool,
+    }
+}
+
+diesel::table! {
+    rekey_progress (table_name) {
+        table_name -> Varchar,
+        key_fingerprint -> Varchar,
+        last_id -> Nullable<Uuid>,
+        rows_processed -> Int8,
+        updated_at -> Timestamp,
     }
 }
 
@@ -69,10 +156,11 @@ diesel::table! {
         password_id -> Nullable<Uuid>,
         folder_id -> Nullable<Uuid>,
         user_id -> Uuid,
-        shared_with_user_id -> Uuid,
+        shared_with_user_id -> Nullable<Uuid>,
         permission_level -> Varchar,
         expires_at -> Nullable<Timestamp>,
         created_at -> Timestamp,
+        group_id -> Nullable<Uuid>,
     }
 }
 
@@ -90,16 +178,59 @@ diesel::table! {
         is_sso_user -> Nullable<Bool>,
         sso_display_name -> Nullable<Varchar>,
         sso_avatar_url -> Nullable<Varchar>,
+        is_admin -> Bool,
+        mfa_pending_secret -> Nullable<Varchar>,
+        new_device_alerts -> Bool,
+        share_notifications -> Bool,
+        reset_requested_at -> Nullable<Timestamp>,
+        mfa_last_totp_step -> Nullable<Int8>,
+        pending_email -> Nullable<Varchar>,
+        email_change_token -> Nullable<Varchar>,
+        email_change_expires_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    webauthn_ceremonies (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        kind -> Varchar,
+        state -> Text,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webauthn_credentials (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        credential_id -> Varchar,
+        passkey -> Text,
+        name -> Varchar,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
     }
 }
 
 diesel::joinable!(audit_logs -> users (user_id));
 diesel::joinable!(folders -> users (user_id));
+diesel::joinable!(group_members -> groups (group_id));
+diesel::joinable!(group_members -> users (user_id));
+diesel::joinable!(groups -> users (owner_id));
+diesel::joinable!(login_challenges -> users (user_id));
 diesel::joinable!(login_history -> users (user_id));
+diesel::joinable!(mfa_recovery_codes -> users (user_id));
+diesel::joinable!(password_attachments -> passwords (password_id));
+diesel::joinable!(password_attachments -> users (user_id));
+diesel::joinable!(password_history -> passwords (password_id));
 diesel::joinable!(passwords -> folders (folder_id));
 diesel::joinable!(passwords -> users (user_id));
 diesel::joinable!(shares -> folders (folder_id));
+diesel::joinable!(shares -> groups (group_id));
 diesel::joinable!(shares -> passwords (password_id));
+diesel::joinable!(webauthn_ceremonies -> users (user_id));
+diesel::joinable!(webauthn_credentials -> users (user_id));
 
 diesel::table! {
     oauth_accounts (id) {
@@ -234,6 +365,8 @@ diesel::table! {
         notes -> Nullable<Text>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        trust_token -> Nullable<Varchar>,
+        trust_token_expires_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -265,10 +398,18 @@ diesel::joinable!(trusted_devices -> users (user_id));
 diesel::allow_tables_to_appear_in_same_query!(
     active_sessions,
     audit_logs,
+    failed_login_attempts,
     folders,
+    group_members,
+    groups,
+    login_challenges,
     login_history,
+    mfa_recovery_codes,
     oauth_accounts,
+    password_attachments,
+    password_history,
     passwords,
+    rekey_progress,
     revoked_tokens,
     session_limits,
     session_monitoring_rules,
@@ -277,4 +418,6 @@ diesel::allow_tables_to_appear_in_same_query!(
     token_analytics,
     trusted_devices,
     users,
+    webauthn_ceremonies,
+    webauthn_credentials,
 );
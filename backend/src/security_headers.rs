@@ -0,0 +1,118 @@
+//! Security headers module for the policies the CSP middleware adds to every response
+//!
+//! The API renders no HTML, so the default Content-Security-Policy allows no inline scripts or
+//! styles. Operators serving a front end through the backend can loosen the script and style
+//! sources, or replace the policy entirely. HSTS is opt-in so plain HTTP development keeps working.
+
+use actix_web::http::header::HeaderValue;
+use std::env;
+
+/// Script sources when CSP_SCRIPT_SRC isn't set
+pub const DEFAULT_SCRIPT_SRC: &str = "'self'";
+/// Style sources when CSP_STYLE_SRC isn't set
+pub const DEFAULT_STYLE_SRC: &str = "'self'";
+
+/// Browser features no PassQ page needs
+pub const PERMISSIONS_POLICY: &str = "accelerometer=(), camera=(), geolocation=(), gyroscope=(), magnetometer=(), microphone=(), payment=(), usb=()";
+
+/// Header values shared by every worker
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    pub content_security_policy: HeaderValue,
+    /// Strict-Transport-Security for HTTPS responses, None unless HSTS_MAX_AGE_SECS is set
+    pub strict_transport_security: Option<HeaderValue>,
+}
+
+impl SecurityHeaders {
+    /// Loads CONTENT_SECURITY_POLICY (or CSP_SCRIPT_SRC and CSP_STYLE_SRC for the default policy),
+    /// HSTS_MAX_AGE_SECS and HSTS_INCLUDE_SUBDOMAINS
+    pub fn from_env() -> Self {
+        let policy = match env::var("CONTENT_SECURITY_POLICY") {
+            Ok(policy) => policy,
+            Err(_) => build_csp(
+                &directive_sources("CSP_SCRIPT_SRC", env::var("CSP_SCRIPT_SRC").ok().as_deref(), DEFAULT_SCRIPT_SRC),
+                &directive_sources("CSP_STYLE_SRC", env::var("CSP_STYLE_SRC").ok().as_deref(), DEFAULT_STYLE_SRC),
+            ),
+        };
+
+        let content_security_policy = HeaderValue::from_str(policy.trim()).unwrap_or_else(|_| {
+            log::warn!("Invalid Content-Security-Policy, using the default policy");
+            HeaderValue::from_str(&build_csp(DEFAULT_SCRIPT_SRC, DEFAULT_STYLE_SRC)).expect("default policy is a valid header")
+        });
+
+        let include_subdomains = env::var("HSTS_INCLUDE_SUBDOMAINS").map(|v| v != "false").unwrap_or(true);
+        let strict_transport_security = hsts_value(env::var("HSTS_MAX_AGE_SECS").ok().as_deref(), include_subdomains)
+            .map(|value| HeaderValue::from_str(&value).expect("HSTS value is a valid header"));
+
+        Self { content_security_policy, strict_transport_security }
+    }
+}
+
+/// Strict-Transport-Security value for the configured max-age, None when HSTS is off
+fn hsts_value(max_age: Option<&str>, include_subdomains: bool) -> Option<String> {
+    let max_age = match max_age.map(|v| v.trim().parse::<u64>()) {
+        Some(Ok(secs)) => secs,
+        Some(Err(_)) => {
+            log::warn!("Invalid HSTS_MAX_AGE_SECS, HSTS stays disabled");
+            return None;
+        }
+        None => return None,
+    };
+
+    if include_subdomains {
+        Some(format!("max-age={}; includeSubDomains", max_age))
+    } else {
+        Some(format!("max-age={}", max_age))
+    }
+}
+
+/// Builds the default policy around the given script and style sources
+fn build_csp(script_src: &str, style_src: &str) -> String {
+    format!(
+        "default-src 'self'; script-src {}; style-src {}; img-src 'self' data: https:; font-src 'self'; connect-src 'self'; object-src 'none'; frame-ancestors 'none'; base-uri 'self'; form-action 'self'",
+        script_src, style_src
+    )
+}
+
+/// Sources for one directive; a value that would end the directive early falls back to the default
+fn directive_sources(name: &str, value: Option<&str>, default: &str) -> String {
+    match value.map(str::trim) {
+        Some(sources) if !sources.is_empty() && !sources.contains([';', ',']) => sources.to_string(),
+        Some(_) => {
+            log::warn!("Invalid {}, using {}", name, default);
+            default.to_string()
+        }
+        None => default.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy_has_no_unsafe_inline() {
+        let policy = build_csp(DEFAULT_SCRIPT_SRC, DEFAULT_STYLE_SRC);
+        assert!(!policy.contains("unsafe-inline"));
+        assert!(policy.contains("script-src 'self';"));
+        assert!(policy.contains("frame-ancestors 'none'"));
+    }
+
+    #[test]
+    fn test_hsts_value() {
+        assert_eq!(hsts_value(None, true), None);
+        assert_eq!(hsts_value(Some("soon"), true), None);
+        assert_eq!(hsts_value(Some("31536000"), true).as_deref(), Some("max-age=31536000; includeSubDomains"));
+        assert_eq!(hsts_value(Some(" 300 "), false).as_deref(), Some("max-age=300"));
+        // max-age=0 is how a site tells browsers to forget an earlier policy
+        assert_eq!(hsts_value(Some("0"), false).as_deref(), Some("max-age=0"));
+    }
+
+    #[test]
+    fn test_directive_sources() {
+        assert_eq!(directive_sources("TEST", None, "'self'"), "'self'");
+        assert_eq!(directive_sources("TEST", Some(" 'self' 'sha256-abc=' "), "'self'"), "'self' 'sha256-abc='");
+        assert_eq!(directive_sources("TEST", Some(""), "'self'"), "'self'");
+        assert_eq!(directive_sources("TEST", Some("'self'; script-src *"), "'self'"), "'self'");
+    }
+}
@@ -0,0 +1,434 @@
+//! Sharing module for resolving what a user may do with shared items
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::folder_tree::{self, ParentMap};
+use crate::models::Share;
+
+/// Access a user has to a password, ordered from weakest to strongest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PasswordAccess {
+    None,
+    Read,
+    Write,
+    Owner,
+}
+
+/// Permission a share grants to its recipient
+///
+/// Older clients sent "view" and "edit", which are still accepted as aliases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PermissionLevel {
+    #[serde(alias = "view")]
+    Read,
+    #[serde(alias = "edit")]
+    Write,
+}
+
+impl PermissionLevel {
+    /// The value stored in `shares.permission_level`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PermissionLevel::Read => "read",
+            PermissionLevel::Write => "write",
+        }
+    }
+
+    /// Reads a stored permission level, including the older spellings
+    pub fn from_stored(permission_level: &str) -> Option<Self> {
+        match permission_level {
+            "read" | "view" => Some(PermissionLevel::Read),
+            "write" | "edit" => Some(PermissionLevel::Write),
+            _ => None,
+        }
+    }
+}
+
+/// Returns whether a stored permission level allows editing
+pub fn allows_write(permission_level: &str) -> bool {
+    PermissionLevel::from_stored(permission_level) == Some(PermissionLevel::Write)
+}
+
+/// Returns whether a share with this expiry still grants access; it lapses at the expiry instant
+pub fn share_is_active(expires_at: Option<NaiveDateTime>, now: NaiveDateTime) -> bool {
+    expires_at.is_none_or(|expires_at| expires_at > now)
+}
+
+/// Returns the strongest access granted by a set of `(permission_level, expires_at)` shares
+pub fn access_from_shares(shares: &[(String, Option<NaiveDateTime>)], now: NaiveDateTime) -> PasswordAccess {
+    shares
+        .iter()
+        .filter(|(_, expires_at)| share_is_active(*expires_at, now))
+        .map(|(permission_level, _)| {
+            if allows_write(permission_level) {
+                PasswordAccess::Write
+            } else {
+                PasswordAccess::Read
+            }
+        })
+        .max()
+        .unwrap_or(PasswordAccess::None)
+}
+
+/// Longest a share can be set to last
+pub const MAX_EXPIRATION_DAYS: i32 = 3650;
+
+/// When a share given `days` from `now` lapses
+pub fn expiry_from_days(days: i32, now: NaiveDateTime) -> Result<NaiveDateTime, String> {
+    if !(1..=MAX_EXPIRATION_DAYS).contains(&days) {
+        return Err(format!("expiration_days must be between 1 and {}", MAX_EXPIRATION_DAYS));
+    }
+    Ok(now + Duration::days(days as i64))
+}
+
+/// New permission and expiry for a share; fields left `None` are unchanged
+#[derive(AsChangeset, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::shares)]
+pub struct ShareChanges {
+    pub permission_level: Option<String>,
+    /// `Some(None)` removes the expiry
+    pub expires_at: Option<Option<NaiveDateTime>>,
+}
+
+/// Why a share can't be changed
+#[derive(Debug, PartialEq)]
+pub enum ShareUpdateError {
+    /// The user didn't create the share, or it has already lapsed
+    NotFound,
+    Invalid(String),
+}
+
+/// Works out the changes to a share requested by `user_id`
+///
+/// Only the user who created the share may change it; recipients can only remove shares they
+/// received. A new expiry counts from `now`, and `Some(None)` for `expiration_days` makes the
+/// share permanent.
+pub fn plan_share_update(
+    share: &Share,
+    user_id: Uuid,
+    permission_level: Option<PermissionLevel>,
+    expiration_days: Option<Option<i32>>,
+    now: NaiveDateTime,
+) -> Result<ShareChanges, ShareUpdateError> {
+    if share.user_id != user_id || !share_is_active(share.expires_at, now) {
+        return Err(ShareUpdateError::NotFound);
+    }
+    if permission_level.is_none() && expiration_days.is_none() {
+        return Err(ShareUpdateError::Invalid("Provide permission_level and/or expiration_days".to_string()));
+    }
+
+    let expires_at = match expiration_days {
+        Some(Some(days)) => Some(Some(expiry_from_days(days, now).map_err(ShareUpdateError::Invalid)?)),
+        Some(None) => Some(None),
+        None => None,
+    };
+
+    Ok(ShareChanges {
+        permission_level: permission_level.map(|level| level.as_str().to_string()),
+        expires_at,
+    })
+}
+
+/// Gets the ids of all groups the user is currently a member of
+pub fn user_group_ids(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<Vec<Uuid>> {
+    use crate::schema::group_members;
+
+    group_members::table
+        .filter(group_members::user_id.eq(user_id))
+        .select(group_members::group_id)
+        .load::<Uuid>(conn)
+}
+
+/// Strongest access the user's active shares grant, directly or through a group
+///
+/// A share counts if it is on the password or on any of `folder_ids`, so callers pass the
+/// folder holding the item together with all of its ancestors.
+fn access_through_shares(conn: &mut PgConnection, user_id: Uuid, password_id: Option<Uuid>, folder_ids: &[Uuid]) -> QueryResult<PasswordAccess> {
+    use crate::schema::shares;
+
+    let group_ids = user_group_ids(conn, user_id)?;
+    let query = shares::table
+        .filter(shares::shared_with_user_id.eq(user_id).or(shares::group_id.eq_any(&group_ids)))
+        .into_boxed();
+    let query = match password_id {
+        Some(password_id) => query.filter(shares::password_id.eq(password_id).or(shares::folder_id.eq_any(folder_ids))),
+        None => query.filter(shares::folder_id.eq_any(folder_ids)),
+    };
+    let found = query
+        .select((shares::permission_level, shares::expires_at))
+        .load::<(String, Option<NaiveDateTime>)>(conn)?;
+
+    Ok(access_from_shares(&found, chrono::Utc::now().naive_utc()))
+}
+
+/// Resolves whether the user owns a password or holds an active share on it
+///
+/// Sharing a folder shares everything in it, so shares on the password's folder and that
+/// folder's ancestors count too. Passwords in the trash can't be reached by anyone until they
+/// are restored.
+pub fn resolve_password_access(conn: &mut PgConnection, user_id: Uuid, password_id: Uuid) -> QueryResult<PasswordAccess> {
+    use crate::schema::passwords;
+
+    let password = passwords::table
+        .filter(passwords::id.eq(password_id))
+        .filter(passwords::deleted_at.is_null())
+        .select((passwords::user_id, passwords::folder_id))
+        .first::<(Uuid, Option<Uuid>)>(conn)
+        .optional()?;
+    let (owner_id, folder_id) = match password {
+        None => return Ok(PasswordAccess::None),
+        Some((owner_id, _)) if owner_id == user_id => return Ok(PasswordAccess::Owner),
+        Some(password) => password,
+    };
+
+    let folder_ids = match folder_id {
+        Some(folder_id) => folder_tree::ancestor_ids(folder_id, &folder_tree::load_parent_map(conn, owner_id)?),
+        None => Vec::new(),
+    };
+    access_through_shares(conn, user_id, Some(password_id), &folder_ids)
+}
+
+/// A folder as seen by a user it may have been shared with
+pub struct FolderAccess {
+    pub owner_id: Uuid,
+    pub access: PasswordAccess,
+    /// The owner's folder tree, for walking the folder's contents
+    pub parents: ParentMap,
+}
+
+/// Resolves whether the user owns a folder or holds an active share on it or one of its ancestors
+pub fn resolve_folder_access(conn: &mut PgConnection, user_id: Uuid, folder_id: Uuid) -> QueryResult<Option<FolderAccess>> {
+    use crate::schema::folders;
+
+    let owner_id = folders::table
+        .filter(folders::id.eq(folder_id))
+        .select(folders::user_id)
+        .first::<Uuid>(conn)
+        .optional()?;
+    let Some(owner_id) = owner_id else { return Ok(None) };
+
+    let parents = folder_tree::load_parent_map(conn, owner_id)?;
+    let access = if owner_id == user_id {
+        PasswordAccess::Owner
+    } else {
+        access_through_shares(conn, user_id, None, &folder_tree::ancestor_ids(folder_id, &parents))?
+    };
+    Ok(Some(FolderAccess { owner_id, access, parents }))
+}
+
+/// Where a recipient's existing access to an item comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExistingAccess {
+    /// An active share of the item itself
+    Direct,
+    /// An active share of a folder containing the item, granting at least the requested permission
+    Folder,
+}
+
+/// Whether a new share would duplicate access the recipient already has
+///
+/// `shares` are the recipient's `(is_direct, permission_level, expires_at)` shares of the item and
+/// the folders containing it. A folder share with weaker permission doesn't count, so an item in a
+/// read-only shared folder can still be shared writable.
+pub fn existing_access(
+    shares: &[(bool, String, Option<NaiveDateTime>)],
+    requested: PermissionLevel,
+    now: NaiveDateTime,
+) -> Option<ExistingAccess> {
+    let active = || shares.iter().filter(|(_, _, expires_at)| share_is_active(*expires_at, now));
+    if active().any(|(is_direct, _, _)| *is_direct) {
+        return Some(ExistingAccess::Direct);
+    }
+    let covered = active().any(|(_, permission_level, _)| {
+        requested == PermissionLevel::Read || allows_write(permission_level)
+    });
+    covered.then_some(ExistingAccess::Folder)
+}
+
+/// Finds access the recipient (a user or a group) already has to a password or folder
+///
+/// For a password, `folder_id` is the folder it is in; for a folder share, `password_id` is
+/// `None` and `folder_id` is the folder being shared. Only shares to this exact recipient count,
+/// not ones a user holds through their groups.
+pub fn find_existing_access(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    recipient_user_id: Option<Uuid>,
+    recipient_group_id: Option<Uuid>,
+    password_id: Option<Uuid>,
+    folder_id: Option<Uuid>,
+    requested: PermissionLevel,
+) -> QueryResult<Option<ExistingAccess>> {
+    use crate::schema::shares;
+
+    let folder_ids = match folder_id {
+        Some(folder_id) => folder_tree::ancestor_ids(folder_id, &folder_tree::load_parent_map(conn, owner_id)?),
+        None => Vec::new(),
+    };
+
+    let query = shares::table.filter(shares::user_id.eq(owner_id)).into_boxed();
+    let query = match (recipient_user_id, recipient_group_id) {
+        (Some(user_id), _) => query.filter(shares::shared_with_user_id.eq(user_id)),
+        (None, Some(group_id)) => query.filter(shares::group_id.eq(group_id)),
+        (None, None) => return Ok(None),
+    };
+    let query = match password_id {
+        Some(password_id) => query.filter(shares::password_id.eq(password_id).or(shares::folder_id.eq_any(&folder_ids))),
+        None => query.filter(shares::folder_id.eq_any(&folder_ids)),
+    };
+    let found = query
+        .select((shares::password_id, shares::folder_id, shares::permission_level, shares::expires_at))
+        .load::<(Option<Uuid>, Option<Uuid>, String, Option<NaiveDateTime>)>(conn)?
+        .into_iter()
+        .map(|(shared_password_id, shared_folder_id, permission_level, expires_at)| {
+            let is_direct = match password_id {
+                Some(_) => shared_password_id.is_some(),
+                None => shared_folder_id == folder_id,
+            };
+            (is_direct, permission_level, expires_at)
+        })
+        .collect::<Vec<_>>();
+
+    Ok(existing_access(&found, requested, chrono::Utc::now().naive_utc()))
+}
+
+/// Deletes every share whose expiry has passed, returning how many were removed
+pub fn purge_expired_shares(conn: &mut PgConnection) -> QueryResult<usize> {
+    use crate::schema::shares;
+
+    // The complement of `share_is_active`, so nothing is both hidden and kept
+    diesel::delete(shares::table.filter(shares::expires_at.le(chrono::Utc::now().naive_utc())))
+        .execute(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_access_from_shares_permission_levels() {
+        let now = Utc::now().naive_utc();
+        assert_eq!(access_from_shares(&[], now), PasswordAccess::None);
+        assert_eq!(access_from_shares(&[("read".to_string(), None)], now), PasswordAccess::Read);
+        assert_eq!(access_from_shares(&[("view".to_string(), None)], now), PasswordAccess::Read);
+        assert_eq!(access_from_shares(&[("write".to_string(), None)], now), PasswordAccess::Write);
+        assert_eq!(access_from_shares(&[("edit".to_string(), None)], now), PasswordAccess::Write);
+
+        // A direct read share and a group write share combine to write access
+        let shares = vec![("read".to_string(), None), ("write".to_string(), None)];
+        assert_eq!(access_from_shares(&shares, now), PasswordAccess::Write);
+    }
+
+    #[test]
+    fn test_permission_level_serde() {
+        let parse = |json: &str| serde_json::from_str::<PermissionLevel>(json).ok();
+        assert_eq!(parse(r#""read""#), Some(PermissionLevel::Read));
+        assert_eq!(parse(r#""view""#), Some(PermissionLevel::Read));
+        assert_eq!(parse(r#""write""#), Some(PermissionLevel::Write));
+        assert_eq!(parse(r#""edit""#), Some(PermissionLevel::Write));
+        assert_eq!(parse(r#""admin""#), None);
+        assert_eq!(parse(r#""Write""#), None);
+        assert_eq!(serde_json::to_string(&PermissionLevel::Write).unwrap(), r#""write""#);
+        assert_eq!(PermissionLevel::from_stored("admin"), None);
+    }
+
+    #[test]
+    fn test_share_is_active_at_expiry_boundary() {
+        let now = Utc::now().naive_utc();
+        assert!(share_is_active(None, now));
+        assert!(share_is_active(Some(now + Duration::microseconds(1)), now));
+        assert!(!share_is_active(Some(now), now));
+        assert!(!share_is_active(Some(now - Duration::seconds(2)), now));
+    }
+
+    #[test]
+    fn test_access_from_shares_ignores_expired() {
+        let now = Utc::now().naive_utc();
+        let expired = vec![("write".to_string(), Some(now - Duration::minutes(1)))];
+        assert_eq!(access_from_shares(&expired, now), PasswordAccess::None);
+
+        let mixed = vec![
+            ("write".to_string(), Some(now)),
+            ("read".to_string(), Some(now + Duration::days(1))),
+        ];
+        assert_eq!(access_from_shares(&mixed, now), PasswordAccess::Read);
+    }
+
+    #[test]
+    fn test_existing_access() {
+        let now = Utc::now().naive_utc();
+        let lapsed = Some(now - Duration::minutes(1));
+        assert_eq!(existing_access(&[], PermissionLevel::Write, now), None);
+
+        // Sharing the same item twice is a duplicate whatever the permission
+        let direct = vec![(true, "read".to_string(), None)];
+        assert_eq!(existing_access(&direct, PermissionLevel::Write, now), Some(ExistingAccess::Direct));
+
+        let read_folder = vec![(false, "read".to_string(), None)];
+        assert_eq!(existing_access(&read_folder, PermissionLevel::Read, now), Some(ExistingAccess::Folder));
+        assert_eq!(existing_access(&read_folder, PermissionLevel::Write, now), None);
+
+        let write_folder = vec![(false, "write".to_string(), None)];
+        assert_eq!(existing_access(&write_folder, PermissionLevel::Write, now), Some(ExistingAccess::Folder));
+
+        // Lapsed shares no longer grant anything
+        let expired = vec![(true, "write".to_string(), lapsed), (false, "write".to_string(), lapsed)];
+        assert_eq!(existing_access(&expired, PermissionLevel::Read, now), None);
+    }
+
+    fn share(owner: Uuid, recipient: Uuid, expires_at: Option<NaiveDateTime>) -> Share {
+        Share {
+            id: Uuid::new_v4(),
+            password_id: Some(Uuid::new_v4()),
+            folder_id: None,
+            user_id: owner,
+            shared_with_user_id: Some(recipient),
+            permission_level: "read".to_string(),
+            expires_at,
+            created_at: Utc::now().naive_utc(),
+            group_id: None,
+        }
+    }
+
+    #[test]
+    fn test_recipient_cannot_update_share() {
+        let now = Utc::now().naive_utc();
+        let (owner, recipient) = (Uuid::new_v4(), Uuid::new_v4());
+        let received = share(owner, recipient, None);
+
+        assert_eq!(plan_share_update(&received, recipient, Some(PermissionLevel::Write), None, now), Err(ShareUpdateError::NotFound));
+        assert_eq!(plan_share_update(&received, recipient, None, Some(Some(30)), now), Err(ShareUpdateError::NotFound));
+        assert_eq!(plan_share_update(&received, Uuid::new_v4(), Some(PermissionLevel::Write), None, now), Err(ShareUpdateError::NotFound));
+        assert!(plan_share_update(&received, owner, Some(PermissionLevel::Write), None, now).is_ok());
+    }
+
+    #[test]
+    fn test_plan_share_update() {
+        let now = Utc::now().naive_utc();
+        let owner = Uuid::new_v4();
+        let expiring = share(owner, Uuid::new_v4(), Some(now + Duration::days(1)));
+
+        assert_eq!(plan_share_update(&expiring, owner, Some(PermissionLevel::Write), Some(Some(30)), now), Ok(ShareChanges {
+            permission_level: Some("write".to_string()),
+            expires_at: Some(Some(now + Duration::days(30))),
+        }));
+        assert_eq!(plan_share_update(&expiring, owner, None, Some(None), now), Ok(ShareChanges {
+            permission_level: None,
+            expires_at: Some(None),
+        }));
+
+        assert!(matches!(plan_share_update(&expiring, owner, None, Some(Some(0)), now), Err(ShareUpdateError::Invalid(_))));
+        assert!(matches!(plan_share_update(&expiring, owner, None, Some(Some(MAX_EXPIRATION_DAYS + 1)), now), Err(ShareUpdateError::Invalid(_))));
+        assert!(matches!(plan_share_update(&expiring, owner, None, None, now), Err(ShareUpdateError::Invalid(_))));
+
+        // A lapsed share is gone, so it can't be revived by extending it
+        let lapsed = share(owner, Uuid::new_v4(), Some(now - Duration::minutes(1)));
+        assert_eq!(plan_share_update(&lapsed, owner, None, Some(Some(30)), now), Err(ShareUpdateError::NotFound));
+    }
+}
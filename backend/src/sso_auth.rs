@@ -16,8 +16,10 @@ use crate::{
     models::{User, NewUser},
     auth,
     schema::{users, oauth_accounts},
+    token_management::TokenManager,
 };
 use std::env;
+use std::sync::Arc;
 use ring::digest;
 
 // Helper function to hash tokens
@@ -55,6 +57,20 @@ fn get_oauth_client(provider: &OAuthProvider) -> Result<BasicClient, Box<dyn std
              "https://www.googleapis.com/oauth2/v4/token".to_string(),
              redirect_uri)
         },
+        OAuthProvider::Oidc => {
+            let client_id = env::var("OIDC_CLIENT_ID")
+                .map_err(|_| "OIDC_CLIENT_ID not set")?;
+            let client_secret = env::var("OIDC_CLIENT_SECRET")
+                .map_err(|_| "OIDC_CLIENT_SECRET not set")?;
+            let redirect_uri = env::var("OIDC_REDIRECT_URI")
+                .map_err(|_| "OIDC_REDIRECT_URI not set")?;
+            let auth_url = env::var("OIDC_AUTH_URL")
+                .map_err(|_| "OIDC_AUTH_URL not set")?;
+            let token_url = env::var("OIDC_TOKEN_URL")
+                .map_err(|_| "OIDC_TOKEN_URL not set")?;
+            
+            (client_id, client_secret, auth_url, token_url, redirect_uri)
+        },
     };
 
     let client = BasicClient::new(
@@ -89,6 +105,7 @@ pub async fn get_oauth_auth_url(
     let provider = match provider_str.as_str() {
         "microsoft" => OAuthProvider::Microsoft,
         "google" => OAuthProvider::Google,
+        "oidc" => OAuthProvider::Oidc,
         _ => {
             return Ok(HttpResponse::BadRequest().json(serde_json::json!({
                 "error": "Invalid OAuth provider"
@@ -112,6 +129,7 @@ pub async fn get_oauth_auth_url(
     let scopes = match provider {
         OAuthProvider::Microsoft => vec!["openid", "profile", "email"],
         OAuthProvider::Google => vec!["openid", "profile", "email"],
+        OAuthProvider::Oidc => vec!["openid", "profile", "email"],
     };
     
     for scope in scopes {
@@ -128,15 +146,18 @@ pub async fn get_oauth_auth_url(
 
 /// Handle OAuth callback
 pub async fn handle_oauth_callback(
+    req: HttpRequest,
     path: web::Path<String>,
     callback_data: web::Json<OAuthCallbackRequest>,
     db_pool: web::Data<DbPool>,
+    token_manager: web::Data<Arc<TokenManager>>,
 ) -> Result<HttpResponse, Error> {
     let provider_str = path.into_inner();
     
     let provider = match provider_str.as_str() {
         "microsoft" => OAuthProvider::Microsoft,
         "google" => OAuthProvider::Google,
+        "oidc" => OAuthProvider::Oidc,
         _ => {
             return Ok(HttpResponse::BadRequest().json(serde_json::json!({
                 "error": "Invalid OAuth provider"
@@ -167,16 +188,33 @@ pub async fn handle_oauth_callback(
     
     // Get user information from provider
     let user_info_url = match provider {
-        OAuthProvider::Microsoft => "https://graph.microsoft.com/v1.0/me",
-        OAuthProvider::Google => "https://www.googleapis.com/oauth2/v2/userinfo",
+        OAuthProvider::Microsoft => "https://graph.microsoft.com/v1.0/me".to_string(),
+        OAuthProvider::Google => "https://www.googleapis.com/oauth2/v2/userinfo".to_string(),
+        OAuthProvider::Oidc => match env::var("OIDC_USERINFO_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                log::error!("OAuth client configuration error: OIDC_USERINFO_URL not set");
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "OAuth provider not configured"
+                })));
+            }
+        },
     };
 
-    let user_info = get_user_info(&provider, access_token, user_info_url).await
+    let user_info = get_user_info(&provider, access_token, &user_info_url).await
         .map_err(|e| {
             log::error!("Failed to get user info: {}", e);
             actix_web::error::ErrorInternalServerError("Failed to retrieve user information")
         })?;
 
+    // Accounts are linked by email, so a generic IdP must vouch for the address
+    if matches!(provider, OAuthProvider::Oidc) && user_info.verified_email != Some(true) {
+        log::warn!("Rejected OIDC login for unverified email: {}", user_info.email);
+        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Email address not verified by identity provider"
+        })));
+    }
+
     // Process user authentication/registration
     let mut conn = db_pool.get().map_err(|e| {
         log::error!("Database connection error: {}", e);
@@ -189,8 +227,8 @@ pub async fn handle_oauth_callback(
             actix_web::error::ErrorInternalServerError("User authentication failed")
         })?;
 
-    // Generate JWT tokens
-    let token_pair = auth::generate_token_pair(user.id)
+    // Start a session and issue its tokens
+    let token_pair = token_manager.start_session(&req, user.id)
         .map_err(|e| {
             log::error!("Token generation failed: {}", e);
             actix_web::error::ErrorInternalServerError("Token generation failed")
@@ -241,6 +279,16 @@ async fn get_user_info(
                 verified_email: response["verified_email"].as_bool(),
             }
         },
+        OAuthProvider::Oidc => {
+            // Standard OpenID Connect userinfo claims
+            OAuthUserInfo {
+                id: response["sub"].as_str().unwrap_or_default().to_string(),
+                email: response["email"].as_str().unwrap_or_default().to_string(),
+                name: response["name"].as_str().map(|s| s.to_string()),
+                picture: response["picture"].as_str().map(|s| s.to_string()),
+                verified_email: response["email_verified"].as_bool(),
+            }
+        },
     };
 
     Ok(user_info)
@@ -349,14 +397,7 @@ pub async fn get_user_oauth_accounts(
     req: HttpRequest,
     db_pool: web::Data<DbPool>,
 ) -> Result<HttpResponse, Error> {
-    let user_id = match auth::extract_user_id_from_request(&req) {
-        Ok(id) => id,
-        Err(_) => {
-            return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Authentication required"
-            })));
-        }
-    };
+    let user_id = auth::authenticate(&req)?;
 
     let mut conn = db_pool.get().map_err(|e| {
         log::error!("Database connection error: {}", e);
@@ -378,14 +419,7 @@ pub async fn unlink_oauth_account(
     path: web::Path<Uuid>,
     db_pool: web::Data<DbPool>,
 ) -> Result<HttpResponse, Error> {
-    let user_id = match auth::extract_user_id_from_request(&req) {
-        Ok(id) => id,
-        Err(_) => {
-            return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
-                "error": "Authentication required"
-            })));
-        }
-    };
+    let user_id = auth::authenticate(&req)?;
 
     let account_id = path.into_inner();
     
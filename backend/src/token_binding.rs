@@ -0,0 +1,145 @@
+//! Token binding module for tying refresh tokens to where they were issued
+//!
+//! Refresh tokens record the device id and a coarse network of the login. With
+//! BIND_REFRESH_TOKENS set, a refresh from another device or network is refused and the
+//! client has to sign in again.
+
+use std::env;
+use std::net::IpAddr;
+use crate::auth::Claims;
+use crate::token_management::EnhancedClaims;
+
+/// Where a token was issued
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenBinding {
+    /// Client-generated id from the X-Device-Id header
+    pub device: Option<String>,
+    /// Network of the client IP, e.g. "203.0.113.0/24"
+    pub network: Option<String>,
+}
+
+impl TokenBinding {
+    /// The binding a base token was issued with
+    pub fn from_claims(claims: &Claims) -> Self {
+        Self {
+            device: claims.device.clone(),
+            network: claims.network.clone(),
+        }
+    }
+
+    /// The binding an enhanced token was issued with
+    pub fn from_enhanced_claims(claims: &EnhancedClaims) -> Self {
+        Self {
+            device: claims.device_id.clone(),
+            network: claims.network.clone(),
+        }
+    }
+}
+
+/// The coarse network of an IP address given as text, if it is one
+pub fn network_of(ip_address: &str) -> Option<String> {
+    ip_address.parse().ok().map(coarse_network)
+}
+
+/// Reduces an address to its /24 (IPv4) or /48 (IPv6) network, so address changes within a
+/// provider's block don't break the binding
+pub fn coarse_network(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        IpAddr::V6(ip) => {
+            let s = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", s[0], s[1], s[2])
+        }
+    }
+}
+
+/// Which parts of the binding refreshes must match
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BindingPolicy {
+    pub device: bool,
+    pub network: bool,
+}
+
+impl BindingPolicy {
+    /// Loads BIND_REFRESH_TOKENS, a comma-separated list of `device` and `network`
+    pub fn from_env() -> Self {
+        parse_policy(env::var("BIND_REFRESH_TOKENS").ok().as_deref())
+    }
+
+    /// Whether a refresh token may be used from `presented`
+    ///
+    /// Parts the token was issued without, e.g. a login that sent no device id, aren't checked.
+    pub fn allows(&self, issued: &TokenBinding, presented: &TokenBinding) -> bool {
+        let matches = |enforced: bool, issued: &Option<String>, presented: &Option<String>| {
+            !enforced || issued.is_none() || issued == presented
+        };
+        matches(self.device, &issued.device, &presented.device)
+            && matches(self.network, &issued.network, &presented.network)
+    }
+}
+
+fn parse_policy(value: Option<&str>) -> BindingPolicy {
+    let mut policy = BindingPolicy::default();
+    for part in value.unwrap_or("").split(',').map(|part| part.trim()) {
+        match part {
+            "" | "off" | "false" => {}
+            "device" => policy.device = true,
+            "network" => policy.network = true,
+            other => log::warn!("Ignoring unknown BIND_REFRESH_TOKENS value: {}", other),
+        }
+    }
+    policy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(device: Option<&str>, network: Option<&str>) -> TokenBinding {
+        TokenBinding {
+            device: device.map(String::from),
+            network: network.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_coarse_network() {
+        assert_eq!(coarse_network("203.0.113.77".parse().unwrap()), "203.0.113.0/24");
+        assert_eq!(coarse_network("::ffff:203.0.113.77".parse().unwrap()), "203.0.113.0/24");
+        assert_eq!(coarse_network("2001:db8:abcd:12::1".parse().unwrap()), "2001:db8:abcd::/48");
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(parse_policy(None), BindingPolicy::default());
+        assert_eq!(parse_policy(Some("off")), BindingPolicy::default());
+        assert_eq!(parse_policy(Some("device")), BindingPolicy { device: true, network: false });
+        assert_eq!(parse_policy(Some(" device , network ")), BindingPolicy { device: true, network: true });
+        assert_eq!(parse_policy(Some("asn")), BindingPolicy::default());
+    }
+
+    #[test]
+    fn test_policy_allows() {
+        let issued = binding(Some("laptop-1234"), Some("203.0.113.0/24"));
+        let elsewhere = binding(Some("laptop-1234"), Some("198.51.100.0/24"));
+        let other_device = binding(Some("phone-5678"), Some("203.0.113.0/24"));
+        let no_header = binding(None, Some("203.0.113.0/24"));
+
+        let off = BindingPolicy::default();
+        assert!(off.allows(&issued, &other_device));
+
+        let device = BindingPolicy { device: true, network: false };
+        assert!(device.allows(&issued, &elsewhere));
+        assert!(!device.allows(&issued, &other_device));
+        assert!(!device.allows(&issued, &no_header));
+        // Nothing to compare against for tokens issued without a device id
+        assert!(device.allows(&binding(None, None), &other_device));
+
+        let both = BindingPolicy { device: true, network: true };
+        assert!(both.allows(&issued, &issued));
+        assert!(!both.allows(&issued, &elsewhere));
+    }
+}
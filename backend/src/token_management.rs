@@ -3,7 +3,7 @@
 //! This module provides enterprise-grade JWT token management with:
 //! - Short-lived access tokens (15 minutes)
 //! - Long-lived refresh tokens (7 days)
-//! - Token revocation list (blacklist)
+//! - Token revocation list (blacklist) persisted in the revoked_tokens table
 //! - Token rotation and refresh mechanisms
 //! - Concurrent session management
 //! - Token analytics and monitoring
@@ -17,21 +17,95 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::env;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use uuid::Uuid;
 use crate::auth::TokenPair;
+use crate::enterprise_session_manager::{EnterpriseRevokedToken, EnterpriseSession};
+use crate::schema::{active_sessions, revoked_tokens, session_limits};
+use crate::token_binding::{network_of, BindingPolicy, TokenBinding};
 
 /// Database connection pool type
 type DbPool = Pool<ConnectionManager<PgConnection>>;
 
-/// Token revocation entry
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RevokedToken {
-    pub jti: String,           // JWT ID
-    pub user_id: Uuid,         // User ID
-    pub token_type: String,    // "access" or "refresh"
-    pub revoked_at: chrono::DateTime<Utc>,
-    pub expires_at: chrono::DateTime<Utc>,
-    pub reason: String,        // Revocation reason
+/// How long a "not revoked" lookup is trusted before the database is asked again
+const REVOCATION_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How long a user's idle timeout is trusted before the session limits are read again
+const IDLE_TIMEOUT_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Revocation reason recorded when a session is ended for inactivity
+pub const IDLE_TIMEOUT_REASON: &str = "idle_timeout";
+
+/// Revocation reason recorded for a refresh token that has been exchanged for a new pair
+pub const TOKEN_ROTATION_REASON: &str = "token_rotation";
+
+/// Revocation reason recorded for a refresh token family after one of its tokens was reused
+pub const REFRESH_REUSE_REASON: &str = "refresh_token_reuse";
+
+/// Least time between two writes of a session's last activity
+const ACTIVITY_WRITE_INTERVAL: Duration = Duration::seconds(60);
+
+/// Lifetime of a session, matching its refresh token
+const SESSION_LIFETIME_DAYS: i64 = 7;
+
+/// Issuer of enhanced tokens
+const TOKEN_ISSUER: &str = "passq-auth";
+/// Audience of enhanced access tokens
+const ACCESS_TOKEN_AUDIENCE: &str = "passq-api";
+/// Audience of enhanced refresh tokens, which are only accepted by the auth service
+const REFRESH_TOKEN_AUDIENCE: &str = "passq-auth";
+
+/// Header carrying the stable device id a client generates once and sends with every login
+pub const DEVICE_ID_HEADER: &str = "X-Device-Id";
+
+/// Shortest and longest device ids accepted
+const DEVICE_ID_LENGTH: std::ops::RangeInclusive<usize> = 8..=128;
+
+/// Checks a client-supplied device id, which may only use letters, digits and `-_.:`
+pub fn sanitize_device_id(value: &str) -> Option<String> {
+    let value = value.trim();
+    let valid = DEVICE_ID_LENGTH.contains(&value.len())
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+    valid.then(|| value.to_string())
+}
+
+/// Reads the device id header, ignoring values that fail validation
+pub fn device_id_from_request(req: &actix_web::HttpRequest) -> Option<String> {
+    let value = req.headers().get(DEVICE_ID_HEADER)?.to_str().ok()?;
+    let device_id = sanitize_device_id(value);
+    if device_id.is_none() {
+        log::warn!("Ignoring malformed {} header", DEVICE_ID_HEADER);
+    }
+    device_id
+}
+
+/// Returns the audience a token of the given type must carry
+fn expected_audience(token_type: &str) -> Option<&'static str> {
+    match token_type {
+        "access" => Some(ACCESS_TOKEN_AUDIENCE),
+        "refresh" => Some(REFRESH_TOKEN_AUDIENCE),
+        _ => None,
+    }
+}
+
+/// Cached result of a revocation lookup
+#[derive(Debug, Clone, Copy)]
+struct CachedRevocation {
+    revoked: bool,
+    checked_at: Instant,
+}
+
+impl CachedRevocation {
+    /// Revocations are permanent, so only negative results expire
+    fn is_fresh(&self, now: Instant) -> bool {
+        self.revoked || now.duration_since(self.checked_at) < REVOCATION_CACHE_TTL
+    }
+}
+
+/// A user's idle timeout as read from `session_limits`, `None` when they have no limits
+struct CachedIdleTimeout {
+    timeout: Option<Duration>,
+    checked_at: Instant,
 }
 
 /// Active session information
@@ -46,6 +120,36 @@ pub struct ActiveSession {
     pub ip_address: Option<String>,
     pub user_agent: Option<String>,
     pub device_fingerprint: Option<String>,
+    pub device_name: Option<String>,
+    pub device_type: Option<String>,
+}
+
+impl From<EnterpriseSession> for ActiveSession {
+    fn from(session: EnterpriseSession) -> Self {
+        Self {
+            session_id: session.session_id,
+            user_id: session.user_id,
+            access_token_jti: session.access_token_jti,
+            refresh_token_jti: session.refresh_token_jti,
+            created_at: session.created_at,
+            last_activity: session.last_activity,
+            ip_address: session.ip_address,
+            user_agent: session.user_agent,
+            device_fingerprint: session.device_fingerprint,
+            device_name: session.device_name,
+            device_type: session.device_type,
+        }
+    }
+}
+
+/// Whether a session's last activity is old enough to be written again
+fn activity_write_due(last_activity: chrono::DateTime<Utc>, now: chrono::DateTime<Utc>) -> bool {
+    now - last_activity >= ACTIVITY_WRITE_INTERVAL
+}
+
+/// Whether a session has gone without activity for longer than the idle timeout
+fn idle_timeout_exceeded(last_activity: chrono::DateTime<Utc>, now: chrono::DateTime<Utc>, timeout: Duration) -> bool {
+    now - last_activity > timeout
 }
 
 /// Token analytics data
@@ -73,6 +177,142 @@ pub struct EnhancedClaims {
     pub iss: String,           // Issuer
     pub device_id: Option<String>, // Device identifier
     pub scope: Vec<String>,    // Token scope/permissions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>, // Coarse network a refresh token was issued to
+}
+
+/// Why a refresh token could not be exchanged
+#[derive(Debug)]
+pub enum RefreshError {
+    /// Malformed, expired, revoked or not a refresh token
+    Invalid(String),
+    /// Already exchanged once, so every token of its session has been revoked
+    Reused { user_id: Uuid },
+    /// Presented from another device or network than it was issued to
+    BindingMismatch,
+    /// The revocation list or the sessions couldn't be read or written
+    Failed(String),
+}
+
+impl std::fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefreshError::Invalid(reason) => write!(f, "Invalid refresh token: {}", reason),
+            RefreshError::Reused { user_id } => write!(f, "Refresh token of user {} was reused", user_id),
+            RefreshError::BindingMismatch => write!(f, "Refresh token presented from another device or network"),
+            RefreshError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RefreshError {}
+
+impl From<String> for RefreshError {
+    fn from(e: String) -> Self {
+        RefreshError::Failed(e)
+    }
+}
+
+/// Revocation list key standing for every token of a session, or of a base token family
+fn family_key(family: &str) -> String {
+    format!("family:{}", family)
+}
+
+/// A freshly encoded token pair and the ids its session has to remember
+pub struct IssuedTokenPair {
+    pub tokens: TokenPair,
+    pub access_jti: String,
+    pub refresh_jti: String,
+    pub refresh_expires_at: chrono::DateTime<Utc>,
+}
+
+/// Encode an access and a refresh token for a session without storing anything
+pub fn encode_enhanced_token_pair(
+    user_id: Uuid,
+    session_id: &str,
+    device_id: Option<String>,
+    network: Option<String>,
+) -> Result<IssuedTokenPair, jsonwebtoken::errors::Error> {
+    let secret = env::var("JWT_SECRET")
+        .map_err(|_| jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken))?;
+
+    let now = Utc::now();
+    let access_jti = Uuid::new_v4().to_string();
+    let refresh_jti = Uuid::new_v4().to_string();
+
+    // Generate short-lived access token (15 minutes)
+    let access_expiration = now + Duration::minutes(15);
+    let access_claims = EnhancedClaims {
+        sub: user_id,
+        exp: access_expiration.timestamp() as usize,
+        iat: now.timestamp() as usize,
+        jti: access_jti.clone(),
+        token_type: "access".to_string(),
+        session_id: session_id.to_string(),
+        aud: ACCESS_TOKEN_AUDIENCE.to_string(),
+        iss: TOKEN_ISSUER.to_string(),
+        device_id: device_id.clone(),
+        scope: vec!["read".to_string(), "write".to_string()],
+        network: None,
+    };
+
+    let access_token = encode(&Header::default(), &access_claims, &EncodingKey::from_secret(secret.as_ref()))?;
+
+    // Generate long-lived refresh token (7 days)
+    let refresh_expiration = now + Duration::days(SESSION_LIFETIME_DAYS);
+    let refresh_claims = EnhancedClaims {
+        sub: user_id,
+        exp: refresh_expiration.timestamp() as usize,
+        iat: now.timestamp() as usize,
+        jti: refresh_jti.clone(),
+        token_type: "refresh".to_string(),
+        session_id: session_id.to_string(),
+        aud: REFRESH_TOKEN_AUDIENCE.to_string(),
+        iss: TOKEN_ISSUER.to_string(),
+        device_id,
+        scope: vec!["refresh".to_string()],
+        network,
+    };
+
+    let refresh_token = encode(&Header::default(), &refresh_claims, &EncodingKey::from_secret(secret.as_ref()))?;
+
+    Ok(IssuedTokenPair {
+        tokens: TokenPair {
+            access_token,
+            refresh_token,
+            expires_in: 900, // 15 minutes in seconds
+        },
+        access_jti,
+        refresh_jti,
+        refresh_expires_at: refresh_expiration,
+    })
+}
+
+/// Decode an enhanced token, checking its signature, issuer, audience and expiry
+///
+/// Revocation and idle timeouts are checked separately by `TokenManager::check_enhanced_claims`.
+pub fn decode_enhanced_token(token: &str) -> Result<EnhancedClaims, jsonwebtoken::errors::Error> {
+    let secret = env::var("JWT_SECRET")
+        .map_err(|_| jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken))?;
+
+    // Only HS256 is accepted, so unsigned ("alg": "none") and re-algorithmed tokens fail to decode
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_audience(&[ACCESS_TOKEN_AUDIENCE, REFRESH_TOKEN_AUDIENCE]);
+    validation.set_issuer(&[TOKEN_ISSUER]);
+
+    let token_data = decode::<EnhancedClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_ref()),
+        &validation,
+    )?;
+
+    // A refresh token must not pass as an access token or vice versa
+    if expected_audience(&token_data.claims.token_type) != Some(token_data.claims.aud.as_str()) {
+        log::warn!("Enhanced token audience {} does not match token type {}", token_data.claims.aud, token_data.claims.token_type);
+        return Err(jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidAudience));
+    }
+
+    Ok(token_data.claims)
 }
 
 /// Token refresh request
@@ -98,11 +338,13 @@ pub struct SessionManagementRequest {
 }
 
 /// Token manager with in-memory caching and persistence
+///
+/// Sessions live in the `active_sessions` table; the in-memory map only caches them.
 pub struct TokenManager {
-    revoked_tokens: Arc<Mutex<HashMap<String, RevokedToken>>>,
+    revocation_cache: Arc<Mutex<HashMap<String, CachedRevocation>>>,
     active_sessions: Arc<Mutex<HashMap<String, ActiveSession>>>,
+    idle_timeouts: Arc<Mutex<HashMap<Uuid, CachedIdleTimeout>>>,
     token_analytics: Arc<Mutex<Vec<TokenAnalytics>>>,
-    #[allow(dead_code)]
     db_pool: DbPool,
 }
 
@@ -110,8 +352,9 @@ impl TokenManager {
     /// Create a new token manager instance
     pub fn new(db_pool: DbPool) -> Self {
         Self {
-            revoked_tokens: Arc::new(Mutex::new(HashMap::new())),
+            revocation_cache: Arc::new(Mutex::new(HashMap::new())),
             active_sessions: Arc::new(Mutex::new(HashMap::new())),
+            idle_timeouts: Arc::new(Mutex::new(HashMap::new())),
             token_analytics: Arc::new(Mutex::new(Vec::new())),
             db_pool,
         }
@@ -125,63 +368,29 @@ impl TokenManager {
         device_id: Option<String>,
         ip_address: Option<String>,
         user_agent: Option<String>,
-    ) -> Result<TokenPair, jsonwebtoken::errors::Error> {
+    ) -> Result<TokenPair, Box<dyn std::error::Error>> {
         log::info!("Generating enhanced token pair for user: {} session: {}", user_id, session_id);
-        
-        let secret = env::var("JWT_SECRET")
-            .map_err(|_| jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken))?;
 
         let now = Utc::now();
-        let access_jti = Uuid::new_v4().to_string();
-        let refresh_jti = Uuid::new_v4().to_string();
-
-        // Generate short-lived access token (15 minutes)
-        let access_expiration = now + Duration::minutes(15);
-        let access_claims = EnhancedClaims {
-            sub: user_id,
-            exp: access_expiration.timestamp() as usize,
-            iat: now.timestamp() as usize,
-            jti: access_jti.clone(),
-            token_type: "access".to_string(),
-            session_id: session_id.clone(),
-            aud: "passq-api".to_string(),
-            iss: "passq-auth".to_string(),
-            device_id: device_id.clone(),
-            scope: vec!["read".to_string(), "write".to_string()],
-        };
-
-        let access_token = encode(&Header::default(), &access_claims, &EncodingKey::from_secret(secret.as_ref()))?;
-
-        // Generate long-lived refresh token (7 days)
-        let refresh_expiration = now + Duration::days(7);
-        let refresh_claims = EnhancedClaims {
-            sub: user_id,
-            exp: refresh_expiration.timestamp() as usize,
-            iat: now.timestamp() as usize,
-            jti: refresh_jti.clone(),
-            token_type: "refresh".to_string(),
-            session_id: session_id.clone(),
-            aud: "passq-auth".to_string(),
-            iss: "passq-auth".to_string(),
-            device_id: device_id.clone(),
-            scope: vec!["refresh".to_string()],
-        };
-
-        let refresh_token = encode(&Header::default(), &refresh_claims, &EncodingKey::from_secret(secret.as_ref()))?;
+        let network = ip_address.as_deref().and_then(network_of);
+        let issued = encode_enhanced_token_pair(user_id, &session_id, device_id.clone(), network)?;
 
         // Store active session
         let session = ActiveSession {
             session_id: session_id.clone(),
             user_id,
-            access_token_jti: access_jti,
-            refresh_token_jti: refresh_jti,
+            access_token_jti: issued.access_jti,
+            refresh_token_jti: issued.refresh_jti,
             created_at: now,
             last_activity: now,
             ip_address: ip_address.clone(),
             user_agent: user_agent.clone(),
             device_fingerprint: device_id.clone(),
+            device_name: None,
+            device_type: user_agent.as_deref().map(|ua| crate::user_agent::parse(ua).device_type.to_string()),
         };
 
+        self.persist_session(&session, issued.refresh_expires_at)?;
         if let Ok(mut sessions) = self.active_sessions.lock() {
             sessions.insert(session_id.clone(), session);
         }
@@ -198,85 +407,117 @@ impl TokenManager {
         });
 
         log::info!("Enhanced token pair generated successfully for user: {} session: {}", user_id, session_id);
-        Ok(TokenPair {
-            access_token,
-            refresh_token,
-            expires_in: 900, // 15 minutes in seconds
-        })
+        Ok(issued.tokens)
+    }
+
+    /// Start a session for a user who just signed in, taking the client details from the request
+    pub fn start_session(&self, req: &actix_web::HttpRequest, user_id: Uuid) -> Result<TokenPair, String> {
+        let user_agent = req.headers()
+            .get("user-agent")
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string());
+        self.generate_enhanced_token_pair(
+            user_id,
+            Uuid::new_v4().to_string(),
+            device_id_from_request(req),
+            crate::ip_controls::extract_client_ip(req).map(|ip| ip.to_string()),
+            user_agent,
+        )
+        .map_err(|e| e.to_string())
     }
 
     /// Validate enhanced token with revocation check
     pub fn validate_enhanced_token(&self, token: &str) -> Result<EnhancedClaims, jsonwebtoken::errors::Error> {
         log::debug!("Validating enhanced JWT token");
-        
-        let secret = env::var("JWT_SECRET")
-            .map_err(|_| jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken))?;
-
-        let token_data = decode::<EnhancedClaims>(
-            token,
-            &DecodingKey::from_secret(secret.as_ref()),
-            &Validation::new(Algorithm::HS256),
-        )?;
-
-        // Check if token is revoked
-        if let Ok(revoked_tokens) = self.revoked_tokens.lock() {
-            if revoked_tokens.contains_key(&token_data.claims.jti) {
-                log::warn!("Attempted use of revoked token: {}", token_data.claims.jti);
+
+        let claims = decode_enhanced_token(token)?;
+        self.check_enhanced_claims(&claims)?;
+
+        log::info!("Enhanced JWT token validation successful for user: {:?}", claims.sub);
+        Ok(claims)
+    }
+
+    /// Reject decoded claims whose token or session was revoked or whose session went idle
+    ///
+    /// Records activity on the session when the token is accepted.
+    pub fn check_enhanced_claims(&self, claims: &EnhancedClaims) -> Result<(), jsonwebtoken::errors::Error> {
+        // Check if token or its session is revoked, failing closed if the revocation list can't be read
+        let revoked = self.is_token_revoked(&claims.jti)
+            .and_then(|revoked| if revoked { Ok(true) } else { self.is_family_revoked(&claims.session_id) });
+        match revoked {
+            Ok(false) => {}
+            Ok(true) => {
+                log::warn!("Attempted use of revoked token: {}", claims.jti);
+                return Err(jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken));
+            }
+            Err(e) => {
+                log::error!("Failed to check token revocation for {}: {}", claims.jti, e);
                 return Err(jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken));
             }
         }
 
-        // Update session activity
-        if let Ok(mut sessions) = self.active_sessions.lock() {
-            if let Some(session) = sessions.get_mut(&token_data.claims.session_id) {
-                session.last_activity = Utc::now();
-            }
+        if self.end_idle_session(claims) {
+            return Err(jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::ExpiredSignature));
         }
 
-        log::info!("Enhanced JWT token validation successful for user: {:?}", token_data.claims.sub);
-        Ok(token_data.claims)
+        self.touch_session(&claims.session_id);
+        Ok(())
     }
 
-    /// Refresh token pair with rotation
+    /// Exchange a refresh token for the next pair of its session
+    ///
+    /// Each refresh token is recorded as consumed the first time it is used. Presenting it again
+    /// means two parties hold it, so the whole session is revoked and both must sign in again.
+    /// Base refresh tokens from before logins issued enhanced tokens are exchanged once for a new
+    /// session.
     pub fn refresh_token_pair(
         &self,
         refresh_token: &str,
         device_id: Option<String>,
         ip_address: Option<String>,
         user_agent: Option<String>,
-    ) -> Result<TokenPair, Box<dyn std::error::Error>> {
+    ) -> Result<TokenPair, RefreshError> {
         log::info!("Refreshing token pair");
 
-        // Validate refresh token
-        let claims = self.validate_enhanced_token(refresh_token)
-            .map_err(|e| {
-                log::error!("Refresh token validation failed: {}", e);
-                e
-            })?;
+        let claims = match decode_enhanced_token(refresh_token) {
+            Ok(claims) => claims,
+            Err(e) => {
+                return match crate::auth::validate_refresh_token(refresh_token) {
+                    Ok(claims) => self.upgrade_base_refresh_token(&claims, device_id, ip_address, user_agent),
+                    Err(_) => Err(RefreshError::Invalid(e.to_string())),
+                };
+            }
+        };
 
         // Ensure it's a refresh token
         if claims.token_type != "refresh" {
-            log::error!("Invalid token type for refresh: {}", claims.token_type);
-            return Err("Invalid token type".into());
-        }
-
-        // Revoke old refresh token
-        self.revoke_token(
-            &claims.jti,
-            claims.sub,
-            "refresh".to_string(),
-            "token_rotation".to_string(),
-        );
-
-        // Generate new token pair
-        let new_session_id = Uuid::new_v4().to_string();
-        let new_token_pair = self.generate_enhanced_token_pair(
-            claims.sub,
-            new_session_id,
-            device_id,
-            ip_address.clone(),
-            user_agent.clone(),
-        )?;
+            return Err(RefreshError::Invalid(format!("token type {}", claims.token_type)));
+        }
+        if self.is_family_revoked(&claims.session_id)? {
+            return Err(RefreshError::Invalid(format!("session {} was revoked", claims.session_id)));
+        }
+
+        let presented = TokenBinding { device: device_id.clone(), network: ip_address.as_deref().and_then(network_of) };
+        if !BindingPolicy::from_env().allows(&TokenBinding::from_enhanced_claims(&claims), &presented) {
+            return Err(RefreshError::BindingMismatch);
+        }
+
+        if let Err(e) = self.consume_refresh_token(&claims.jti, claims.sub, &claims.session_id, claims.exp) {
+            if let RefreshError::Reused { .. } = e {
+                if let Err(e) = self.revoke_session(claims.sub, &claims.session_id, REFRESH_REUSE_REASON.to_string()) {
+                    log::warn!("Failed to end session {} after refresh token reuse: {}", claims.session_id, e);
+                }
+            }
+            return Err(e);
+        }
+
+        if self.end_idle_session(&claims) {
+            return Err(RefreshError::Invalid(format!("session {} timed out", claims.session_id)));
+        }
+
+        // Stay on the same device unless the client names one
+        let device_id = device_id.or(claims.device_id.clone());
+        let new_token_pair = self.rotate_session(&claims, device_id, ip_address.clone())?;
 
         // Record analytics
         self.record_token_analytics(TokenAnalytics {
@@ -293,6 +534,149 @@ impl TokenManager {
         Ok(new_token_pair)
     }
 
+    /// Exchange a base refresh token for a new enhanced session, at most once
+    fn upgrade_base_refresh_token(
+        &self,
+        claims: &crate::auth::Claims,
+        device_id: Option<String>,
+        ip_address: Option<String>,
+        user_agent: Option<String>,
+    ) -> Result<TokenPair, RefreshError> {
+        let (Some(jti), Some(family)) = (claims.jti.as_deref(), claims.family) else {
+            return Err(RefreshError::Invalid("base refresh token predates rotation".to_string()));
+        };
+        let family = family.to_string();
+        if self.is_family_revoked(&family)? {
+            return Err(RefreshError::Invalid(format!("token family {} was revoked", family)));
+        }
+
+        let presented = TokenBinding { device: device_id.clone(), network: ip_address.as_deref().and_then(network_of) };
+        if !BindingPolicy::from_env().allows(&TokenBinding::from_claims(claims), &presented) {
+            return Err(RefreshError::BindingMismatch);
+        }
+
+        self.consume_refresh_token(jti, claims.sub, &family, claims.exp)?;
+
+        log::info!("Moving base token family {} of user {} to a new session", family, claims.sub);
+        self.generate_enhanced_token_pair(claims.sub, Uuid::new_v4().to_string(), device_id, ip_address, user_agent)
+            .map_err(|e| RefreshError::Failed(format!("Failed to generate token pair: {}", e)))
+    }
+
+    /// Record a refresh token as used, detecting a second use
+    ///
+    /// A token already consumed by rotation revokes its whole family and yields `Reused`.
+    fn consume_refresh_token(&self, jti: &str, user_id: Uuid, family: &str, exp: usize) -> Result<(), RefreshError> {
+        let now = Utc::now();
+        let expires_at = chrono::DateTime::from_timestamp(exp as i64, 0).unwrap_or(now);
+        let consumed = EnterpriseRevokedToken {
+            id: Uuid::new_v4(),
+            jti: jti.to_string(),
+            user_id,
+            session_id: None,
+            token_type: "refresh".to_string(),
+            revoked_at: now,
+            expires_at,
+            revocation_reason: TOKEN_ROTATION_REASON.to_string(),
+            revoked_by_user_id: None,
+            revoked_by_admin: Some(false),
+            original_expiry: Some(expires_at),
+            ip_address: None,
+            user_agent: None,
+        };
+
+        let mut conn = self.db_pool.get()
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+
+        // The unique jti makes this the single point where two concurrent refreshes are told apart
+        let inserted = diesel::insert_into(revoked_tokens::table)
+            .values(&consumed)
+            .on_conflict(revoked_tokens::jti)
+            .do_nothing()
+            .execute(&mut conn)
+            .map_err(|e| format!("Failed to record refresh token use: {}", e))?;
+        if inserted == 1 {
+            drop(conn);
+            self.cache_revocation(jti, true);
+            return Ok(());
+        }
+
+        // Tokens revoked for other reasons, e.g. logout, are simply refused
+        let reason = revoked_tokens::table
+            .filter(revoked_tokens::jti.eq(jti))
+            .select(revoked_tokens::revocation_reason)
+            .first::<String>(&mut conn)
+            .optional()
+            .map_err(|e| format!("Failed to query revoked tokens: {}", e))?;
+        drop(conn);
+        if reason.as_deref() != Some(TOKEN_ROTATION_REASON) {
+            return Err(RefreshError::Invalid("token was revoked".to_string()));
+        }
+
+        log::warn!("Refresh token {} of user {} was reused; revoking family {}", jti, user_id, family);
+        self.revoke_family(family, user_id, REFRESH_REUSE_REASON)?;
+        Err(RefreshError::Reused { user_id })
+    }
+
+    /// Replace a session's tokens with a new pair, keeping the session and its binding
+    fn rotate_session(&self, claims: &EnhancedClaims, device_id: Option<String>, ip_address: Option<String>) -> Result<TokenPair, RefreshError> {
+        let issued = encode_enhanced_token_pair(claims.sub, &claims.session_id, device_id.clone(), claims.network.clone())
+            .map_err(|e| RefreshError::Failed(format!("Failed to generate token pair: {}", e)))?;
+
+        let mut conn = self.db_pool.get()
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+        let session = diesel::update(active_sessions::table)
+            .filter(active_sessions::session_id.eq(&claims.session_id))
+            .filter(active_sessions::is_active.eq(true))
+            .set((
+                active_sessions::access_token_jti.eq(&issued.access_jti),
+                active_sessions::refresh_token_jti.eq(&issued.refresh_jti),
+                active_sessions::last_activity.eq(Utc::now()),
+                active_sessions::expires_at.eq(issued.refresh_expires_at),
+                active_sessions::device_fingerprint.eq(&device_id),
+                active_sessions::last_seen_ip.eq(&ip_address),
+            ))
+            .returning(EnterpriseSession::as_returning())
+            .get_result(&mut conn)
+            .optional()
+            .map_err(|e| format!("Failed to rotate session {}: {}", claims.session_id, e))?;
+
+        let Some(session) = session else {
+            return Err(RefreshError::Invalid(format!("session {} has ended", claims.session_id)));
+        };
+        if let Ok(mut sessions) = self.active_sessions.lock() {
+            sessions.insert(claims.session_id.clone(), ActiveSession::from(session));
+        }
+        Ok(issued.tokens)
+    }
+
+    /// Check whether a token has been revoked, consulting the cache before the database
+    pub fn is_token_revoked(&self, jti: &str) -> Result<bool, String> {
+        let now = Instant::now();
+        if let Ok(cache) = self.revocation_cache.lock() {
+            if let Some(cached) = cache.get(jti).filter(|cached| cached.is_fresh(now)) {
+                return Ok(cached.revoked);
+            }
+        }
+
+        let mut conn = self.db_pool.get()
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+
+        let revoked = diesel::select(diesel::dsl::exists(
+            revoked_tokens::table.filter(revoked_tokens::jti.eq(jti))
+        ))
+            .get_result::<bool>(&mut conn)
+            .map_err(|e| format!("Failed to query revoked tokens: {}", e))?;
+
+        self.cache_revocation(jti, revoked);
+        Ok(revoked)
+    }
+
+    fn cache_revocation(&self, jti: &str, revoked: bool) {
+        if let Ok(mut cache) = self.revocation_cache.lock() {
+            cache.insert(jti.to_string(), CachedRevocation { revoked, checked_at: Instant::now() });
+        }
+    }
+
     /// Revoke a specific token
     pub fn revoke_token(
         &self,
@@ -300,21 +684,41 @@ impl TokenManager {
         user_id: Uuid,
         token_type: String,
         reason: String,
-    ) {
+    ) -> Result<(), String> {
         log::info!("Revoking token: {} for user: {} reason: {}", jti, user_id, reason);
 
-        let revoked_token = RevokedToken {
+        let now = Utc::now();
+        let revoked_token = EnterpriseRevokedToken {
+            id: Uuid::new_v4(),
             jti: jti.to_string(),
             user_id,
+            session_id: None,
             token_type: token_type.clone(),
-            revoked_at: Utc::now(),
-            expires_at: Utc::now() + Duration::days(30), // Keep revocation record for 30 days
-            reason: reason.clone(),
+            revoked_at: now,
+            expires_at: now + Duration::days(30), // Keep revocation record for 30 days
+            revocation_reason: reason.chars().take(100).collect(),
+            revoked_by_user_id: None,
+            revoked_by_admin: Some(false),
+            original_expiry: None,
+            ip_address: None,
+            user_agent: None,
         };
 
-        if let Ok(mut revoked_tokens) = self.revoked_tokens.lock() {
-            revoked_tokens.insert(jti.to_string(), revoked_token);
-        }
+        let mut conn = self.db_pool.get()
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+
+        // A token may already be revoked, e.g. by the enterprise session manager
+        diesel::insert_into(revoked_tokens::table)
+            .values(&revoked_token)
+            .on_conflict(revoked_tokens::jti)
+            .do_nothing()
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to persist token revocation {}: {}", jti, e);
+                format!("Failed to revoke token: {}", e)
+            })?;
+
+        self.cache_revocation(jti, true);
 
         // Record analytics
         self.record_token_analytics(TokenAnalytics {
@@ -328,98 +732,351 @@ impl TokenManager {
         });
 
         log::info!("Token revoked successfully: {}", jti);
+        Ok(())
     }
 
-    /// Revoke all tokens for a user
-    pub fn revoke_all_user_tokens(&self, user_id: Uuid, reason: String) {
-        log::info!("Revoking all tokens for user: {} reason: {}", user_id, reason);
+    /// Whether every token of a session, or of a base token family, has been revoked
+    pub fn is_family_revoked(&self, family: &str) -> Result<bool, String> {
+        self.is_token_revoked(&family_key(family))
+    }
 
-        // Revoke all active sessions for the user
-        if let Ok(mut sessions) = self.active_sessions.lock() {
-            let user_sessions: Vec<_> = sessions
-                .iter()
-                .filter(|(_, session)| session.user_id == user_id)
-                .map(|(session_id, session)| (session_id.clone(), session.clone()))
-                .collect();
-
-            for (session_id, session) in user_sessions {
-                // Revoke access token
-                self.revoke_token(
-                    &session.access_token_jti,
-                    user_id,
-                    "access".to_string(),
-                    reason.clone(),
-                );
+    /// Revoke every access and refresh token of a session, or of a base token family
+    pub fn revoke_family(&self, family: &str, user_id: Uuid, reason: &str) -> Result<(), String> {
+        self.revoke_token(&family_key(family), user_id, "family".to_string(), reason.to_string())
+    }
 
-                // Revoke refresh token
-                self.revoke_token(
-                    &session.refresh_token_jti,
-                    user_id,
-                    "refresh".to_string(),
-                    reason.clone(),
-                );
+    /// Store a newly issued session in the database
+    fn persist_session(&self, session: &ActiveSession, expires_at: chrono::DateTime<Utc>) -> Result<(), String> {
+        let mut conn = self.db_pool.get()
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+
+        let record = EnterpriseSession {
+            id: Uuid::new_v4(),
+            session_id: session.session_id.clone(),
+            user_id: session.user_id,
+            access_token_jti: session.access_token_jti.clone(),
+            refresh_token_jti: session.refresh_token_jti.clone(),
+            created_at: session.created_at,
+            last_activity: session.last_activity,
+            expires_at,
+            ip_address: session.ip_address.clone(),
+            user_agent: session.user_agent.clone(),
+            device_fingerprint: session.device_fingerprint.clone(),
+            device_name: session.device_name.clone(),
+            device_type: session.device_type.clone(),
+            location_country: None,
+            location_region: None,
+            location_city: None,
+            is_active: true,
+            created_by_ip: session.ip_address.clone(),
+            last_seen_ip: session.ip_address.clone(),
+            session_flags: Some(serde_json::json!({})),
+        };
+
+        diesel::insert_into(active_sessions::table)
+            .values(&record)
+            .execute(&mut conn)
+            .map_err(|e| format!("Failed to persist session {}: {}", session.session_id, e))?;
+        Ok(())
+    }
 
-                // Remove session
-                sessions.remove(&session_id);
+    /// The idle timeout from the user's session limits, if they have one
+    fn idle_timeout(&self, user_id: Uuid) -> Result<Option<Duration>, String> {
+        let now = Instant::now();
+        if let Ok(cache) = self.idle_timeouts.lock() {
+            if let Some(cached) = cache.get(&user_id).filter(|cached| now.duration_since(cached.checked_at) < IDLE_TIMEOUT_CACHE_TTL) {
+                return Ok(cached.timeout);
             }
         }
 
+        let mut conn = self.db_pool.get()
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+        let minutes = session_limits::table
+            .filter(session_limits::user_id.eq(user_id))
+            .select(session_limits::session_timeout_minutes)
+            .first::<i32>(&mut conn)
+            .optional()
+            .map_err(|e| format!("Failed to load session limits: {}", e))?;
+
+        // A non-positive timeout switches the check off
+        let timeout = minutes.filter(|minutes| *minutes > 0).map(|minutes| Duration::minutes(minutes as i64));
+        if let Ok(mut cache) = self.idle_timeouts.lock() {
+            cache.insert(user_id, CachedIdleTimeout { timeout, checked_at: now });
+        }
+        Ok(timeout)
+    }
+
+    /// Forget a user's cached idle timeout after their session limits change
+    pub fn invalidate_idle_timeout(&self, user_id: Uuid) {
+        if let Ok(mut cache) = self.idle_timeouts.lock() {
+            cache.remove(&user_id);
+        }
+    }
+
+    /// A session from the cache, loading it from the database if it isn't cached yet
+    fn find_session(&self, session_id: &str) -> Result<Option<ActiveSession>, String> {
+        if let Ok(sessions) = self.active_sessions.lock() {
+            if let Some(session) = sessions.get(session_id) {
+                return Ok(Some(session.clone()));
+            }
+        }
+
+        let mut conn = self.db_pool.get()
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+        let session = active_sessions::table
+            .filter(active_sessions::session_id.eq(session_id))
+            .filter(active_sessions::is_active.eq(true))
+            .select(EnterpriseSession::as_select())
+            .first(&mut conn)
+            .optional()
+            .map_err(|e| format!("Failed to load session: {}", e))?
+            .map(ActiveSession::from);
+
+        if let (Some(session), Ok(mut sessions)) = (&session, self.active_sessions.lock()) {
+            sessions.insert(session_id.to_string(), session.clone());
+        }
+        Ok(session)
+    }
+
+    /// End the token's session if it has been idle for longer than the user allows
+    ///
+    /// Errors reading the limits or the session are logged and let the token through.
+    fn end_idle_session(&self, claims: &EnhancedClaims) -> bool {
+        let timeout = match self.idle_timeout(claims.sub) {
+            Ok(Some(timeout)) => timeout,
+            Ok(None) => return false,
+            Err(e) => {
+                log::error!("Failed to check idle timeout of user {}: {}", claims.sub, e);
+                return false;
+            }
+        };
+        let session = match self.find_session(&claims.session_id) {
+            Ok(Some(session)) => session,
+            Ok(None) => return false,
+            Err(e) => {
+                log::error!("Failed to check idle timeout of session {}: {}", claims.session_id, e);
+                return false;
+            }
+        };
+        if !idle_timeout_exceeded(session.last_activity, Utc::now(), timeout) {
+            return false;
+        }
+
+        log::warn!("Session {} of user {} timed out after {} minutes of inactivity", session.session_id, claims.sub, timeout.num_minutes());
+        if let Err(e) = self.end_session(&session, IDLE_TIMEOUT_REASON) {
+            log::error!("Failed to end idle session {}: {}", session.session_id, e);
+        }
+        true
+    }
+
+    /// Record activity on a session, writing to the database at most once per interval
+    fn touch_session(&self, session_id: &str) {
+        let now = Utc::now();
+        // (write due, already cached)
+        let (due, cached) = match self.active_sessions.lock() {
+            Ok(mut sessions) => match sessions.get_mut(session_id) {
+                Some(session) if activity_write_due(session.last_activity, now) => {
+                    session.last_activity = now;
+                    (true, true)
+                }
+                Some(_) => (false, true),
+                // Not cached, e.g. after a restart
+                None => (true, false),
+            },
+            Err(_) => (true, true),
+        };
+        if !due {
+            return;
+        }
+
+        let touched = self.db_pool.get()
+            .map_err(|e| e.to_string())
+            .and_then(|mut conn| {
+                let session = diesel::update(active_sessions::table)
+                    .filter(active_sessions::session_id.eq(session_id))
+                    .filter(active_sessions::is_active.eq(true))
+                    .set(active_sessions::last_activity.eq(now))
+                    .returning(EnterpriseSession::as_returning())
+                    .get_result(&mut conn)
+                    .optional()
+                    .map_err(|e| e.to_string())?;
+                Ok(session)
+            });
+        match touched {
+            Ok(Some(session)) if !cached => {
+                if let Ok(mut sessions) = self.active_sessions.lock() {
+                    sessions.insert(session_id.to_string(), ActiveSession::from(session));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => log::error!("Failed to update activity of session {}: {}", session_id, e),
+        }
+    }
+
+    /// Load a user's active sessions from the database
+    fn load_user_sessions(&self, user_id: Uuid) -> Result<Vec<ActiveSession>, String> {
+        let mut conn = self.db_pool.get()
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+
+        let sessions = active_sessions::table
+            .filter(active_sessions::user_id.eq(user_id))
+            .filter(active_sessions::is_active.eq(true))
+            .filter(active_sessions::expires_at.gt(Utc::now()))
+            .order(active_sessions::last_activity.desc())
+            .select(EnterpriseSession::as_select())
+            .load(&mut conn)
+            .map_err(|e| format!("Failed to load sessions: {}", e))?;
+
+        Ok(sessions.into_iter().map(ActiveSession::from).collect())
+    }
+
+    /// Revoke the tokens of a session and mark it inactive
+    fn end_session(&self, session: &ActiveSession, reason: &str) -> Result<(), String> {
+        // Also covers tokens the session was issued before its last rotation
+        self.revoke_family(&session.session_id, session.user_id, reason)?;
+        self.revoke_token(&session.access_token_jti, session.user_id, "access".to_string(), reason.to_string())?;
+        self.revoke_token(&session.refresh_token_jti, session.user_id, "refresh".to_string(), reason.to_string())?;
+
+        let mut conn = self.db_pool.get()
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+        diesel::update(active_sessions::table)
+            .filter(active_sessions::session_id.eq(&session.session_id))
+            .set(active_sessions::is_active.eq(false))
+            .execute(&mut conn)
+            .map_err(|e| format!("Failed to deactivate session {}: {}", session.session_id, e))?;
+
+        if let Ok(mut sessions) = self.active_sessions.lock() {
+            sessions.remove(&session.session_id);
+        }
+        Ok(())
+    }
+
+    /// Revoke all tokens for a user, returning how many sessions were ended
+    pub fn revoke_all_user_tokens(&self, user_id: Uuid, reason: String) -> Result<usize, String> {
+        log::info!("Revoking all tokens for user: {} reason: {}", user_id, reason);
+        let ended = self.end_user_sessions(user_id, None, &reason)?;
         log::info!("All tokens revoked for user: {}", user_id);
+        Ok(ended)
+    }
+
+    /// Revoke the tokens of every session of a user except `keep_session_id`, returning how
+    /// many sessions were ended
+    pub fn revoke_other_user_tokens(&self, user_id: Uuid, keep_session_id: &str, reason: String) -> Result<usize, String> {
+        log::info!("Revoking tokens of other sessions for user: {} reason: {}", user_id, reason);
+        self.end_user_sessions(user_id, Some(keep_session_id), &reason)
+    }
+
+    fn end_user_sessions(&self, user_id: Uuid, keep_session_id: Option<&str>, reason: &str) -> Result<usize, String> {
+        let kept = |session: &ActiveSession| Some(session.session_id.as_str()) == keep_session_id;
+        let mut ended = 0;
+
+        for session in self.load_user_sessions(user_id)? {
+            if !kept(&session) {
+                self.end_session(&session, reason)?;
+                ended += 1;
+            }
+        }
+
+        // Sessions whose row is already gone can still sit in the cache
+        let cached: Vec<ActiveSession> = match self.active_sessions.lock() {
+            Ok(sessions) => sessions.values().filter(|session| session.user_id == user_id && !kept(session)).cloned().collect(),
+            Err(_) => Vec::new(),
+        };
+        for session in cached {
+            self.end_session(&session, reason)?;
+            ended += 1;
+        }
+
+        Ok(ended)
+    }
+
+    /// Ends the sessions of a user whose row was just deleted, returning how many were ended
+    ///
+    /// Session and revocation rows were deleted with the user, so refreshing already fails; the
+    /// tokens are marked revoked in the cache and the sessions dropped from it, so access tokens
+    /// stop working here right away instead of when they expire.
+    pub fn end_deleted_user_sessions(&self, user_id: Uuid, sessions: &[ActiveSession]) -> usize {
+        let mut ended: Vec<ActiveSession> = sessions.to_vec();
+        if let Ok(mut cached) = self.active_sessions.lock() {
+            cached.retain(|_, session| {
+                if session.user_id != user_id {
+                    return true;
+                }
+                if !ended.iter().any(|ended| ended.session_id == session.session_id) {
+                    ended.push(session.clone());
+                }
+                false
+            });
+        }
+
+        for session in &ended {
+            self.cache_revocation(&family_key(&session.session_id), true);
+            self.cache_revocation(&session.access_token_jti, true);
+            self.cache_revocation(&session.refresh_token_jti, true);
+        }
+        log::info!("Ended {} sessions of deleted user {}", ended.len(), user_id);
+        ended.len()
     }
 
     /// Get active sessions for a user
     pub fn get_user_sessions(&self, user_id: Uuid) -> Vec<ActiveSession> {
-        if let Ok(sessions) = self.active_sessions.lock() {
-            sessions
-                .values()
-                .filter(|session| session.user_id == user_id)
-                .cloned()
-                .collect()
-        } else {
-            Vec::new()
+        match self.load_user_sessions(user_id) {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                log::error!("Failed to load sessions of user {}, using cached sessions: {}", user_id, e);
+                match self.active_sessions.lock() {
+                    Ok(sessions) => sessions.values().filter(|session| session.user_id == user_id).cloned().collect(),
+                    Err(_) => Vec::new(),
+                }
+            }
         }
     }
 
-    /// Revoke a specific session
-    pub fn revoke_session(&self, session_id: &str, reason: String) -> Result<(), String> {
+    /// Revoke one of the user's sessions
+    pub fn revoke_session(&self, user_id: Uuid, session_id: &str, reason: String) -> Result<(), String> {
         log::info!("Revoking session: {} reason: {}", session_id, reason);
 
-        if let Ok(mut sessions) = self.active_sessions.lock() {
-            if let Some(session) = sessions.remove(session_id) {
-                // Revoke associated tokens
-                self.revoke_token(
-                    &session.access_token_jti,
-                    session.user_id,
-                    "access".to_string(),
-                    reason.clone(),
-                );
-
-                self.revoke_token(
-                    &session.refresh_token_jti,
-                    session.user_id,
-                    "refresh".to_string(),
-                    reason,
-                );
-
+        let mut conn = self.db_pool.get()
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+        let session = active_sessions::table
+            .filter(active_sessions::session_id.eq(session_id))
+            .filter(active_sessions::user_id.eq(user_id))
+            .filter(active_sessions::is_active.eq(true))
+            .select(EnterpriseSession::as_select())
+            .first(&mut conn)
+            .optional()
+            .map_err(|e| format!("Failed to load session: {}", e))?;
+        drop(conn);
+
+        match session {
+            Some(session) => {
+                self.end_session(&ActiveSession::from(session), &reason)?;
                 log::info!("Session revoked successfully: {}", session_id);
                 Ok(())
-            } else {
+            }
+            None => {
                 log::warn!("Session not found: {}", session_id);
                 Err("Session not found".to_string())
             }
-        } else {
-            Err("Failed to access sessions".to_string())
         }
     }
 
     /// Clean up expired tokens and sessions
-    pub fn cleanup_expired_tokens(&self) {
+    pub fn cleanup_expired_tokens(&self) -> Result<usize, String> {
         log::info!("Cleaning up expired tokens and sessions");
         let now = Utc::now();
 
         // Clean up expired revoked tokens
-        if let Ok(mut revoked_tokens) = self.revoked_tokens.lock() {
-            revoked_tokens.retain(|_, token| token.expires_at > now);
+        let mut conn = self.db_pool.get()
+            .map_err(|e| format!("Failed to get database connection: {}", e))?;
+
+        let deleted = diesel::delete(revoked_tokens::table.filter(revoked_tokens::expires_at.lt(now)))
+            .execute(&mut conn)
+            .map_err(|e| format!("Failed to clean up revoked tokens: {}", e))?;
+
+        if let Ok(mut cache) = self.revocation_cache.lock() {
+            let now = Instant::now();
+            cache.retain(|_, cached| !cached.revoked && cached.is_fresh(now));
         }
 
         // Clean up expired sessions (inactive for more than 30 days)
@@ -435,6 +1092,7 @@ impl TokenManager {
         }
 
         log::info!("Token cleanup completed");
+        Ok(deleted)
     }
 
     /// Record token analytics
@@ -463,12 +1121,37 @@ impl TokenManager {
     pub fn get_token_statistics(&self) -> HashMap<String, u64> {
         let mut stats = HashMap::new();
 
-        if let Ok(sessions) = self.active_sessions.lock() {
-            stats.insert("active_sessions".to_string(), sessions.len() as u64);
+        let session_count = self.db_pool.get()
+            .map_err(|e| e.to_string())
+            .and_then(|mut conn| {
+                active_sessions::table
+                    .filter(active_sessions::is_active.eq(true))
+                    .filter(active_sessions::expires_at.gt(Utc::now()))
+                    .count()
+                    .get_result::<i64>(&mut conn)
+                    .map_err(|e| e.to_string())
+            });
+        match session_count {
+            Ok(count) => {
+                stats.insert("active_sessions".to_string(), count as u64);
+            }
+            Err(e) => log::error!("Failed to count active sessions: {}", e),
         }
 
-        if let Ok(revoked_tokens) = self.revoked_tokens.lock() {
-            stats.insert("revoked_tokens".to_string(), revoked_tokens.len() as u64);
+        let revoked_count = self.db_pool.get()
+            .map_err(|e| e.to_string())
+            .and_then(|mut conn| {
+                revoked_tokens::table
+                    .filter(revoked_tokens::expires_at.gt(Utc::now()))
+                    .count()
+                    .get_result::<i64>(&mut conn)
+                    .map_err(|e| e.to_string())
+            });
+        match revoked_count {
+            Ok(count) => {
+                stats.insert("revoked_tokens".to_string(), count as u64);
+            }
+            Err(e) => log::error!("Failed to count revoked tokens: {}", e),
         }
 
         if let Ok(analytics) = self.token_analytics.lock() {
@@ -487,16 +1170,25 @@ impl TokenManager {
 
 /// Token refresh endpoint
 pub async fn refresh_token(
+    http_req: actix_web::HttpRequest,
     token_manager: web::Data<Arc<TokenManager>>,
     req: web::Json<TokenRefreshRequest>,
 ) -> ActixResult<HttpResponse> {
     log::info!("Token refresh request received");
 
+    let device_id = req.device_id.as_deref()
+        .and_then(sanitize_device_id)
+        .or_else(|| device_id_from_request(&http_req));
+    let ip_address = crate::ip_controls::extract_client_ip(&http_req).map(|ip| ip.to_string());
+    let user_agent = http_req.headers()
+        .get("user-agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
     match token_manager.refresh_token_pair(
         &req.refresh_token,
-        req.device_id.clone(),
-        None, // IP address would be extracted from request headers
-        None, // User agent would be extracted from request headers
+        device_id,
+        ip_address,
+        user_agent,
     ) {
         Ok(token_pair) => {
             log::info!("Token refresh successful");
@@ -527,12 +1219,18 @@ pub async fn revoke_token(
         "access" | "refresh" => {
             // Validate and extract JTI from the provided token
             if let Ok(token_claims) = token_manager.validate_enhanced_token(&req.token) {
-                token_manager.revoke_token(
+                if let Err(e) = token_manager.revoke_token(
                     &token_claims.jti,
                     user_id,
                     req.token_type.clone(),
                     reason,
-                );
+                ) {
+                    log::error!("Token revocation failed: {}", e);
+                    return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                        "error": "Token revocation failed",
+                        "message": "Failed to revoke token"
+                    })));
+                }
                 Ok(HttpResponse::Ok().json(serde_json::json!({
                     "success": true,
                     "message": "Token revoked successfully"
@@ -545,7 +1243,13 @@ pub async fn revoke_token(
             }
         }
         "all" => {
-            token_manager.revoke_all_user_tokens(user_id, reason);
+            if let Err(e) = token_manager.revoke_all_user_tokens(user_id, reason) {
+                log::error!("Token revocation failed: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Token revocation failed",
+                    "message": "Failed to revoke tokens"
+                })));
+            }
             Ok(HttpResponse::Ok().json(serde_json::json!({
                 "success": true,
                 "message": "All tokens revoked successfully"
@@ -579,7 +1283,7 @@ pub async fn manage_sessions(
         }
         "revoke" => {
             if let Some(session_id) = &req.session_id {
-                match token_manager.revoke_session(session_id, "user_request".to_string()) {
+                match token_manager.revoke_session(user_id, session_id, "user_request".to_string()) {
                     Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
                         "success": true,
                         "message": "Session revoked successfully"
@@ -597,7 +1301,13 @@ pub async fn manage_sessions(
             }
         }
         "revoke_all" => {
-            token_manager.revoke_all_user_tokens(user_id, "user_request".to_string());
+            if let Err(e) = token_manager.revoke_all_user_tokens(user_id, "user_request".to_string()) {
+                log::error!("Session revocation failed: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Session revocation failed",
+                    "message": "Failed to revoke sessions"
+                })));
+            }
             Ok(HttpResponse::Ok().json(serde_json::json!({
                 "success": true,
                 "message": "All sessions revoked successfully"
@@ -612,46 +1322,55 @@ pub async fn manage_sessions(
     }
 }
 
-/// Token analytics endpoint
+/// Token analytics endpoint for the caller's own tokens
+///
+/// Server-wide statistics are only available to admins, through `GET /auth/enhanced/statistics`.
 pub async fn get_token_analytics(
+    req: actix_web::HttpRequest,
     token_manager: web::Data<Arc<TokenManager>>,
-    _claims: web::ReqData<EnhancedClaims>,
     query: web::Query<HashMap<String, String>>,
 ) -> ActixResult<HttpResponse> {
     log::info!("Token analytics request received");
 
-    let user_id = _claims.sub;
+    let user_id = crate::auth::authenticate(&req)?;
     let days = query
         .get("days")
         .and_then(|d| d.parse::<i64>().ok())
         .unwrap_or(30);
 
     let analytics = token_manager.get_user_analytics(user_id, days);
-    let statistics = token_manager.get_token_statistics();
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "analytics": analytics,
-        "statistics": statistics,
         "period_days": days
     })))
 }
 
 /// Cleanup endpoint (admin only)
 pub async fn cleanup_tokens(
+    req: actix_web::HttpRequest,
     token_manager: web::Data<Arc<TokenManager>>,
-    _claims: web::ReqData<EnhancedClaims>,
 ) -> ActixResult<HttpResponse> {
     log::info!("Token cleanup request received");
 
-    // TODO: Add admin role check
-    // For now, allow any authenticated user to trigger cleanup
-    
-    token_manager.cleanup_expired_tokens();
+    if let Err(response) = crate::auth::require_admin(&req) {
+        return Ok(response);
+    }
     
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": "Token cleanup completed successfully"
-    })))
+    match token_manager.cleanup_expired_tokens() {
+        Ok(removed) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "message": "Token cleanup completed successfully",
+            "revocations_removed": removed
+        }))),
+        Err(e) => {
+            log::error!("Token cleanup failed: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Token cleanup failed",
+                "message": "Failed to clean up expired tokens"
+            })))
+        }
+    }
 }
 
 /// Configure token management routes
@@ -665,4 +1384,107 @@ pub fn configure_token_routes(cfg: &mut web::ServiceConfig) {
             .route("/analytics", web::get().to(get_token_analytics))
             .route("/cleanup", web::post().to(cleanup_tokens))
     );
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revocation_cache_freshness() {
+        let now = Instant::now();
+        let revoked = CachedRevocation { revoked: true, checked_at: now };
+        let not_revoked = CachedRevocation { revoked: false, checked_at: now };
+
+        assert!(not_revoked.is_fresh(now));
+        assert!(!not_revoked.is_fresh(now + REVOCATION_CACHE_TTL));
+        // Positive results never need re-checking
+        assert!(revoked.is_fresh(now + REVOCATION_CACHE_TTL * 100));
+    }
+
+    fn session(session_id: &str, user_id: Uuid) -> ActiveSession {
+        ActiveSession {
+            session_id: session_id.to_string(),
+            user_id,
+            access_token_jti: format!("{}-access", session_id),
+            refresh_token_jti: format!("{}-refresh", session_id),
+            created_at: Utc::now(),
+            last_activity: Utc::now(),
+            ip_address: None,
+            user_agent: None,
+            device_fingerprint: None,
+            device_name: None,
+            device_type: None,
+        }
+    }
+
+    #[test]
+    fn test_end_deleted_user_sessions() {
+        // Never connects: every lookup below is answered from the caches
+        let manager = diesel::r2d2::ConnectionManager::<diesel::PgConnection>::new("postgres://unused");
+        let tokens = TokenManager::new(r2d2::Pool::builder().build_unchecked(manager));
+        let (deleted, other) = (Uuid::new_v4(), Uuid::new_v4());
+        {
+            let mut cached = tokens.active_sessions.lock().unwrap();
+            for session in [session("cached", deleted), session("loaded", deleted), session("other", other)] {
+                cached.insert(session.session_id.clone(), session);
+            }
+        }
+
+        assert_eq!(tokens.end_deleted_user_sessions(deleted, &[session("loaded", deleted), session("db-only", deleted)]), 3);
+
+        for jti in ["cached-access", "loaded-refresh", "db-only-access", "family:db-only"] {
+            assert!(tokens.is_token_revoked(jti).unwrap(), "{} should be revoked", jti);
+        }
+        let cached = tokens.active_sessions.lock().unwrap();
+        assert_eq!(cached.keys().collect::<Vec<_>>(), vec!["other"]);
+    }
+
+    #[test]
+    fn test_enhanced_token_pair_shares_its_session() {
+        std::env::set_var("JWT_SECRET", "test-jwt-secret");
+        let user_id = Uuid::new_v4();
+        let issued = encode_enhanced_token_pair(user_id, "session-1", Some("laptop-1234".to_string()), Some("203.0.113.0/24".to_string())).unwrap();
+
+        let access = decode_enhanced_token(&issued.tokens.access_token).unwrap();
+        let refresh = decode_enhanced_token(&issued.tokens.refresh_token).unwrap();
+        assert_eq!((access.sub, access.token_type.as_str(), access.jti.as_str()), (user_id, "access", issued.access_jti.as_str()));
+        assert_eq!((refresh.token_type.as_str(), refresh.jti.as_str()), ("refresh", issued.refresh_jti.as_str()));
+        assert_eq!(access.session_id, refresh.session_id);
+        // Only the refresh token is checked against where it was issued
+        assert_eq!(refresh.network.as_deref(), Some("203.0.113.0/24"));
+        assert_eq!(access.network, None);
+        assert_eq!(refresh.device_id.as_deref(), Some("laptop-1234"));
+
+        assert!(decode_enhanced_token("not-a-jwt").is_err());
+    }
+
+    #[test]
+    fn test_sanitize_device_id() {
+        let id = Uuid::new_v4().to_string();
+        assert_eq!(sanitize_device_id(&id), Some(id.clone()));
+        assert_eq!(sanitize_device_id(&format!(" {} ", id)), Some(id));
+        assert_eq!(sanitize_device_id("ios:ABCDEF12.v2_x"), Some("ios:ABCDEF12.v2_x".to_string()));
+
+        assert_eq!(sanitize_device_id("short"), None);
+        assert_eq!(sanitize_device_id(&"a".repeat(129)), None);
+        assert_eq!(sanitize_device_id("device id with spaces"), None);
+        assert_eq!(sanitize_device_id("<script>alert(1)</script>"), None);
+    }
+
+    #[test]
+    fn test_idle_timeout_exceeded() {
+        let now = Utc::now();
+        let timeout = Duration::minutes(15);
+        assert!(!idle_timeout_exceeded(now, now, timeout));
+        assert!(!idle_timeout_exceeded(now - Duration::minutes(15), now, timeout));
+        assert!(idle_timeout_exceeded(now - Duration::minutes(16), now, timeout));
+    }
+
+    #[test]
+    fn test_activity_write_interval() {
+        let now = Utc::now();
+        assert!(!activity_write_due(now, now));
+        assert!(!activity_write_due(now - ACTIVITY_WRITE_INTERVAL + Duration::seconds(1), now));
+        assert!(activity_write_due(now - ACTIVITY_WRITE_INTERVAL, now));
+    }
+}
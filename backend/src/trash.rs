@@ -0,0 +1,76 @@
+//! Trash module for purging soft-deleted passwords once their retention period is over
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use std::env;
+use uuid::Uuid;
+
+/// Days a deleted password stays restorable when TRASH_RETENTION_DAYS isn't set
+pub const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+
+/// Days a deleted password stays in the trash (TRASH_RETENTION_DAYS)
+pub fn retention_days() -> i64 {
+    parse_retention_days(env::var("TRASH_RETENTION_DAYS").ok().as_deref())
+}
+
+fn parse_retention_days(value: Option<&str>) -> i64 {
+    match value.map(|v| v.trim().parse::<i64>()) {
+        Some(Ok(days)) if days >= 0 => days,
+        Some(_) => {
+            log::warn!("Invalid TRASH_RETENTION_DAYS, using {} days", DEFAULT_TRASH_RETENTION_DAYS);
+            DEFAULT_TRASH_RETENTION_DAYS
+        }
+        None => DEFAULT_TRASH_RETENTION_DAYS,
+    }
+}
+
+/// The deletion time before which trashed passwords are purged
+pub fn purge_cutoff(now: NaiveDateTime, retention_days: i64) -> NaiveDateTime {
+    now - Duration::days(retention_days)
+}
+
+/// Permanently deletes trashed passwords older than the cutoff, for one user or for everyone
+///
+/// Shares don't cascade, so they are removed first; history rows cascade with the password.
+pub fn purge_expired(conn: &mut PgConnection, user_id: Option<Uuid>, cutoff: NaiveDateTime) -> QueryResult<usize> {
+    use crate::schema::{passwords, shares};
+
+    conn.transaction(|conn| {
+        let expired = passwords::table
+            .filter(passwords::deleted_at.lt(cutoff))
+            .select(passwords::id);
+        // Lock the rows so a concurrent restore can't bring back an entry whose shares are gone
+        let expired = match user_id {
+            Some(user_id) => expired.filter(passwords::user_id.eq(user_id)).for_update().load::<Uuid>(conn)?,
+            None => expired.for_update().load::<Uuid>(conn)?,
+        };
+        if expired.is_empty() {
+            return Ok(0);
+        }
+
+        diesel::delete(shares::table.filter(shares::password_id.eq_any(&expired))).execute(conn)?;
+        diesel::delete(passwords::table.filter(passwords::id.eq_any(&expired))).execute(conn)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retention_days() {
+        assert_eq!(parse_retention_days(None), DEFAULT_TRASH_RETENTION_DAYS);
+        assert_eq!(parse_retention_days(Some("7")), 7);
+        assert_eq!(parse_retention_days(Some(" 0 ")), 0);
+        assert_eq!(parse_retention_days(Some("-1")), DEFAULT_TRASH_RETENTION_DAYS);
+        assert_eq!(parse_retention_days(Some("soon")), DEFAULT_TRASH_RETENTION_DAYS);
+    }
+
+    #[test]
+    fn test_purge_cutoff() {
+        let now = chrono::Utc::now().naive_utc();
+        assert_eq!(purge_cutoff(now, 0), now);
+        assert_eq!(purge_cutoff(now, 30), now - Duration::days(30));
+    }
+}
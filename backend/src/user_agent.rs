@@ -0,0 +1,100 @@
+//! User agent module for describing the device behind a session
+
+use serde::Serialize;
+
+/// Browser, operating system and kind of device read from a User-Agent header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceInfo {
+    pub browser: Option<&'static str>,
+    pub os: Option<&'static str>,
+    /// One of the `active_sessions.device_type` values: "web", "mobile" or "api"
+    pub device_type: &'static str,
+}
+
+impl DeviceInfo {
+    /// Human-friendly description such as "Firefox on Windows"
+    pub fn label(&self) -> String {
+        match (self.browser, self.os) {
+            (Some(browser), Some(os)) => format!("{} on {}", browser, os),
+            (Some(name), None) | (None, Some(name)) => name.to_string(),
+            (None, None) => "API client".to_string(),
+        }
+    }
+}
+
+/// Parses the parts of a User-Agent header that are useful for telling sessions apart
+pub fn parse(user_agent: &str) -> DeviceInfo {
+    // Order matters: most browsers also claim to be the ones they are derived from
+    let browser = if user_agent.contains("Edg/") || user_agent.contains("EdgA/") || user_agent.contains("EdgiOS/") {
+        Some("Edge")
+    } else if user_agent.contains("OPR/") || user_agent.contains("Opera") {
+        Some("Opera")
+    } else if user_agent.contains("Firefox/") || user_agent.contains("FxiOS/") {
+        Some("Firefox")
+    } else if user_agent.contains("Chrome/") || user_agent.contains("CriOS/") {
+        Some("Chrome")
+    } else if user_agent.contains("Safari/") {
+        Some("Safari")
+    } else {
+        None
+    };
+
+    let os = if user_agent.contains("iPhone") || user_agent.contains("iPad") {
+        Some("iOS")
+    } else if user_agent.contains("Android") {
+        Some("Android")
+    } else if user_agent.contains("Windows") {
+        Some("Windows")
+    } else if user_agent.contains("CrOS") {
+        Some("ChromeOS")
+    } else if user_agent.contains("Mac OS X") || user_agent.contains("Macintosh") {
+        Some("macOS")
+    } else if user_agent.contains("Linux") {
+        Some("Linux")
+    } else {
+        None
+    };
+
+    let device_type = if browser.is_none() {
+        "api"
+    } else if matches!(os, Some("iOS") | Some("Android")) || user_agent.contains("Mobile") {
+        "mobile"
+    } else {
+        "web"
+    };
+
+    DeviceInfo { browser, os, device_type }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_common_browsers() {
+        let firefox = parse("Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:128.0) Gecko/20100101 Firefox/128.0");
+        assert_eq!(firefox, DeviceInfo { browser: Some("Firefox"), os: Some("Windows"), device_type: "web" });
+        assert_eq!(firefox.label(), "Firefox on Windows");
+
+        let edge = parse("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36 Edg/126.0.0.0");
+        assert_eq!(edge.label(), "Edge on Windows");
+
+        let chrome = parse("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36");
+        assert_eq!(chrome.label(), "Chrome on macOS");
+
+        let safari = parse("Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1");
+        assert_eq!(safari, DeviceInfo { browser: Some("Safari"), os: Some("iOS"), device_type: "mobile" });
+
+        let android = parse("Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Mobile Safari/537.36");
+        assert_eq!(android.label(), "Chrome on Android");
+        assert_eq!(android.device_type, "mobile");
+    }
+
+    #[test]
+    fn test_parse_non_browsers() {
+        let curl = parse("curl/8.5.0");
+        assert_eq!(curl, DeviceInfo { browser: None, os: None, device_type: "api" });
+        assert_eq!(curl.label(), "API client");
+        assert_eq!(parse("").label(), "API client");
+    }
+}
@@ -0,0 +1,393 @@
+//! WebAuthn module for passkey registration and login
+//!
+//! A signed-in user enrolls passkeys in a two-step ceremony: the server hands out creation
+//! options, the browser has the authenticator create a key pair, and the server stores the
+//! resulting public key. Logging in with a passkey works the same way with a signed challenge.
+//! The state between the two steps of a ceremony is kept in the database, so any instance can
+//! finish a ceremony another started, and each ceremony can only be finished once. Ceremonies
+//! nobody finished are removed by the background cleanup once they expire.
+
+use base64::Engine;
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::Serialize;
+use std::env;
+use std::sync::OnceLock;
+use uuid::Uuid;
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, CredentialID, Passkey, PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
+    RegisterPublicKeyCredential, RequestChallengeResponse, Url, Webauthn, WebauthnBuilder,
+};
+
+/// How long a started registration or login can be finished
+pub const CEREMONY_EXPIRY_MINUTES: i64 = 5;
+
+/// Longest name a passkey can be given
+const MAX_PASSKEY_NAME_CHARS: usize = 64;
+
+const REGISTRATION: &str = "registration";
+const AUTHENTICATION: &str = "authentication";
+
+/// Why a ceremony couldn't be finished
+#[derive(Debug, PartialEq)]
+pub enum CeremonyError {
+    /// No such ceremony, or it expired or was already finished
+    Expired,
+    /// The authenticator's response didn't verify, or names an unknown or duplicate passkey
+    Rejected(String),
+    /// Something went wrong on the server
+    Failed(String),
+}
+
+impl From<diesel::result::Error> for CeremonyError {
+    fn from(e: diesel::result::Error) -> Self {
+        CeremonyError::Failed(format!("Database error: {}", e))
+    }
+}
+
+/// The relying party, configured from WEBAUTHN_RP_ORIGIN and WEBAUTHN_RP_ID
+pub fn webauthn() -> Result<&'static Webauthn, String> {
+    static WEBAUTHN: OnceLock<Result<Webauthn, String>> = OnceLock::new();
+    WEBAUTHN
+        .get_or_init(|| {
+            let origin = env::var("WEBAUTHN_RP_ORIGIN")
+                .or_else(|_| env::var("FRONTEND_BASE_URL"))
+                .unwrap_or_else(|_| "http://localhost".to_string());
+            let (rp_id, origin) = relying_party(env::var("WEBAUTHN_RP_ID").ok().as_deref(), &origin)?;
+            WebauthnBuilder::new(&rp_id, &origin)
+                .and_then(|builder| builder.rp_name("PassQ").build())
+                .map_err(|e| format!("Invalid WebAuthn relying party {} for {}: {}", rp_id, origin, e))
+        })
+        .as_ref()
+        .map_err(|e| e.clone())
+}
+
+/// The relying party id and origin; the id defaults to the origin's host
+fn relying_party(rp_id: Option<&str>, origin: &str) -> Result<(String, Url), String> {
+    let origin = Url::parse(origin.trim()).map_err(|e| format!("Invalid WebAuthn origin {}: {}", origin, e))?;
+    let rp_id = match rp_id.map(str::trim).filter(|id| !id.is_empty()) {
+        Some(rp_id) => rp_id.to_string(),
+        None => origin.host_str().ok_or_else(|| format!("WebAuthn origin {} has no host", origin))?.to_string(),
+    };
+    Ok((rp_id, origin))
+}
+
+/// How a credential id is stored and looked up
+fn credential_key(credential_id: &CredentialID) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(credential_id.as_ref())
+}
+
+/// Trims a user-chosen passkey name, falling back to a generic one
+pub fn passkey_name(name: Option<&str>) -> String {
+    let name: String = name.unwrap_or("").trim().chars().filter(|c| !c.is_control()).take(MAX_PASSKEY_NAME_CHARS).collect();
+    if name.is_empty() {
+        "Passkey".to_string()
+    } else {
+        name
+    }
+}
+
+/// Whether starting a ceremony of this kind cancels the user's earlier ones
+///
+/// Only a signed-in user can start a registration, so a new one replaces theirs. Anyone can start
+/// a login for any username, so logins run side by side; otherwise a stranger could keep
+/// cancelling the user's login by starting new ones.
+fn replaces_earlier(kind: &str) -> bool {
+    kind == REGISTRATION
+}
+
+/// Stores the state of a started ceremony
+fn store_ceremony<T: Serialize>(conn: &mut PgConnection, user_id: Uuid, kind: &str, state: &T, now: NaiveDateTime) -> Result<Uuid, String> {
+    use crate::schema::webauthn_ceremonies;
+
+    let state = serde_json::to_string(state).map_err(|e| format!("Failed to store WebAuthn state: {}", e))?;
+    let ceremony_id = Uuid::new_v4();
+    conn.transaction(|conn| {
+        if replaces_earlier(kind) {
+            diesel::delete(
+                webauthn_ceremonies::table
+                    .filter(webauthn_ceremonies::user_id.eq(user_id))
+                    .filter(webauthn_ceremonies::kind.eq(kind)),
+            )
+            .execute(conn)?;
+        }
+
+        diesel::insert_into(webauthn_ceremonies::table)
+            .values((
+                webauthn_ceremonies::id.eq(ceremony_id),
+                webauthn_ceremonies::user_id.eq(user_id),
+                webauthn_ceremonies::kind.eq(kind),
+                webauthn_ceremonies::state.eq(state),
+                webauthn_ceremonies::expires_at.eq(now + Duration::minutes(CEREMONY_EXPIRY_MINUTES)),
+                webauthn_ceremonies::created_at.eq(now),
+            ))
+            .execute(conn)
+    })
+    .map_err(|e| format!("Failed to store WebAuthn ceremony: {}", e))?;
+
+    Ok(ceremony_id)
+}
+
+/// Removes a ceremony and returns its user and state, if it exists and hasn't expired
+///
+/// Deleting first means a ceremony can't be finished twice, even by concurrent requests.
+fn take_ceremony(conn: &mut PgConnection, ceremony_id: Uuid, kind: &str, now: NaiveDateTime) -> Result<(Uuid, String), CeremonyError> {
+    use crate::schema::webauthn_ceremonies;
+
+    let ceremony = diesel::delete(
+        webauthn_ceremonies::table
+            .filter(webauthn_ceremonies::id.eq(ceremony_id))
+            .filter(webauthn_ceremonies::kind.eq(kind)),
+    )
+    .returning((webauthn_ceremonies::user_id, webauthn_ceremonies::state, webauthn_ceremonies::expires_at))
+    .get_result::<(Uuid, String, NaiveDateTime)>(conn)
+    .optional()?;
+
+    match ceremony {
+        Some((user_id, state, expires_at)) if expires_at > now => Ok((user_id, state)),
+        _ => Err(CeremonyError::Expired),
+    }
+}
+
+/// Deletes every ceremony that can no longer be finished, returning how many were removed
+pub fn purge_expired_ceremonies(conn: &mut PgConnection, now: NaiveDateTime) -> QueryResult<usize> {
+    use crate::schema::webauthn_ceremonies;
+
+    // The complement of the check in `take_ceremony`
+    diesel::delete(webauthn_ceremonies::table.filter(webauthn_ceremonies::expires_at.le(now))).execute(conn)
+}
+
+/// A registered passkey as its owner sees it
+#[derive(Queryable, Serialize, Debug)]
+pub struct PasskeySummary {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+/// The user's passkeys, oldest first
+pub fn list_passkeys(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<Vec<PasskeySummary>> {
+    use crate::schema::webauthn_credentials;
+
+    webauthn_credentials::table
+        .filter(webauthn_credentials::user_id.eq(user_id))
+        .order(webauthn_credentials::created_at.asc())
+        .select((
+            webauthn_credentials::id,
+            webauthn_credentials::name,
+            webauthn_credentials::created_at,
+            webauthn_credentials::last_used_at,
+        ))
+        .load(conn)
+}
+
+/// Removes one of the user's passkeys, returning its name if it existed
+pub fn delete_passkey(conn: &mut PgConnection, user_id: Uuid, passkey_id: Uuid) -> QueryResult<Option<String>> {
+    use crate::schema::webauthn_credentials;
+
+    diesel::delete(
+        webauthn_credentials::table
+            .filter(webauthn_credentials::id.eq(passkey_id))
+            .filter(webauthn_credentials::user_id.eq(user_id)),
+    )
+    .returning(webauthn_credentials::name)
+    .get_result(conn)
+    .optional()
+}
+
+/// A user's passkeys with the ids of their rows
+fn load_passkeys(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<Vec<(Uuid, Passkey)>> {
+    use crate::schema::webauthn_credentials;
+
+    let rows = webauthn_credentials::table
+        .filter(webauthn_credentials::user_id.eq(user_id))
+        .select((webauthn_credentials::id, webauthn_credentials::passkey))
+        .load::<(Uuid, String)>(conn)?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(id, passkey)| match serde_json::from_str(&passkey) {
+            Ok(passkey) => Some((id, passkey)),
+            Err(e) => {
+                log::error!("Skipping unreadable passkey {}: {}", id, e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Starts enrolling a passkey, excluding the ones the user already has
+pub fn start_registration(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    username: &str,
+    display_name: &str,
+    now: NaiveDateTime,
+) -> Result<(Uuid, CreationChallengeResponse), String> {
+    let existing = load_passkeys(conn, user_id).map_err(|e| format!("Database error: {}", e))?;
+    let exclude: Vec<CredentialID> = existing.iter().map(|(_, passkey)| passkey.cred_id().clone()).collect();
+
+    let (options, state) = webauthn()?
+        .start_passkey_registration(user_id, username, display_name, Some(exclude))
+        .map_err(|e| format!("Failed to start passkey registration: {}", e))?;
+    let ceremony_id = store_ceremony::<PasskeyRegistration>(conn, user_id, REGISTRATION, &state, now)?;
+
+    Ok((ceremony_id, options))
+}
+
+/// Verifies the authenticator's response and stores the new passkey, returning its id
+pub fn finish_registration(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    ceremony_id: Uuid,
+    name: &str,
+    credential: &RegisterPublicKeyCredential,
+    now: NaiveDateTime,
+) -> Result<Uuid, CeremonyError> {
+    use crate::schema::webauthn_credentials;
+
+    let (ceremony_user, state) = take_ceremony(conn, ceremony_id, REGISTRATION, now)?;
+    if ceremony_user != user_id {
+        return Err(CeremonyError::Expired);
+    }
+    let state: PasskeyRegistration = serde_json::from_str(&state).map_err(|e| CeremonyError::Failed(format!("Unreadable WebAuthn state: {}", e)))?;
+
+    let passkey = webauthn()
+        .map_err(CeremonyError::Failed)?
+        .finish_passkey_registration(credential, &state)
+        .map_err(|e| CeremonyError::Rejected(format!("Passkey registration failed: {}", e)))?;
+    let stored = serde_json::to_string(&passkey).map_err(|e| CeremonyError::Failed(format!("Failed to store passkey: {}", e)))?;
+
+    let id = Uuid::new_v4();
+    let inserted = diesel::insert_into(webauthn_credentials::table)
+        .values((
+            webauthn_credentials::id.eq(id),
+            webauthn_credentials::user_id.eq(user_id),
+            webauthn_credentials::credential_id.eq(credential_key(passkey.cred_id())),
+            webauthn_credentials::passkey.eq(stored),
+            webauthn_credentials::name.eq(name),
+            webauthn_credentials::created_at.eq(now),
+        ))
+        .on_conflict(webauthn_credentials::credential_id)
+        .do_nothing()
+        .execute(conn)?;
+    if inserted == 0 {
+        return Err(CeremonyError::Rejected("This passkey is already registered".to_string()));
+    }
+
+    Ok(id)
+}
+
+/// Starts a passkey login for a user, or returns None if they have no passkeys
+pub fn start_authentication(conn: &mut PgConnection, user_id: Uuid, now: NaiveDateTime) -> Result<Option<(Uuid, RequestChallengeResponse)>, String> {
+    let passkeys: Vec<Passkey> = load_passkeys(conn, user_id)
+        .map_err(|e| format!("Database error: {}", e))?
+        .into_iter()
+        .map(|(_, passkey)| passkey)
+        .collect();
+    if passkeys.is_empty() {
+        return Ok(None);
+    }
+
+    let (options, state) = webauthn()?
+        .start_passkey_authentication(&passkeys)
+        .map_err(|e| format!("Failed to start passkey login: {}", e))?;
+    let ceremony_id = store_ceremony::<PasskeyAuthentication>(conn, user_id, AUTHENTICATION, &state, now)?;
+
+    Ok(Some((ceremony_id, options)))
+}
+
+/// The user a passkey login ceremony belongs to, without finishing it
+pub fn ceremony_user(conn: &mut PgConnection, ceremony_id: Uuid) -> QueryResult<Option<Uuid>> {
+    use crate::schema::webauthn_ceremonies;
+
+    webauthn_ceremonies::table
+        .filter(webauthn_ceremonies::id.eq(ceremony_id))
+        .filter(webauthn_ceremonies::kind.eq(AUTHENTICATION))
+        .select(webauthn_ceremonies::user_id)
+        .first(conn)
+        .optional()
+}
+
+/// Verifies a signed login challenge and returns the user it logs in
+///
+/// The passkey's signature counter and backup state are saved, so a cloned authenticator
+/// replaying an old counter is refused.
+pub fn finish_authentication(
+    conn: &mut PgConnection,
+    ceremony_id: Uuid,
+    credential: &PublicKeyCredential,
+    now: NaiveDateTime,
+) -> Result<Uuid, CeremonyError> {
+    use crate::schema::webauthn_credentials;
+
+    let (user_id, state) = take_ceremony(conn, ceremony_id, AUTHENTICATION, now)?;
+    let state: PasskeyAuthentication = serde_json::from_str(&state).map_err(|e| CeremonyError::Failed(format!("Unreadable WebAuthn state: {}", e)))?;
+
+    let result = webauthn()
+        .map_err(CeremonyError::Failed)?
+        .finish_passkey_authentication(credential, &state)
+        .map_err(|e| CeremonyError::Rejected(format!("Passkey login failed: {}", e)))?;
+
+    let (row_id, mut passkey) = load_passkeys(conn, user_id)?
+        .into_iter()
+        .find(|(_, passkey)| passkey.cred_id() == result.cred_id())
+        .ok_or_else(|| CeremonyError::Rejected("Unknown passkey".to_string()))?;
+
+    let mut stored = None;
+    if passkey.update_credential(&result) == Some(true) {
+        stored = Some(serde_json::to_string(&passkey).map_err(|e| CeremonyError::Failed(format!("Failed to store passkey: {}", e)))?);
+    }
+    diesel::update(webauthn_credentials::table.find(row_id))
+        .set((
+            webauthn_credentials::last_used_at.eq(Some(now)),
+            stored.map(|stored| webauthn_credentials::passkey.eq(stored)),
+        ))
+        .execute(conn)?;
+
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relying_party() {
+        let (rp_id, origin) = relying_party(None, "https://vault.example.com").unwrap();
+        assert_eq!(rp_id, "vault.example.com");
+        assert_eq!(origin.as_str(), "https://vault.example.com/");
+
+        let (rp_id, _) = relying_party(Some("example.com"), "https://vault.example.com").unwrap();
+        assert_eq!(rp_id, "example.com");
+
+        assert!(relying_party(None, "not a url").is_err());
+    }
+
+    #[test]
+    fn test_relying_party_builds() {
+        let (rp_id, origin) = relying_party(None, "http://localhost:3000").unwrap();
+        assert!(WebauthnBuilder::new(&rp_id, &origin).is_ok());
+
+        // The id must be the origin's host or a parent domain of it
+        let (rp_id, origin) = relying_party(Some("other.example"), "https://vault.example.com").unwrap();
+        assert!(WebauthnBuilder::new(&rp_id, &origin).is_err());
+    }
+
+    #[test]
+    fn test_only_registrations_replace_earlier_ceremonies() {
+        assert!(replaces_earlier(REGISTRATION));
+        assert!(!replaces_earlier(AUTHENTICATION));
+    }
+
+    #[test]
+    fn test_passkey_name() {
+        assert_eq!(passkey_name(Some("  YubiKey 5C ")), "YubiKey 5C");
+        assert_eq!(passkey_name(Some("")), "Passkey");
+        assert_eq!(passkey_name(None), "Passkey");
+        assert_eq!(passkey_name(Some("Laptop\r\nTouch ID")), "LaptopTouch ID");
+        assert_eq!(passkey_name(Some(&"k".repeat(100))).chars().count(), MAX_PASSKEY_NAME_CHARS);
+    }
+}
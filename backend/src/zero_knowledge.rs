@@ -9,15 +9,48 @@ use log;
 use base64::{Engine as _, engine::general_purpose};
 
 /// Client-side encryption parameters
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct EncryptionParams {
     pub salt: String,           // Base64 encoded salt for key derivation
     pub iterations: u32,        // PBKDF2 iterations
     pub nonce: String,          // Base64 encoded nonce for encryption
 }
 
+/// Fewest PBKDF2 iterations accepted from a client (OWASP's recommendation for PBKDF2-HMAC-SHA256 is higher still)
+pub const MIN_PBKDF2_ITERATIONS: u32 = 100_000;
+/// Most PBKDF2 iterations accepted, so verifying a blob can't be turned into a CPU sink
+pub const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+const MIN_SALT_BYTES: usize = 16;
+const NONCE_BYTES: usize = 12; // 96-bit nonce for AES-GCM
+
+impl EncryptionParams {
+    /// Checks that client-supplied key derivation parameters are strong and well-formed
+    pub fn validate(&self) -> Result<(), String> {
+        if self.iterations < MIN_PBKDF2_ITERATIONS {
+            return Err(format!("PBKDF2 iterations must be at least {}", MIN_PBKDF2_ITERATIONS));
+        }
+        if self.iterations > MAX_PBKDF2_ITERATIONS {
+            return Err(format!("PBKDF2 iterations must be at most {}", MAX_PBKDF2_ITERATIONS));
+        }
+
+        let salt = general_purpose::STANDARD.decode(&self.salt)
+            .map_err(|_| "Salt must be valid base64".to_string())?;
+        if salt.len() < MIN_SALT_BYTES {
+            return Err(format!("Salt must be at least {} bytes", MIN_SALT_BYTES));
+        }
+
+        let nonce = general_purpose::STANDARD.decode(&self.nonce)
+            .map_err(|_| "Nonce must be valid base64".to_string())?;
+        if nonce.len() != NONCE_BYTES {
+            return Err(format!("Nonce must be exactly {} bytes", NONCE_BYTES));
+        }
+
+        Ok(())
+    }
+}
+
 /// Encrypted data structure sent from client
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct EncryptedData {
     pub data: String,           // Base64 encoded encrypted data
     pub params: EncryptionParams,
@@ -79,7 +112,7 @@ impl ZeroKnowledgeManager {
 
     /// Generate a cryptographically secure nonce
     pub fn generate_nonce(&self) -> Result<Vec<u8>, String> {
-        let mut nonce = [0u8; 12]; // 96-bit nonce for AES-GCM
+        let mut nonce = [0u8; NONCE_BYTES];
         self.system_random.fill(&mut nonce)
             .map_err(|e| format!("Failed to generate nonce: {}", e))?;
         Ok(nonce.to_vec())
@@ -121,25 +154,12 @@ impl ZeroKnowledgeManager {
         let data = general_purpose::STANDARD.decode(&encrypted_data.data)
             .map_err(|e| format!("Invalid base64 data: {}", e))?;
         
-        // Verify minimum length (nonce + tag + at least 1 byte of data)
-        if data.len() < 12 + 16 + 1 {
-            return Ok(false);
-        }
-        
-        // Verify nonce format
-        general_purpose::STANDARD.decode(&encrypted_data.params.nonce)
-            .map_err(|e| format!("Invalid nonce format: {}", e))?;
-        
-        // Verify salt format
-        general_purpose::STANDARD.decode(&encrypted_data.params.salt)
-            .map_err(|e| format!("Invalid salt format: {}", e))?;
-        
-        // Verify iterations are within acceptable range
-        if encrypted_data.params.iterations < 10000 || encrypted_data.params.iterations > 1000000 {
+        // Verify minimum length (tag + at least 1 byte of data); the nonce travels in params
+        if data.len() < 16 + 1 {
             return Ok(false);
         }
         
-        Ok(true)
+        Ok(encrypted_data.params.validate().is_ok())
     }
 
     /// Validate zero-knowledge authentication response
@@ -179,7 +199,7 @@ impl ZeroKnowledgeManager {
         
         Ok(EncryptionParams {
             salt: general_purpose::STANDARD.encode(salt),
-            iterations: 100000, // OWASP recommended minimum
+            iterations: MIN_PBKDF2_ITERATIONS,
             nonce: general_purpose::STANDARD.encode(nonce),
         })
     }
@@ -218,6 +238,21 @@ impl ZeroKnowledgeManager {
     }
 }
 
+/// Validates a client-encrypted blob and serializes it for storage in `encrypted_password`
+pub fn encode_client_blob(encrypted_data: &EncryptedData) -> Result<Vec<u8>, String> {
+    encrypted_data.params.validate()
+        .map_err(|e| format!("Invalid encryption parameters: {}", e))?;
+    if !ZeroKnowledgeManager::new().verify_encrypted_data(encrypted_data)? {
+        return Err("Client-encrypted data is too short to hold a ciphertext and tag".to_string());
+    }
+    serde_json::to_vec(encrypted_data).map_err(|e| format!("Failed to serialize client-encrypted data: {}", e))
+}
+
+/// Reads back a client-encrypted blob exactly as the client sent it
+pub fn decode_client_blob(stored: &[u8]) -> Result<EncryptedData, String> {
+    serde_json::from_slice(stored).map_err(|e| format!("Stored client-encrypted data is corrupt: {}", e))
+}
+
 /// Client-side encryption utilities (for reference/documentation)
 /// These functions should be implemented in JavaScript on the client side
 pub mod client_side_reference {
@@ -363,4 +398,37 @@ mod tests {
         assert!(!params.nonce.is_empty());
         assert_eq!(params.iterations, 100000);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_client_blob_round_trips_verbatim() {
+        let blob = EncryptedData {
+            data: general_purpose::STANDARD.encode([7u8; 24]),
+            params: ZeroKnowledgeManager::new().generate_encryption_params().unwrap(),
+        };
+        let stored = encode_client_blob(&blob).unwrap();
+        assert_eq!(decode_client_blob(&stored).unwrap(), blob);
+
+        let weak = EncryptedData { params: EncryptionParams { iterations: 1000, ..blob.params.clone() }, ..blob.clone() };
+        assert!(encode_client_blob(&weak).is_err());
+        let truncated = EncryptedData { data: general_purpose::STANDARD.encode([7u8; 8]), ..blob };
+        assert!(encode_client_blob(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_encryption_params_validation() {
+        let params = ZeroKnowledgeManager::new().generate_encryption_params().unwrap();
+        assert!(params.validate().is_ok());
+
+        let with = |f: fn(&mut EncryptionParams)| {
+            let mut params = params.clone();
+            f(&mut params);
+            params.validate()
+        };
+        assert!(with(|p| p.iterations = 1).is_err());
+        assert!(with(|p| p.iterations = MIN_PBKDF2_ITERATIONS - 1).is_err());
+        assert!(with(|p| p.iterations = MAX_PBKDF2_ITERATIONS + 1).is_err());
+        assert!(with(|p| p.salt = "not base64!".to_string()).is_err());
+        assert!(with(|p| p.salt = general_purpose::STANDARD.encode([1u8; 8])).is_err());
+        assert!(with(|p| p.nonce = general_purpose::STANDARD.encode([1u8; 16])).is_err());
+    }
+}
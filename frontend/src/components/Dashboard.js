@@ -126,7 +126,7 @@ function Dashboard() {
       await loadData();
       
       // Show success message
-      const importedCount = response.data;
+      const importedCount = response.data.imported;
       alert(`Successfully imported ${importedCount} passwords`);
     } catch (err) {
       console.error('Error importing CSV:', err);
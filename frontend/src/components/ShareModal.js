@@ -5,7 +5,7 @@ import { sanitizeUsername } from '../utils/sanitization';
 
 function ShareModal({ isOpen, onClose, itemType, itemId, onSuccess }) {
   const [recipient, setRecipient] = useState('');
-  const [shareType, setShareType] = useState('view'); // 'view' or 'edit'
+  const [shareType, setShareType] = useState('read'); // 'read' or 'write'
   const [expirationDays, setExpirationDays] = useState('7');
 
   if (!isOpen) return null;
@@ -46,7 +46,7 @@ function ShareModal({ isOpen, onClose, itemType, itemId, onSuccess }) {
       if (response.data.success) {
         alert(`${itemType.charAt(0).toUpperCase() + itemType.slice(1)} shared successfully!`);
         setRecipient('');
-        setShareType('view');
+        setShareType('read');
         setExpirationDays('7');
         if (onSuccess) {
           onSuccess();
@@ -94,8 +94,8 @@ function ShareModal({ isOpen, onClose, itemType, itemId, onSuccess }) {
               onChange={(e) => setShareType(e.target.value)}
               className="w-full px-3 py-2 border border-black border-2 rounded-md focus:outline-none focus:ring-2 focus:ring-blue-500"
             >
-              <option value="view">View Only</option>
-              <option value="edit">Can Edit</option>
+              <option value="read">View Only</option>
+              <option value="write">Can Edit</option>
             </select>
           </div>
           